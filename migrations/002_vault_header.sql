@@ -0,0 +1,13 @@
+-- Vault header: per-vault KDF salt, parameters and master password verifier
+-- Single row (id = 1); absent on vaults created before the header existed
+CREATE TABLE IF NOT EXISTS vault_header (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    salt BLOB NOT NULL,
+    kdf_algorithm TEXT NOT NULL DEFAULT 'argon2id',
+    kdf_m_cost INTEGER NOT NULL,
+    kdf_t_cost INTEGER NOT NULL,
+    kdf_p_cost INTEGER NOT NULL,
+    verifier BLOB NOT NULL, -- age-encrypted known plaintext
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
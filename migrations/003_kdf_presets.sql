@@ -0,0 +1,3 @@
+-- KDF versioning and the preset selected in Settings
+ALTER TABLE vault_header ADD COLUMN kdf_version INTEGER NOT NULL DEFAULT 19; -- Argon2 version (0x13)
+ALTER TABLE vault_header ADD COLUMN kdf_preset TEXT; -- applied at the next unlock, NULL keeps current parameters
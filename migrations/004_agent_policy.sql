@@ -0,0 +1,18 @@
+-- Agent usage policy per identity
+ALTER TABLE identities ADD COLUMN agent_confirm INTEGER NOT NULL DEFAULT 0; -- prompt before each signature
+ALTER TABLE identities ADD COLUMN agent_restrict_hosts INTEGER NOT NULL DEFAULT 0; -- sign only for linked hosts
+
+-- Agent sign log: every signing request and the decision taken
+CREATE TABLE IF NOT EXISTS agent_sign_log (
+    id TEXT PRIMARY KEY NOT NULL,
+    identity_id TEXT,
+    host_id TEXT, -- host the agent socket was bound to, NULL for the shared socket
+    key_fingerprint TEXT NOT NULL,
+    forwarded INTEGER NOT NULL DEFAULT 0, -- request arrived through agent forwarding
+    decision TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    FOREIGN KEY (identity_id) REFERENCES identities(id) ON DELETE SET NULL,
+    FOREIGN KEY (host_id) REFERENCES hosts(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_sign_log_created_at ON agent_sign_log(created_at);
@@ -0,0 +1,13 @@
+-- Known hosts: server host keys trusted for a hostname and port
+CREATE TABLE IF NOT EXISTS known_hosts (
+    id TEXT PRIMARY KEY NOT NULL,
+    hostname TEXT NOT NULL, -- lowercase
+    port INTEGER NOT NULL DEFAULT 22,
+    key_type TEXT NOT NULL, -- e.g. ssh-ed25519, ssh-rsa
+    fingerprint TEXT NOT NULL, -- SHA256:<base64>, as printed by ssh-keygen -l
+    public_key TEXT NOT NULL, -- base64 key blob, as in OpenSSH known_hosts files
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    UNIQUE (hostname, port, key_type)
+);
+
+CREATE INDEX IF NOT EXISTS idx_known_hosts_hostname_port ON known_hosts(hostname, port);
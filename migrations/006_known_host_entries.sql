@@ -0,0 +1,13 @@
+-- Known host entries imported from OpenSSH known_hosts files. Unlike known_hosts,
+-- the host field is kept as written: patterns, [host]:port and hashed |1| names
+CREATE TABLE IF NOT EXISTS known_host_entries (
+    id TEXT PRIMARY KEY NOT NULL,
+    marker TEXT NOT NULL DEFAULT '' CHECK (marker IN ('', 'cert-authority', 'revoked')),
+    hosts TEXT NOT NULL, -- host field as written, e.g. 'a.example.com,10.0.0.1' or '|1|salt|hash'
+    key_type TEXT NOT NULL,
+    fingerprint TEXT NOT NULL, -- SHA256:<base64>
+    public_key TEXT NOT NULL, -- base64 key blob
+    comment TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    UNIQUE (marker, hosts, key_type, public_key)
+);
@@ -0,0 +1,3 @@
+-- Extra ssh options per host, as found in ssh_config
+ALTER TABLE hosts ADD COLUMN proxy_jump TEXT; -- ProxyJump destination(s), e.g. bastion or user@jump:2222
+ALTER TABLE hosts ADD COLUMN local_forwards TEXT; -- JSON array of LocalForward specs, e.g. "8080 localhost:80"
@@ -0,0 +1,8 @@
+-- Custom terminals, launched from an argument-vector template
+CREATE TABLE IF NOT EXISTS terminal_templates (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    argv TEXT NOT NULL, -- JSON array, e.g. ["wezterm", "start", "--", "{ssh_argv}"]
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
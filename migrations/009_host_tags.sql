@@ -0,0 +1,20 @@
+-- Host tags, one row per tag, replacing the JSON array in hosts.tags
+CREATE TABLE IF NOT EXISTS host_tags (
+    host_id TEXT NOT NULL,
+    tag TEXT NOT NULL, -- lowercase, e.g. "prod"
+    PRIMARY KEY (host_id, tag),
+    FOREIGN KEY (host_id) REFERENCES hosts(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_host_tags_tag ON host_tags(tag);
+
+INSERT OR IGNORE INTO host_tags (host_id, tag)
+SELECT hosts.id, lower(trim(json_each.value))
+FROM hosts, json_each(hosts.tags)
+WHERE hosts.tags IS NOT NULL
+  AND json_valid(hosts.tags)
+  AND json_each.type = 'text'
+  AND trim(json_each.value) <> '';
+
+DROP INDEX IF EXISTS idx_hosts_tags;
+ALTER TABLE hosts DROP COLUMN tags;
@@ -0,0 +1,15 @@
+-- Connection history: every connection attempt and how it ended
+CREATE TABLE IF NOT EXISTS connection_history (
+    id TEXT PRIMARY KEY NOT NULL,
+    host_id TEXT NOT NULL,
+    identity_id TEXT,
+    target TEXT NOT NULL, -- terminal or multiplexer launched, e.g. "kitty", "tmux"; "ssh" for the CLI
+    success INTEGER NOT NULL,
+    error TEXT, -- why the attempt failed
+    connected_at TEXT NOT NULL,
+    FOREIGN KEY (host_id) REFERENCES hosts(id) ON DELETE CASCADE,
+    FOREIGN KEY (identity_id) REFERENCES identities(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_connection_history_host ON connection_history(host_id, connected_at);
+CREATE INDEX IF NOT EXISTS idx_connection_history_connected_at ON connection_history(connected_at);
@@ -0,0 +1,898 @@
+use anyhow::{Context, Result};
+use futures::Stream;
+use russh::CryptoVec;
+use russh_keys::encoding::{Encoding, Reader};
+use russh_keys::key::{self, KeyPair, SignatureHash};
+use russh_keys::PublicKeyBase64;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::db;
+use crate::known_hosts::{self, HostKeyStatus, ServerKey};
+
+// ssh-agent protocol messages (draft-miller-ssh-agent)
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENT_SUCCESS: u8 = 6;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH_AGENTC_EXTENSION: u8 = 27;
+
+// Sign request flags
+const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+/// Sent by OpenSSH >= 8.9 to tell the agent which server a connection is for
+/// and whether it arrived through agent forwarding
+const SESSION_BIND_EXTENSION: &[u8] = b"session-bind@openssh.com";
+
+/// Upper bound on a single agent message, as in OpenSSH
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// How long a confirmation prompt waits for the user before refusing
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+
+static NEXT_AGENT_INSTANCE: AtomicU64 = AtomicU64::new(1);
+
+/// Restrictions on how the agent may use a key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyPolicy {
+    /// Ask the user before every signature, like `ssh-add -c`
+    pub confirm: bool,
+    /// Only sign on sockets bound to a host linked to the identity
+    pub restrict_to_linked_hosts: bool,
+}
+
+/// A signing request waiting for the user's approval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfirmRequest {
+    pub id: u64,
+    pub identity_id: String,
+    pub comment: String,
+    pub key_fingerprint: String,
+    /// Host the requesting socket is bound to, `None` for the shared socket
+    pub host_id: Option<String>,
+    /// The request came through agent forwarding from a remote host
+    pub forwarded: bool,
+}
+
+/// Outcome of a signing request, as recorded in the sign log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignDecision {
+    Allowed,
+    Confirmed,
+    Rejected,
+    TimedOut,
+    HostNotAllowed,
+}
+
+impl SignDecision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignDecision::Allowed => "allowed",
+            SignDecision::Confirmed => "confirmed",
+            SignDecision::Rejected => "rejected",
+            SignDecision::TimedOut => "timed_out",
+            SignDecision::HostNotAllowed => "host_not_allowed",
+        }
+    }
+
+    fn permits(&self) -> bool {
+        matches!(self, SignDecision::Allowed | SignDecision::Confirmed)
+    }
+}
+
+/// A decrypted identity held by the agent
+#[derive(Clone)]
+struct AgentKey {
+    identity_id: String,
+    comment: String,
+    policy: KeyPolicy,
+    key_pair: Arc<KeyPair>,
+}
+
+/// State shared by the agent handle and every client connection
+struct Shared {
+    /// Keys served by the agent, indexed by public key blob
+    keys: RwLock<HashMap<Vec<u8>, AgentKey>>,
+    pool: SqlitePool,
+    confirm_tx: mpsc::UnboundedSender<ConfirmRequest>,
+    pending: Mutex<HashMap<u64, oneshot::Sender<bool>>>,
+    next_request_id: AtomicU64,
+}
+
+/// ssh-agent protocol server holding decrypted vault identities in memory only.
+///
+/// Keys are never written to disk; dropping the agent stops the listeners,
+/// forgets every key and removes the sockets. Besides the shared socket, the
+/// agent can bind one socket per host so keys restricted to linked hosts are
+/// only offered to ssh sessions launched for those hosts.
+pub struct SshAgent {
+    instance: u64,
+    socket_path: PathBuf,
+    shared: Arc<Shared>,
+    listener: JoinHandle<()>,
+    host_listeners: Mutex<HashMap<String, (PathBuf, JoinHandle<()>)>>,
+    confirm_rx: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<ConfirmRequest>>>,
+}
+
+impl std::fmt::Debug for SshAgent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SshAgent")
+            .field("socket_path", &self.socket_path)
+            .field("keys", &"<redacted>")
+            .finish()
+    }
+}
+
+impl SshAgent {
+    /// Per-user socket location: `$XDG_RUNTIME_DIR/nebulavault/agent.sock`,
+    /// falling back to a user-named directory in the temp dir
+    pub fn default_socket_path() -> PathBuf {
+        let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+            Some(runtime_dir) => PathBuf::from(runtime_dir).join("nebulavault"),
+            None => {
+                let user = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
+                std::env::temp_dir().join(format!("nebulavault-{}", user))
+            }
+        };
+        dir.join("agent.sock")
+    }
+
+    /// Socket [`SshAgent::host_socket`] binds for a host, next to the agent's `socket_path`
+    pub fn host_socket_path(socket_path: &Path, host_id: &str) -> PathBuf {
+        socket_path.with_file_name(host_socket_file_name(host_id))
+    }
+
+    /// Bind the shared socket and start serving requests.
+    ///
+    /// Signing requests are recorded in the agent sign log of `pool`.
+    pub async fn start(socket_path: PathBuf, pool: SqlitePool) -> Result<Self> {
+        if let Some(dir) = socket_path.parent() {
+            use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .context("Failed to create agent socket directory")?;
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+                .context("Failed to restrict agent socket directory")?;
+        }
+
+        let (confirm_tx, confirm_rx) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            keys: RwLock::new(HashMap::new()),
+            pool,
+            confirm_tx,
+            pending: Mutex::new(HashMap::new()),
+            next_request_id: AtomicU64::new(1),
+        });
+        let listener = spawn_listener(&socket_path, shared.clone(), None)?;
+
+        Ok(Self {
+            instance: NEXT_AGENT_INSTANCE.fetch_add(1, Ordering::Relaxed),
+            socket_path,
+            shared,
+            listener,
+            host_listeners: Mutex::new(HashMap::new()),
+            confirm_rx: Arc::new(tokio::sync::Mutex::new(confirm_rx)),
+        })
+    }
+
+    /// Distinguishes this agent from earlier ones using the same socket path
+    pub fn instance(&self) -> u64 {
+        self.instance
+    }
+
+    /// Path of the shared Unix socket to use as `SSH_AUTH_SOCK`
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Socket bound to one host, binding it on first use.
+    ///
+    /// Keys restricted to linked hosts are only offered on the socket of a host
+    /// whose identity they belong to.
+    pub fn host_socket(&self, host_id: &str) -> Result<PathBuf> {
+        if host_id.is_empty() || !host_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            anyhow::bail!("Invalid host id: {}", host_id);
+        }
+
+        let mut host_listeners = self
+            .host_listeners
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Agent listeners poisoned"))?;
+        if let Some((path, _)) = host_listeners.get(host_id) {
+            return Ok(path.clone());
+        }
+
+        let path = Self::host_socket_path(&self.socket_path, host_id);
+        let listener = spawn_listener(&path, self.shared.clone(), Some(host_id.to_string()))?;
+        host_listeners.insert(host_id.to_string(), (path.clone(), listener));
+
+        Ok(path)
+    }
+
+    /// Decode a private key and serve it for the given identity
+    pub fn add_identity(
+        &self,
+        identity_id: &str,
+        comment: &str,
+        private_key: &str,
+        passphrase: Option<&str>,
+        policy: KeyPolicy,
+    ) -> Result<()> {
+        let key_pair = russh_keys::decode_secret_key(private_key, passphrase)
+            .map_err(|e| anyhow::anyhow!("Failed to parse private key: {}", e))?;
+
+        self.add_key_pair(identity_id, comment, key_pair, policy)
+    }
+
+    fn add_key_pair(
+        &self,
+        identity_id: &str,
+        comment: &str,
+        key_pair: KeyPair,
+        policy: KeyPolicy,
+    ) -> Result<()> {
+        let blob = key_pair
+            .clone_public_key()
+            .map_err(|e| anyhow::anyhow!("Failed to derive public key: {}", e))?
+            .public_key_bytes();
+
+        let mut keys = self
+            .shared
+            .keys
+            .write()
+            .map_err(|_| anyhow::anyhow!("Agent key store poisoned"))?;
+        keys.insert(
+            blob,
+            AgentKey {
+                identity_id: identity_id.to_string(),
+                comment: comment.to_string(),
+                policy,
+                key_pair: Arc::new(key_pair),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Whether a key for this identity is currently loaded
+    pub fn has_identity(&self, identity_id: &str) -> bool {
+        self.shared
+            .keys
+            .read()
+            .map(|keys| keys.values().any(|k| k.identity_id == identity_id))
+            .unwrap_or(false)
+    }
+
+    /// Signing requests that need the user's approval.
+    ///
+    /// Answer each one with [`SshAgent::resolve_confirmation`]; unanswered
+    /// requests are refused after a timeout.
+    pub fn confirmation_requests(&self) -> impl Stream<Item = ConfirmRequest> + Send + 'static {
+        futures::stream::unfold(self.confirm_rx.clone(), |confirm_rx| async move {
+            let request = confirm_rx.lock().await.recv().await;
+            request.map(|request| (request, confirm_rx))
+        })
+    }
+
+    /// Approve or refuse a pending signing request
+    pub fn resolve_confirmation(&self, request_id: u64, approved: bool) {
+        let sender = self
+            .shared
+            .pending
+            .lock()
+            .ok()
+            .and_then(|mut pending| pending.remove(&request_id));
+        if let Some(sender) = sender {
+            let _ = sender.send(approved);
+        }
+    }
+
+    /// Whether a signing request is still waiting for an answer
+    pub fn is_confirmation_pending(&self, request_id: u64) -> bool {
+        self.shared
+            .pending
+            .lock()
+            .map(|pending| pending.contains_key(&request_id))
+            .unwrap_or(false)
+    }
+
+    /// Forget every loaded key and refuse pending confirmations
+    pub fn clear(&self) {
+        if let Ok(mut keys) = self.shared.keys.write() {
+            keys.clear();
+        }
+        if let Ok(mut pending) = self.shared.pending.lock() {
+            pending.clear();
+        }
+    }
+}
+
+impl Drop for SshAgent {
+    fn drop(&mut self) {
+        self.listener.abort();
+        let _ = std::fs::remove_file(&self.socket_path);
+        if let Ok(mut host_listeners) = self.host_listeners.lock() {
+            for (_, (path, listener)) in host_listeners.drain() {
+                listener.abort();
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        self.clear();
+    }
+}
+
+fn host_socket_file_name(host_id: &str) -> String {
+    format!("agent-{}.sock", host_id)
+}
+
+/// Bind a restricted socket and accept clients on it
+fn spawn_listener(
+    socket_path: &Path,
+    shared: Arc<Shared>,
+    host_id: Option<String>,
+) -> Result<JoinHandle<()>> {
+    // A socket left behind by a previous run would make bind fail
+    if socket_path.exists() {
+        std::fs::remove_file(socket_path).context("Failed to remove stale agent socket")?;
+    }
+
+    let listener = UnixListener::bind(socket_path).context("Failed to bind agent socket")?;
+
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))
+            .context("Failed to restrict agent socket")?;
+    }
+
+    Ok(tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let connection = Connection {
+                shared: shared.clone(),
+                host_id: host_id.clone(),
+                forwarded: false,
+                binding: Binding::Unbound,
+            };
+            tokio::spawn(async move {
+                let _ = connection.serve(stream).await;
+            });
+        }
+    }))
+}
+
+/// Which server ssh has bound a connection to with session-bind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Binding {
+    /// Nothing was bound: ssh before 8.9, or a client that is not ssh at all
+    Unbound,
+    /// A key trusted for the socket's host
+    Trusted,
+    /// A key not trusted for the socket's host, or a bad signature; never
+    /// cleared, like `forwarded`
+    Untrusted,
+}
+
+/// One client connection and what is known about its destination
+struct Connection {
+    shared: Arc<Shared>,
+    host_id: Option<String>,
+    /// Set once ssh reports the connection as forwarded; never cleared, so a
+    /// remote peer cannot rebind a forwarded connection as local
+    forwarded: bool,
+    binding: Binding,
+}
+
+impl Connection {
+    /// Answer requests until the client closes the connection
+    async fn serve(mut self, mut stream: UnixStream) -> Result<()> {
+        loop {
+            let len = match stream.read_u32().await {
+                Ok(len) => len as usize,
+                // Client hung up
+                Err(_) => return Ok(()),
+            };
+            if len == 0 || len > MAX_MESSAGE_LEN {
+                anyhow::bail!("Invalid agent message length: {}", len);
+            }
+
+            let mut request = CryptoVec::new();
+            request.resize(len);
+            stream.read_exact(&mut request).await?;
+
+            let mut response = CryptoVec::new();
+            response.push_u32_be(0);
+            if self.respond(&request, &mut response).await.is_err() {
+                response.resize(4);
+                response.push(SSH_AGENT_FAILURE);
+            }
+            let body_len = (response.len() - 4) as u32;
+            response[..4].copy_from_slice(&body_len.to_be_bytes());
+
+            stream.write_all(&response).await?;
+            stream.flush().await?;
+        }
+    }
+
+    /// Build the response body for one request
+    async fn respond(&mut self, request: &[u8], response: &mut CryptoVec) -> Result<()> {
+        let mut reader = request.reader(0);
+
+        match reader.read_byte()? {
+            SSH_AGENTC_REQUEST_IDENTITIES => {
+                let keys: Vec<(Vec<u8>, AgentKey)> = self
+                    .shared
+                    .keys
+                    .read()
+                    .map_err(|_| anyhow::anyhow!("Agent key store poisoned"))?
+                    .iter()
+                    .map(|(blob, key)| (blob.clone(), key.clone()))
+                    .collect();
+
+                // Don't advertise keys that would be refused on this connection
+                let mut offered = Vec::new();
+                for (blob, key) in keys {
+                    if self.host_permits(&key).await {
+                        offered.push((blob, key));
+                    }
+                }
+
+                response.push(SSH_AGENT_IDENTITIES_ANSWER);
+                response.push_u32_be(offered.len() as u32);
+                for (blob, key) in &offered {
+                    response.extend_ssh_string(blob);
+                    response.extend_ssh_string(key.comment.as_bytes());
+                }
+            }
+            SSH_AGENTC_SIGN_REQUEST => {
+                let blob = reader.read_string()?;
+                let data = reader.read_string()?;
+                let flags = reader.read_u32().unwrap_or(0);
+
+                let key = self
+                    .shared
+                    .keys
+                    .read()
+                    .map_err(|_| anyhow::anyhow!("Agent key store poisoned"))?
+                    .get(blob)
+                    .cloned()
+                    .context("Unknown key")?;
+                let fingerprint = key
+                    .key_pair
+                    .clone_public_key()
+                    .map_err(|e| anyhow::anyhow!("Failed to derive public key: {}", e))?
+                    .fingerprint();
+
+                let decision = self.authorize(&key, &fingerprint).await;
+                let _ = db::log_agent_signature(
+                    &self.shared.pool,
+                    &key.identity_id,
+                    self.host_id.as_deref(),
+                    &fingerprint,
+                    self.forwarded,
+                    decision.as_str(),
+                )
+                .await;
+                if !decision.permits() {
+                    anyhow::bail!("Signature refused: {}", decision.as_str());
+                }
+
+                let key_pair = match flags {
+                    f if f & SSH_AGENT_RSA_SHA2_512 != 0 => key.key_pair.with_signature_hash(SignatureHash::SHA2_512),
+                    f if f & SSH_AGENT_RSA_SHA2_256 != 0 => key.key_pair.with_signature_hash(SignatureHash::SHA2_256),
+                    _ => key.key_pair.with_signature_hash(SignatureHash::SHA1),
+                };
+                let key_pair = key_pair.as_ref().unwrap_or(&key.key_pair);
+
+                response.push(SSH_AGENT_SIGN_RESPONSE);
+                key_pair
+                    .add_signature(response, data)
+                    .map_err(|e| anyhow::anyhow!("Failed to sign: {}", e))?;
+            }
+            SSH_AGENTC_EXTENSION if reader.read_string()? == SESSION_BIND_EXTENSION => {
+                let host_key = reader.read_string()?;
+                let session_id = reader.read_string()?;
+                let signature = reader.read_string()?;
+                self.forwarded |= reader.read_byte()? != 0;
+
+                if !session_signature_valid(host_key, session_id, signature) {
+                    self.binding = Binding::Untrusted;
+                    anyhow::bail!("Invalid session-bind signature");
+                }
+                if self.binding != Binding::Untrusted {
+                    self.binding = if self.host_key_trusted(host_key).await {
+                        Binding::Trusted
+                    } else {
+                        Binding::Untrusted
+                    };
+                }
+                response.push(SSH_AGENT_SUCCESS);
+            }
+            // Adding, removing and locking keys is only done through the vault
+            _ => response.push(SSH_AGENT_FAILURE),
+        }
+
+        Ok(())
+    }
+
+    /// Whether the key's host restriction allows it on this connection.
+    ///
+    /// Restricted keys need a socket bound to a linked host, on a connection
+    /// ssh has bound to a key trusted for that host. Without session-bind the
+    /// server is unknown, so they are refused; they are also never used for
+    /// forwarded requests, whose final destination is unknown.
+    async fn host_permits(&self, key: &AgentKey) -> bool {
+        if !key.policy.restrict_to_linked_hosts {
+            return true;
+        }
+
+        match &self.host_id {
+            Some(host_id) if !self.forwarded && self.binding == Binding::Trusted => {
+                db::is_host_linked_to_identity(&self.shared.pool, host_id, &key.identity_id)
+                    .await
+                    .unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `host_key` is trusted for the host this socket is bound to
+    async fn host_key_trusted(&self, host_key: &[u8]) -> bool {
+        let Some(host_id) = &self.host_id else {
+            return false;
+        };
+        let Ok(key) = ServerKey::from_blob(host_key) else {
+            return false;
+        };
+        let host = match db::get_host_by_id(&self.shared.pool, host_id).await {
+            Ok(Some(host)) => host,
+            _ => return false,
+        };
+        let Ok(port) = u16::try_from(host.port) else {
+            return false;
+        };
+
+        matches!(
+            known_hosts::check(&self.shared.pool, &host.hostname, port, &key).await,
+            Ok(HostKeyStatus::Trusted)
+        )
+    }
+
+    /// Apply the key's policy to a signing request
+    async fn authorize(&self, key: &AgentKey, fingerprint: &str) -> SignDecision {
+        if !self.host_permits(key).await {
+            return SignDecision::HostNotAllowed;
+        }
+        if !key.policy.confirm {
+            return SignDecision::Allowed;
+        }
+
+        let id = self.shared.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        match self.shared.pending.lock() {
+            Ok(mut pending) => {
+                pending.insert(id, reply_tx);
+            }
+            Err(_) => return SignDecision::Rejected,
+        }
+
+        let request = ConfirmRequest {
+            id,
+            identity_id: key.identity_id.clone(),
+            comment: key.comment.clone(),
+            key_fingerprint: fingerprint.to_string(),
+            host_id: self.host_id.clone(),
+            forwarded: self.forwarded,
+        };
+        if self.shared.confirm_tx.send(request).is_err() {
+            self.forget_pending(id);
+            return SignDecision::Rejected;
+        }
+
+        match tokio::time::timeout(CONFIRM_TIMEOUT, reply_rx).await {
+            Ok(Ok(true)) => SignDecision::Confirmed,
+            // Refused, or the agent was cleared while waiting
+            Ok(_) => SignDecision::Rejected,
+            Err(_) => {
+                self.forget_pending(id);
+                SignDecision::TimedOut
+            }
+        }
+    }
+
+    fn forget_pending(&self, id: u64) {
+        if let Ok(mut pending) = self.shared.pending.lock() {
+            pending.remove(&id);
+        }
+    }
+}
+
+/// Check the server's signature over the session identifier, which proves
+/// the bound host key is the one the session was established with
+fn session_signature_valid(host_key: &[u8], session_id: &[u8], signature: &[u8]) -> bool {
+    let mut reader = signature.reader(0);
+    let (Ok(algorithm), Ok(signature)) = (reader.read_string(), reader.read_string()) else {
+        return false;
+    };
+    let Ok(public_key) = key::parse_public_key(host_key, SignatureHash::from_rsa_hostkey_algo(algorithm)) else {
+        return false;
+    };
+    public_key.verify_detached(session_id, signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use russh_keys::agent::client::AgentClient;
+
+    struct TestAgent {
+        agent: SshAgent,
+        pool: SqlitePool,
+        socket_path: PathBuf,
+        _db: db::TestDb,
+    }
+
+    async fn start_test_agent() -> TestAgent {
+        let test_db = db::TestDb::new().await;
+        let pool = test_db.pool.clone();
+        let socket_path = test_db.dir.join("agent.sock");
+        let agent = SshAgent::start(socket_path.clone(), pool.clone()).await.unwrap();
+
+        TestAgent {
+            agent,
+            pool,
+            socket_path,
+            _db: test_db,
+        }
+    }
+
+    impl TestAgent {
+        async fn finish(self) {
+            drop(self.agent);
+            assert!(!self.socket_path.exists());
+        }
+    }
+
+    const SESSION_ID: &[u8] = b"session id";
+
+    /// Send session-bind as ssh does after key exchange, with the server's
+    /// signature over `signed`; returns whether the agent accepted it
+    async fn session_bind(stream: &mut UnixStream, host_key: &KeyPair, signed: &[u8], forwarded: bool) -> bool {
+        let mut message = CryptoVec::new();
+        message.push(SSH_AGENTC_EXTENSION);
+        message.extend_ssh_string(SESSION_BIND_EXTENSION);
+        message.extend_ssh_string(&host_key.clone_public_key().unwrap().public_key_bytes());
+        message.extend_ssh_string(SESSION_ID);
+        host_key.add_signature(&mut message, signed).unwrap();
+        message.push(forwarded as u8);
+
+        stream.write_u32(message.len() as u32).await.unwrap();
+        stream.write_all(&message).await.unwrap();
+        let mut reply = vec![0; stream.read_u32().await.unwrap() as usize];
+        stream.read_exact(&mut reply).await.unwrap();
+        reply == [SSH_AGENT_SUCCESS]
+    }
+
+    /// Connect to `socket` and bind the connection to a server with `host_key`
+    async fn connect_bound(socket: &Path, host_key: &KeyPair) -> AgentClient<UnixStream> {
+        let mut stream = UnixStream::connect(socket).await.unwrap();
+        assert!(session_bind(&mut stream, host_key, SESSION_ID, false).await);
+        AgentClient::connect(stream)
+    }
+
+    #[tokio::test]
+    async fn test_agent_lists_and_signs_with_loaded_key() {
+        let test = start_test_agent().await;
+        let identity = db::create_identity(&test.pool, "test key".to_string(), vec![]).await.unwrap();
+
+        let key_pair = KeyPair::generate_ed25519().unwrap();
+        let public_key = key_pair.clone_public_key().unwrap();
+        test.agent
+            .add_key_pair(&identity.id, "test key", key_pair, KeyPolicy::default())
+            .unwrap();
+        assert!(test.agent.has_identity(&identity.id));
+
+        let mut client = AgentClient::connect_uds(&test.socket_path).await.unwrap();
+        let identities = client.request_identities().await.unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].public_key_bytes(), public_key.public_key_bytes());
+
+        let data = b"session data".to_vec();
+        let (_client, signed) = client.sign_request(&public_key, CryptoVec::from_slice(&data)).await;
+        let signed = signed.unwrap();
+
+        let mut reader = signed[data.len()..].reader(0);
+        let mut signature = reader.read_string().unwrap().reader(0);
+        assert_eq!(signature.read_string().unwrap(), b"ssh-ed25519");
+        assert!(public_key.verify_detached(&data, signature.read_string().unwrap()));
+
+        let log = db::get_agent_sign_log(&test.pool, 10).await.unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].decision, "allowed");
+        assert_eq!(log[0].key_fingerprint, public_key.fingerprint());
+
+        test.finish().await;
+    }
+
+    #[tokio::test]
+    async fn test_restricted_key_only_signs_for_linked_host() {
+        let test = start_test_agent().await;
+        let identity = db::create_identity(&test.pool, "deploy".to_string(), vec![]).await.unwrap();
+        let linked = db::create_host(
+            &test.pool,
+            None,
+            Some(identity.id.clone()),
+            "linked".to_string(),
+            "linked.example.com".to_string(),
+            22,
+            "deploy".to_string(),
+            &[],
+        )
+        .await
+        .unwrap();
+        let other = db::create_host(
+            &test.pool,
+            None,
+            None,
+            "other".to_string(),
+            "other.example.com".to_string(),
+            22,
+            "deploy".to_string(),
+            &[],
+        )
+        .await
+        .unwrap();
+
+        let key_pair = KeyPair::generate_ed25519().unwrap();
+        let public_key = key_pair.clone_public_key().unwrap();
+        let policy = KeyPolicy {
+            confirm: false,
+            restrict_to_linked_hosts: true,
+        };
+        test.agent.add_key_pair(&identity.id, "deploy", key_pair, policy).unwrap();
+
+        let host_key = KeyPair::generate_ed25519().unwrap();
+        let server_key = ServerKey::from_public_key(&host_key.clone_public_key().unwrap());
+        for hostname in ["linked.example.com", "other.example.com"] {
+            known_hosts::trust(&test.pool, hostname, 22, &server_key).await.unwrap();
+        }
+
+        // Neither the shared socket nor another host's socket sees the key
+        for socket in [test.socket_path.clone(), test.agent.host_socket(&other.id).unwrap()] {
+            let mut client = connect_bound(&socket, &host_key).await;
+            assert!(client.request_identities().await.unwrap().is_empty());
+            let (_client, signed) = client.sign_request(&public_key, CryptoVec::from_slice(b"data")).await;
+            assert!(signed.is_err());
+        }
+
+        let linked_socket = test.agent.host_socket(&linked.id).unwrap();
+        let mut client = connect_bound(&linked_socket, &host_key).await;
+        assert_eq!(client.request_identities().await.unwrap().len(), 1);
+        let (_client, signed) = client.sign_request(&public_key, CryptoVec::from_slice(b"data")).await;
+        assert!(signed.is_ok());
+
+        let decisions: Vec<String> = db::get_agent_sign_log(&test.pool, 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.decision)
+            .collect();
+        assert_eq!(decisions.iter().filter(|d| *d == "host_not_allowed").count(), 2);
+        assert_eq!(decisions.iter().filter(|d| *d == "allowed").count(), 1);
+
+        test.finish().await;
+    }
+
+    #[tokio::test]
+    async fn test_restricted_key_needs_session_bound_to_trusted_host_key() {
+        let test = start_test_agent().await;
+        let identity = db::create_identity(&test.pool, "deploy".to_string(), vec![]).await.unwrap();
+        let host = db::create_host(
+            &test.pool,
+            None,
+            Some(identity.id.clone()),
+            "linked".to_string(),
+            "linked.example.com".to_string(),
+            22,
+            "deploy".to_string(),
+            &[],
+        )
+        .await
+        .unwrap();
+
+        let host_key = KeyPair::generate_ed25519().unwrap();
+        let server_key = ServerKey::from_public_key(&host_key.clone_public_key().unwrap());
+        known_hosts::trust(&test.pool, "linked.example.com", 22, &server_key).await.unwrap();
+
+        let restricted = KeyPolicy {
+            confirm: false,
+            restrict_to_linked_hosts: true,
+        };
+        test.agent
+            .add_key_pair(&identity.id, "deploy", KeyPair::generate_ed25519().unwrap(), restricted)
+            .unwrap();
+        test.agent
+            .add_key_pair(&identity.id, "personal", KeyPair::generate_ed25519().unwrap(), KeyPolicy::default())
+            .unwrap();
+        let socket = test.agent.host_socket(&host.id).unwrap();
+
+        // ssh before 8.9 never binds: the server is unknown, only unrestricted keys are offered
+        let mut client = AgentClient::connect_uds(&socket).await.unwrap();
+        assert_eq!(client.request_identities().await.unwrap().len(), 1);
+
+        let mut client = connect_bound(&socket, &host_key).await;
+        assert_eq!(client.request_identities().await.unwrap().len(), 2);
+
+        // A server key not trusted for the host, even when bound to the trusted one first
+        let impostor = KeyPair::generate_ed25519().unwrap();
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        assert!(session_bind(&mut stream, &host_key, SESSION_ID, false).await);
+        assert!(session_bind(&mut stream, &impostor, SESSION_ID, false).await);
+        assert!(session_bind(&mut stream, &host_key, SESSION_ID, false).await);
+        assert_eq!(AgentClient::connect(stream).request_identities().await.unwrap().len(), 1);
+
+        // A signature that does not cover the session
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        assert!(!session_bind(&mut stream, &host_key, b"another session", false).await);
+        assert_eq!(AgentClient::connect(stream).request_identities().await.unwrap().len(), 1);
+
+        // Forwarded through the trusted host to somewhere else
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        assert!(session_bind(&mut stream, &host_key, SESSION_ID, true).await);
+        assert_eq!(AgentClient::connect(stream).request_identities().await.unwrap().len(), 1);
+
+        test.finish().await;
+    }
+
+    #[tokio::test]
+    async fn test_confirmation_decides_signature() {
+        let test = start_test_agent().await;
+        let identity = db::create_identity(&test.pool, "prod".to_string(), vec![]).await.unwrap();
+
+        let key_pair = KeyPair::generate_ed25519().unwrap();
+        let public_key = key_pair.clone_public_key().unwrap();
+        let policy = KeyPolicy {
+            confirm: true,
+            restrict_to_linked_hosts: false,
+        };
+        test.agent.add_key_pair(&identity.id, "prod", key_pair, policy).unwrap();
+
+        let mut requests = Box::pin(test.agent.confirmation_requests());
+        for approve in [false, true] {
+            let client = AgentClient::connect_uds(&test.socket_path).await.unwrap();
+            let sign = tokio::spawn({
+                let public_key = public_key.clone();
+                async move { client.sign_request(&public_key, CryptoVec::from_slice(b"data")).await.1 }
+            });
+
+            let request = requests.next().await.unwrap();
+            assert_eq!(request.identity_id, identity.id);
+            assert_eq!(request.key_fingerprint, public_key.fingerprint());
+            test.agent.resolve_confirmation(request.id, approve);
+
+            assert_eq!(sign.await.unwrap().is_ok(), approve);
+        }
+
+        let log = db::get_agent_sign_log(&test.pool, 10).await.unwrap();
+        let mut decisions: Vec<&str> = log.iter().map(|record| record.decision.as_str()).collect();
+        decisions.sort();
+        assert_eq!(decisions, ["confirmed", "rejected"]);
+
+        drop(requests);
+        test.finish().await;
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::{Context, Result};
+use rand::RngCore;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+use zeroize::Zeroizing;
+
+use crate::agent::SshAgent;
+
+/// Environment variable carrying the single-use token to the askpass helper
+pub const TOKEN_ENV: &str = "NEBULAVAULT_ASKPASS_TOKEN";
+
+/// Environment variable carrying the socket of the process holding the password
+pub const SOCKET_ENV: &str = "NEBULAVAULT_ASKPASS_SOCKET";
+
+/// How long a token stays valid if ssh never asks for the password
+const TOKEN_TTL: Duration = Duration::from_secs(120);
+
+/// Longest token line a client may send
+const MAX_TOKEN_LEN: usize = 128;
+
+/// A password waiting for its ssh process to ask for it
+struct Ticket {
+    password: Zeroizing<String>,
+    expires: Instant,
+}
+
+type Tickets = Arc<Mutex<HashMap<String, Ticket>>>;
+
+/// Hands host passwords to `nebulavault askpass` over a per-user Unix socket.
+///
+/// Each password is stored under a random token that ssh receives in its
+/// environment; the first request with the token consumes it, so a password
+/// can be fetched once. Dropping the server forgets every password and removes
+/// the socket.
+pub struct AskpassServer {
+    socket_path: PathBuf,
+    tickets: Tickets,
+    listener: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for AskpassServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AskpassServer")
+            .field("socket_path", &self.socket_path)
+            .field("tickets", &"<redacted>")
+            .finish()
+    }
+}
+
+impl AskpassServer {
+    /// `askpass.sock` next to the default agent socket
+    pub fn default_socket_path() -> PathBuf {
+        SshAgent::default_socket_path().with_file_name("askpass.sock")
+    }
+
+    /// Bind `socket_path` (mode 0600) and start answering requests
+    pub async fn start(socket_path: PathBuf) -> Result<Self> {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+        if let Some(dir) = socket_path.parent() {
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .context("Failed to create askpass socket directory")?;
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+                .context("Failed to restrict askpass socket directory")?;
+        }
+
+        // A socket left behind by a previous run would make bind fail
+        if socket_path.exists() {
+            std::fs::remove_file(&socket_path).context("Failed to remove stale askpass socket")?;
+        }
+        let listener = UnixListener::bind(&socket_path).context("Failed to bind askpass socket")?;
+        std::fs::set_permissions(&socket_path, std::fs::Permissions::from_mode(0o600))
+            .context("Failed to restrict askpass socket")?;
+
+        let tickets: Tickets = Arc::new(Mutex::new(HashMap::new()));
+        let serving = tickets.clone();
+        let listener = tokio::spawn(async move {
+            // SAFETY: getuid cannot fail
+            let uid = unsafe { libc::getuid() };
+            while let Ok((stream, _)) = listener.accept().await {
+                if stream.peer_cred().map(|cred| cred.uid()).ok() != Some(uid) {
+                    continue;
+                }
+                let tickets = serving.clone();
+                tokio::spawn(async move {
+                    let _ = serve(stream, tickets).await;
+                });
+            }
+        });
+
+        Ok(Self {
+            socket_path,
+            tickets,
+            listener,
+        })
+    }
+
+    /// Path to pass to the helper in [`SOCKET_ENV`]
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Store `password` and return the single-use token that retrieves it
+    pub fn issue(&self, password: &str) -> String {
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let mut tickets = self.tickets.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        tickets.retain(|_, ticket| ticket.expires > now);
+        tickets.insert(
+            token.clone(),
+            Ticket {
+                password: Zeroizing::new(password.to_string()),
+                expires: now + TOKEN_TTL,
+            },
+        );
+        token
+    }
+
+    /// Forget every password that has not been fetched yet
+    pub fn clear(&self) {
+        if let Ok(mut tickets) = self.tickets.lock() {
+            tickets.clear();
+        }
+    }
+}
+
+impl Drop for AskpassServer {
+    fn drop(&mut self) {
+        self.listener.abort();
+        let _ = std::fs::remove_file(&self.socket_path);
+        self.clear();
+    }
+}
+
+/// Answer one request: a token line in, the password line out, or nothing for
+/// an unknown or expired token
+async fn serve(stream: UnixStream, tickets: Tickets) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    let mut reader = BufReader::new(reader.take(MAX_TOKEN_LEN as u64 + 1));
+    reader.read_line(&mut line).await?;
+
+    let ticket = tickets
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(line.trim_end())
+        .filter(|ticket| ticket.expires > Instant::now());
+
+    if let Some(ticket) = ticket {
+        let response = Zeroizing::new(format!("{}\n", ticket.password.as_str()));
+        writer.write_all(response.as_bytes()).await?;
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
+/// Fetch the password for `token` from the server at `socket`
+pub fn request_password(socket: &Path, token: &str) -> Result<Zeroizing<String>> {
+    let mut stream = std::os::unix::net::UnixStream::connect(socket)
+        .with_context(|| format!("Failed to connect to {}", socket.display()))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    writeln!(stream, "{}", token)?;
+
+    let mut line = Zeroizing::new(String::new());
+    std::io::BufReader::new(stream).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        anyhow::bail!("Password token is unknown, expired or already used");
+    }
+    line.pop();
+    Ok(line)
+}
+
+/// Whether ssh ran us as `SSH_ASKPASS` for a connection we launched
+pub fn is_requested() -> bool {
+    std::env::var_os(TOKEN_ENV).is_some()
+}
+
+/// Run as `SSH_ASKPASS`: print the password for the token in the environment.
+///
+/// Only password prompts are answered; host key confirmations and key
+/// passphrase prompts fail, which ssh treats as "no" and moves on.
+pub fn answer(prompt: Option<&str>) -> Result<()> {
+    let prompt = prompt.unwrap_or_default();
+    if !prompt.to_ascii_lowercase().contains("password") {
+        anyhow::bail!("Not a password prompt: {}", prompt.trim());
+    }
+
+    let token = std::env::var(TOKEN_ENV).with_context(|| format!("{} is not set", TOKEN_ENV))?;
+    let socket = std::env::var_os(SOCKET_ENV).with_context(|| format!("{} is not set", SOCKET_ENV))?;
+    let password = request_password(Path::new(&socket), &token)?;
+
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", password.as_str())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_token_is_single_use() {
+        let dir = std::env::temp_dir().join(format!("nebulavault_test_{}", uuid::Uuid::new_v4()));
+        let server = AskpassServer::start(dir.join("askpass.sock")).await.unwrap();
+        let socket = server.socket_path().to_path_buf();
+        let token = server.issue("hunter2");
+
+        let fetch = |token: String| {
+            let socket = socket.clone();
+            tokio::task::spawn_blocking(move || request_password(&socket, &token))
+        };
+
+        assert_eq!(fetch(token.clone()).await.unwrap().unwrap().as_str(), "hunter2");
+        assert!(fetch(token).await.unwrap().is_err());
+        assert!(fetch("0".repeat(64)).await.unwrap().is_err());
+
+        let token = server.issue("hunter2");
+        server.clear();
+        assert!(fetch(token).await.unwrap().is_err());
+
+        drop(server);
+        assert!(!socket.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
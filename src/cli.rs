@@ -0,0 +1,903 @@
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use crate::agent::{KeyPolicy, SshAgent};
+use crate::askpass::{self, AskpassServer};
+use crate::daemon::{self, Daemon, DaemonClient};
+use crate::config;
+use crate::db::{self, VaultLock};
+use crate::fs_util::write_private_file;
+use crate::known_hosts::{self, HostKeyError, HostKeyStatus};
+use crate::models::{Host, HostView, Identity, IdentityData, IdentityView};
+use crate::ssh_config;
+use crate::tags::TagQuery;
+use crate::terminal_launcher::{self, AskpassOptions, SshOptions};
+use crate::vault::{self, Vault};
+use crate::ssh;
+
+/// Connection attempts printed by `show`
+const SHOW_HISTORY_LIMIT: i64 = 10;
+/// Target recorded in the connection history for `nebulavault connect`
+const CLI_CONNECT_TARGET: &str = "ssh";
+
+/// Nebula Vault command line. Without a subcommand the GUI is started.
+#[derive(Debug, Parser)]
+#[command(name = "nebulavault", version, about = "Secure SSH connection manager")]
+pub struct Cli {
+    /// Print JSON instead of text
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Vault to use instead of `$XDG_DATA_HOME/nebulavault/vault.db`: a
+    /// database file, or the name of a vault added in the GUI
+    #[arg(long, global = true, value_name = "PATH|NAME")]
+    pub vault: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// The vault given with `--vault`, or the default one in the data directory
+    pub fn vault_path(&self) -> PathBuf {
+        match &self.vault {
+            Some(vault) => {
+                let settings = config::Settings::load(&config::settings_path()).unwrap_or_default();
+                config::resolve_vault(vault, &settings)
+            }
+            None => config::default_vault_path(),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List hosts
+    List {
+        /// Only hosts matching a tag filter, e.g. "prod AND db NOT legacy"
+        #[arg(long)]
+        tags: Option<String>,
+    },
+    /// Show a host by name, alias or id
+    Show { host: String },
+    /// Add a host
+    AddHost {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        hostname: String,
+        #[arg(long, default_value_t = 22)]
+        port: u16,
+        #[arg(long)]
+        user: String,
+        /// Identity name or id
+        #[arg(long)]
+        identity: Option<String>,
+        #[arg(long)]
+        proxy_jump: Option<String>,
+        /// LocalForward spec, e.g. "8080 localhost:80"; may be repeated
+        #[arg(long = "local-forward")]
+        local_forwards: Vec<String>,
+        /// Tag, e.g. "prod"; may be repeated
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+    /// Remove a host
+    RmHost { host: String },
+    /// List identities
+    Identities,
+    /// Connect to a host with ssh in this terminal
+    Connect { host: String },
+    /// Write an encrypted backup of the vault database
+    Export { path: PathBuf },
+    /// Replace the vault database with an encrypted backup
+    Import { path: PathBuf },
+    /// Keep the vault unlocked in the background and serve it on a local socket
+    Daemon {
+        /// Minutes without requests before the vault locks again; 0 never locks
+        #[arg(long, default_value_t = 15)]
+        lock_timeout: u64,
+    },
+    /// Unlock the running daemon
+    Unlock,
+    /// Lock the running daemon
+    Lock,
+    /// Show whether the daemon is running and unlocked
+    Status,
+    /// Print the password of a connection started by Nebula Vault; ssh runs
+    /// this as `SSH_ASKPASS`
+    Askpass { prompt: Option<String> },
+}
+
+/// Run a subcommand, returning the process exit code
+pub async fn run(cli: Cli) -> Result<ExitCode> {
+    let vault_path = cli.vault_path();
+    let Some(command) = cli.command else {
+        return Ok(ExitCode::SUCCESS);
+    };
+    // Needs nothing but the token in its environment
+    if let Command::Askpass { prompt } = &command {
+        askpass::answer(prompt.as_deref())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let pool = db::init_db(&vault_path).await?;
+
+    match command {
+        Command::List { tags } => {
+            let hosts = list_hosts(&pool, tags.as_deref()).await?;
+
+            if cli.json {
+                print_json(&hosts)?;
+            } else {
+                for host in &hosts {
+                    println!(
+                        "{:<24} {}@{}:{}{}{}",
+                        host.alias,
+                        host.username,
+                        host.hostname,
+                        host.port,
+                        host.identity.as_ref().map(|i| format!("  ({})", i)).unwrap_or_default(),
+                        if host.tags.is_empty() { String::new() } else { format!("  [{}]", host.tags.join(", ")) }
+                    );
+                }
+            }
+        }
+
+        Command::Show { host } => {
+            let view = show_host(&pool, &host).await?;
+
+            if cli.json {
+                print_json(&view)?;
+            } else {
+                println!("Name:      {}", view.name);
+                println!("Alias:     {}", view.alias);
+                println!("Host:      {}@{}:{}", view.username, view.hostname, view.port);
+                println!("Identity:  {}", view.identity.as_deref().unwrap_or("-"));
+                println!("ProxyJump: {}", view.proxy_jump.as_deref().unwrap_or("-"));
+                for forward in &view.local_forwards {
+                    println!("Forward:   {}", forward);
+                }
+                if !view.tags.is_empty() {
+                    println!("Tags:      {}", view.tags.join(", "));
+                }
+                println!("Id:        {}", view.id);
+                for record in view.history.iter().flatten() {
+                    let outcome = match &record.error {
+                        _ if record.success => "connected",
+                        Some(error) => error,
+                        None => "failed",
+                    };
+                    println!("History:   {}  {}  {}", record.local_time(), record.target, outcome);
+                }
+            }
+        }
+
+        Command::AddHost {
+            name,
+            hostname,
+            port,
+            user,
+            identity,
+            proxy_jump,
+            local_forwards,
+            tags,
+        } => {
+            let host = db::HostInput {
+                name,
+                hostname,
+                port: port as i64,
+                username: user,
+                tags,
+                proxy_jump,
+                local_forwards,
+                ..db::HostInput::default()
+            };
+            let host = add_host(&pool, identity.as_deref(), host).await?;
+            update_include(&pool, &vault_path).await?;
+
+            if cli.json {
+                print_json(&HostView::new(&host, &db::get_all_identities(&pool).await?))?;
+            } else {
+                println!("Added {} ({})", host.name, ssh_config::host_alias(&host.name));
+            }
+        }
+
+        Command::RmHost { host } => {
+            let host = db::find_host(&pool, &host).await?;
+            db::delete_host(&pool, &host.id).await?;
+            update_include(&pool, &vault_path).await?;
+
+            if cli.json {
+                print_json(&serde_json::json!({ "removed": host.id }))?;
+            } else {
+                println!("Removed {}", host.name);
+            }
+        }
+
+        Command::Identities => {
+            let identities = list_identities(&pool).await?;
+
+            if cli.json {
+                print_json(&identities)?;
+            } else {
+                for identity in &identities {
+                    println!("{:<24} {} hosts  {}", identity.name, identity.hosts, identity.id);
+                }
+            }
+        }
+
+        Command::Connect { host } => {
+            let host = db::find_host(&pool, &host).await?;
+            return connect(&pool, &vault_path, &host).await;
+        }
+
+        Command::Export { path } => {
+            // Checked against the vault, then used to encrypt the backup itself
+            let password = tokio::task::spawn_blocking(read_master_password).await??;
+            export(&pool, &vault_path, &path, &password).await?;
+            if cli.json {
+                print_json(&serde_json::json!({ "exported": path }))?;
+            } else {
+                println!("Exported vault to {}", path.display());
+            }
+        }
+
+        Command::Daemon { lock_timeout } => {
+            let lock_timeout = (lock_timeout > 0).then(|| std::time::Duration::from_secs(lock_timeout * 60));
+            let socket = daemon::socket_path();
+            eprintln!("Listening on {}", socket.display());
+            // Held while running, so the vault cannot be imported over under it
+            let _lock = VaultLock::shared(&vault_path)?;
+            let serve = Daemon::new(pool, vault_path.clone(), daemon::agent_socket_path(), lock_timeout).serve(socket.clone());
+
+            let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+            tokio::select! {
+                result = serve => result?,
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            let _ = std::fs::remove_file(&socket);
+        }
+
+        Command::Unlock => {
+            let mut client = DaemonClient::connect(&daemon::socket_path()).await?;
+            let password = tokio::task::spawn_blocking(read_master_password).await??;
+            let result = client.call("unlock", json!({ "password": password })).await?;
+            print_status(cli.json, &result)?;
+        }
+
+        Command::Lock => {
+            let mut client = DaemonClient::connect(&daemon::socket_path()).await?;
+            let result = client.call("lock", Value::Null).await?;
+            print_status(cli.json, &result)?;
+        }
+
+        Command::Status => {
+            let result = match DaemonClient::connect(&daemon::socket_path()).await {
+                Ok(mut client) => client.call("status", Value::Null).await?,
+                Err(_) => json!({ "running": false }),
+            };
+            print_status(cli.json, &result)?;
+        }
+
+        Command::Import { path } => {
+            let blob = std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            let lock = VaultLock::exclusive(&vault_path)?;
+            pool.close().await;
+
+            // The backup opens with the master password it was exported with
+            let password = tokio::task::spawn_blocking(|| read_password("Master password of the backup: ")).await??;
+            let database = vault::open_backup(&blob, &password)?;
+            lock.replace_database(&database)?;
+            if cli.json {
+                print_json(&serde_json::json!({ "imported": path }))?;
+            } else {
+                println!("Imported vault from {}", path.display());
+            }
+        }
+
+        Command::Askpass { .. } => unreachable!("askpass is answered before opening the database"),
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Hosts printed by `list`, only those matching the tag filter if one is given
+async fn list_hosts(pool: &SqlitePool, tags: Option<&str>) -> Result<Vec<HostView>> {
+    let query = tags.map(TagQuery::parse).transpose()?;
+    let identities = db::get_all_identities(pool).await?;
+    Ok(db::get_all_hosts(pool)
+        .await?
+        .iter()
+        .filter(|h| query.as_ref().is_none_or(|q| q.matches(&h.tags)))
+        .map(|h| HostView::new(h, &identities))
+        .collect())
+}
+
+/// Host printed by `show`, with its latest connection attempts
+async fn show_host(pool: &SqlitePool, query: &str) -> Result<HostView> {
+    let host = db::find_host(pool, query).await?;
+    Ok(HostView {
+        history: Some(db::get_host_history(pool, &host.id, SHOW_HISTORY_LIMIT).await?),
+        ..HostView::new(&host, &db::get_all_identities(pool).await?)
+    })
+}
+
+/// Identities printed by `identities`
+async fn list_identities(pool: &SqlitePool) -> Result<Vec<IdentityView>> {
+    let hosts = db::get_all_hosts(pool).await?;
+    Ok(db::get_all_identities(pool)
+        .await?
+        .iter()
+        .map(|i| IdentityView::new(i, &hosts))
+        .collect())
+}
+
+/// Save a new host using the identity with the given name or id
+async fn add_host(pool: &SqlitePool, identity: Option<&str>, mut host: db::HostInput) -> Result<Host> {
+    host.identity_id = match identity {
+        Some(identity) => Some(db::find_identity(pool, identity).await?.id),
+        None => None,
+    };
+    db::find_host(pool, &db::save_host(pool, None, &host).await?).await
+}
+
+/// Write an encrypted backup of the vault at `vault_path` to `path`. The
+/// password is checked against the vault, then encrypts the backup itself.
+async fn export(pool: &SqlitePool, vault_path: &Path, path: &Path, password: &str) -> Result<()> {
+    Vault::unlock(pool, password).await?;
+    // Flush everything to the database file before reading it
+    pool.close().await;
+
+    let blob = vault::export_backup(vault_path, password)?;
+    write_private_file(path, &blob)
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn print_status(as_json: bool, status: &Value) -> Result<()> {
+    if as_json {
+        return print_json(status);
+    }
+    match (status.get("running"), status.get("unlocked")) {
+        (Some(Value::Bool(false)), _) => println!("Daemon not running"),
+        (_, Some(Value::Bool(true))) => println!("Unlocked"),
+        _ => println!("Locked"),
+    }
+    Ok(())
+}
+
+/// Connection to a running daemon whose vault is unlocked
+async fn unlocked_daemon() -> Option<DaemonClient> {
+    let mut client = DaemonClient::connect(&daemon::socket_path()).await.ok()?;
+    let status = client.call("status", Value::Null).await.ok()?;
+    (status["unlocked"] == true).then_some(client)
+}
+
+/// Ask on the terminal about signing requests the daemon is holding
+async fn answer_daemon_confirmations(mut client: DaemonClient) {
+    loop {
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        let Ok(Value::Array(requests)) = client.call("agent.confirmations", Value::Null).await else {
+            return;
+        };
+        for request in requests {
+            let question = format!(
+                "Allow key \"{}\" ({}) to sign?",
+                request["comment"].as_str().unwrap_or_default(),
+                request["key_fingerprint"].as_str().unwrap_or_default()
+            );
+            let approved = tokio::task::spawn_blocking(move || prompt_yes_no(&question))
+                .await
+                .unwrap_or(false);
+            let _ = client
+                .call("agent.resolve", json!({ "id": request["id"], "approved": approved }))
+                .await;
+        }
+    }
+}
+
+/// Keep `~/.ssh/nebulavault.conf` in sync after changing hosts, when it is
+/// turned on for this vault
+async fn update_include(pool: &SqlitePool, vault_path: &Path) -> Result<()> {
+    let settings = config::Settings::load(&config::settings_path()).unwrap_or_default();
+    if !ssh_config::include_enabled(&settings, vault_path) {
+        return Ok(());
+    }
+    // While a daemon runs, its agent serves the keys
+    let agent_socket = match DaemonClient::connect(&daemon::socket_path()).await {
+        Ok(_) => daemon::agent_socket_path(),
+        Err(_) => SshAgent::default_socket_path(),
+    };
+    let hosts = db::get_all_hosts(pool).await?;
+    ssh_config::write_include(&ssh_config::include_file_path(), &hosts, &agent_socket)?;
+    Ok(())
+}
+
+/// Ask for the master password on the terminal, or read it from stdin when
+/// stdin is not a terminal
+fn read_master_password() -> Result<String> {
+    read_password("Master password: ")
+}
+
+/// Ask for a password on the terminal with `prompt`, or read it from stdin
+/// when stdin is not a terminal
+fn read_password(prompt: &str) -> Result<String> {
+    if std::io::stdin().is_terminal() {
+        return prompt_hidden(prompt);
+    }
+
+    let mut password = String::new();
+    std::io::stdin().lock().read_line(&mut password).context("Failed to read password from stdin")?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+async fn unlock(pool: &SqlitePool) -> Result<Vault> {
+    let password = tokio::task::spawn_blocking(read_master_password).await??;
+    if password.is_empty() {
+        anyhow::bail!("Password cannot be empty");
+    }
+    Vault::unlock(pool, &password).await
+}
+
+/// Read a line from the controlling terminal without echoing it
+fn prompt_hidden(prompt: &str) -> Result<String> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .context("No terminal to read the password from")?;
+    write!(tty, "{}", prompt)?;
+    tty.flush()?;
+
+    let fd = tty.as_raw_fd();
+    // SAFETY: `termios` is plain data and `fd` is an open terminal
+    let mut term: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut term) } != 0 {
+        anyhow::bail!("Failed to read terminal settings");
+    }
+    let original = term;
+    term.c_lflag &= !libc::ECHO;
+    term.c_lflag |= libc::ECHONL;
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &term) };
+
+    let mut line = String::new();
+    let read = std::io::BufReader::new(&tty).read_line(&mut line);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    read.context("Failed to read password")?;
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Ask a yes/no question on the controlling terminal; anything but "y" or "yes" is no
+fn prompt_yes_no(question: &str) -> bool {
+    let Ok(mut tty) = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty") else {
+        return false;
+    };
+    if write!(tty, "{} [y/N] ", question).and_then(|_| tty.flush()).is_err() {
+        return false;
+    }
+
+    let mut answer = String::new();
+    std::io::BufReader::new(&tty)
+        .read_line(&mut answer)
+        .is_ok_and(|_| matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Credentials served to one ssh process; dropping them stops serving
+enum Served {
+    /// Key in a private agent, with the host's socket
+    Agent(Arc<SshAgent>, PathBuf),
+    /// Password for `nebulavault askpass`, with its single-use token
+    Askpass(AskpassServer, String),
+}
+
+impl Served {
+    /// Stop serving and remove the private socket directory
+    fn close(self) {
+        let dir = match &self {
+            Served::Agent(agent, _) => agent.socket_path().parent().map(PathBuf::from),
+            Served::Askpass(askpass, _) => askpass.socket_path().parent().map(PathBuf::from),
+        };
+        drop(self);
+        if let Some(dir) = dir {
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Serve the identity to ssh: a key from a private agent, or a password through
+/// a private askpass socket
+async fn serve_identity(pool: &SqlitePool, host: &Host, identity: &Identity) -> Result<Served> {
+    let vault = unlock(pool).await?;
+    // A directory of its own, so the sockets do not replace the GUI's
+    let dir = SshAgent::default_socket_path().with_file_name(format!("cli-{}", std::process::id()));
+
+    let (private_key, passphrase) = match vault.decrypt_identity(&identity.encrypted_data)? {
+        IdentityData::SshKey { private_key, passphrase } => (private_key, passphrase),
+        IdentityData::Password { password } => {
+            let askpass = AskpassServer::start(dir.join("askpass.sock")).await?;
+            let token = askpass.issue(&password);
+            return Ok(Served::Askpass(askpass, token));
+        }
+    };
+
+    let socket_path = dir.join("agent.sock");
+    let agent = Arc::new(SshAgent::start(socket_path, pool.clone()).await?);
+    agent.add_identity(
+        &identity.id,
+        &identity.name,
+        &private_key,
+        passphrase.as_deref(),
+        KeyPolicy {
+            confirm: identity.agent_confirm,
+            restrict_to_linked_hosts: identity.agent_restrict_hosts,
+        },
+    )?;
+    let socket = agent.host_socket(&host.id)?;
+
+    // Weak, so dropping the agent removes its sockets
+    let confirming = Arc::downgrade(&agent);
+    let mut requests = Box::pin(agent.confirmation_requests());
+    tokio::spawn(async move {
+        while let Some(request) = requests.next().await {
+            let question = format!("Allow key \"{}\" ({}) to sign?", request.comment, request.key_fingerprint);
+            let approved = tokio::task::spawn_blocking(move || prompt_yes_no(&question))
+                .await
+                .unwrap_or(false);
+            match confirming.upgrade() {
+                Some(agent) => agent.resolve_confirmation(request.id, approved),
+                None => break,
+            }
+        }
+    });
+
+    Ok(Served::Agent(agent, socket))
+}
+
+/// Run ssh to `host` in this terminal and record the attempt in the
+/// connection history
+async fn connect(pool: &SqlitePool, vault_path: &Path, host: &Host) -> Result<ExitCode> {
+    let result = run_ssh(pool, vault_path, host).await;
+
+    // ssh reports its own failures with 255; other codes come from the remote side
+    let error = match &result {
+        Ok(status) if status.code() == Some(255) => Some("ssh exited with status 255".to_string()),
+        Ok(_) => None,
+        Err(e) => Some(format!("{:#}", e)),
+    };
+    let retention_days = config::Settings::load(&config::settings_path())
+        .unwrap_or_default()
+        .history_retention_days;
+    let logged = async {
+        db::log_connection(pool, &host.id, host.identity_id.as_deref(), CLI_CONNECT_TARGET, error.as_deref()).await?;
+        db::purge_connection_history(pool, retention_days).await
+    };
+    if let Err(e) = logged.await {
+        eprintln!("Could not record the connection: {:#}", e);
+    }
+
+    Ok(result?
+        .code()
+        .and_then(|code| u8::try_from(code).ok())
+        .map_or(ExitCode::FAILURE, ExitCode::from))
+}
+
+/// Verify the host key like the GUI does, then run ssh in this terminal.
+///
+/// SSH keys are served by a private agent that lives as long as the ssh process;
+/// confirmations for keys that require them are asked on the terminal. Passwords
+/// are handed to ssh through `nebulavault askpass`.
+async fn run_ssh(pool: &SqlitePool, vault_path: &Path, host: &Host) -> Result<std::process::ExitStatus> {
+    let port = u16::try_from(host.port).context("Invalid port")?;
+
+    // Hosts behind a jump are checked by ssh itself on the way through
+    let probe = if ssh::can_probe(host) {
+        Some(ssh::probe_host_key(pool, &host.hostname, port).await)
+    } else {
+        None
+    };
+    match probe {
+        None | Some(Ok((_, HostKeyStatus::Trusted))) => {}
+        Some(Ok((key, HostKeyStatus::Unknown))) => {
+            eprintln!(
+                "The authenticity of {}:{} can't be established.\n{} key fingerprint is {}.",
+                host.hostname, port, key.key_type, key.fingerprint
+            );
+            let question = "Trust this host key and connect?";
+            if !tokio::task::spawn_blocking(move || prompt_yes_no(question)).await? {
+                anyhow::bail!("Host key not trusted");
+            }
+            known_hosts::trust(pool, &host.hostname, port, &key).await?;
+        }
+        Some(Ok((key, status))) => {
+            return Err(HostKeyError {
+                hostname: host.hostname.clone(),
+                port,
+                key,
+                status,
+            }
+            .into());
+        }
+        Some(Err(e)) => return Err(e.context("Could not check the host key")),
+    }
+
+    let known_hosts_file = known_hosts::launch_file_path(vault_path);
+    known_hosts::export_file(pool, &known_hosts_file).await?;
+
+    let identity = match &host.identity_id {
+        Some(id) => db::get_identity(pool, id).await?,
+        None => None,
+    };
+
+    let local_forwards = host.get_local_forwards();
+    let mut options = SshOptions {
+        agent_socket: None,
+        known_hosts_file: Some(&known_hosts_file),
+        proxy_jump: host.proxy_jump.as_deref(),
+        local_forwards: &local_forwards,
+        askpass: None,
+        title: None,
+    };
+
+    // Kept alive until ssh exits
+    let mut served = None;
+    let mut daemon_agent_socket = None;
+    let mut daemon_confirmations = None;
+    if let Some(identity) = identity {
+        // Reuse the daemon's unlock session when there is one. The daemon never
+        // hands out passwords, so ssh asks for those on the terminal.
+        if let Some(mut client) = unlocked_daemon().await {
+            let loaded = client.call("agent.load_host", json!({ "host": host.id })).await?;
+            daemon_agent_socket = loaded["socket"].as_str().map(PathBuf::from);
+            daemon_confirmations = Some(tokio::spawn(answer_daemon_confirmations(client)));
+        } else {
+            served = Some(serve_identity(pool, host, &identity).await?);
+        }
+    }
+
+    let program = std::env::current_exe().context("Failed to find the nebulavault executable")?;
+    options.agent_socket = daemon_agent_socket.as_deref();
+    match &served {
+        Some(Served::Agent(_, socket)) => options.agent_socket = Some(socket),
+        Some(Served::Askpass(askpass, token)) => {
+            options.askpass = Some(AskpassOptions {
+                program: &program,
+                socket: askpass.socket_path(),
+                token,
+            });
+        }
+        None => {}
+    }
+
+    let status = tokio::process::Command::new("ssh")
+        .args(terminal_launcher::ssh_args(&host.hostname, port, &host.username, &options))
+        .envs(terminal_launcher::ssh_env(&options))
+        .status()
+        .await
+        .context("Failed to run ssh")?;
+
+    if let Some(task) = daemon_confirmations {
+        task.abort();
+    }
+    if let Some(served) = served {
+        served.close();
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("nebulavault").chain(args.iter().copied()))
+    }
+
+    fn host_input(name: &str, hostname: &str, tags: &[&str]) -> db::HostInput {
+        db::HostInput {
+            name: name.to_string(),
+            hostname: hostname.to_string(),
+            port: 22,
+            username: "deploy".to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..db::HostInput::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        let cli = parse(&[
+            "add-host",
+            "--name",
+            "web",
+            "--hostname",
+            "web.example.com",
+            "--user",
+            "deploy",
+            "--tag",
+            "prod",
+            "--tag",
+            "web",
+            "--local-forward",
+            "8080 localhost:80",
+            "--json",
+        ])
+        .unwrap();
+        assert!(cli.json);
+        let Some(Command::AddHost {
+            port,
+            identity,
+            proxy_jump,
+            local_forwards,
+            tags,
+            ..
+        }) = cli.command
+        else {
+            panic!("not add-host: {:?}", cli.command);
+        };
+        assert_eq!(port, 22);
+        assert_eq!((identity, proxy_jump), (None, None));
+        assert_eq!(local_forwards, ["8080 localhost:80"]);
+        assert_eq!(tags, ["prod", "web"]);
+
+        // Global options also go before the subcommand
+        let cli = parse(&["--vault", "work", "list", "--tags", "prod AND db"]).unwrap();
+        assert_eq!(cli.vault, Some(PathBuf::from("work")));
+        assert!(matches!(cli.command, Some(Command::List { tags: Some(tags) }) if tags == "prod AND db"));
+
+        assert!(parse(&[]).unwrap().command.is_none());
+        assert!(matches!(parse(&["daemon"]).unwrap().command, Some(Command::Daemon { lock_timeout: 15 })));
+        assert!(matches!(parse(&["askpass"]).unwrap().command, Some(Command::Askpass { prompt: None })));
+
+        for args in [
+            &["add-host", "--name", "web", "--user", "deploy"][..],
+            &["add-host", "--name", "web", "--hostname", "web", "--user", "deploy", "--port", "70000"],
+            &["show"],
+            &["connect", "web", "db"],
+            &["frobnicate"],
+        ] {
+            assert!(parse(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vault_argument_takes_a_database_path() {
+        let test_db = db::TestDb::new().await;
+        let vault_path = test_db.dir.join("vault.db");
+
+        let cli = parse(&["--vault", vault_path.to_str().unwrap(), "status"]).unwrap();
+        assert_eq!(cli.vault_path(), vault_path);
+    }
+
+    #[tokio::test]
+    async fn test_hosts_are_found_by_id_name_or_alias() {
+        let test_db = db::TestDb::new().await;
+        let pool = &test_db.pool;
+        let identity = db::create_identity(pool, "deploy key".to_string(), vec![]).await.unwrap();
+
+        let host = add_host(pool, Some("Deploy Key"), host_input("Web Server", "web.example.com", &[]))
+            .await
+            .unwrap();
+        assert_eq!(host.identity_id, Some(identity.id.clone()));
+        let other = add_host(pool, Some(&identity.id), host_input("db", "db.example.com", &[]))
+            .await
+            .unwrap();
+        assert_eq!(other.identity_id, Some(identity.id));
+
+        for query in [host.id.as_str(), "Web Server", "web server", "web-server"] {
+            assert_eq!(show_host(pool, query).await.unwrap().id, host.id, "{}", query);
+        }
+        let error = show_host(pool, "mail").await.unwrap_err();
+        assert_eq!(error.to_string(), "No host named \"mail\"");
+
+        // An unknown identity adds nothing
+        assert!(add_host(pool, Some("missing"), host_input("mail", "mail.example.com", &[]))
+            .await
+            .is_err());
+        assert_eq!(list_hosts(pool, None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_json_output() {
+        let test_db = db::TestDb::new().await;
+        let pool = &test_db.pool;
+        let identity = db::create_identity(pool, "deploy key".to_string(), vec![]).await.unwrap();
+
+        let web = add_host(
+            pool,
+            Some("deploy key"),
+            db::HostInput {
+                proxy_jump: Some("bastion".to_string()),
+                local_forwards: vec!["8080 localhost:80".to_string()],
+                ..host_input("Web Server", "web.example.com", &["prod", "web"])
+            },
+        )
+        .await
+        .unwrap();
+        let db_host = add_host(pool, None, host_input("db", "db.example.com", &["prod", "db"]))
+            .await
+            .unwrap();
+
+        let hosts = serde_json::to_value(list_hosts(pool, Some("prod AND web")).await.unwrap()).unwrap();
+        assert_eq!(
+            hosts,
+            json!([{
+                "id": web.id,
+                "name": "Web Server",
+                "alias": "web-server",
+                "hostname": "web.example.com",
+                "port": 22,
+                "username": "deploy",
+                "identity": "deploy key",
+                "proxy_jump": "bastion",
+                "local_forwards": ["8080 localhost:80"],
+                "tags": ["prod", "web"],
+            }])
+        );
+        assert!(list_hosts(pool, Some("prod AND")).await.is_err());
+
+        // Only `show` includes the connection history
+        let shown = serde_json::to_value(show_host(pool, "db").await.unwrap()).unwrap();
+        assert_eq!(shown["id"], json!(db_host.id));
+        assert_eq!(shown["identity"], Value::Null);
+        assert_eq!(shown["history"], json!([]));
+
+        let identities = serde_json::to_value(list_identities(pool).await.unwrap()).unwrap();
+        assert_eq!(
+            identities,
+            json!([{
+                "id": identity.id,
+                "name": "deploy key",
+                "agent_confirm": false,
+                "agent_restrict_hosts": false,
+                "hosts": 1,
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_writes_a_private_backup() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let test_db = db::TestDb::new().await;
+        let vault_path = test_db.dir.join("vault.db");
+        let backup_path = test_db.dir.join("backup.age");
+        Vault::unlock(&test_db.pool, "master password").await.unwrap();
+        add_host(&test_db.pool, None, host_input("web", "web.example.com", &[]))
+            .await
+            .unwrap();
+
+        assert!(export(&test_db.pool, &vault_path, &backup_path, "wrong").await.is_err());
+        assert!(!backup_path.exists());
+
+        export(&test_db.pool, &vault_path, &backup_path, "master password")
+            .await
+            .unwrap();
+        let mode = std::fs::metadata(&backup_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // The backup holds everything written before the export
+        let database = vault::open_backup(&std::fs::read(&backup_path).unwrap(), "master password").unwrap();
+        let restored_path = test_db.dir.join("restored.db");
+        std::fs::write(&restored_path, database).unwrap();
+        let restored = db::init_db(&restored_path).await.unwrap();
+        let hosts = db::get_all_hosts(&restored).await.unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].name, "web");
+    }
+}
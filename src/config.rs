@@ -0,0 +1,433 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::terminal_launcher::{MultiplexerTarget, TerminalApp, TerminalTemplate, DEFAULT_MULTIPLEXER_SESSION};
+
+/// Directory under the XDG base directories
+const APP_DIR: &str = "nebulavault";
+
+/// File name of the settings file in the config directory
+pub const SETTINGS_FILE_NAME: &str = "settings.toml";
+
+/// File name of the default vault in the data directory
+pub const VAULT_FILE_NAME: &str = "vault.db";
+
+/// Name of the vault at [`default_vault_path`] in the vault list
+pub const DEFAULT_VAULT_NAME: &str = "Default";
+
+/// System-wide shortcut opening the quick-connect launcher
+pub const DEFAULT_HOTKEY: &str = "ctrl+alt+space";
+
+/// Vault location before it moved to the data directory, relative to the
+/// working directory
+pub const LEGACY_VAULT_PATH: &str = "nebulavault.db";
+
+/// `$XDG_CONFIG_HOME/nebulavault`, or `~/.config/nebulavault`
+pub fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config").join(APP_DIR)
+}
+
+/// `$XDG_DATA_HOME/nebulavault`, or `~/.local/share/nebulavault`
+pub fn data_dir() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME", ".local/share").join(APP_DIR)
+}
+
+/// A base directory from its variable, or its default under `$HOME`.
+///
+/// Relative values are ignored, as the XDG spec requires.
+fn xdg_dir(var: &str, home_default: &str) -> PathBuf {
+    match std::env::var_os(var).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => {
+            let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+            home.join(home_default)
+        }
+    }
+}
+
+pub fn settings_path() -> PathBuf {
+    config_dir().join(SETTINGS_FILE_NAME)
+}
+
+pub fn default_vault_path() -> PathBuf {
+    data_dir().join(VAULT_FILE_NAME)
+}
+
+/// Copy a vault left at the old location in the working directory to the
+/// default path, while none exists there yet; returns the vault copied.
+///
+/// The old file is left in place.
+pub fn migrate_legacy_vault() -> Result<Option<PathBuf>> {
+    let legacy = PathBuf::from(LEGACY_VAULT_PATH);
+    Ok(copy_vault(&legacy, &default_vault_path())?.then_some(legacy))
+}
+
+/// Copy the vault at `from` to `to` unless `to` exists; returns whether it was copied
+fn copy_vault(from: &Path, to: &Path) -> Result<bool> {
+    if !from.is_file() || to.exists() {
+        return Ok(false);
+    }
+    // A journal holds changes that are not in the database file yet
+    for suffix in ["-journal", "-wal"] {
+        let mut journal = from.as_os_str().to_owned();
+        journal.push(suffix);
+        if Path::new(&journal).exists() {
+            anyhow::bail!(
+                "{} was not closed cleanly; open it once with --vault {} before it can be moved",
+                from.display(),
+                from.display()
+            );
+        }
+    }
+
+    let data = std::fs::read(from).with_context(|| format!("Failed to read {}", from.display()))?;
+    if let Some(dir) = to.parent() {
+        use std::os::unix::fs::DirBuilderExt;
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    crate::fs_util::write_private_file(to, &data)?;
+    Ok(true)
+}
+
+/// A vault file offered on the password screen. Each has its own salt and
+/// master password.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VaultEntry {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl std::fmt::Display for VaultEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Preferences kept between runs in `settings.toml`.
+///
+/// Missing keys take their defaults, so older and hand-written files load.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Idle minutes before the vault locks itself; 0 never locks
+    pub auto_lock_minutes: u64,
+    /// Vault the GUI opens on start; the default vault when unset
+    pub vault: Option<PathBuf>,
+    /// Vaults besides the default one
+    pub vaults: Vec<VaultEntry>,
+    pub terminal: TerminalSettings,
+    /// Days connection history is kept; 0 keeps it forever
+    pub history_retention_days: u64,
+    /// Shortcut opening the quick-connect launcher from anywhere, e.g.
+    /// `ctrl+alt+space`; empty turns it off
+    pub hotkey: String,
+    /// Keep `~/.ssh/nebulavault.conf` in sync with the default vault's hosts
+    pub ssh_include: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            auto_lock_minutes: 15,
+            vault: None,
+            vaults: Vec::new(),
+            terminal: TerminalSettings::default(),
+            history_retention_days: 90,
+            hotkey: DEFAULT_HOTKEY.to_string(),
+            ssh_include: false,
+        }
+    }
+}
+
+/// Terminal connections open in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerminalSettings {
+    /// Terminal by name (`kitty`, `gnome-terminal`, `tmux`, ...); unset for
+    /// the platform default
+    pub app: Option<String>,
+    /// Custom terminal, used when `app` is unset
+    pub custom: Option<TerminalTemplate>,
+    /// Session tmux, zellij and screen connections open in
+    pub session: String,
+    /// Open tmux and zellij connections in a new pane instead of a window or tab
+    pub pane: bool,
+}
+
+impl Default for TerminalSettings {
+    fn default() -> Self {
+        Self {
+            app: None,
+            custom: None,
+            session: DEFAULT_MULTIPLEXER_SESSION.to_string(),
+            pane: false,
+        }
+    }
+}
+
+impl TerminalSettings {
+    /// Settings selecting `terminal`, keeping `session` for multiplexers
+    pub fn new(terminal: &TerminalApp, session: &str) -> Self {
+        let mut settings = Self {
+            app: terminal.config_name().map(String::from),
+            session: session.to_string(),
+            ..Self::default()
+        };
+        match terminal {
+            TerminalApp::Multiplexer(target) => settings.pane = target.pane,
+            TerminalApp::Custom(template) => settings.custom = Some(template.clone()),
+            _ => {}
+        }
+        settings
+    }
+
+    /// The configured terminal, if any is set and known
+    pub fn terminal(&self) -> Option<TerminalApp> {
+        match (&self.app, &self.custom) {
+            (Some(name), _) => TerminalApp::from_config_name(name, |multiplexer| MultiplexerTarget {
+                multiplexer,
+                session: self.session.clone(),
+                pane: self.pane,
+            }),
+            (None, Some(template)) => Some(TerminalApp::Custom(template.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+impl Settings {
+    /// Read the settings file; a missing file gives the defaults
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        toml::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Write the settings file, replacing it atomically
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = toml::to_string(self).context("Failed to serialize settings")?;
+        crate::fs_util::write_private_file(path, contents.as_bytes())
+    }
+
+    /// The default vault, then the configured ones, then `current` if it is
+    /// none of them (a vault opened with `--vault`)
+    pub fn vault_list(&self, current: &Path) -> Vec<VaultEntry> {
+        let mut vaults = vec![VaultEntry {
+            name: DEFAULT_VAULT_NAME.to_string(),
+            path: default_vault_path(),
+        }];
+        vaults.extend(self.vaults.iter().cloned());
+        if !vaults.iter().any(|vault| vault.path == current) {
+            vaults.push(VaultEntry {
+                name: current.file_stem().unwrap_or(current.as_os_str()).to_string_lossy().into_owned(),
+                path: current.to_path_buf(),
+            });
+        }
+        vaults
+    }
+
+    /// Vault the GUI opens when none is given on the command line
+    pub fn startup_vault(&self) -> PathBuf {
+        self.vault.clone().unwrap_or_else(default_vault_path)
+    }
+
+    /// Add a vault to the list. Without a path it is kept in the data
+    /// directory, in a file named after it.
+    pub fn add_vault(&mut self, name: &str, path: Option<&Path>) -> Result<VaultEntry> {
+        let name = name.trim();
+        if name.is_empty() {
+            anyhow::bail!("Vault name cannot be empty");
+        }
+        let path = match path {
+            Some(path) if path.is_absolute() => path.to_path_buf(),
+            Some(path) => std::env::current_dir()
+                .context("Failed to resolve the vault path")?
+                .join(path),
+            None => data_dir().join(format!("{}.db", file_stem_for(name))),
+        };
+
+        let listed = self.vault_list(&default_vault_path());
+        if let Some(existing) = listed.iter().find(|vault| vault.name.eq_ignore_ascii_case(name)) {
+            anyhow::bail!("A vault named '{}' already exists", existing.name);
+        }
+        if let Some(existing) = listed.iter().find(|vault| vault.path == path) {
+            anyhow::bail!("{} is already listed as '{}'", path.display(), existing.name);
+        }
+
+        let vault = VaultEntry {
+            name: name.to_string(),
+            path,
+        };
+        self.vaults.push(vault.clone());
+        Ok(vault)
+    }
+
+    /// Remove a vault from the list; its file is kept
+    pub fn remove_vault(&mut self, path: &Path) {
+        self.vaults.retain(|vault| vault.path != path);
+        if self.vault.as_deref() == Some(path) {
+            self.vault = None;
+        }
+    }
+}
+
+/// The vault for `--vault`: an existing file, else a vault from the list by
+/// name, else a new file at that path
+pub fn resolve_vault(arg: &Path, settings: &Settings) -> PathBuf {
+    if arg.exists() {
+        return arg.to_path_buf();
+    }
+    settings
+        .vaults
+        .iter()
+        .find(|vault| arg.to_str().is_some_and(|name| vault.name.eq_ignore_ascii_case(name)))
+        .map(|vault| vault.path.clone())
+        .unwrap_or_else(|| arg.to_path_buf())
+}
+
+/// A file name from a vault name: lowercase letters and digits, other runs of
+/// characters replaced by `-`
+fn file_stem_for(name: &str) -> String {
+    let mut stem = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            stem.push(c.to_ascii_lowercase());
+        } else if !stem.is_empty() && !stem.ends_with('-') {
+            stem.push('-');
+        }
+    }
+    let stem = stem.trim_end_matches('-');
+    if stem.is_empty() { "vault".to_string() } else { stem.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal_launcher::Multiplexer;
+
+    #[test]
+    fn test_legacy_vault_is_copied_once() {
+        let dir = std::env::temp_dir().join(format!("nebulavault_test_{}", uuid::Uuid::new_v4()));
+        let legacy = dir.join(LEGACY_VAULT_PATH);
+        let target = dir.join("data").join(VAULT_FILE_NAME);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        assert!(!copy_vault(&legacy, &target).unwrap());
+        std::fs::write(&legacy, b"old vault").unwrap();
+
+        // Changes still in a journal would be lost
+        let journal = dir.join(format!("{}-journal", LEGACY_VAULT_PATH));
+        std::fs::write(&journal, b"").unwrap();
+        assert!(copy_vault(&legacy, &target).is_err());
+        std::fs::remove_file(&journal).unwrap();
+
+        assert!(copy_vault(&legacy, &target).unwrap());
+        assert_eq!(std::fs::read(&target).unwrap(), b"old vault");
+        assert!(legacy.exists());
+
+        // A vault at the new location is never overwritten
+        std::fs::write(&legacy, b"older vault").unwrap();
+        assert!(!copy_vault(&legacy, &target).unwrap());
+        assert_eq!(std::fs::read(&target).unwrap(), b"old vault");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("nebulavault_test_{}", uuid::Uuid::new_v4()))
+            .join(SETTINGS_FILE_NAME);
+
+        assert_eq!(Settings::load(&path).unwrap(), Settings::default());
+
+        let template = TerminalTemplate::parse("WezTerm", "wezterm start -- {ssh_argv}").unwrap();
+        for terminal in [
+            TerminalApp::Kitty,
+            TerminalApp::GnomeTerminal,
+            TerminalApp::ITerm2,
+            TerminalApp::Multiplexer(MultiplexerTarget {
+                multiplexer: Multiplexer::Tmux,
+                session: "ops".to_string(),
+                pane: true,
+            }),
+            TerminalApp::Custom(template),
+        ] {
+            let settings = Settings {
+                auto_lock_minutes: 0,
+                hotkey: String::new(),
+                ssh_include: true,
+                terminal: TerminalSettings::new(&terminal, "ops"),
+                ..Settings::default()
+            };
+            settings.save(&path).unwrap();
+            let loaded = Settings::load(&path).unwrap();
+            assert_eq!(loaded, settings);
+            assert_eq!(loaded.terminal.terminal(), Some(terminal));
+        }
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_settings_defaults_for_missing_keys() {
+        let settings: Settings = toml::from_str("[terminal]\napp = \"zellij\"\n").unwrap();
+        assert_eq!(settings.auto_lock_minutes, 15);
+        assert_eq!(settings.history_retention_days, 90);
+        assert_eq!(settings.hotkey, DEFAULT_HOTKEY);
+        assert!(!settings.ssh_include);
+        assert_eq!(
+            settings.terminal.terminal(),
+            Some(TerminalApp::Multiplexer(MultiplexerTarget {
+                multiplexer: Multiplexer::Zellij,
+                session: DEFAULT_MULTIPLEXER_SESSION.to_string(),
+                pane: false,
+            }))
+        );
+
+        let unknown: Settings = toml::from_str("[terminal]\napp = \"no-such-terminal\"\n").unwrap();
+        assert_eq!(unknown.terminal.terminal(), None);
+        assert!(toml::from_str::<Settings>("auto_lock_minutes = \"soon\"\n").is_err());
+    }
+
+    #[test]
+    fn test_vault_list() {
+        let mut settings = Settings::default();
+        let work = settings.add_vault("Client: ACME / Prod", None).unwrap();
+        assert_eq!(work.path, data_dir().join("client-acme-prod.db"));
+        let home = settings.add_vault("Home", Some(Path::new("/srv/vaults/home.db"))).unwrap();
+
+        assert!(settings.add_vault("  ", None).is_err());
+        assert!(settings.add_vault("default", None).is_err());
+        assert!(settings.add_vault("HOME", Some(Path::new("/srv/other.db"))).is_err());
+        assert!(settings.add_vault("Home 2", Some(Path::new("/srv/vaults/home.db"))).is_err());
+        assert!(settings.add_vault("Again", Some(&default_vault_path())).is_err());
+
+        let names = |vaults: Vec<VaultEntry>| vaults.into_iter().map(|vault| vault.name).collect::<Vec<_>>();
+        assert_eq!(names(settings.vault_list(&home.path)), [DEFAULT_VAULT_NAME, "Client: ACME / Prod", "Home"]);
+        assert_eq!(
+            names(settings.vault_list(Path::new("/tmp/scratch.db"))),
+            [DEFAULT_VAULT_NAME, "Client: ACME / Prod", "Home", "scratch"]
+        );
+
+        assert_eq!(resolve_vault(Path::new("home"), &settings), home.path);
+        assert_eq!(resolve_vault(Path::new("missing.db"), &settings), PathBuf::from("missing.db"));
+
+        settings.vault = Some(home.path.clone());
+        settings.remove_vault(&home.path);
+        assert_eq!(settings.vaults, [work]);
+        assert_eq!(settings.startup_vault(), default_vault_path());
+
+        // The list survives the settings file
+        let contents = toml::to_string(&settings).unwrap();
+        assert_eq!(toml::from_str::<Settings>(&contents).unwrap(), settings);
+    }
+}
@@ -0,0 +1,717 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Mutex;
+
+use crate::agent::{ConfirmRequest, SshAgent};
+use crate::db;
+use crate::models::{HostView, IdentityView};
+use crate::ssh_config;
+use crate::vault::Vault;
+
+/// Version of the JSON-RPC API, bumped on incompatible changes
+pub const API_VERSION: u32 = 1;
+
+/// How often the idle timer is checked
+const LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// JSON-RPC 2.0 error codes, plus our own
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const VAULT_LOCKED: i64 = 1;
+pub const REQUEST_FAILED: i64 = 2;
+
+/// Socket the daemon listens on, next to the agent socket
+pub fn socket_path() -> PathBuf {
+    SshAgent::default_socket_path().with_file_name("daemon.sock")
+}
+
+/// Socket of the daemon's SSH agent. It has a directory of its own, so its host
+/// sockets never collide with those of an agent started by the GUI.
+pub fn agent_socket_path() -> PathBuf {
+    socket_path().with_file_name("daemon").join("agent.sock")
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Error member of a JSON-RPC response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn failed(error: anyhow::Error) -> Self {
+        Self::new(REQUEST_FAILED, format!("{:#}", error))
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+fn param<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Missing string parameter \"{}\"", name)))
+}
+
+/// A parameter of any JSON type; a missing one is read as `null`
+fn typed_param<T: DeserializeOwned>(params: &Value, name: &str) -> Result<T, RpcError> {
+    serde_json::from_value(params.get(name).cloned().unwrap_or(Value::Null))
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid parameter \"{}\": {}", name, e)))
+}
+
+/// The unlocked vault and everything derived from it
+#[derive(Default)]
+struct Session {
+    vault: Option<Arc<Vault>>,
+    agent: Option<Arc<SshAgent>>,
+    /// Signing requests waiting for a client to answer them
+    confirmations: Vec<ConfirmRequest>,
+}
+
+/// Holds one unlock session for the GUI, the CLI and scripts
+pub struct Daemon {
+    pool: SqlitePool,
+    /// Database of the vault, reported by `status`
+    vault_path: PathBuf,
+    agent_socket: PathBuf,
+    lock_timeout: Option<Duration>,
+    session: Mutex<Session>,
+    last_activity: std::sync::Mutex<Instant>,
+}
+
+impl Daemon {
+    /// `lock_timeout` is the idle time after which the vault is locked again
+    pub fn new(pool: SqlitePool, vault_path: PathBuf, agent_socket: PathBuf, lock_timeout: Option<Duration>) -> Arc<Self> {
+        Arc::new(Self {
+            pool,
+            vault_path,
+            agent_socket,
+            lock_timeout,
+            session: Mutex::new(Session::default()),
+            last_activity: std::sync::Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Listen on `path` until the process exits.
+    ///
+    /// The socket is only accessible to the current user, and connections from
+    /// other users are refused as well.
+    pub async fn serve(self: Arc<Self>, path: PathBuf) -> Result<()> {
+        let path = path.as_path();
+        if UnixStream::connect(path).await.is_ok() {
+            anyhow::bail!("A daemon is already listening on {}", path.display());
+        }
+        if let Some(dir) = path.parent() {
+            use std::os::unix::fs::DirBuilderExt;
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .context("Failed to create daemon socket directory")?;
+        }
+        if path.exists() {
+            std::fs::remove_file(path).context("Failed to remove stale daemon socket")?;
+        }
+
+        let listener = UnixListener::bind(path).context("Failed to bind daemon socket")?;
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                .context("Failed to restrict daemon socket")?;
+        }
+
+        if self.lock_timeout.is_some() {
+            let daemon = Arc::downgrade(&self);
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(LOCK_CHECK_INTERVAL);
+                loop {
+                    ticks.tick().await;
+                    let Some(daemon) = daemon.upgrade() else { break };
+                    if daemon.is_idle() {
+                        daemon.lock().await;
+                    }
+                }
+            });
+        }
+
+        // SAFETY: getuid cannot fail
+        let uid = unsafe { libc::getuid() };
+        loop {
+            let (stream, _) = listener.accept().await.context("Failed to accept connection")?;
+            if stream.peer_cred().map(|cred| cred.uid()).ok() != Some(uid) {
+                continue;
+            }
+
+            let daemon = self.clone();
+            tokio::spawn(async move {
+                let _ = daemon.serve_connection(stream).await;
+            });
+        }
+    }
+
+    /// Answer newline-delimited JSON-RPC requests until the client disconnects
+    async fn serve_connection(self: Arc<Self>, stream: UnixStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut response = self.handle(&line).await;
+            response.push('\n');
+            writer.write_all(response.as_bytes()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle one request line and return the response line
+    pub async fn handle(self: &Arc<Self>, line: &str) -> String {
+        let (id, outcome) = match serde_json::from_str::<Request>(line) {
+            Ok(request) => {
+                self.touch();
+                (request.id, self.dispatch(&request.method, &request.params).await)
+            }
+            Err(e) => (Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string()))),
+        };
+
+        let response = match outcome {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+        };
+        response.to_string()
+    }
+
+    async fn dispatch(self: &Arc<Self>, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "version" => Ok(json!({
+                "api_version": API_VERSION,
+                "version": env!("CARGO_PKG_VERSION"),
+            })),
+
+            "status" => Ok(json!({
+                "unlocked": self.session.lock().await.vault.is_some(),
+                "vault": self.vault_path,
+                "lock_timeout_secs": self.lock_timeout.map(|t| t.as_secs()),
+            })),
+
+            "unlock" => {
+                let password = param(params, "password")?;
+                self.unlock(password).await.map_err(RpcError::failed)?;
+                Ok(json!({ "unlocked": true }))
+            }
+
+            "lock" => {
+                self.lock().await;
+                Ok(json!({ "unlocked": false }))
+            }
+
+            "change_password" => {
+                let old_password = param(params, "old_password")?;
+                let new_password = param(params, "new_password")?;
+                let unlocked = self
+                    .change_password(old_password, new_password)
+                    .await
+                    .map_err(RpcError::failed)?;
+                Ok(json!({ "unlocked": unlocked }))
+            }
+
+            "hosts.list" => {
+                let identities = db::get_all_identities(&self.pool).await.map_err(RpcError::failed)?;
+                let hosts = db::get_all_hosts(&self.pool).await.map_err(RpcError::failed)?;
+                let hosts: Vec<HostView> = hosts.iter().map(|h| HostView::new(h, &identities)).collect();
+                Ok(json!(hosts))
+            }
+
+            "hosts.get" => {
+                let host = db::find_host(&self.pool, param(params, "host")?)
+                    .await
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+                let identities = db::get_all_identities(&self.pool).await.map_err(RpcError::failed)?;
+                Ok(json!(HostView::new(&host, &identities)))
+            }
+
+            "identities.list" => {
+                let identities = db::get_all_identities(&self.pool).await.map_err(RpcError::failed)?;
+                let hosts = db::get_all_hosts(&self.pool).await.map_err(RpcError::failed)?;
+                let identities: Vec<IdentityView> = identities.iter().map(|i| IdentityView::new(i, &hosts)).collect();
+                Ok(json!(identities))
+            }
+
+            // Full records and edits, for the GUI sharing the daemon's session
+            "hosts.records" => {
+                let hosts = db::get_all_hosts(&self.pool).await.map_err(RpcError::failed)?;
+                Ok(json!(hosts))
+            }
+
+            "hosts.save" => {
+                let id: Option<String> = typed_param(params, "id")?;
+                let host: db::HostInput = typed_param(params, "host")?;
+                let id = db::save_host(&self.pool, id.as_deref(), &host)
+                    .await
+                    .map_err(RpcError::failed)?;
+                Ok(json!({ "id": id }))
+            }
+
+            "hosts.move" => {
+                let ids: Vec<String> = typed_param(params, "ids")?;
+                let group_id: Option<String> = typed_param(params, "group_id")?;
+                db::set_hosts_group(&self.pool, &ids, group_id)
+                    .await
+                    .map_err(RpcError::failed)?;
+                Ok(json!({ "moved": ids }))
+            }
+
+            "hosts.delete" => {
+                let id = param(params, "id")?;
+                db::delete_host(&self.pool, id).await.map_err(RpcError::failed)?;
+                Ok(json!({ "deleted": id }))
+            }
+
+            "identities.records" => {
+                let identities = db::get_all_identities(&self.pool).await.map_err(RpcError::failed)?;
+                Ok(json!(identities))
+            }
+
+            "identities.save" => {
+                let id: Option<String> = typed_param(params, "id")?;
+                let identity: db::IdentityInput = typed_param(params, "identity")?;
+                // Only data the session's key opens, so the agent can serve it
+                let (vault, _) = self.unlocked().await?;
+                vault.decrypt_identity(&identity.encrypted_data).map_err(|e| {
+                    RpcError::new(INVALID_PARAMS, format!("Identity is not encrypted with the vault key: {}", e))
+                })?;
+                let id = db::save_identity(&self.pool, id.as_deref(), &identity)
+                    .await
+                    .map_err(RpcError::failed)?;
+                Ok(json!({ "id": id }))
+            }
+
+            "identities.delete" => {
+                let id = param(params, "id")?;
+                db::delete_identity(&self.pool, id).await.map_err(RpcError::failed)?;
+                Ok(json!({ "deleted": id }))
+            }
+
+            "agent.load_host" => {
+                let host = db::find_host(&self.pool, param(params, "host")?)
+                    .await
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+                let (vault, agent) = self.unlocked().await?;
+
+                let identities = db::get_all_identities(&self.pool).await.map_err(RpcError::failed)?;
+                let loaded_host = host.clone();
+                let serving = agent.clone();
+                let served = tokio::task::spawn_blocking(move || {
+                    ssh_config::serve_host_keys(&serving, &vault, &[loaded_host], &identities)
+                })
+                .await
+                .map_err(|e| RpcError::new(REQUEST_FAILED, e.to_string()))?
+                .map_err(RpcError::failed)?;
+                if let Some(failure) = served.failed.first() {
+                    return Err(RpcError::new(REQUEST_FAILED, format!("Cannot serve the key of {}", failure)));
+                }
+
+                // Only hosts with a key get a socket; password identities have nothing to serve
+                let socket = match &host.identity_id {
+                    Some(identity_id) if agent.has_identity(identity_id) => {
+                        Some(agent.host_socket(&host.id).map_err(RpcError::failed)?)
+                    }
+                    _ => None,
+                };
+                Ok(json!({ "socket": socket }))
+            }
+
+            "agent.serve" => {
+                let (vault, agent) = self.unlocked().await?;
+                let served = self.serve_keys(vault, agent).await.map_err(RpcError::failed)?;
+                Ok(json!(served))
+            }
+
+            "agent.confirmations" => {
+                let mut session = self.session.lock().await;
+                let Some(agent) = session.agent.clone() else {
+                    return Ok(json!([]));
+                };
+                session.confirmations.retain(|r| agent.is_confirmation_pending(r.id));
+                Ok(json!(session.confirmations))
+            }
+
+            "agent.resolve" => {
+                let request_id = params
+                    .get("id")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing integer parameter \"id\""))?;
+                let approved = params.get("approved").and_then(Value::as_bool).unwrap_or(false);
+
+                let mut session = self.session.lock().await;
+                if let Some(agent) = &session.agent {
+                    agent.resolve_confirmation(request_id, approved);
+                }
+                session.confirmations.retain(|r| r.id != request_id);
+                Ok(json!({ "resolved": request_id }))
+            }
+
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method \"{}\"", method))),
+        }
+    }
+
+    async fn unlocked(&self) -> Result<(Arc<Vault>, Arc<SshAgent>), RpcError> {
+        let session = self.session.lock().await;
+        match (&session.vault, &session.agent) {
+            (Some(vault), Some(agent)) => Ok((vault.clone(), agent.clone())),
+            _ => Err(RpcError::new(VAULT_LOCKED, "Vault is locked")),
+        }
+    }
+
+    /// Unlock the vault and start a session for it
+    async fn unlock(self: &Arc<Self>, password: &str) -> Result<()> {
+        // Held throughout, so clients unlocking at the same time start one agent
+        let mut session = self.session.lock().await;
+        if session.vault.is_some() {
+            return Ok(());
+        }
+
+        let vault = Arc::new(Vault::unlock(&self.pool, password).await?);
+        *session = self.start_session(vault).await?;
+        Ok(())
+    }
+
+    /// Change the master password. An unlocked session starts over with the
+    /// new key, so keys loaded before the change are no longer served.
+    /// Returns whether the vault is unlocked.
+    async fn change_password(self: &Arc<Self>, old_password: &str, new_password: &str) -> Result<bool> {
+        let mut session = self.session.lock().await;
+        let vault = Arc::new(Vault::change_password(&self.pool, old_password, new_password).await?);
+        if session.vault.is_none() {
+            return Ok(false);
+        }
+
+        // The old agent removes its sockets when dropped, so it goes before the new one binds
+        *session = Session::default();
+        *session = self.start_session(vault).await?;
+        Ok(true)
+    }
+
+    /// Start the agent for an unlocked vault and serve the keys of every host
+    async fn start_session(self: &Arc<Self>, vault: Arc<Vault>) -> Result<Session> {
+        let agent = Arc::new(SshAgent::start(self.agent_socket.clone(), self.pool.clone()).await?);
+
+        let served = self.serve_keys(vault.clone(), agent.clone()).await?;
+        // One broken key must not keep the others from being served
+        for failure in &served.failed {
+            eprintln!("Not serving the key of {}", failure);
+        }
+
+        // Collect signing requests for clients to answer through agent.resolve
+        let daemon: Weak<Self> = Arc::downgrade(self);
+        let requests = agent.confirmation_requests();
+        tokio::spawn(async move {
+            use futures::StreamExt;
+            let mut requests = Box::pin(requests);
+            while let Some(request) = requests.next().await {
+                let Some(daemon) = daemon.upgrade() else { break };
+                daemon.session.lock().await.confirmations.push(request);
+            }
+        });
+
+        Ok(Session {
+            vault: Some(vault),
+            agent: Some(agent),
+            confirmations: Vec::new(),
+        })
+    }
+
+    /// Serve the keys of every host on the agent's host sockets
+    async fn serve_keys(&self, vault: Arc<Vault>, agent: Arc<SshAgent>) -> Result<ssh_config::ServedKeys> {
+        let hosts = db::get_all_hosts(&self.pool).await?;
+        let identities = db::get_all_identities(&self.pool).await?;
+        tokio::task::spawn_blocking(move || ssh_config::serve_host_keys(&agent, &vault, &hosts, &identities)).await?
+    }
+
+    /// Forget the vault key and every loaded SSH key
+    pub async fn lock(&self) {
+        // Dropping the agent removes its sockets
+        *self.session.lock().await = Session::default();
+    }
+
+    fn touch(&self) {
+        if let Ok(mut last_activity) = self.last_activity.lock() {
+            *last_activity = Instant::now();
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        match (self.lock_timeout, self.last_activity.lock()) {
+            (Some(timeout), Ok(last_activity)) => last_activity.elapsed() >= timeout,
+            _ => false,
+        }
+    }
+}
+
+/// Connection to a running daemon
+#[derive(Debug)]
+pub struct DaemonClient {
+    lines: tokio::io::Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+}
+
+impl DaemonClient {
+    /// Connect to the daemon at `path`, failing if none is running
+    pub async fn connect(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path)
+            .await
+            .with_context(|| format!("No daemon listening on {}", path.display()))?;
+        let (reader, writer) = stream.into_split();
+
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 1,
+        })
+    }
+
+    /// Call a method; errors returned by the daemon are [`RpcError`]s
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }).to_string();
+        request.push('\n');
+        self.writer.write_all(request.as_bytes()).await?;
+
+        let line = self
+            .lines
+            .next_line()
+            .await?
+            .context("Daemon closed the connection")?;
+        let mut response: Value = serde_json::from_str(&line).context("Invalid response from daemon")?;
+
+        if let Some(error) = response.get_mut("error") {
+            let error: RpcError = serde_json::from_value(error.take()).context("Invalid error from daemon")?;
+            return Err(error.into());
+        }
+        Ok(response.get_mut("result").map(Value::take).unwrap_or(Value::Null))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_json_rpc_session() {
+        let test_db = db::TestDb::new().await;
+        let (dir, pool) = (&test_db.dir, test_db.pool.clone());
+        db::create_host(
+            &pool, None, None, "Prod DB".to_string(), "db.example.com".to_string(), 22, "deploy".to_string(), &[],
+        )
+        .await
+        .unwrap();
+
+        let socket = dir.join("daemon.sock");
+        let daemon = Daemon::new(pool.clone(), dir.join("vault.db"), dir.join("agent").join("agent.sock"), None);
+        let server = tokio::spawn(daemon.clone().serve(socket.clone()));
+        while !socket.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let mut client = DaemonClient::connect(&socket).await.unwrap();
+        let version = client.call("version", Value::Null).await.unwrap();
+        assert_eq!(version["api_version"], API_VERSION);
+
+        let hosts = client.call("hosts.list", Value::Null).await.unwrap();
+        assert_eq!(hosts[0]["alias"], "prod-db");
+
+        // Secrets need an unlocked vault
+        let error = client.call("agent.load_host", json!({ "host": "prod-db" })).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<RpcError>().unwrap().code, VAULT_LOCKED);
+
+        client.call("unlock", json!({ "password": "correct horse" })).await.unwrap();
+        let status = client.call("status", Value::Null).await.unwrap();
+        assert_eq!(status["unlocked"], true);
+        assert_eq!(status["vault"], json!(dir.join("vault.db")));
+        let served: ssh_config::ServedKeys =
+            serde_json::from_value(client.call("agent.serve", Value::Null).await.unwrap()).unwrap();
+        assert_eq!(served, ssh_config::ServedKeys::default());
+        let loaded = client.call("agent.load_host", json!({ "host": "prod-db" })).await.unwrap();
+        assert!(loaded["socket"].is_null());
+
+        // A second client shares the session
+        let mut other = DaemonClient::connect(&socket).await.unwrap();
+        other.call("lock", Value::Null).await.unwrap();
+        assert_eq!(client.call("status", Value::Null).await.unwrap()["unlocked"], false);
+
+        let error = client.call("unlock", json!({ "password": "wrong" })).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<RpcError>().unwrap().code, REQUEST_FAILED);
+        let error = client.call("hosts.rename", Value::Null).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<RpcError>().unwrap().code, METHOD_NOT_FOUND);
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_host_key_loads_after_password_change() {
+        use crate::models::IdentityData;
+        use russh_keys::agent::client::AgentClient;
+        use russh_keys::key::KeyPair;
+
+        let test_db = db::TestDb::new().await;
+        let (dir, pool) = (&test_db.dir, test_db.pool.clone());
+
+        let vault = Vault::unlock(&pool, "old password").await.unwrap();
+        let mut pem = Vec::new();
+        russh_keys::encode_pkcs8_pem(&KeyPair::generate_ed25519().unwrap(), &mut pem).unwrap();
+        let private_key = String::from_utf8(pem).unwrap();
+        let data = vault.encrypt_identity(&IdentityData::SshKey { private_key, passphrase: None }).unwrap();
+        let identity = db::create_identity(&pool, "deploy".to_string(), data).await.unwrap();
+        db::create_host(
+            &pool, None, Some(identity.id), "web".to_string(), "web.example.com".to_string(), 22, "deploy".to_string(), &[],
+        )
+        .await
+        .unwrap();
+
+        // Host sockets are named after the host id; nested deeper they would not fit in a socket address
+        let daemon = Daemon::new(pool.clone(), dir.join("vault.db"), dir.join("agent.sock"), None);
+        let call = |method: &'static str, params: Value| {
+            let daemon = daemon.clone();
+            async move {
+                let response: Value = serde_json::from_str(
+                    &daemon.handle(&json!({ "id": 1, "method": method, "params": params }).to_string()).await,
+                )
+                .unwrap();
+                response
+            }
+        };
+
+        let response = call("unlock", json!({ "password": "old password" })).await;
+        assert_eq!(response["result"]["unlocked"], true, "{}", response);
+        let response = call(
+            "change_password",
+            json!({ "old_password": "wrong", "new_password": "new password" }),
+        )
+        .await;
+        assert_eq!(response["error"]["code"], REQUEST_FAILED);
+        let response = call(
+            "change_password",
+            json!({ "old_password": "old password", "new_password": "new password" }),
+        )
+        .await;
+        assert_eq!(response["result"]["unlocked"], true);
+
+        // The session was started over with the new key
+        let loaded = call("agent.load_host", json!({ "host": "web" })).await;
+        let socket = loaded["result"]["socket"].as_str().expect("no host socket").to_string();
+        let mut client = AgentClient::connect_uds(&socket).await.unwrap();
+        assert_eq!(client.request_identities().await.unwrap().len(), 1);
+
+        call("lock", Value::Null).await;
+        let response = call("unlock", json!({ "password": "old password" })).await;
+        assert_eq!(response["error"]["code"], REQUEST_FAILED);
+        let response = call("unlock", json!({ "password": "new password" })).await;
+        assert_eq!(response["result"]["unlocked"], true);
+    }
+
+    #[tokio::test]
+    async fn test_records_edited_through_the_daemon() {
+        use crate::models::{Host, Identity, IdentityData};
+
+        let test_db = db::TestDb::new().await;
+        let (dir, pool) = (&test_db.dir, test_db.pool.clone());
+        let group = db::create_group(&pool, None, "Production".to_string()).await.unwrap();
+        let vault = Vault::unlock(&pool, "correct horse").await.unwrap();
+
+        let daemon = Daemon::new(pool.clone(), dir.join("vault.db"), dir.join("agent.sock"), None);
+        let call = |method: &'static str, params: Value| {
+            let daemon = daemon.clone();
+            async move {
+                let response: Value = serde_json::from_str(
+                    &daemon.handle(&json!({ "id": 1, "method": method, "params": params }).to_string()).await,
+                )
+                .unwrap();
+                response
+            }
+        };
+
+        let host = db::HostInput {
+            name: "web".to_string(),
+            hostname: "web.example.com".to_string(),
+            port: 22,
+            username: "deploy".to_string(),
+            tags: vec!["prod".to_string()],
+            ..Default::default()
+        };
+        let saved = call("hosts.save", json!({ "host": host })).await;
+        let host_id = saved["result"]["id"].as_str().expect("no host id").to_string();
+        let response = call("hosts.move", json!({ "ids": [&host_id], "group_id": &group.id })).await;
+        assert!(response["error"].is_null(), "{}", response);
+
+        let hosts: Vec<Host> = serde_json::from_value(call("hosts.records", Value::Null).await["result"].take()).unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].group_id.as_deref(), Some(group.id.as_str()));
+        assert_eq!(hosts[0].tags, vec!["prod"]);
+
+        // Identities are only taken once they open with the session's key
+        let identity = db::IdentityInput {
+            name: "deploy".to_string(),
+            encrypted_data: vault
+                .encrypt_identity(&IdentityData::Password { password: "secret".to_string() })
+                .unwrap(),
+            agent_confirm: true,
+            agent_restrict_hosts: false,
+        };
+        let response = call("identities.save", json!({ "identity": identity })).await;
+        assert_eq!(response["error"]["code"], VAULT_LOCKED);
+        call("unlock", json!({ "password": "correct horse" })).await;
+        let garbled = db::IdentityInput { encrypted_data: b"not encrypted".to_vec(), ..identity.clone() };
+        let response = call("identities.save", json!({ "identity": garbled })).await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+        let saved = call("identities.save", json!({ "identity": identity })).await;
+        let identity_id = saved["result"]["id"].as_str().expect("no identity id").to_string();
+
+        let identities: Vec<Identity> =
+            serde_json::from_value(call("identities.records", Value::Null).await["result"].take()).unwrap();
+        assert_eq!(identities.len(), 1);
+        assert!(identities[0].agent_confirm);
+        assert_eq!(identities[0].encrypted_data, identity.encrypted_data);
+
+        call("hosts.delete", json!({ "id": host_id })).await;
+        call("identities.delete", json!({ "id": identity_id })).await;
+        assert!(db::get_all_hosts(&pool).await.unwrap().is_empty());
+        assert!(db::get_all_identities(&pool).await.unwrap().is_empty());
+    }
+}
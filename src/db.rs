@@ -1,12 +1,28 @@
 use anyhow::{Context, Result};
-use sqlx::{sqlite::SqlitePool, Row};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::{SqliteConnectOptions, SqlitePool}, Row};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
-use crate::models::{Group, Host, Identity};
+use crate::models::{AgentSignRecord, ConnectionRecord, Group, Host, HostUsage, Identity, KnownHost, KnownHostEntry, VaultHeader};
+use crate::tags;
+use crate::terminal_launcher::TerminalTemplate;
 
-/// Initialize the SQLite database and run migrations
-pub async fn init_db(db_path: &str) -> Result<SqlitePool> {
-    let pool = SqlitePool::connect(&format!("sqlite:{}?mode=rwc", db_path))
+/// Open the SQLite database, creating it and its directory if needed, and run migrations
+pub async fn init_db(db_path: impl AsRef<Path>) -> Result<SqlitePool> {
+    let db_path = db_path.as_ref();
+    if let Some(dir) = db_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        use std::os::unix::fs::DirBuilderExt;
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let options = SqliteConnectOptions::new().filename(db_path).create_if_missing(true);
+    let pool = SqlitePool::connect_with(options)
         .await
         .context("Failed to connect to database")?;
 
@@ -15,20 +31,274 @@ pub async fn init_db(db_path: &str) -> Result<SqlitePool> {
         .run(&pool)
         .await
         .context("Failed to run migrations")?;
+    repair_host_tags(&pool).await?;
 
     Ok(pool)
 }
 
+/// Rewrite tags that [`tags::normalize_tag`] rejects, carried over from the
+/// JSON column before tags were checked, and drop those that cannot be repaired
+async fn repair_host_tags(pool: &SqlitePool) -> Result<()> {
+    let stored: Vec<String> = sqlx::query_scalar("SELECT DISTINCT tag FROM host_tags")
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch tags")?;
+    let invalid: Vec<String> = stored
+        .into_iter()
+        .filter(|tag| tags::normalize_tag(tag).ok().as_deref() != Some(tag.as_str()))
+        .collect();
+    if invalid.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await.context("Failed to start transaction")?;
+    for tag in &invalid {
+        if let Some(repaired) = tags::repair_tag(tag) {
+            sqlx::query("INSERT OR IGNORE INTO host_tags (host_id, tag) SELECT host_id, ? FROM host_tags WHERE tag = ?")
+                .bind(&repaired)
+                .bind(tag)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to repair tag")?;
+        }
+        sqlx::query("DELETE FROM host_tags WHERE tag = ?")
+            .bind(tag)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to repair tag")?;
+    }
+    tx.commit().await.context("Failed to commit transaction")?;
+
+    Ok(())
+}
+
+/// Advisory lock on a vault, held by every process that keeps the vault open: the
+/// GUI while it is unlocked and the daemon while it runs. Import takes it
+/// exclusively, so a vault is never replaced under one of them.
+///
+/// The lock is taken on `<vault>.lock` next to the database: SQLite's own locks
+/// would be released if another descriptor of the database file were closed.
+pub struct VaultLock {
+    _file: std::fs::File,
+    db_path: PathBuf,
+    exclusive: bool,
+}
+
+impl std::fmt::Debug for VaultLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VaultLock")
+            .field("db_path", &self.db_path)
+            .field("exclusive", &self.exclusive)
+            .finish()
+    }
+}
+
+impl VaultLock {
+    /// Share the vault with other processes that have it open
+    pub fn shared(db_path: &Path) -> Result<Self> {
+        Self::acquire(db_path, false)
+    }
+
+    /// Take the vault for this process alone; fails while any other holds it
+    pub fn exclusive(db_path: &Path) -> Result<Self> {
+        Self::acquire(db_path, true)
+    }
+
+    fn acquire(db_path: &Path, exclusive: bool) -> Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::AsRawFd;
+
+        let mut lock_path = db_path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open {}", Path::new(&lock_path).display()))?;
+
+        let operation = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+        // SAFETY: the descriptor stays open for as long as `file` lives
+        if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } != 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::WouldBlock {
+                anyhow::bail!(
+                    "{} is in use by another Nebula Vault process; close the GUI and stop the daemon first",
+                    db_path.display()
+                );
+            }
+            return Err(error).with_context(|| format!("Failed to lock {}", db_path.display()));
+        }
+
+        Ok(Self {
+            _file: file,
+            db_path: db_path.to_path_buf(),
+            exclusive,
+        })
+    }
+
+    /// Replace the database with `data`, a complete SQLite database file. The new
+    /// file is written next to the old one and renamed into place, so the vault is
+    /// either the old or the new database, never a mix.
+    pub fn replace_database(&self, data: &[u8]) -> Result<()> {
+        if !self.exclusive {
+            anyhow::bail!("Replacing a vault needs the exclusive lock");
+        }
+        if !data.starts_with(b"SQLite format 3\0") {
+            anyhow::bail!("Not a Nebula Vault database");
+        }
+
+        // A journal left behind belongs to the old database and must not be
+        // replayed into the new one
+        for suffix in ["-journal", "-wal", "-shm"] {
+            let mut path = self.db_path.as_os_str().to_owned();
+            path.push(suffix);
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("Failed to remove {}", Path::new(&path).display()));
+                }
+                _ => {}
+            }
+        }
+
+        crate::fs_util::write_private_file(&self.db_path, data)
+    }
+}
+
+// ============================================================================
+// Vault header
+// ============================================================================
+
+/// Get the vault header, if this vault has one
+pub async fn get_vault_header(pool: &SqlitePool) -> Result<Option<VaultHeader>> {
+    let header = sqlx::query_as::<_, VaultHeader>(
+        "SELECT salt, kdf_algorithm, kdf_m_cost, kdf_t_cost, kdf_p_cost, kdf_version, kdf_preset, verifier, created_at, updated_at
+         FROM vault_header WHERE id = 1",
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch vault header")?;
+
+    Ok(header)
+}
+
+/// Re-encrypt every identity with `reencrypt` and write the new vault header in a
+/// single `BEGIN IMMEDIATE` transaction, so the header never disagrees with the
+/// stored identities. Other writers wait for it, which means an identity added by
+/// the CLI or the daemon cannot be left behind under the old key.
+///
+/// `previous_salt` is the salt of the header the caller unlocked (`None` for a vault
+/// without one); if another process re-keyed the vault since, nothing is written.
+pub async fn rekey_vault(
+    pool: &SqlitePool,
+    previous_salt: Option<&[u8]>,
+    header: &VaultHeader,
+    mut reencrypt: impl FnMut(&Identity) -> Result<Vec<u8>>,
+) -> Result<()> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let mut tx = pool
+        .begin_with("BEGIN IMMEDIATE")
+        .await
+        .context("Failed to begin transaction")?;
+
+    let current_salt: Option<Vec<u8>> = sqlx::query_scalar("SELECT salt FROM vault_header WHERE id = 1")
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to fetch vault header")?;
+    if current_salt.as_deref() != previous_salt {
+        anyhow::bail!("The vault was re-keyed by another process; unlock it again");
+    }
+
+    let identities = sqlx::query_as::<_, Identity>("SELECT * FROM identities")
+        .fetch_all(&mut *tx)
+        .await
+        .context("Failed to fetch identities")?;
+
+    for identity in &identities {
+        let encrypted_data = reencrypt(identity)?;
+        let result = sqlx::query("UPDATE identities SET encrypted_data = ?, updated_at = ? WHERE id = ?")
+            .bind(encrypted_data)
+            .bind(&now)
+            .bind(&identity.id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to re-encrypt identity")?;
+        if result.rows_affected() != 1 {
+            anyhow::bail!("Identity \"{}\" disappeared while re-encrypting", identity.name);
+        }
+    }
+
+    sqlx::query(
+        "INSERT INTO vault_header (id, salt, kdf_algorithm, kdf_m_cost, kdf_t_cost, kdf_p_cost, kdf_version, kdf_preset, verifier, created_at, updated_at)
+         VALUES (1, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+             salt = excluded.salt,
+             kdf_algorithm = excluded.kdf_algorithm,
+             kdf_m_cost = excluded.kdf_m_cost,
+             kdf_t_cost = excluded.kdf_t_cost,
+             kdf_p_cost = excluded.kdf_p_cost,
+             kdf_version = excluded.kdf_version,
+             verifier = excluded.verifier,
+             updated_at = excluded.updated_at",
+    )
+    .bind(&header.salt)
+    .bind(&header.kdf_algorithm)
+    .bind(header.kdf_m_cost)
+    .bind(header.kdf_t_cost)
+    .bind(header.kdf_p_cost)
+    .bind(header.kdf_version)
+    .bind(&header.kdf_preset)
+    .bind(&header.verifier)
+    .bind(&header.created_at)
+    .bind(&now)
+    .execute(&mut *tx)
+    .await
+    .context("Failed to write vault header")?;
+
+    tx.commit().await.context("Failed to commit transaction")?;
+
+    Ok(())
+}
+
+/// Record the KDF preset to apply at the next unlock
+pub async fn set_kdf_preset(pool: &SqlitePool, preset: &str) -> Result<()> {
+    let now = chrono::Utc::now().to_rfc3339();
+
+    sqlx::query("UPDATE vault_header SET kdf_preset = ?, updated_at = ? WHERE id = 1")
+        .bind(preset)
+        .bind(&now)
+        .execute(pool)
+        .await
+        .context("Failed to update KDF preset")?;
+
+    Ok(())
+}
+
 // ============================================================================
 // Groups
 // ============================================================================
 
+/// Validate a group name and return it trimmed
+fn validate_group_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        anyhow::bail!("Group name cannot be empty");
+    }
+    if name.chars().any(char::is_control) {
+        anyhow::bail!("Group name cannot contain control characters");
+    }
+    Ok(name.to_string())
+}
+
 /// Create a new group
 pub async fn create_group(
     pool: &SqlitePool,
     parent_id: Option<String>,
     name: String,
 ) -> Result<Group> {
+    let name = validate_group_name(&name)?;
     let id = Uuid::new_v4().to_string();
     let now = chrono::Utc::now().to_rfc3339();
 
@@ -55,7 +325,7 @@ pub async fn create_group(
 
 /// Get all groups
 pub async fn get_all_groups(pool: &SqlitePool) -> Result<Vec<Group>> {
-    let groups = sqlx::query_as::<_, Group>("SELECT * FROM groups ORDER BY name")
+    let groups = sqlx::query_as::<_, Group>("SELECT * FROM groups ORDER BY name COLLATE NOCASE")
         .fetch_all(pool)
         .await
         .context("Failed to fetch groups")?;
@@ -63,14 +333,109 @@ pub async fn get_all_groups(pool: &SqlitePool) -> Result<Vec<Group>> {
     Ok(groups)
 }
 
-/// Delete a group
+/// Rename a group
+pub async fn rename_group(pool: &SqlitePool, id: &str, name: String) -> Result<()> {
+    let name = validate_group_name(&name)?;
+    let now = chrono::Utc::now().to_rfc3339();
+
+    let result = sqlx::query("UPDATE groups SET name = ?, updated_at = ? WHERE id = ?")
+        .bind(&name)
+        .bind(&now)
+        .bind(id)
+        .execute(pool)
+        .await
+        .context("Failed to rename group")?;
+    if result.rows_affected() == 0 {
+        anyhow::bail!("Group not found");
+    }
+
+    Ok(())
+}
+
+/// Move a group under another group, or to the top level when `parent_id` is None
+pub async fn move_group(pool: &SqlitePool, id: &str, parent_id: Option<String>) -> Result<()> {
+    if let Some(parent_id) = &parent_id {
+        let groups = get_all_groups(pool).await?;
+        let parent = groups
+            .iter()
+            .find(|g| &g.id == parent_id)
+            .context("Target group does not exist")?;
+        if parent.is_within(id, &groups) {
+            anyhow::bail!("A group cannot be moved into itself or one of its subgroups");
+        }
+    }
+    let now = chrono::Utc::now().to_rfc3339();
+
+    let result = sqlx::query("UPDATE groups SET parent_id = ?, updated_at = ? WHERE id = ?")
+        .bind(&parent_id)
+        .bind(&now)
+        .bind(id)
+        .execute(pool)
+        .await
+        .context("Failed to move group")?;
+    if result.rows_affected() == 0 {
+        anyhow::bail!("Group not found");
+    }
+
+    Ok(())
+}
+
+/// Delete a group. Its hosts and subgroups move up to the group's parent
+/// (or the top level), so deleting a folder never deletes a host.
 pub async fn delete_group(pool: &SqlitePool, id: &str) -> Result<()> {
+    let mut tx = pool.begin().await.context("Failed to begin transaction")?;
+
+    let parent_id: Option<Option<String>> = sqlx::query_scalar("SELECT parent_id FROM groups WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to fetch group")?;
+    let Some(parent_id) = parent_id else {
+        anyhow::bail!("Group not found");
+    };
+
+    sqlx::query("UPDATE hosts SET group_id = ? WHERE group_id = ?")
+        .bind(&parent_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to move hosts out of group")?;
+
+    sqlx::query("UPDATE groups SET parent_id = ? WHERE parent_id = ?")
+        .bind(&parent_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to move subgroups out of group")?;
+
     sqlx::query("DELETE FROM groups WHERE id = ?")
         .bind(id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .context("Failed to delete group")?;
 
+    tx.commit().await.context("Failed to commit transaction")?;
+
+    Ok(())
+}
+
+/// Move hosts into a group, or to the top level when `group_id` is None
+pub async fn set_hosts_group(pool: &SqlitePool, host_ids: &[String], group_id: Option<String>) -> Result<()> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let mut tx = pool.begin().await.context("Failed to begin transaction")?;
+
+    for host_id in host_ids {
+        sqlx::query("UPDATE hosts SET group_id = ?, updated_at = ? WHERE id = ?")
+            .bind(&group_id)
+            .bind(&now)
+            .bind(host_id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to move host")?;
+    }
+
+    tx.commit().await.context("Failed to commit transaction")?;
+
     Ok(())
 }
 
@@ -103,6 +468,8 @@ pub async fn create_identity(
         id,
         name,
         encrypted_data,
+        agent_confirm: false,
+        agent_restrict_hosts: false,
         created_at: now.clone(),
         updated_at: now,
     })
@@ -111,7 +478,7 @@ pub async fn create_identity(
 /// Get a single identity by ID
 pub async fn get_identity(pool: &SqlitePool, id: &str) -> Result<Option<Identity>> {
     let identity = sqlx::query_as::<_, Identity>(
-        "SELECT id, name, encrypted_data, created_at, updated_at FROM identities WHERE id = ?"
+        "SELECT * FROM identities WHERE id = ?"
     )
     .bind(id)
     .fetch_optional(pool)
@@ -131,6 +498,17 @@ pub async fn get_all_identities(pool: &SqlitePool) -> Result<Vec<Identity>> {
     Ok(identities)
 }
 
+/// Find an identity by id or name
+pub async fn find_identity(pool: &SqlitePool, query: &str) -> Result<Identity> {
+    let identities = get_all_identities(pool).await?;
+    identities
+        .iter()
+        .find(|i| i.id == query)
+        .or_else(|| identities.iter().find(|i| i.name.eq_ignore_ascii_case(query)))
+        .cloned()
+        .with_context(|| format!("No identity named \"{}\"", query))
+}
+
 /// Get identity by ID
 pub async fn get_identity_by_id(pool: &SqlitePool, id: &str) -> Result<Option<Identity>> {
     let identity = sqlx::query_as::<_, Identity>("SELECT * FROM identities WHERE id = ?")
@@ -142,6 +520,66 @@ pub async fn get_identity_by_id(pool: &SqlitePool, id: &str) -> Result<Option<Id
     Ok(identity)
 }
 
+/// Every field of an identity set by the identity dialog
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityInput {
+    pub name: String,
+    #[serde(with = "crate::models::base64_bytes")]
+    pub encrypted_data: Vec<u8>,
+    pub agent_confirm: bool,
+    pub agent_restrict_hosts: bool,
+}
+
+/// Create an identity, or replace every field of the identity `id`, in one
+/// transaction; returns the id of the identity
+pub async fn save_identity(pool: &SqlitePool, id: Option<&str>, identity: &IdentityInput) -> Result<String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let mut tx = pool.begin().await.context("Failed to begin transaction")?;
+
+    let id = match id {
+        Some(id) => {
+            let result = sqlx::query(
+                "UPDATE identities SET name = ?, encrypted_data = ?, agent_confirm = ?, agent_restrict_hosts = ?,
+                 updated_at = ? WHERE id = ?",
+            )
+            .bind(&identity.name)
+            .bind(&identity.encrypted_data)
+            .bind(identity.agent_confirm)
+            .bind(identity.agent_restrict_hosts)
+            .bind(&now)
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to update identity")?;
+            if result.rows_affected() == 0 {
+                anyhow::bail!("Identity not found");
+            }
+            id.to_string()
+        }
+        None => {
+            let id = Uuid::new_v4().to_string();
+            sqlx::query(
+                "INSERT INTO identities (id, name, encrypted_data, agent_confirm, agent_restrict_hosts, created_at, updated_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&id)
+            .bind(&identity.name)
+            .bind(&identity.encrypted_data)
+            .bind(identity.agent_confirm)
+            .bind(identity.agent_restrict_hosts)
+            .bind(&now)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to create identity")?;
+            id
+        }
+    };
+
+    tx.commit().await.context("Failed to commit transaction")?;
+    Ok(id)
+}
+
 /// Delete an identity
 pub async fn delete_identity(pool: &SqlitePool, id: &str) -> Result<()> {
     sqlx::query("DELETE FROM identities WHERE id = ?")
@@ -157,7 +595,77 @@ pub async fn delete_identity(pool: &SqlitePool, id: &str) -> Result<()> {
 // Hosts
 // ============================================================================
 
+/// Check a hostname or IP address before it is stored and later passed to ssh.
+///
+/// Only letters, digits and `.-_:%` are accepted (covering DNS names, IPv4,
+/// IPv6 and zone ids), and a leading `-` is refused so the value can never be
+/// read as an ssh option.
+pub fn validate_hostname(hostname: &str) -> Result<()> {
+    if hostname.is_empty() || hostname.len() > 253 {
+        anyhow::bail!("Hostname must be 1 to 253 characters");
+    }
+    if hostname.starts_with('-') {
+        anyhow::bail!("Hostname must not start with '-'");
+    }
+    if let Some(c) = hostname.chars().find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':' | '%'))) {
+        anyhow::bail!("Hostname contains invalid character {:?}", c);
+    }
+    Ok(())
+}
+
+/// Check a login name: letters, digits and `._-@\$` (for `user@domain`,
+/// `DOMAIN\user` and machine accounts), not starting with `-`
+pub fn validate_username(username: &str) -> Result<()> {
+    if username.is_empty() || username.len() > 256 {
+        anyhow::bail!("Username must be 1 to 256 characters");
+    }
+    if username.starts_with('-') {
+        anyhow::bail!("Username must not start with '-'");
+    }
+    if let Some(c) = username.chars().find(|c| !(c.is_alphanumeric() || matches!(c, '.' | '_' | '-' | '@' | '\\' | '$'))) {
+        anyhow::bail!("Username contains invalid character {:?}", c);
+    }
+    Ok(())
+}
+
+/// Check a ProxyJump value: comma-separated `[user@]host[:port]` hops
+pub fn validate_proxy_jump(proxy_jump: &str) -> Result<()> {
+    for hop in proxy_jump.split(',') {
+        let (user, host) = match hop.rsplit_once('@') {
+            Some((user, host)) => (Some(user), host),
+            None => (None, hop),
+        };
+        if let Some(user) = user {
+            validate_username(user).with_context(|| format!("Invalid ProxyJump {:?}", proxy_jump))?;
+        }
+        // A port follows the last colon unless the host is a bare IPv6 address
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if !name.contains(':') => {
+                port.parse::<u16>()
+                    .ok()
+                    .filter(|port| *port > 0)
+                    .with_context(|| format!("Invalid ProxyJump port {:?}", port))?;
+                name
+            }
+            _ => host,
+        };
+        validate_hostname(host).with_context(|| format!("Invalid ProxyJump {:?}", proxy_jump))?;
+    }
+    Ok(())
+}
+
+/// Check the fields every host needs to be passed to ssh
+pub(crate) fn validate_host(hostname: &str, port: i64, username: &str) -> Result<()> {
+    validate_hostname(hostname)?;
+    validate_username(username)?;
+    if !(1..=65535).contains(&port) {
+        anyhow::bail!("Port must be between 1 and 65535");
+    }
+    Ok(())
+}
+
 /// Create a new host
+#[allow(clippy::too_many_arguments)]
 pub async fn create_host(
     pool: &SqlitePool,
     group_id: Option<String>,
@@ -166,14 +674,17 @@ pub async fn create_host(
     hostname: String,
     port: i64,
     username: String,
-    tags: Option<String>,
+    tags: &[String],
 ) -> Result<Host> {
+    validate_host(&hostname, port, &username)?;
+    let tags = tags::normalize_tags(tags)?;
     let id = Uuid::new_v4().to_string();
     let now = chrono::Utc::now().to_rfc3339();
+    let mut tx = pool.begin().await.context("Failed to begin transaction")?;
 
     sqlx::query(
-        "INSERT INTO hosts (id, group_id, identity_id, name, hostname, port, username, tags, created_at, updated_at) 
-         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        "INSERT INTO hosts (id, group_id, identity_id, name, hostname, port, username, created_at, updated_at) 
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
     )
     .bind(&id)
     .bind(&group_id)
@@ -182,13 +693,15 @@ pub async fn create_host(
     .bind(&hostname)
     .bind(port)
     .bind(&username)
-    .bind(&tags)
     .bind(&now)
     .bind(&now)
-    .execute(pool)
+    .execute(&mut *tx)
     .await
     .context("Failed to create host")?;
 
+    insert_host_tags(&mut tx, &id, &tags).await?;
+    tx.commit().await.context("Failed to commit transaction")?;
+
     Ok(Host {
         id,
         group_id,
@@ -198,50 +711,183 @@ pub async fn create_host(
         port,
         username,
         tags,
+        proxy_jump: None,
+        local_forwards: None,
         created_at: now.clone(),
         updated_at: now,
     })
 }
 
-/// Update an existing host
-pub async fn update_host(
-    pool: &SqlitePool,
-    id: &str,
-    name: String,
-    hostname: String,
-    port: i64,
-    username: String,
-    identity_id: Option<String>,
-) -> Result<()> {
+/// Every field of a host set by the host dialog, `add-host` and the ssh_config import
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostInput {
+    pub group_id: Option<String>,
+    pub identity_id: Option<String>,
+    pub name: String,
+    pub hostname: String,
+    pub port: i64,
+    pub username: String,
+    pub tags: Vec<String>,
+    pub proxy_jump: Option<String>,
+    pub local_forwards: Vec<String>,
+}
+
+/// Create a host, or replace every field of the host `id`, in one transaction,
+/// so a host is never left half-saved; returns the id of the host
+pub async fn save_host(pool: &SqlitePool, id: Option<&str>, host: &HostInput) -> Result<String> {
+    validate_host(&host.hostname, host.port, &host.username)?;
+    let tags = tags::normalize_tags(&host.tags)?;
+    let local_forwards = encode_ssh_options(host.proxy_jump.as_deref(), &host.local_forwards)?;
     let now = chrono::Utc::now().to_rfc3339();
+    let mut tx = pool.begin().await.context("Failed to begin transaction")?;
 
-    sqlx::query(
-        "UPDATE hosts SET name = ?, hostname = ?, port = ?, username = ?, identity_id = ?, updated_at = ? WHERE id = ?",
-    )
-    .bind(&name)
-    .bind(&hostname)
-    .bind(port)
-    .bind(&username)
-    .bind(&identity_id)
-    .bind(&now)
-    .bind(id)
-    .execute(pool)
-    .await
-    .context("Failed to update host")?;
+    let id = match id {
+        Some(id) => {
+            let result = sqlx::query(
+                "UPDATE hosts SET group_id = ?, identity_id = ?, name = ?, hostname = ?, port = ?, username = ?,
+                 proxy_jump = ?, local_forwards = ?, updated_at = ? WHERE id = ?",
+            )
+            .bind(&host.group_id)
+            .bind(&host.identity_id)
+            .bind(&host.name)
+            .bind(&host.hostname)
+            .bind(host.port)
+            .bind(&host.username)
+            .bind(&host.proxy_jump)
+            .bind(&local_forwards)
+            .bind(&now)
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to update host")?;
+            if result.rows_affected() == 0 {
+                anyhow::bail!("Host not found");
+            }
+
+            sqlx::query("DELETE FROM host_tags WHERE host_id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to clear host tags")?;
+            id.to_string()
+        }
+        None => {
+            let id = Uuid::new_v4().to_string();
+            sqlx::query(
+                "INSERT INTO hosts (id, group_id, identity_id, name, hostname, port, username, proxy_jump, local_forwards, created_at, updated_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&id)
+            .bind(&host.group_id)
+            .bind(&host.identity_id)
+            .bind(&host.name)
+            .bind(&host.hostname)
+            .bind(host.port)
+            .bind(&host.username)
+            .bind(&host.proxy_jump)
+            .bind(&local_forwards)
+            .bind(&now)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to create host")?;
+            id
+        }
+    };
+
+    insert_host_tags(&mut tx, &id, &tags).await?;
+    tx.commit().await.context("Failed to commit transaction")?;
+
+    Ok(id)
+}
+
+/// Check the ProxyJump and LocalForward options of a host and return the
+/// forwards as stored
+fn encode_ssh_options(proxy_jump: Option<&str>, local_forwards: &[String]) -> Result<Option<String>> {
+    if let Some(proxy_jump) = proxy_jump {
+        validate_proxy_jump(proxy_jump)?;
+    }
+    if let Some(forward) = local_forwards.iter().find(|f| f.trim_start().starts_with('-')) {
+        anyhow::bail!("Invalid LocalForward {:?}", forward);
+    }
+
+    if local_forwards.is_empty() {
+        return Ok(None);
+    }
+    serde_json::to_string(local_forwards)
+        .map(Some)
+        .context("Failed to encode local forwards")
+}
+
+/// Replace the tags of a host
+pub async fn set_host_tags(pool: &SqlitePool, id: &str, tags: &[String]) -> Result<()> {
+    let tags = tags::normalize_tags(tags)?;
+    let mut tx = pool.begin().await.context("Failed to begin transaction")?;
+
+    sqlx::query("DELETE FROM host_tags WHERE host_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to clear host tags")?;
+    insert_host_tags(&mut tx, id, &tags).await?;
+
+    tx.commit().await.context("Failed to commit transaction")?;
 
     Ok(())
 }
 
+async fn insert_host_tags(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, id: &str, tags: &[String]) -> Result<()> {
+    for tag in tags {
+        sqlx::query("INSERT OR IGNORE INTO host_tags (host_id, tag) VALUES (?, ?)")
+            .bind(id)
+            .bind(tag)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to tag host")?;
+    }
+    Ok(())
+}
+
+/// Fill in the tags of fetched hosts
+async fn load_host_tags(pool: &SqlitePool, hosts: &mut [Host]) -> Result<()> {
+    let rows: Vec<(String, String)> = sqlx::query_as("SELECT host_id, tag FROM host_tags ORDER BY tag")
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch host tags")?;
+
+    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+    for (host_id, tag) in rows {
+        tags.entry(host_id).or_default().push(tag);
+    }
+    for host in hosts {
+        host.tags = tags.remove(&host.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
 /// Get all hosts
 pub async fn get_all_hosts(pool: &SqlitePool) -> Result<Vec<Host>> {
-    let hosts = sqlx::query_as::<_, Host>("SELECT * FROM hosts ORDER BY name")
+    let mut hosts = sqlx::query_as::<_, Host>("SELECT * FROM hosts ORDER BY name")
         .fetch_all(pool)
         .await
         .context("Failed to fetch hosts")?;
+    load_host_tags(pool, &mut hosts).await?;
 
     Ok(hosts)
 }
 
+/// Find a host by id, name or ssh_config alias (names case-insensitively)
+pub async fn find_host(pool: &SqlitePool, query: &str) -> Result<Host> {
+    let hosts = get_all_hosts(pool).await?;
+    hosts
+        .iter()
+        .find(|h| h.id == query)
+        .or_else(|| hosts.iter().find(|h| h.name.eq_ignore_ascii_case(query)))
+        .or_else(|| hosts.iter().find(|h| crate::ssh_config::host_alias(&h.name) == query))
+        .cloned()
+        .with_context(|| format!("No host named \"{}\"", query))
+}
+
 /// Get host by ID
 pub async fn get_host_by_id(pool: &SqlitePool, id: &str) -> Result<Option<Host>> {
     let host = sqlx::query_as::<_, Host>("SELECT * FROM hosts WHERE id = ?")
@@ -249,8 +895,16 @@ pub async fn get_host_by_id(pool: &SqlitePool, id: &str) -> Result<Option<Host>>
         .fetch_optional(pool)
         .await
         .context("Failed to fetch host")?;
+    let Some(mut host) = host else {
+        return Ok(None);
+    };
+    host.tags = sqlx::query_scalar("SELECT tag FROM host_tags WHERE host_id = ? ORDER BY tag")
+        .bind(id)
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch host tags")?;
 
-    Ok(host)
+    Ok(Some(host))
 }
 
 /// Delete a host
@@ -264,6 +918,24 @@ pub async fn delete_host(pool: &SqlitePool, id: &str) -> Result<()> {
     Ok(())
 }
 
+/// Whether a host is linked to the given identity
+pub async fn is_host_linked_to_identity(
+    pool: &SqlitePool,
+    host_id: &str,
+    identity_id: &str,
+) -> Result<bool> {
+    let count: i64 = sqlx::query("SELECT COUNT(*) as count FROM hosts WHERE id = ? AND identity_id = ?")
+        .bind(host_id)
+        .bind(identity_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to check host identity")?
+        .try_get("count")
+        .context("Failed to get count")?;
+
+    Ok(count > 0)
+}
+
 /// Check if database is empty (no hosts)
 pub async fn is_database_empty(pool: &SqlitePool) -> Result<bool> {
     let count: i64 = sqlx::query("SELECT COUNT(*) as count FROM hosts")
@@ -275,3 +947,687 @@ pub async fn is_database_empty(pool: &SqlitePool) -> Result<bool> {
 
     Ok(count == 0)
 }
+
+// ============================================================================
+// Known hosts
+// ============================================================================
+
+/// Get the host keys trusted for a hostname and port
+pub async fn get_known_hosts(pool: &SqlitePool, hostname: &str, port: i64) -> Result<Vec<KnownHost>> {
+    let known_hosts = sqlx::query_as::<_, KnownHost>(
+        "SELECT * FROM known_hosts WHERE hostname = ? AND port = ? ORDER BY key_type",
+    )
+    .bind(hostname)
+    .bind(port)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch known hosts")?;
+
+    Ok(known_hosts)
+}
+
+/// Trust a host key for a hostname and port
+pub async fn add_known_host(
+    pool: &SqlitePool,
+    hostname: &str,
+    port: i64,
+    key_type: &str,
+    fingerprint: &str,
+    public_key: &str,
+) -> Result<KnownHost> {
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().to_rfc3339();
+
+    sqlx::query(
+        "INSERT INTO known_hosts (id, hostname, port, key_type, fingerprint, public_key, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&id)
+    .bind(hostname)
+    .bind(port)
+    .bind(key_type)
+    .bind(fingerprint)
+    .bind(public_key)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .context("Failed to add known host")?;
+
+    Ok(KnownHost {
+        id,
+        hostname: hostname.to_string(),
+        port,
+        key_type: key_type.to_string(),
+        fingerprint: fingerprint.to_string(),
+        public_key: public_key.to_string(),
+        created_at: now,
+    })
+}
+
+/// Forget every host key trusted for a hostname and port, returning how many were removed
+pub async fn delete_known_hosts(pool: &SqlitePool, hostname: &str, port: i64) -> Result<u64> {
+    let result = sqlx::query("DELETE FROM known_hosts WHERE hostname = ? AND port = ?")
+        .bind(hostname)
+        .bind(port)
+        .execute(pool)
+        .await
+        .context("Failed to delete known hosts")?;
+
+    Ok(result.rows_affected())
+}
+
+/// Get every host key trusted for a stored host
+pub async fn get_all_known_hosts(pool: &SqlitePool) -> Result<Vec<KnownHost>> {
+    let known_hosts = sqlx::query_as::<_, KnownHost>(
+        "SELECT * FROM known_hosts ORDER BY hostname, port, key_type",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch known hosts")?;
+
+    Ok(known_hosts)
+}
+
+/// Store an imported known_hosts line, returning false if it was already present
+pub async fn add_known_host_entry(
+    pool: &SqlitePool,
+    marker: &str,
+    hosts: &str,
+    key_type: &str,
+    fingerprint: &str,
+    public_key: &str,
+    comment: Option<&str>,
+) -> Result<bool> {
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().to_rfc3339();
+
+    let result = sqlx::query(
+        "INSERT OR IGNORE INTO known_host_entries (id, marker, hosts, key_type, fingerprint, public_key, comment, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&id)
+    .bind(marker)
+    .bind(hosts)
+    .bind(key_type)
+    .bind(fingerprint)
+    .bind(public_key)
+    .bind(comment)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .context("Failed to add known host entry")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Get every imported known_hosts line
+pub async fn get_known_host_entries(pool: &SqlitePool) -> Result<Vec<KnownHostEntry>> {
+    let entries = sqlx::query_as::<_, KnownHostEntry>(
+        "SELECT * FROM known_host_entries ORDER BY created_at",
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch known host entries")?;
+
+    Ok(entries)
+}
+
+// ============================================================================
+// Terminal templates
+// ============================================================================
+
+/// Get every custom terminal template, by name
+pub async fn get_terminal_templates(pool: &SqlitePool) -> Result<Vec<TerminalTemplate>> {
+    let rows = sqlx::query("SELECT name, argv FROM terminal_templates ORDER BY name")
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch terminal templates")?;
+
+    rows.iter()
+        .map(|row| {
+            let name: String = row.get("name");
+            let argv: String = row.get("argv");
+            Ok(TerminalTemplate {
+                argv: serde_json::from_str(&argv).with_context(|| format!("Invalid terminal template {:?}", name))?,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Add a terminal template, or replace the one with the same name
+pub async fn save_terminal_template(pool: &SqlitePool, template: &TerminalTemplate) -> Result<()> {
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().to_rfc3339();
+    let argv = serde_json::to_string(&template.argv).context("Failed to encode terminal template")?;
+
+    sqlx::query(
+        "INSERT INTO terminal_templates (id, name, argv, created_at, updated_at) VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT (name) DO UPDATE SET argv = excluded.argv, updated_at = excluded.updated_at",
+    )
+    .bind(&id)
+    .bind(&template.name)
+    .bind(&argv)
+    .bind(&now)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .context("Failed to save terminal template")?;
+
+    Ok(())
+}
+
+/// Delete a terminal template by name
+pub async fn delete_terminal_template(pool: &SqlitePool, name: &str) -> Result<()> {
+    sqlx::query("DELETE FROM terminal_templates WHERE name = ?")
+        .bind(name)
+        .execute(pool)
+        .await
+        .context("Failed to delete terminal template")?;
+
+    Ok(())
+}
+
+// ============================================================================
+// Agent sign log
+// ============================================================================
+
+/// Record a signing request and the agent's decision
+pub async fn log_agent_signature(
+    pool: &SqlitePool,
+    identity_id: &str,
+    host_id: Option<&str>,
+    key_fingerprint: &str,
+    forwarded: bool,
+    decision: &str,
+) -> Result<()> {
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().to_rfc3339();
+
+    sqlx::query(
+        "INSERT INTO agent_sign_log (id, identity_id, host_id, key_fingerprint, forwarded, decision, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&id)
+    .bind(identity_id)
+    .bind(host_id)
+    .bind(key_fingerprint)
+    .bind(forwarded)
+    .bind(decision)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .context("Failed to record agent signature")?;
+
+    Ok(())
+}
+
+/// Get the most recent agent sign log entries, newest first
+pub async fn get_agent_sign_log(pool: &SqlitePool, limit: i64) -> Result<Vec<AgentSignRecord>> {
+    let records = sqlx::query_as::<_, AgentSignRecord>(
+        "SELECT * FROM agent_sign_log ORDER BY created_at DESC LIMIT ?",
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch agent sign log")?;
+
+    Ok(records)
+}
+
+// ============================================================================
+// Connection history
+// ============================================================================
+
+/// Timestamp of a history entry; fixed width, so entries sort as text
+fn history_timestamp(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+/// Record a connection attempt; `error` is None when it succeeded
+pub async fn log_connection(
+    pool: &SqlitePool,
+    host_id: &str,
+    identity_id: Option<&str>,
+    target: &str,
+    error: Option<&str>,
+) -> Result<()> {
+    let id = Uuid::new_v4().to_string();
+    let now = history_timestamp(chrono::Utc::now());
+
+    sqlx::query(
+        "INSERT INTO connection_history (id, host_id, identity_id, target, success, error, connected_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&id)
+    .bind(host_id)
+    .bind(identity_id)
+    .bind(target)
+    .bind(error.is_none())
+    .bind(error)
+    .bind(&now)
+    .execute(pool)
+    .await
+    .context("Failed to record connection")?;
+
+    Ok(())
+}
+
+/// Get a host's most recent connection attempts, newest first
+pub async fn get_host_history(pool: &SqlitePool, host_id: &str, limit: i64) -> Result<Vec<ConnectionRecord>> {
+    let records = sqlx::query_as::<_, ConnectionRecord>(
+        "SELECT * FROM connection_history WHERE host_id = ? ORDER BY connected_at DESC, rowid DESC LIMIT ?",
+    )
+    .bind(host_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch connection history")?;
+
+    Ok(records)
+}
+
+/// Hosts by their last connection attempt, most recent first
+pub async fn get_recent_hosts(pool: &SqlitePool, limit: i64) -> Result<Vec<String>> {
+    let host_ids = sqlx::query_scalar::<_, String>(
+        "SELECT host_id FROM connection_history
+         GROUP BY host_id ORDER BY MAX(connected_at) DESC, MAX(rowid) DESC LIMIT ?",
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch recent hosts")?;
+
+    Ok(host_ids)
+}
+
+/// Hosts by their number of successful connections, most used first
+pub async fn get_frequent_hosts(pool: &SqlitePool, limit: i64) -> Result<Vec<HostUsage>> {
+    let usage = sqlx::query_as::<_, HostUsage>(
+        "SELECT host_id, COUNT(*) AS connections, MAX(connected_at) AS last_connected_at
+         FROM connection_history WHERE success = 1
+         GROUP BY host_id ORDER BY connections DESC, last_connected_at DESC, MAX(rowid) DESC LIMIT ?",
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch frequent hosts")?;
+
+    Ok(usage)
+}
+
+/// Delete history entries older than `retention_days`; 0 keeps everything.
+/// Returns the number of entries deleted.
+pub async fn purge_connection_history(pool: &SqlitePool, retention_days: u64) -> Result<u64> {
+    if retention_days == 0 {
+        return Ok(0);
+    }
+    let days = i64::try_from(retention_days).unwrap_or(i64::MAX).min(365_000);
+    let cutoff = history_timestamp(chrono::Utc::now() - chrono::Duration::days(days));
+
+    let result = sqlx::query("DELETE FROM connection_history WHERE connected_at < ?")
+        .bind(&cutoff)
+        .execute(pool)
+        .await
+        .context("Failed to purge connection history")?;
+
+    Ok(result.rows_affected())
+}
+
+/// Vault database in a fresh temporary directory, for tests. The directory and
+/// anything else a test puts in it are removed on drop, also when an assert fails.
+#[cfg(test)]
+pub(crate) struct TestDb {
+    pub dir: std::path::PathBuf,
+    pub pool: SqlitePool,
+}
+
+#[cfg(test)]
+impl TestDb {
+    pub async fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("nebulavault_test_{}", Uuid::new_v4()));
+        let pool = init_db(dir.join("vault.db")).await.unwrap();
+        Self { dir, pool }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_hostname() {
+        for hostname in ["db.example.com", "10.0.0.5", "::1", "fe80::1%eth0", "my_host-01"] {
+            assert!(validate_hostname(hostname).is_ok(), "{}", hostname);
+        }
+        for hostname in ["", "-oProxyCommand=sh", "db; rm -rf ~", "db'", "$(id)", "db example", "db\n", "`id`"] {
+            assert!(validate_hostname(hostname).is_err(), "{:?}", hostname);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vault_lock_guards_replacing_the_database() {
+        let test_db = TestDb::new().await;
+        let db_path = test_db.dir.join("vault.db");
+        test_db.pool.close().await;
+        let original = std::fs::read(&db_path).unwrap();
+
+        let gui = VaultLock::shared(&db_path).unwrap();
+        let daemon = VaultLock::shared(&db_path).unwrap();
+        assert!(VaultLock::exclusive(&db_path).is_err());
+        assert!(daemon.replace_database(&original).is_err());
+        drop((gui, daemon));
+
+        let import = VaultLock::exclusive(&db_path).unwrap();
+        assert!(VaultLock::shared(&db_path).is_err());
+        assert!(import.replace_database(b"not a database").is_err());
+        assert_eq!(std::fs::read(&db_path).unwrap(), original);
+
+        let mut replacement = original.clone();
+        replacement.extend_from_slice(&[0; 16]);
+        import.replace_database(&replacement).unwrap();
+        assert_eq!(std::fs::read(&db_path).unwrap(), replacement);
+    }
+
+    #[test]
+    fn test_validate_username() {
+        for username in ["deploy", "first.last", "me@corp.example", "CORP\\deploy", "HOST$", "jürgen"] {
+            assert!(validate_username(username).is_ok(), "{}", username);
+        }
+        for username in ["", "-l", "root'", "a b", "x;id", "$(id)", "\"q\""] {
+            assert!(validate_username(username).is_err(), "{:?}", username);
+        }
+    }
+
+    #[test]
+    fn test_validate_proxy_jump() {
+        for proxy_jump in ["bastion", "ops@bastion:2222", "a.example,b@b.example:22", "fe80::1"] {
+            assert!(validate_proxy_jump(proxy_jump).is_ok(), "{}", proxy_jump);
+        }
+        for proxy_jump in ["", "-oProxyCommand=sh", "bastion:0", "bastion:ssh", "a,,b", "b;id"] {
+            assert!(validate_proxy_jump(proxy_jump).is_err(), "{:?}", proxy_jump);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hosts_with_hostile_fields_are_rejected() {
+        let test_db = TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let create = |hostname: &str, port: i64, username: &str| {
+            create_host(&pool, None, None, "test".to_string(), hostname.to_string(), port, username.to_string(), &[])
+        };
+        assert!(create("db'; touch /tmp/pwned; '", 22, "deploy").await.is_err());
+        assert!(create("db.example.com", 22, "-oProxyCommand=sh").await.is_err());
+        assert!(create("db.example.com", 70000, "deploy").await.is_err());
+
+        let host = create("db.example.com", 22, "deploy").await.unwrap();
+        let input = HostInput {
+            name: host.name.clone(),
+            hostname: host.hostname.clone(),
+            port: 22,
+            username: host.username.clone(),
+            ..HostInput::default()
+        };
+        for hostile in [
+            HostInput { hostname: "$(id)".to_string(), ..input.clone() },
+            HostInput { proxy_jump: Some("-J".to_string()), ..input.clone() },
+            HostInput { local_forwards: vec!["-oProxyCommand=sh".to_string()], ..input.clone() },
+        ] {
+            assert!(save_host(&pool, Some(&host.id), &hostile).await.is_err());
+            assert!(save_host(&pool, None, &hostile).await.is_err());
+        }
+        assert!(get_all_hosts(&pool).await.unwrap().len() == 1);
+    }
+
+    #[tokio::test]
+    async fn test_save_host_is_all_or_nothing() {
+        let test_db = TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let group = create_group(&pool, None, "Production".to_string()).await.unwrap();
+        let input = HostInput {
+            name: "pg".to_string(),
+            hostname: "pg.example.com".to_string(),
+            port: 22,
+            username: "deploy".to_string(),
+            tags: vec!["db".to_string()],
+            proxy_jump: Some("bastion".to_string()),
+            local_forwards: vec!["5432 localhost:5432".to_string()],
+            ..HostInput::default()
+        };
+        let id = save_host(&pool, None, &input).await.unwrap();
+        let saved = get_host_by_id(&pool, &id).await.unwrap().unwrap();
+        assert_eq!((saved.tags.as_slice(), saved.proxy_jump.as_deref()), (["db".to_string()].as_slice(), Some("bastion")));
+        assert_eq!(saved.get_local_forwards(), ["5432 localhost:5432"]);
+
+        // A rejected tag leaves every other field of the host as it was
+        let edited = HostInput {
+            group_id: Some(group.id.clone()),
+            hostname: "pg2.example.com".to_string(),
+            tags: vec!["two words".to_string()],
+            proxy_jump: None,
+            ..input.clone()
+        };
+        assert!(save_host(&pool, Some(&id), &edited).await.is_err());
+        let unchanged = get_host_by_id(&pool, &id).await.unwrap().unwrap();
+        assert_eq!((unchanged.hostname.as_str(), unchanged.group_id.as_deref()), ("pg.example.com", None));
+        assert_eq!(unchanged.proxy_jump.as_deref(), Some("bastion"));
+
+        let edited = HostInput {
+            tags: vec!["replica".to_string()],
+            ..edited
+        };
+        save_host(&pool, Some(&id), &edited).await.unwrap();
+        let saved = get_host_by_id(&pool, &id).await.unwrap().unwrap();
+        assert_eq!((saved.hostname.as_str(), saved.group_id.as_deref()), ("pg2.example.com", Some(group.id.as_str())));
+        assert_eq!((saved.tags, saved.proxy_jump), (vec!["replica".to_string()], None));
+
+        assert!(save_host(&pool, Some("unknown"), &input).await.is_err());
+        assert_eq!(get_all_hosts(&pool).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_save_identity_with_its_agent_policy() {
+        let test_db = TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let input = IdentityInput {
+            name: "deploy".to_string(),
+            encrypted_data: vec![1, 2, 3],
+            agent_confirm: true,
+            agent_restrict_hosts: false,
+        };
+        let id = save_identity(&pool, None, &input).await.unwrap();
+        let saved = get_identity(&pool, &id).await.unwrap().unwrap();
+        assert_eq!((saved.name.as_str(), saved.encrypted_data.as_slice()), ("deploy", [1, 2, 3].as_slice()));
+        assert_eq!((saved.agent_confirm, saved.agent_restrict_hosts), (true, false));
+
+        let edited = IdentityInput {
+            encrypted_data: vec![4, 5],
+            agent_confirm: false,
+            agent_restrict_hosts: true,
+            ..input.clone()
+        };
+        assert_eq!(save_identity(&pool, Some(&id), &edited).await.unwrap(), id);
+        let saved = get_identity(&pool, &id).await.unwrap().unwrap();
+        assert_eq!(saved.encrypted_data, [4, 5]);
+        assert_eq!((saved.agent_confirm, saved.agent_restrict_hosts), (false, true));
+
+        assert!(save_identity(&pool, Some("unknown"), &input).await.is_err());
+        assert_eq!(get_all_identities(&pool).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_find_host_by_id_name_or_alias() {
+        let test_db = TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let host = create_host(
+            &pool, None, None, "Prod DB".to_string(), "db.example.com".to_string(), 22, "deploy".to_string(), &[],
+        )
+        .await
+        .unwrap();
+
+        for query in [host.id.as_str(), "prod db", "prod-db"] {
+            assert_eq!(find_host(&pool, query).await.unwrap().id, host.id);
+        }
+        assert!(find_host(&pool, "prod").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_group_tree_operations() {
+        let test_db = TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let prod = create_group(&pool, None, " Production ".to_string()).await.unwrap();
+        assert_eq!(prod.name, "Production");
+        assert!(create_group(&pool, None, "  ".to_string()).await.is_err());
+        let db = create_group(&pool, Some(prod.id.clone()), "Databases".to_string()).await.unwrap();
+        let replicas = create_group(&pool, Some(db.id.clone()), "Replicas".to_string()).await.unwrap();
+
+        rename_group(&pool, &db.id, "DB".to_string()).await.unwrap();
+        assert!(rename_group(&pool, &db.id, String::new()).await.is_err());
+
+        // A group cannot end up inside itself or its own subtree
+        assert!(move_group(&pool, &prod.id, Some(prod.id.clone())).await.is_err());
+        assert!(move_group(&pool, &prod.id, Some(replicas.id.clone())).await.is_err());
+        move_group(&pool, &replicas.id, Some(prod.id.clone())).await.unwrap();
+        move_group(&pool, &replicas.id, Some(db.id.clone())).await.unwrap();
+
+        let host = create_host(&pool, None, None, "pg".to_string(), "pg.example.com".to_string(), 22, "deploy".to_string(), &[])
+            .await
+            .unwrap();
+        set_hosts_group(&pool, std::slice::from_ref(&host.id), Some(db.id.clone())).await.unwrap();
+
+        // Deleting a group hands its hosts and subgroups to the parent
+        delete_group(&pool, &db.id).await.unwrap();
+        let hosts = get_all_hosts(&pool).await.unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].group_id.as_deref(), Some(prod.id.as_str()));
+        let groups = get_all_groups(&pool).await.unwrap();
+        assert_eq!(groups.len(), 2);
+        let replicas = groups.iter().find(|g| g.id == replicas.id).unwrap();
+        assert_eq!(replicas.parent_id.as_deref(), Some(prod.id.as_str()));
+
+        delete_group(&pool, &prod.id).await.unwrap();
+        assert!(get_all_hosts(&pool).await.unwrap()[0].group_id.is_none());
+        assert!(get_all_groups(&pool).await.unwrap()[0].parent_id.is_none());
+        assert!(delete_group(&pool, &prod.id).await.is_err());
+
+        // A deleted or unknown group cannot be renamed or moved
+        assert!(rename_group(&pool, &prod.id, "Gone".to_string()).await.is_err());
+        assert!(move_group(&pool, &prod.id, None).await.is_err());
+        assert!(move_group(&pool, "unknown", Some(replicas.id.clone())).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_host_tags() {
+        let test_db = TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let host = create_host(&pool, None, None, "pg".to_string(), "pg.example.com".to_string(), 22, "deploy".to_string(), &tags(&[" Prod", "db", "prod"]))
+            .await
+            .unwrap();
+        assert_eq!(host.tags, tags(&["db", "prod"]));
+        assert_eq!(get_all_hosts(&pool).await.unwrap()[0].tags, tags(&["db", "prod"]));
+
+        set_host_tags(&pool, &host.id, &tags(&["legacy", "db"])).await.unwrap();
+        assert_eq!(get_host_by_id(&pool, &host.id).await.unwrap().unwrap().tags, tags(&["db", "legacy"]));
+        assert!(set_host_tags(&pool, &host.id, &tags(&["two words"])).await.is_err());
+        assert_eq!(get_host_by_id(&pool, &host.id).await.unwrap().unwrap().tags, tags(&["db", "legacy"]));
+
+        delete_host(&pool, &host.id).await.unwrap();
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM host_tags").fetch_one(&pool).await.unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_tags_are_repaired_on_upgrade() {
+        let test_db = TestDb::new().await;
+        let db_path = test_db.dir.join("legacy.db");
+
+        // A vault from before host tags had their own table
+        let options = SqliteConnectOptions::new().filename(&db_path).create_if_missing(true);
+        let legacy = SqlitePool::connect_with(options).await.unwrap();
+        let mut migrator = sqlx::migrate!("./migrations");
+        migrator.migrations = migrator.migrations.iter().filter(|m| m.version <= 8).cloned().collect();
+        migrator.run(&legacy).await.unwrap();
+        sqlx::query("INSERT INTO hosts (id, name, hostname, username, tags) VALUES ('web', 'web', 'web.example.com', 'deploy', ?)")
+            .bind(r#"["Web Server", "prod, db", " Prod", "and", "(eu)", "ok:1"]"#)
+            .execute(&legacy)
+            .await
+            .unwrap();
+        legacy.close().await;
+
+        let pool = init_db(&db_path).await.unwrap();
+        let host = get_host_by_id(&pool, "web").await.unwrap().unwrap();
+        assert_eq!(host.tags, ["eu", "ok:1", "prod", "prod-db", "web-server"]);
+        assert_eq!(tags::normalize_tags(&host.tags).unwrap(), host.tags);
+    }
+
+    #[tokio::test]
+    async fn test_terminal_templates_are_saved_by_name() {
+        let test_db = TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let mut template = TerminalTemplate::parse("foot", "foot {ssh_argv}").unwrap();
+        save_terminal_template(&pool, &template).await.unwrap();
+        template.argv.insert(1, "--maximized".to_string());
+        save_terminal_template(&pool, &template).await.unwrap();
+        assert_eq!(get_terminal_templates(&pool).await.unwrap(), [template]);
+
+        delete_terminal_template(&pool, "foot").await.unwrap();
+        assert!(get_terminal_templates(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_history() {
+        let test_db = TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let web = create_host(&pool, None, None, "web".to_string(), "web.example.com".to_string(), 22, "deploy".to_string(), &[])
+            .await
+            .unwrap();
+        let db = create_host(&pool, None, None, "db".to_string(), "db.example.com".to_string(), 22, "deploy".to_string(), &[])
+            .await
+            .unwrap();
+
+        log_connection(&pool, &web.id, None, "kitty", None).await.unwrap();
+        log_connection(&pool, &web.id, None, "tmux", None).await.unwrap();
+        log_connection(&pool, &db.id, None, "kitty", Some("No identity configured for this host")).await.unwrap();
+
+        // Failed attempts count as recent use, but not towards frequent
+        assert_eq!(get_recent_hosts(&pool, 10).await.unwrap(), [db.id.clone(), web.id.clone()]);
+        let frequent = get_frequent_hosts(&pool, 10).await.unwrap();
+        assert_eq!(frequent.len(), 1);
+        assert_eq!((frequent[0].host_id.as_str(), frequent[0].connections), (web.id.as_str(), 2));
+
+        let history = get_host_history(&pool, &db.id, 10).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(!history[0].success);
+        assert_eq!(history[0].error.as_deref(), Some("No identity configured for this host"));
+        assert_eq!(get_host_history(&pool, &web.id, 1).await.unwrap()[0].target, "tmux");
+
+        // Only entries past the retention period are purged
+        sqlx::query("UPDATE connection_history SET connected_at = ? WHERE target = 'kitty'")
+            .bind(history_timestamp(chrono::Utc::now() - chrono::Duration::days(40)))
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(purge_connection_history(&pool, 0).await.unwrap(), 0);
+        assert_eq!(purge_connection_history(&pool, 30).await.unwrap(), 2);
+        assert_eq!(get_recent_hosts(&pool, 10).await.unwrap(), vec![web.id.clone()]);
+
+        delete_host(&pool, &web.id).await.unwrap();
+        assert!(get_recent_hosts(&pool, 10).await.unwrap().is_empty());
+    }
+}
@@ -1,25 +1,143 @@
-use iced::{Element, Subscription, Task};
+use iced::keyboard::{self, key, Key, Modifiers};
+use iced::widget::{scrollable, text_input};
+use iced::{event, window, Element, Event, Subscription, Task};
+use super::hotkey::{self, GlobalHotkey};
 use super::messages::Message;
-use super::state::{AppState, NebulaVaultState};
-use crate::{db, models, ssh};
+use super::views::{launcher, main_view};
+use super::state::{AppState, AutoLockTimeout, DaemonConnection, HostForm, Launcher, NebulaVaultState, PendingHostKey, VaultForm};
+use sqlx::SqlitePool;
+use crate::agent::{KeyPolicy, SshAgent};
+use crate::askpass::AskpassServer;
+use crate::vault::{KdfPreset, Vault};
+use crate::known_hosts::{self, HostKeyError, HostKeyStatus};
+use crate::ssh_config::{self, SshConfig};
+use crate::tags::{self, TagQuery};
+use crate::terminal_launcher::{self, AskpassOptions, Connection, SshOptions, TerminalApp, TerminalTemplate};
+use crate::config;
+use crate::daemon::{self, DaemonClient};
+use crate::db;
+use crate::{models, ssh};
+use std::path::{Path, PathBuf};
+use serde_json::{json, Value};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
 
-const DB_PATH: &str = "nebulavault.db";
+
+/// How often the idle timer is checked while the vault is unlocked
+const AUTO_LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often stale agent confirmation prompts are cleared
+const AGENT_CONFIRM_PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the daemon is asked for signing requests awaiting approval
+const DAEMON_CONFIRM_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of agent sign log entries shown with the identities
+const AGENT_SIGN_LOG_LIMIT: i64 = 20;
+
+/// Number of recent and of frequent hosts loaded from the connection history
+const HOST_USAGE_LIMIT: i64 = 20;
+
+/// Number of connection attempts shown in the host dialog
+const HOST_HISTORY_LIMIT: i64 = 20;
 
 pub struct NebulaVault {
     state: NebulaVaultState,
 }
 
 impl NebulaVault {
-    pub fn new() -> (Self, Task<Message>) {
-        (
-            Self {
-                state: NebulaVaultState::new(),
+    /// The app with its main window, which is opened by the caller
+    pub fn new(vault_path: Option<PathBuf>, main_window: window::Id) -> (Self, Task<Message>) {
+        let (settings, error) = match config::Settings::load(&config::settings_path()) {
+            Ok(settings) => (settings, None),
+            Err(e) => (config::Settings::default(), Some(format!("{:#}", e))),
+        };
+        let mut state = NebulaVaultState::new(vault_path.unwrap_or_else(|| settings.startup_vault()));
+        state.apply_settings(settings);
+        state.error_message = error;
+        state.main_window = Some(main_window);
+
+        match GlobalHotkey::new() {
+            Ok(mut global_hotkey) => {
+                if let Err(e) = global_hotkey.register(&state.settings.hotkey) {
+                    state.hotkey_form.error = Some(e);
+                }
+                state.hotkey = Some(global_hotkey);
+            }
+            Err(e) => state.hotkey_form.unavailable = Some(e),
+        }
+
+        (Self { state }, Task::none())
+    }
+
+    /// Open the launcher window with the search focused, the most recent
+    /// host selected
+    fn open_launcher(&mut self) -> Task<Message> {
+        let (id, open) = window::open(launcher::window_settings());
+        self.state.launcher = Some(Launcher::new(id));
+        open.then(|id| {
+            window::gain_focus(id).chain(text_input::focus(text_input::Id::new(launcher::LAUNCHER_INPUT)))
+        })
+    }
+
+    /// Lock the vault, and the daemon serving its keys when there is one
+    fn lock_vault(&mut self) -> Task<Message> {
+        let daemon = self.state.daemon.take();
+        self.state.lock();
+        let Some(daemon) = daemon else {
+            return Task::none();
+        };
+
+        Task::perform(
+            async move {
+                daemon
+                    .lock()
+                    .await
+                    .call("lock", Value::Null)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| format!("Failed to lock the daemon: {}", e))
             },
-            Task::none(),
+            Message::DaemonCalled,
         )
     }
 
+    /// Switch to the vault at `path`, locking the open one
+    fn select_vault(&mut self, path: PathBuf) {
+        if self.state.vault.is_some() {
+            self.state.lock();
+        }
+        self.state.password_input.clear();
+        self.state.error_message = None;
+        self.state.settings.vault = (path != config::default_vault_path()).then(|| path.clone());
+        self.state.vault_path = path;
+        self.save_settings();
+    }
+
+    /// Write the settings file after a preference changed.
+    ///
+    /// Written in place rather than in a task so quick successive changes
+    /// cannot land out of order.
+    fn save_settings(&mut self) {
+        if let Err(e) = self.state.settings.save(&config::settings_path()) {
+            self.state.error_message = Some(format!("Failed to save settings: {:#}", e));
+        }
+    }
+
+    /// Record the preferred terminal in the settings; `None` goes back to the
+    /// platform default
+    fn save_terminal_setting(&mut self, terminal: Option<&TerminalApp>) {
+        self.state.settings.terminal = match terminal {
+            Some(terminal) => config::TerminalSettings::new(terminal, &self.state.multiplexer_session),
+            None => config::TerminalSettings {
+                session: self.state.multiplexer_session.clone(),
+                ..Default::default()
+            },
+        };
+        self.save_settings();
+    }
+
     pub fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::PasswordChanged(password) => {
@@ -28,6 +146,53 @@ impl NebulaVault {
                 Task::none()
             }
 
+            Message::VaultSelected(vault) => {
+                self.select_vault(vault.path);
+                Task::none()
+            }
+
+            Message::ToggleVaultForm => {
+                let form = &mut self.state.vault_form;
+                *form = VaultForm {
+                    open: !form.open,
+                    ..VaultForm::default()
+                };
+                Task::none()
+            }
+
+            Message::VaultNameChanged(name) => {
+                self.state.vault_form.name = name;
+                self.state.vault_form.error = None;
+                Task::none()
+            }
+
+            Message::VaultPathChanged(path) => {
+                self.state.vault_form.path = path;
+                self.state.vault_form.error = None;
+                Task::none()
+            }
+
+            Message::AddVault => {
+                let form = &self.state.vault_form;
+                let path = (!form.path.trim().is_empty()).then(|| expand_home(&form.path));
+                match self.state.settings.add_vault(&form.name, path.as_deref()) {
+                    Ok(vault) => {
+                        self.state.vault_form = VaultForm::default();
+                        // Saved along with the selection
+                        self.select_vault(vault.path);
+                    }
+                    Err(e) => self.state.vault_form.error = Some(e.to_string()),
+                }
+                Task::none()
+            }
+
+            Message::RemoveVault => {
+                let path = self.state.vault_path.clone();
+                self.state.settings.remove_vault(&path);
+                self.select_vault(config::default_vault_path());
+                Task::none()
+            }
+
             Message::UnlockVault => {
                 if self.state.password_input.is_empty() {
                     self.state.error_message = Some("Password cannot be empty".to_string());
@@ -37,100 +202,373 @@ impl NebulaVault {
                 self.state.state = AppState::Loading;
                 let password = self.state.password_input.clone();
 
+                let db_path = self.state.vault_path.clone();
                 Task::perform(
                     async move {
-                        let mut vault = crate::vault::Vault::new();
-                        if let Err(e) = vault.derive_key(&password) {
-                            return (false, Some(format!("Failed to derive key: {}", e)));
-                        }
-
-                        match db::init_db(DB_PATH).await {
-                            Ok(_pool) => (true, None),
-                            Err(e) => (false, Some(format!("Failed to initialize database: {}", e))),
-                        }
+                        let pool = db::init_db(&db_path)
+                            .await
+                            .map_err(|e| format!("Failed to initialize database: {}", e))?;
+
+                        let vault = Vault::unlock(&pool, &password)
+                            .await
+                            .map(Arc::new)
+                            .map_err(|e| e.to_string())?;
+                        let daemon = connect_daemon(&db_path, &password).await?;
+                        Ok((vault, pool, daemon))
                     },
-                    |(success, error)| Message::VaultUnlockResult(success, error),
+                    Message::VaultUnlockResult,
                 )
             }
 
-            Message::VaultUnlockResult(success, error) => {
-                if success {
-                    let password = self.state.password_input.clone();
-                    self.state.password_input.clear();
-                    
-                    let mut vault = crate::vault::Vault::new();
-                    if let Err(e) = vault.derive_key(&password) {
+            Message::VaultUnlockResult(result) => {
+                match result {
+                    Ok((vault, pool, daemon)) => {
+                        match db::VaultLock::shared(&self.state.vault_path) {
+                            Ok(lock) => self.state.vault_lock = Some(lock),
+                            Err(e) => {
+                                self.state.error_message = Some(e.to_string());
+                                self.state.state = AppState::PasswordEntry;
+                                return Task::none();
+                            }
+                        }
+                        self.state.password_input.clear();
+                        self.state.last_activity = Instant::now();
+                        self.state.kdf_preset = KdfPreset::from_params(&vault.kdf_params());
+                        self.state.vault = Some(vault);
+                        self.state.db_pool = Some(pool.clone());
+                        self.state.state = AppState::Ready;
+
+                        // The daemon's agent already serves the keys; they are
+                        // served again once the identities are loaded
+                        self.state.daemon = daemon;
+                        let start_agent = if self.state.daemon.is_some() {
+                            Task::none()
+                        } else {
+                            let pool = pool.clone();
+                            Task::perform(
+                                async move {
+                                    SshAgent::start(SshAgent::default_socket_path(), pool)
+                                        .await
+                                        .map(Arc::new)
+                                        .map_err(|e| format!("Failed to start SSH agent: {}", e))
+                                },
+                                Message::AgentStarted,
+                            )
+                        };
+
+                        let load_hosts = load_hosts(&self.state);
+
+                        let start_askpass = Task::perform(
+                            async move {
+                                AskpassServer::start(AskpassServer::default_socket_path())
+                                    .await
+                                    .map(Arc::new)
+                                    .map_err(|e| format!("Failed to start askpass helper: {}", e))
+                            },
+                            Message::AskpassStarted,
+                        );
+
+                        let load_terminal_templates = Task::perform(
+                            async move {
+                                db::get_terminal_templates(&pool)
+                                    .await
+                                    .map_err(|e| format!("Failed to load terminal templates: {:#}", e))
+                            },
+                            Message::TerminalTemplatesLoaded,
+                        );
+
+                        // An unlock from the launcher goes straight on to its search
+                        let focus_launcher = match &self.state.launcher {
+                            Some(_) => text_input::focus(text_input::Id::new(launcher::LAUNCHER_INPUT)),
+                            None => Task::none(),
+                        };
+                        Task::batch([start_agent, start_askpass, load_hosts, load_terminal_templates, focus_launcher])
+                    }
+                    Err(error) => {
+                        self.state.password_input.clear();
                         self.state.state = AppState::PasswordEntry;
-                        self.state.error_message = Some(format!("Failed to unlock: {}", e));
-                        return Task::none();
+                        self.state.error_message = Some(error);
+                        Task::none()
                     }
-                    
-                    self.state.vault = Some(vault);
-                    self.state.state = AppState::Ready;
-                    
-                    Task::perform(
-                        async move {
-                            match db::init_db(DB_PATH).await {
-                                Ok(pool) => {
-                                    match db::get_all_hosts(&pool).await {
-                                        Ok(_hosts) => (true, None),
-                                        Err(e) => (false, Some(format!("Failed to load hosts: {}", e))),
-                                    }
-                                }
-                                Err(e) => (false, Some(format!("Database error: {}", e))),
-                            }
-                        },
-                        |(success, error)| Message::HostsLoadResult(success, error),
-                    )
+                }
+            }
+
+            Message::AgentStarted(result) => {
+                match result {
+                    // The vault may have been locked while the agent was starting
+                    Ok(agent) if self.state.vault.is_some() => {
+                        self.state.agent = Some(agent);
+                        return serve_host_keys(&self.state);
+                    }
+                    Ok(_) => {}
+                    Err(error) => self.state.error_message = Some(error),
+                }
+                Task::none()
+            }
+
+            Message::AskpassStarted(result) => {
+                match result {
+                    // The vault may have been locked while the helper was starting
+                    Ok(askpass) if self.state.vault.is_some() => self.state.askpass = Some(askpass),
+                    Ok(_) => {}
+                    Err(error) => self.state.error_message = Some(error),
+                }
+                Task::none()
+            }
+
+            Message::HostKeysServed(result) => {
+                match result {
+                    Ok(served) => {
+                        // Keys that keep failing are reported the first time only
+                        let new_failures: Vec<String> = served
+                            .failed
+                            .iter()
+                            .filter(|failure| self.state.unservable_keys.insert(failure.identity_id.clone()))
+                            .map(|failure| failure.to_string())
+                            .collect();
+                        if !new_failures.is_empty() {
+                            self.state.error_message =
+                                Some(format!("Some SSH keys are not served by the agent:\n{}", new_failures.join("\n")));
+                        }
+                    }
+                    Err(error) => self.state.error_message = Some(error),
+                }
+                Task::none()
+            }
+
+            Message::LockVault => self.lock_vault(),
+
+            Message::UserActivity => {
+                self.state.last_activity = Instant::now();
+                Task::none()
+            }
+
+            Message::AutoLockTick => {
+                let idle = self.state.last_activity.elapsed();
+                let expired = self.state.auto_lock.duration().is_some_and(|timeout| idle >= timeout);
+                if self.state.vault.is_some() && expired {
+                    return self.lock_vault();
+                }
+                Task::none()
+            }
+
+            Message::AutoLockChanged(timeout) => {
+                self.state.auto_lock = timeout;
+                self.state.settings.auto_lock_minutes = match timeout {
+                    AutoLockTimeout::Never => 0,
+                    AutoLockTimeout::Minutes(minutes) => minutes,
+                };
+                self.save_settings();
+                Task::none()
+            }
+
+            Message::HistoryRetentionChanged(retention) => {
+                self.state.history_retention = retention;
+                self.state.settings.history_retention_days = retention.days();
+                self.save_settings();
+                // Purges right away rather than at the next unlock
+                if self.state.vault.is_some() {
+                    load_host_usage(&self.state)
                 } else {
-                    self.state.state = AppState::PasswordEntry;
-                    self.state.error_message = error;
                     Task::none()
                 }
             }
 
-            Message::HostsLoadResult(success, _error) => {
-                if success {
+            Message::SshIncludeToggled(enabled) => {
+                self.state.settings.ssh_include = enabled;
+                self.save_settings();
+                if enabled {
+                    write_include(&self.state)
+                } else {
                     Task::perform(
-                        async move {
-                            match db::init_db(DB_PATH).await {
-                                Ok(pool) => {
-                                    match db::get_all_hosts(&pool).await {
-                                        Ok(hosts) => hosts,
-                                        Err(_) => Vec::new(),
-                                    }
-                                }
-                                Err(_) => Vec::new(),
-                            }
+                        async {
+                            ssh_config::remove_include(&ssh_config::include_file_path())
+                                .map_err(|e| format!("Failed to remove ssh_config include: {:#}", e))
                         },
-                        Message::HostsLoaded,
+                        Message::SshIncludeWritten,
                     )
-                } else {
-                    Task::none()
                 }
             }
 
+            Message::HostUsageLoaded(result) => {
+                if self.state.vault.is_none() {
+                    return Task::none();
+                }
+                match result {
+                    Ok((recent, frequent)) => {
+                        self.state.recent_hosts = recent;
+                        self.state.frequent_hosts = frequent;
+                    }
+                    Err(error) => self.state.error_message = Some(error),
+                }
+                Task::none()
+            }
+
+            Message::HostHistoryLoaded(history) => {
+                self.state.host_form.history = history;
+                Task::none()
+            }
+
             Message::HostsLoaded(hosts) => {
+                // Results of requests still in flight when the vault was locked
+                if self.state.vault.is_none() {
+                    return Task::none();
+                }
                 self.state.hosts = hosts;
+                // Keep ~/.ssh/nebulavault.conf in sync with the hosts table
+                let write_include = write_include(&self.state);
+                let hosts = &self.state.hosts;
+                self.state.selected_hosts.retain(|id| hosts.iter().any(|host| host.id == *id));
+
+                // Also purges history past the retention period
+                Task::batch([
+                    write_include,
+                    load_identities(&self.state),
+                    load_groups(&self.state),
+                    load_host_usage(&self.state),
+                ])
+            }
+
+            Message::GroupsLoaded(groups) => {
+                if self.state.vault.is_none() {
+                    return Task::none();
+                }
+                let groups_ref = &groups;
+                self.state.collapsed_groups.retain(|id| groups_ref.iter().any(|group| group.id == *id));
+                self.state.groups = groups;
+                Task::none()
+            }
+
+            Message::SshIncludeWritten(result) => {
+                if let Err(error) = result {
+                    self.state.error_message = Some(error);
+                }
+                Task::none()
+            }
+
+            Message::IdentitiesLoaded(identities) => {
+                if self.state.vault.is_none() {
+                    return Task::none();
+                }
+                self.state.identities = identities;
+                serve_host_keys(&self.state)
+            }
+
+            // Folder tree
+            Message::GroupToggled(group_id) => {
+                if !self.state.collapsed_groups.remove(&group_id) {
+                    self.state.collapsed_groups.insert(group_id);
+                }
+                Task::none()
+            }
+
+            Message::ShowAddGroupDialog(parent_id) => {
+                self.state.group_form.clear();
+                self.state.group_form.parent_id = parent_id;
+                self.state.state = AppState::GroupDialog;
+                Task::none()
+            }
+
+            Message::ShowEditGroupDialog(group_id) => {
+                if let Some(group) = self.state.groups.iter().find(|g| g.id == group_id) {
+                    self.state.group_form.clear();
+                    self.state.group_form.editing_id = Some(group.id.clone());
+                    self.state.group_form.name = group.name.clone();
+                    self.state.group_form.parent_id = group.parent_id.clone();
+                    self.state.state = AppState::GroupDialog;
+                }
+                Task::none()
+            }
+
+            Message::ShowGroupDeleteConfirm(group_id) => {
+                self.state.state = AppState::GroupDeleteConfirm(group_id);
+                Task::none()
+            }
+
+            Message::GroupNameChanged(name) => {
+                self.state.group_form.name = name;
+                Task::none()
+            }
+
+            Message::GroupParentChanged(folder) => {
+                self.state.group_form.parent_id = folder.id;
+                Task::none()
+            }
+
+            Message::SaveGroup => {
+                let form = self.state.group_form.clone();
+                let current_parent = form
+                    .editing_id
+                    .as_ref()
+                    .and_then(|id| self.state.groups.iter().find(|g| &g.id == id))
+                    .map(|group| group.parent_id.clone());
+
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
                 Task::perform(
                     async move {
-                        match db::init_db(DB_PATH).await {
-                            Ok(pool) => {
-                                match db::get_all_identities(&pool).await {
-                                    Ok(identities) => identities,
-                                    Err(_) => Vec::new(),
+                        match form.editing_id {
+                            Some(id) => {
+                                db::rename_group(&pool, &id, form.name)
+                                    .await
+                                    .map_err(|e| format!("{:#}", e))?;
+                                if current_parent != Some(form.parent_id.clone()) {
+                                    db::move_group(&pool, &id, form.parent_id)
+                                        .await
+                                        .map_err(|e| format!("{:#}", e))?;
                                 }
                             }
-                            Err(_) => Vec::new(),
+                            None => {
+                                db::create_group(&pool, form.parent_id, form.name)
+                                    .await
+                                    .map_err(|e| format!("{:#}", e))?;
+                            }
                         }
+                        Ok(())
                     },
-                    Message::IdentitiesLoaded,
+                    Message::GroupSaved,
                 )
             }
 
-            Message::IdentitiesLoaded(identities) => {
-                self.state.identities = identities;
-                Task::none()
+            Message::GroupSaved(result) => match result {
+                Ok(()) => {
+                    // Show the folder the group was saved into
+                    if let Some(parent_id) = &self.state.group_form.parent_id {
+                        self.state.collapsed_groups.remove(parent_id);
+                    }
+                    self.state.group_form.clear();
+                    self.state.state = AppState::Ready;
+                    load_hosts(&self.state)
+                }
+                Err(error) => {
+                    self.state.group_form.error = Some(error);
+                    Task::none()
+                }
+            },
+
+            Message::DeleteGroup(group_id) => {
+                self.state.state = AppState::Loading;
+
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        db::delete_group(&pool, &group_id)
+                            .await
+                            .map_err(|e| format!("Failed to delete folder: {}", e))
+                    },
+                    Message::GroupDeleted,
+                )
+            }
+
+            Message::GroupDeleted(result) => {
+                self.state.state = AppState::Ready;
+                if let Err(error) = result {
+                    self.state.error_message = Some(error);
+                }
+                load_hosts(&self.state)
             }
 
             // Host management
@@ -148,7 +586,80 @@ impl NebulaVault {
                     self.state.host_form.port = host.port.to_string();
                     self.state.host_form.username = host.username.clone();
                     self.state.host_form.identity_id = host.identity_id.clone();
+                    self.state.host_form.group_id = host.group_id.clone();
+                    self.state.host_form.tags = host.tags.clone();
+                    self.state.host_form.tag_input.clear();
+                    self.state.host_form.tag_error = None;
+                    self.state.host_form.proxy_jump = host.proxy_jump.clone().unwrap_or_default();
+                    self.state.host_form.local_forwards = host.get_local_forwards().join(", ");
+                    self.state.host_form.known_keys.clear();
+                    self.state.host_form.history.clear();
                     self.state.state = AppState::HostDialog;
+
+                    let Some(pool) = self.state.db_pool.clone() else {
+                        return Task::none();
+                    };
+                    let hostname = host.hostname.to_ascii_lowercase();
+                    let port = host.port;
+                    let known_hosts_pool = pool.clone();
+                    let load_known_keys = Task::perform(
+                        async move {
+                            db::get_known_hosts(&known_hosts_pool, &hostname, port)
+                                .await
+                                .unwrap_or_default()
+                        },
+                        Message::KnownHostKeysLoaded,
+                    );
+
+                    let host_id = host.id.clone();
+                    let load_history = Task::perform(
+                        async move {
+                            db::get_host_history(&pool, &host_id, HOST_HISTORY_LIMIT)
+                                .await
+                                .unwrap_or_default()
+                        },
+                        Message::HostHistoryLoaded,
+                    );
+                    return Task::batch([load_known_keys, load_history]);
+                }
+                Task::none()
+            }
+
+            Message::KnownHostKeysLoaded(known_keys) => {
+                self.state.host_form.known_keys = known_keys;
+                Task::none()
+            }
+
+            Message::ForgetHostKey => {
+                let Some(host) = self
+                    .state
+                    .host_form
+                    .editing_id
+                    .as_ref()
+                    .and_then(|id| self.state.hosts.iter().find(|h| &h.id == id))
+                else {
+                    return Task::none();
+                };
+
+                let hostname = host.hostname.clone();
+                let port = host.port as u16;
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        known_hosts::forget(&pool, &hostname, port)
+                            .await
+                            .map_err(|e| format!("Failed to forget host key: {}", e))
+                    },
+                    Message::HostKeyForgotten,
+                )
+            }
+
+            Message::HostKeyForgotten(result) => {
+                match result {
+                    Ok(_) => self.state.host_form.known_keys.clear(),
+                    Err(error) => self.state.error_message = Some(error),
                 }
                 Task::none()
             }
@@ -159,7 +670,9 @@ impl NebulaVault {
             }
 
             Message::Disconnect => {
+                // Just return to main view
                 self.state.state = AppState::Ready;
+                self.state.ssh_session = None; // Clear session on disconnect
                 Task::none()
             }
 
@@ -210,36 +723,86 @@ impl NebulaVault {
                 Task::none()
             }
 
+            Message::HostGroupChanged(folder) => {
+                self.state.host_form.group_id = folder.id;
+                Task::none()
+            }
+
+            Message::HostTagInputChanged(input) => {
+                self.state.host_form.tag_input = input;
+                self.state.host_form.tag_error = None;
+                Task::none()
+            }
+
+            Message::AddHostTags => {
+                if let Err(error) = add_typed_tags(&mut self.state.host_form) {
+                    self.state.host_form.tag_error = Some(error);
+                }
+                Task::none()
+            }
+
+            Message::AddHostTag(tag) => {
+                let form = &mut self.state.host_form;
+                if !form.tags.contains(&tag) {
+                    form.tags.push(tag);
+                    form.tags.sort();
+                }
+                form.tag_input.clear();
+                form.tag_error = None;
+                Task::none()
+            }
+
+            Message::RemoveHostTag(tag) => {
+                self.state.host_form.tags.retain(|t| *t != tag);
+                Task::none()
+            }
+
+            Message::HostProxyJumpChanged(proxy_jump) => {
+                self.state.host_form.proxy_jump = proxy_jump;
+                Task::none()
+            }
+
+            Message::HostLocalForwardsChanged(local_forwards) => {
+                self.state.host_form.local_forwards = local_forwards;
+                Task::none()
+            }
+
             Message::SaveHost => {
+                // A tag still in the input counts as entered
+                if let Err(error) = add_typed_tags(&mut self.state.host_form) {
+                    self.state.host_form.tag_error = Some(error);
+                    return Task::none();
+                }
                 let editing_id = self.state.host_form.editing_id.clone();
-                let name = self.state.host_form.name.clone();
-                let hostname = self.state.host_form.hostname.clone();
-                let port = self.state.host_form.port.parse::<i64>().unwrap_or(22);
-                let username = self.state.host_form.username.clone();
-                let identity_id = self.state.host_form.identity_id.clone();
+                let form = &self.state.host_form;
+                let host = db::HostInput {
+                    group_id: form.group_id.clone(),
+                    identity_id: form.identity_id.clone(),
+                    name: form.name.clone(),
+                    hostname: form.hostname.clone(),
+                    port: form.port.parse::<i64>().unwrap_or(22),
+                    username: form.username.clone(),
+                    tags: form.tags.clone(),
+                    proxy_jump: Some(form.proxy_jump.trim().to_string()).filter(|p| !p.is_empty()),
+                    local_forwards: form
+                        .local_forwards
+                        .split(',')
+                        .map(|spec| spec.split_whitespace().collect::<Vec<_>>().join(" "))
+                        .filter(|spec| !spec.is_empty())
+                        .collect(),
+                };
 
                 self.state.state = AppState::Loading;
 
+                let Some(store) = self.state.store() else {
+                    return Task::none();
+                };
                 Task::perform(
                     async move {
-                        let pool = match db::init_db(DB_PATH).await {
-                            Ok(p) => p,
-                            Err(e) => return (false, Some(format!("Database error: {}", e))),
-                        };
-
-                        // Check if we're editing or creating
-                        if let Some(id) = editing_id {
-                            // Update existing host
-                            match db::update_host(&pool, &id, name, hostname, port, username, identity_id).await {
-                                Ok(_) => (true, None),
-                                Err(e) => (false, Some(format!("Failed to update host: {}", e))),
-                            }
-                        } else {
-                            // Create new host
-                            match db::create_host(&pool, None, identity_id, name, hostname, port, username, None).await {
-                                Ok(_) => (true, None),
-                                Err(e) => (false, Some(format!("Failed to create host: {}", e))),
-                            }
+                        // Updates the host being edited, or creates a new one
+                        match store.save_host(editing_id.as_deref(), &host).await {
+                            Ok(_) => (true, None),
+                            Err(e) => (false, Some(format!("Failed to save host: {}", e))),
                         }
                     },
                     |(success, error)| Message::HostSaved(success, error),
@@ -249,20 +812,7 @@ impl NebulaVault {
             Message::HostSaved(success, error) => {
                 if success {
                     self.state.state = AppState::Ready;
-                    Task::perform(
-                        async move {
-                            match db::init_db(DB_PATH).await {
-                                Ok(pool) => {
-                                    match db::get_all_hosts(&pool).await {
-                                        Ok(hosts) => hosts,
-                                        Err(_) => Vec::new(),
-                                    }
-                                }
-                                Err(_) => Vec::new(),
-                            }
-                        },
-                        Message::HostsLoaded,
-                    )
+                    load_hosts(&self.state)
                 } else {
                     self.state.state = AppState::Ready;
                     self.state.error_message = error;
@@ -273,14 +823,12 @@ impl NebulaVault {
             Message::DeleteHost(host_id) => {
                 self.state.state = AppState::Loading;
 
+                let Some(store) = self.state.store() else {
+                    return Task::none();
+                };
                 Task::perform(
                     async move {
-                        let pool = match db::init_db(DB_PATH).await {
-                            Ok(p) => p,
-                            Err(e) => return (false, Some(format!("Database error: {}", e))),
-                        };
-
-                        match db::delete_host(&pool, &host_id).await {
+                        match store.delete_host(&host_id).await {
                             Ok(_) => (true, None),
                             Err(e) => (false, Some(format!("Failed to delete host: {}", e))),
                         }
@@ -292,20 +840,7 @@ impl NebulaVault {
             Message::HostDeleted(success, error) => {
                 if success {
                     self.state.state = AppState::Ready;
-                    Task::perform(
-                        async move {
-                            match db::init_db(DB_PATH).await {
-                                Ok(pool) => {
-                                    match db::get_all_hosts(&pool).await {
-                                        Ok(hosts) => hosts,
-                                        Err(_) => Vec::new(),
-                                    }
-                                }
-                                Err(_) => Vec::new(),
-                            }
-                        },
-                        Message::HostsLoaded,
-                    )
+                    load_hosts(&self.state)
                 } else {
                     self.state.state = AppState::Ready;
                     self.state.error_message = error;
@@ -316,7 +851,17 @@ impl NebulaVault {
             // Identity management - simplified for now
             Message::ShowIdentityList => {
                 self.state.state = AppState::IdentityList;
-                Task::none()
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        db::get_agent_sign_log(&pool, AGENT_SIGN_LOG_LIMIT)
+                            .await
+                            .unwrap_or_default()
+                    },
+                    Message::AgentSignLogLoaded,
+                )
             }
 
             Message::ShowAddIdentityDialog => {
@@ -326,27 +871,11 @@ impl NebulaVault {
             }
 
             Message::ShowEditIdentityDialog(identity_id) => {
-                // Load identity from database and populate form
-                self.state.state = AppState::Loading;
-                
-                Task::perform(
-                    async move {
-                        let pool = match db::init_db(DB_PATH).await {
-                            Ok(p) => p,
-                            Err(e) => return Err(format!("Database error: {}", e)),
-                        };
-
-                        match db::get_identity(&pool, &identity_id).await {
-                            Ok(Some(identity)) => Ok(identity),
-                            Ok(None) => Err("Identity not found".to_string()),
-                            Err(e) => Err(format!("Failed to load identity: {}", e)),
-                        }
-                    },
-                    |result| match result {
-                        Ok(identity) => Message::IdentityLoaded(identity),
-                        Err(e) => Message::IdentitySaved(false, Some(e)),
-                    },
-                )
+                // Populate the form from the identities already loaded
+                match self.state.identities.iter().find(|identity| identity.id == identity_id) {
+                    Some(identity) => Task::done(Message::IdentityLoaded(identity.clone())),
+                    None => Task::done(Message::IdentitySaved(false, Some("Identity not found".to_string()))),
+                }
             }
 
             Message::ShowIdentityDeleteConfirm(identity_id) => {
@@ -379,10 +908,22 @@ impl NebulaVault {
                 Task::none()
             }
 
+            Message::IdentityAgentConfirmToggled(confirm) => {
+                self.state.identity_form.agent_confirm = confirm;
+                Task::none()
+            }
+
+            Message::IdentityAgentRestrictHostsToggled(restrict) => {
+                self.state.identity_form.agent_restrict_hosts = restrict;
+                Task::none()
+            }
+
             Message::IdentityLoaded(identity) => {
                 // Populate form with loaded identity
                 self.state.identity_form.editing_id = Some(identity.id);
                 self.state.identity_form.name = identity.name;
+                self.state.identity_form.agent_confirm = identity.agent_confirm;
+                self.state.identity_form.agent_restrict_hosts = identity.agent_restrict_hosts;
                 // Note: We can't decrypt the identity data without re-entering the master password
                 // So the user will need to re-enter the password/key
                 // This is a security feature - we don't want to expose decrypted credentials
@@ -402,6 +943,9 @@ impl NebulaVault {
                 };
 
                 let name = self.state.identity_form.name.clone();
+                let editing_id = self.state.identity_form.editing_id.clone();
+                let agent_confirm = self.state.identity_form.agent_confirm;
+                let agent_restrict_hosts = self.state.identity_form.agent_restrict_hosts;
                 
                 // Create identity data based on type
                 let identity_data = match self.state.identity_form.identity_type {
@@ -416,7 +960,7 @@ impl NebulaVault {
                         let private_key = if key_input.starts_with("~/") || key_input.starts_with('/') {
                             // It's a file path - read the file
                             let expanded_path = if key_input.starts_with("~/") {
-                                if let Some(home) = std::env::var("HOME").ok() {
+                                if let Ok(home) = std::env::var("HOME") {
                                     key_input.replacen("~/", &format!("{}/", home), 1)
                                 } else {
                                     self.state.error_message = Some("Could not expand ~ in path".to_string());
@@ -464,14 +1008,18 @@ impl NebulaVault {
                 self.state.state = AppState::Loading;
 
                 // Now save to database asynchronously
+                let Some(store) = self.state.store() else {
+                    return Task::none();
+                };
+                let identity = db::IdentityInput {
+                    name,
+                    encrypted_data,
+                    agent_confirm,
+                    agent_restrict_hosts,
+                };
                 Task::perform(
                     async move {
-                        let pool = match db::init_db(DB_PATH).await {
-                            Ok(p) => p,
-                            Err(e) => return (false, Some(format!("Database error: {}", e))),
-                        };
-
-                        match db::create_identity(&pool, name, encrypted_data).await {
+                        match store.save_identity(editing_id.as_deref(), &identity).await {
                             Ok(_) => (true, None),
                             Err(e) => (false, Some(format!("Failed to save identity: {}", e))),
                         }
@@ -483,21 +1031,7 @@ impl NebulaVault {
             Message::IdentitySaved(success, error) => {
                 if success {
                     self.state.state = AppState::IdentityList;
-                    // Reload identities
-                    Task::perform(
-                        async move {
-                            match db::init_db(DB_PATH).await {
-                                Ok(pool) => {
-                                    match db::get_all_identities(&pool).await {
-                                        Ok(identities) => identities,
-                                        Err(_) => Vec::new(),
-                                    }
-                                }
-                                Err(_) => Vec::new(),
-                            }
-                        },
-                        Message::IdentitiesLoaded,
-                    )
+                    load_identities(&self.state)
                 } else {
                     self.state.state = AppState::IdentityList;
                     self.state.error_message = error;
@@ -508,14 +1042,12 @@ impl NebulaVault {
             Message::DeleteIdentity(identity_id) => {
                 self.state.state = AppState::Loading;
 
+                let Some(store) = self.state.store() else {
+                    return Task::none();
+                };
                 Task::perform(
                     async move {
-                        let pool = match db::init_db(DB_PATH).await {
-                            Ok(p) => p,
-                            Err(e) => return (false, Some(format!("Database error: {}", e))),
-                        };
-
-                        match db::delete_identity(&pool, &identity_id).await {
+                        match store.delete_identity(&identity_id).await {
                             Ok(_) => (true, None),
                             Err(e) => (false, Some(format!("Failed to delete identity: {}", e))),
                         }
@@ -527,21 +1059,7 @@ impl NebulaVault {
             Message::IdentityDeleted(success, error) => {
                 if success {
                     self.state.state = AppState::IdentityList;
-                    // Reload identities
-                    Task::perform(
-                        async move {
-                            match db::init_db(DB_PATH).await {
-                                Ok(pool) => {
-                                    match db::get_all_identities(&pool).await {
-                                        Ok(identities) => identities,
-                                        Err(_) => Vec::new(),
-                                    }
-                                }
-                                Err(_) => Vec::new(),
-                            }
-                        },
-                        Message::IdentitiesLoaded,
-                    )
+                    load_identities(&self.state)
                 } else {
                     self.state.state = AppState::IdentityList;
                     self.state.error_message = error;
@@ -551,132 +1069,555 @@ impl NebulaVault {
 
             // Connection - Launch external terminal
             Message::Connect(host_id) => {
+                let Some(host) = self.state.hosts.iter().find(|h| h.id == host_id) else {
+                    return Task::none();
+                };
+                self.state.recent_hosts.retain(|id| *id != host_id);
+                self.state.recent_hosts.insert(0, host_id.clone());
+                self.state.selected_host_id = Some(host_id.clone());
+
+                // Hosts behind a jump are checked by ssh itself on the way through
+                if !ssh::can_probe(host) {
+                    return Task::done(Message::ConnectVerified(host_id));
+                }
+                self.state.state = AppState::Loading;
+
+                // Check the server's host key against the known hosts store first
+                let hostname = host.hostname.clone();
+                let port = host.port as u16;
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        ssh::probe_host_key(&pool, &hostname, port)
+                            .await
+                            .map_err(|e| e.to_string())
+                    },
+                    move |result| Message::HostKeyChecked(host_id.clone(), result),
+                )
+            }
+
+            Message::HostKeyChecked(host_id, result) => {
+                let Some(host) = self.state.hosts.iter().find(|h| h.id == host_id) else {
+                    self.state.state = AppState::Ready;
+                    return Task::none();
+                };
+
+                match result {
+                    Ok((_, HostKeyStatus::Trusted)) => Task::done(Message::ConnectVerified(host_id)),
+                    Ok((key, HostKeyStatus::Unknown)) => {
+                        self.state.state = AppState::HostKeyPrompt(PendingHostKey {
+                            host_id,
+                            hostname: host.hostname.clone(),
+                            port: host.port as u16,
+                            key,
+                        });
+                        Task::none()
+                    }
+                    Ok((key, status)) => {
+                        let hint = match status {
+                            HostKeyStatus::Revoked => "",
+                            _ => " If the key was rotated, use \"Forget Host Key\" when editing the host.",
+                        };
+                        let error = HostKeyError {
+                            hostname: host.hostname.clone(),
+                            port: host.port as u16,
+                            key,
+                            status,
+                        };
+                        let error = format!("{}. Connection refused.{}", error, hint);
+                        let attempt = ConnectionAttempt::new(host, &self.state.terminal_preference, Some(error.clone()));
+                        self.state.state = AppState::Ready;
+                        self.state.error_message = Some(error);
+                        log_connections(&self.state, vec![attempt])
+                    }
+                    Err(error) => {
+                        let error = format!("Could not check the host key of {}: {}", host.name, error);
+                        let attempt = ConnectionAttempt::new(host, &self.state.terminal_preference, Some(error.clone()));
+                        self.state.state = AppState::Ready;
+                        self.state.error_message = Some(error);
+                        log_connections(&self.state, vec![attempt])
+                    }
+                }
+            }
+
+            Message::TrustHostKey => {
+                let AppState::HostKeyPrompt(pending) = &self.state.state else {
+                    return Task::none();
+                };
+                let pending = pending.clone();
+                self.state.state = AppState::Loading;
+
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        known_hosts::trust(&pool, &pending.hostname, pending.port, &pending.key)
+                            .await
+                            .map_err(|e| format!("Failed to save host key: {}", e))
+                    },
+                    move |result| Message::HostKeyTrusted(pending.host_id.clone(), result),
+                )
+            }
+
+            Message::HostKeyTrusted(host_id, result) => match result {
+                Ok(()) => Task::done(Message::ConnectVerified(host_id)),
+                Err(error) => {
+                    self.state.state = AppState::Ready;
+                    self.state.error_message = Some(error);
+                    Task::none()
+                }
+            },
+
+            Message::ConnectVerified(host_id) => {
                 if let Some(host) = self.state.hosts.iter().find(|h| h.id == host_id).cloned() {
                     self.state.state = AppState::Loading;
 
-                    // Load encrypted identity from database
+                    // The encrypted identity, as loaded with the hosts
                     if let Some(identity_id) = host.identity_id.clone() {
+                        let Some(pool) = self.state.db_pool.clone() else {
+                            return Task::none();
+                        };
+                        let identity = self.state.identities.iter().find(|i| i.id == identity_id).cloned();
+                        let db_path = self.state.vault_path.clone();
+                        let host_id = host.id.clone();
                         Task::perform(
                             async move {
-                                let pool = match db::init_db(DB_PATH).await {
-                                    Ok(p) => p,
-                                    Err(e) => return (None, None, Some(format!("Database error: {}", e))),
-                                };
-
-                                match db::get_identity(&pool, &identity_id).await {
-                                    Ok(Some(identity)) => (Some(host), Some(identity.encrypted_data), None),
-                                    Ok(None) => (None, None, Some("Identity not found".to_string())),
-                                    Err(e) => (None, None, Some(format!("Failed to load identity: {}", e))),
+                                // Hand ssh the host keys trusted in the vault; a stale file
+                                // must never stand in for them
+                                if let Err(e) = known_hosts::export_file(&pool, &known_hosts::launch_file_path(&db_path)).await {
+                                    return (None, None, Some(format!("Failed to export trusted host keys: {:#}", e)));
+                                }
+
+                                match identity {
+                                    Some(identity) => (Some(host), Some(identity), None),
+                                    None => (None, None, Some("Identity not found".to_string())),
                                 }
                             },
-                            |(host_opt, encrypted_data_opt, error_opt)| {
-                                if let (Some(host), Some(encrypted_data)) = (host_opt, encrypted_data_opt) {
-                                    Message::DecryptAndConnect(host, encrypted_data)
+                            move |(host_opt, identity_opt, error_opt)| {
+                                if let (Some(host), Some(identity)) = (host_opt, identity_opt) {
+                                    Message::DecryptAndConnect(host, Box::new(identity))
                                 } else {
-                                    Message::ConnectionResult(false, error_opt)
+                                    Message::ConnectionResult(host_id.clone(), false, error_opt)
                                 }
                             },
                         )
                     } else {
                         // No identity configured
+                        let error = "No identity configured for this host".to_string();
+                        let attempt = ConnectionAttempt::new(&host, &self.state.terminal_preference, Some(error.clone()));
                         self.state.state = AppState::Ready;
-                        self.state.error_message = Some("No identity configured for this host".to_string());
-                        Task::none()
+                        self.state.error_message = Some(error);
+                        log_connections(&self.state, vec![attempt])
                     }
                 } else {
                     Task::none()
                 }
             }
 
-            Message::DecryptAndConnect(host, encrypted_data) => {
+            Message::DecryptAndConnect(host, identity) => {
                 // Decrypt identity and launch terminal
-                let vault = match &self.state.vault {
-                    Some(v) => v,
-                    None => {
-                        self.state.state = AppState::Error("Vault not available".to_string());
-                        return Task::none();
+                self.state.state = AppState::Ready;
+                // Written just before by the task that led here
+                let known_hosts_file = known_hosts::launch_file_path(&self.state.vault_path);
+
+                let attempt = ConnectionAttempt::new(&host, &self.state.terminal_preference, None);
+                let result = prepare_host(&self.state, host, &identity).and_then(|prepared| {
+                    let connection = prepared.connection(&known_hosts_file);
+                    terminal_launcher::launch_ssh_connection(
+                        &self.state.terminal_preference,
+                        connection.hostname,
+                        connection.port,
+                        connection.username,
+                        &connection.options,
+                    )
+                    .map_err(|e| format!("Failed to launch terminal: {}", e))
+                });
+                let attempt = ConnectionAttempt {
+                    error: result.err(),
+                    ..attempt
+                };
+                if let Some(error) = &attempt.error {
+                    self.state.error_message = Some(error.clone());
+                }
+                log_connections(&self.state, vec![attempt])
+            }
+
+            Message::HostSelectionToggled(host_id, selected) => {
+                self.state.selected_hosts.retain(|id| *id != host_id);
+                if selected {
+                    self.state.selected_hosts.push(host_id);
+                }
+                Task::none()
+            }
+
+            Message::ClearHostSelection => {
+                self.state.selected_hosts.clear();
+                Task::none()
+            }
+
+            Message::TagFilterChanged(filter) => {
+                let parsed = Some(filter.trim())
+                    .filter(|filter| !filter.is_empty())
+                    .map(TagQuery::parse)
+                    .transpose();
+                match parsed {
+                    Ok(query) => {
+                        self.state.tag_query = query;
+                        self.state.tag_filter_error = None;
+                    }
+                    // Keep the last valid filter while the user is still typing
+                    Err(error) => self.state.tag_filter_error = Some(error.to_string()),
+                }
+                self.state.tag_filter = filter;
+                Task::none()
+            }
+
+            Message::SearchQueryChanged(query) => {
+                self.state.search_query = query;
+                // The best match is what Enter connects to
+                self.state.selected_host_id = self.state.listed_hosts().first().map(|host| host.id.clone());
+                scrollable::snap_to(scrollable::Id::new(main_view::HOST_LIST), scrollable::RelativeOffset::START)
+            }
+
+            Message::FocusSearch => text_input::focus(text_input::Id::new(main_view::SEARCH_INPUT)),
+
+            Message::ClearSearch => {
+                self.state.search_query.clear();
+                Task::none()
+            }
+
+            Message::MoveHostSelection(delta) => {
+                let hosts = self.state.listed_hosts();
+                let Some(index) = step_selection(&hosts, self.state.selected_host_id.as_ref(), delta) else {
+                    return Task::none();
+                };
+                let offset = list_offset(index, hosts.len());
+                self.state.selected_host_id = Some(hosts[index].id.clone());
+                scrollable::snap_to(scrollable::Id::new(main_view::HOST_LIST), offset)
+            }
+
+            Message::ConnectSelectedHost => {
+                let hosts = self.state.listed_hosts();
+                let selected = self
+                    .state
+                    .selected_host_id
+                    .as_ref()
+                    .filter(|id| hosts.iter().any(|host| host.id == **id));
+                match selected {
+                    Some(host_id) => Task::done(Message::Connect(host_id.clone())),
+                    None => Task::none(),
+                }
+            }
+
+            Message::KeyPressed(window, key, modifiers) => {
+                let message = match &self.state.launcher {
+                    Some(launcher) if launcher.window == window => launcher_key(key.as_ref()),
+                    _ if self.state.vault.is_some() && matches!(self.state.state, AppState::Ready) => {
+                        host_list_key(key.as_ref(), modifiers)
                     }
+                    _ => None,
                 };
+                message.map_or_else(Task::none, Task::done)
+            }
 
-                match vault.decrypt_identity(&encrypted_data) {
-                    Ok(identity_data) => {
-                        // Launch external terminal with SSH connection
-                        let terminal_pref = self.state.terminal_preference.clone();
-                        
-                        let result = match identity_data {
-                            models::IdentityData::SshKey { private_key, passphrase: _ } => {
-                                // Write key to temp file and launch terminal
-                                match crate::terminal_launcher::write_temp_key(&private_key) {
-                                    Ok(key_path) => {
-                                        let launch_result = crate::terminal_launcher::launch_ssh_connection(
-                                            &terminal_pref,
-                                            &host.hostname,
-                                            host.port as u16,
-                                            &host.username,
-                                            Some(&key_path),
-                                        );
-                                        
-                                        // Clean up temp key file after delay (let SSH read it first)
-                                        let key_path_clone = key_path.clone();
-                                        tokio::spawn(async move {
-                                            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-                                            let _ = crate::terminal_launcher::cleanup_temp_key(&key_path_clone);
-                                        });
-                                        
-                                        launch_result
-                                    }
-                                    Err(e) => Err(e),
-                                }
+            Message::HotkeyInputChanged(shortcut) => {
+                self.state.hotkey_form.input = shortcut;
+                self.state.hotkey_form.error = None;
+                Task::none()
+            }
+
+            Message::SaveHotkey => {
+                let shortcut = self.state.hotkey_form.input.trim().to_string();
+                let result = match &mut self.state.hotkey {
+                    Some(global_hotkey) => global_hotkey.register(&shortcut),
+                    // Still checked, for the desktops that do have global shortcuts
+                    None if shortcut.is_empty() => Ok(()),
+                    None => hotkey::parse_hotkey(&shortcut).map(|_| ()),
+                };
+                match result {
+                    Ok(()) => {
+                        self.state.hotkey_form.input = shortcut.clone();
+                        self.state.hotkey_form.error = None;
+                        self.state.settings.hotkey = shortcut;
+                        self.save_settings();
+                    }
+                    Err(e) => self.state.hotkey_form.error = Some(e),
+                }
+                Task::none()
+            }
+
+            Message::HotkeyPressed(id) => {
+                if !self.state.hotkey.as_ref().is_some_and(|global_hotkey| global_hotkey.is_pressed(id)) {
+                    return Task::none();
+                }
+                // The shortcut toggles the launcher
+                match &self.state.launcher {
+                    Some(launcher) => window::close(launcher.window),
+                    None => self.open_launcher(),
+                }
+            }
+
+            Message::WindowUnfocused(window) => {
+                // Like other launchers it goes away when clicked past
+                match &self.state.launcher {
+                    Some(launcher) if launcher.window == window => window::close(window),
+                    _ => Task::none(),
+                }
+            }
+
+            Message::WindowClosed(window) => {
+                if self.state.main_window == Some(window) {
+                    return iced::exit();
+                }
+                if self.state.launcher.as_ref().is_some_and(|launcher| launcher.window == window) {
+                    self.state.launcher = None;
+                }
+                Task::none()
+            }
+
+            Message::CloseLauncher => match &self.state.launcher {
+                Some(launcher) => window::close(launcher.window),
+                None => Task::none(),
+            },
+
+            Message::LauncherQueryChanged(query) => {
+                if let Some(launcher) = &mut self.state.launcher {
+                    launcher.query = query;
+                    launcher.selected_host_id = None;
+                }
+                scrollable::snap_to(scrollable::Id::new(launcher::LAUNCHER_LIST), scrollable::RelativeOffset::START)
+            }
+
+            Message::LauncherPasswordChanged(password) => {
+                if let Some(launcher) = &mut self.state.launcher {
+                    launcher.password = password;
+                }
+                self.state.error_message = None;
+                Task::none()
+            }
+
+            Message::LauncherUnlock => {
+                let Some(launcher) = &mut self.state.launcher else {
+                    return Task::none();
+                };
+                if self.state.vault.is_some() {
+                    return Task::none();
+                }
+                // Unlocks through the password screen's flow
+                self.state.password_input = std::mem::take(&mut launcher.password);
+                Task::done(Message::UnlockVault)
+            }
+
+            Message::MoveLauncherSelection(delta) => {
+                let results = self.state.launcher_results();
+                let Some(launcher) = &self.state.launcher else {
+                    return Task::none();
+                };
+                let current = launcher.selected(&results).map(|host| &host.id);
+                let Some(index) = step_selection(&results, current, delta) else {
+                    return Task::none();
+                };
+                let selected = results[index].id.clone();
+                let offset = list_offset(index, results.len());
+                if let Some(launcher) = &mut self.state.launcher {
+                    launcher.selected_host_id = Some(selected);
+                }
+                scrollable::snap_to(scrollable::Id::new(launcher::LAUNCHER_LIST), offset)
+            }
+
+            Message::LauncherConnectSelected => {
+                let results = self.state.launcher_results();
+                let selected = self
+                    .state
+                    .launcher
+                    .as_ref()
+                    .and_then(|launcher| launcher.selected(&results));
+                match selected {
+                    Some(host) => Task::done(Message::LauncherConnect(host.id.clone())),
+                    None => Task::none(),
+                }
+            }
+
+            Message::LauncherConnect(host_id) => {
+                let close = match &self.state.launcher {
+                    Some(launcher) => window::close(launcher.window),
+                    None => Task::none(),
+                };
+                Task::batch([close, Task::done(Message::Connect(host_id))])
+            }
+
+            Message::MoveSelectedHosts(folder) => {
+                let host_ids = self.state.selected_hosts.clone();
+                let Some(store) = self.state.store() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        store
+                            .move_hosts(&host_ids, folder.id)
+                            .await
+                            .map_err(|e| format!("Failed to move hosts: {}", e))
+                    },
+                    Message::HostsMoved,
+                )
+            }
+
+            Message::HostsMoved(result) => match result {
+                Ok(()) => {
+                    self.state.selected_hosts.clear();
+                    load_hosts(&self.state)
+                }
+                Err(error) => {
+                    self.state.error_message = Some(error);
+                    Task::none()
+                }
+            },
+
+            Message::BroadcastConnect => {
+                // In host list order, which is the order of the panes
+                let hosts: Vec<models::Host> = self
+                    .state
+                    .hosts
+                    .iter()
+                    .filter(|host| self.state.selected_hosts.contains(&host.id))
+                    .cloned()
+                    .collect();
+                if hosts.is_empty() {
+                    return Task::none();
+                }
+                self.state.state = AppState::Loading;
+
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                let identities = self.state.identities.clone();
+                let launch_file = known_hosts::launch_file_path(&self.state.vault_path);
+                Task::perform(
+                    async move {
+                        // Host keys are only trusted from the single-host prompt, so every
+                        // key must already be known before broadcasting. Hosts behind a jump
+                        // are checked by ssh itself.
+                        let probes = futures::future::join_all(hosts.iter().map(|host| async {
+                            if ssh::can_probe(host) {
+                                Some(ssh::probe_host_key(&pool, &host.hostname, host.port as u16).await)
+                            } else {
+                                None
                             }
-                            models::IdentityData::Password { password: _ } => {
-                                // For password auth, launch terminal without key
-                                // User will need to enter password manually
-                                crate::terminal_launcher::launch_ssh_connection(
-                                    &terminal_pref,
-                                    &host.hostname,
-                                    host.port as u16,
-                                    &host.username,
-                                    None,
-                                )
+                        }))
+                        .await;
+
+                        let mut problems = Vec::new();
+                        let mut ready = Vec::new();
+                        for (host, probe) in hosts.into_iter().zip(probes) {
+                            match probe {
+                                None | Some(Ok((_, HostKeyStatus::Trusted))) => {}
+                                Some(Err(e)) => {
+                                    problems.push(format!("{}: could not check the host key: {}", host.name, e));
+                                    continue;
+                                }
+                                Some(Ok((_, HostKeyStatus::Unknown))) => {
+                                    problems.push(format!(
+                                        "{}: host key not trusted yet; connect to it alone once to verify it",
+                                        host.name
+                                    ));
+                                    continue;
+                                }
+                                Some(Ok((key, status))) => {
+                                    let error = HostKeyError {
+                                        hostname: host.hostname.clone(),
+                                        port: host.port as u16,
+                                        key,
+                                        status,
+                                    };
+                                    problems.push(format!("{}: {}", host.name, error));
+                                    continue;
+                                }
                             }
-                        };
 
-                        self.state.state = AppState::Ready;
-                        match result {
-                            Ok(_) => {
-                                // Success - terminal launched
-                                Task::none()
-                            }
-                            Err(e) => {
-                                self.state.error_message = Some(format!("Failed to launch terminal: {}", e));
-                                Task::none()
+                            let identity = host
+                                .identity_id
+                                .as_ref()
+                                .and_then(|identity_id| identities.iter().find(|i| i.id == *identity_id).cloned());
+                            match identity {
+                                Some(identity) => ready.push((host, identity)),
+                                None => problems.push(format!("{}: no identity configured", host.name)),
                             }
                         }
-                    }
-                    Err(e) => {
-                        self.state.state = AppState::Ready;
-                        self.state.error_message = Some(format!("Failed to decrypt identity: {}", e));
-                        Task::none()
-                    }
-                }
+                        if !problems.is_empty() {
+                            return Err(format!("Not connecting to the selected hosts:\n{}", problems.join("\n")));
+                        }
+
+                        known_hosts::export_file(&pool, &launch_file)
+                            .await
+                            .map_err(|e| format!("Failed to export trusted host keys: {:#}", e))?;
+                        Ok(ready)
+                    },
+                    Message::BroadcastReady,
+                )
             }
 
-            Message::ConnectionResult(success, error) => {
+            Message::BroadcastReady(result) => {
                 self.state.state = AppState::Ready;
-                if !success {
-                    self.state.error_message = error;
+                // Written just before by the task that led here
+                let known_hosts_file = known_hosts::launch_file_path(&self.state.vault_path);
+
+                // Hosts refused before the launch are reported, not logged
+                let mut attempts = Vec::new();
+                let result = result
+                    .and_then(|ready| {
+                        ready
+                            .into_iter()
+                            .map(|(host, identity)| {
+                                attempts.push(ConnectionAttempt::new(&host, &self.state.terminal_preference, None));
+                                prepare_host(&self.state, host, &identity)
+                            })
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .and_then(|prepared| {
+                        let connections: Vec<_> = prepared
+                            .iter()
+                            .map(|prepared| prepared.connection(&known_hosts_file))
+                            .collect();
+                        terminal_launcher::launch_ssh_broadcast(&self.state.terminal_preference, &connections)
+                            .map_err(|e| format!("Failed to launch terminal: {}", e))
+                    });
+                match result {
+                    Ok(()) => self.state.selected_hosts.clear(),
+                    Err(error) => {
+                        for attempt in &mut attempts {
+                            attempt.error = Some(error.clone());
+                        }
+                        self.state.error_message = Some(error);
+                    }
                 }
-                Task::none()
+                log_connections(&self.state, attempts)
             }
 
-            Message::Disconnect => {
-                // Just return to main view
+            Message::ConnectionResult(host_id, success, error) => {
                 self.state.state = AppState::Ready;
-                self.state.ssh_session = None; // Clear session on disconnect
-                Task::none()
+                if success {
+                    return Task::none();
+                }
+                self.state.error_message = error.clone();
+                match self.state.hosts.iter().find(|host| host.id == host_id) {
+                    Some(host) => {
+                        let error = error.unwrap_or_else(|| "Connection failed".to_string());
+                        let attempt = ConnectionAttempt::new(host, &self.state.terminal_preference, Some(error));
+                        log_connections(&self.state, vec![attempt])
+                    }
+                    None => Task::none(),
+                }
             }
 
             Message::ShowSettings => {
+                self.state.error_message = None;
+                self.state.password_change_form.clear();
+                self.state.known_hosts_form.status = None;
+                self.state.ssh_config_form.status = None;
+                self.state.ssh_config_form.plan = None;
                 self.state.state = AppState::Settings;
                 Task::none()
             }
@@ -687,18 +1628,880 @@ impl NebulaVault {
             }
 
             Message::TerminalPreferenceChanged(terminal) => {
+                self.save_terminal_setting(Some(&terminal));
                 self.state.terminal_preference = terminal;
                 Task::none()
             }
-        }
-    }
 
-    pub fn view(&self) -> Element<'_, Message> {
-        super::views::render(&self.state)
-    }
+            Message::MultiplexerSessionChanged(session) => {
+                if let TerminalApp::Multiplexer(target) = &mut self.state.terminal_preference {
+                    target.session = session.clone();
+                }
+                self.state.settings.terminal.session = session.clone();
+                self.state.multiplexer_session = session;
+                self.save_settings();
+                Task::none()
+            }
 
-    pub fn subscription(&self) -> Subscription<Message> {
-        // No subscriptions needed
-        Subscription::none()
+            Message::TerminalTemplatesLoaded(result) => {
+                match result {
+                    Ok(templates) => self.state.terminal_templates = templates,
+                    Err(error) => self.state.terminal_template_form.status = Some(error),
+                }
+                Task::none()
+            }
+
+            Message::TerminalTemplateNameChanged(name) => {
+                self.state.terminal_template_form.name = name;
+                Task::none()
+            }
+
+            Message::TerminalTemplateCommandChanged(command) => {
+                self.state.terminal_template_form.command = command;
+                Task::none()
+            }
+
+            Message::EditTerminalTemplate(template) => {
+                let form = &mut self.state.terminal_template_form;
+                form.command = template.command_line();
+                form.name = template.name;
+                form.status = None;
+                Task::none()
+            }
+
+            Message::SaveTerminalTemplate => {
+                let form = &mut self.state.terminal_template_form;
+                let template = match TerminalTemplate::parse(&form.name, &form.command) {
+                    Ok(template) => template,
+                    Err(e) => {
+                        form.status = Some(format!("{:#}", e));
+                        return Task::none();
+                    }
+                };
+
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        db::save_terminal_template(&pool, &template)
+                            .await
+                            .map_err(|e| format!("{:#}", e))?;
+                        db::get_terminal_templates(&pool)
+                            .await
+                            .map(|templates| (templates, format!("Saved \"{}\"", template.name)))
+                            .map_err(|e| format!("{:#}", e))
+                    },
+                    Message::TerminalTemplatesUpdated,
+                )
+            }
+
+            Message::DeleteTerminalTemplate(name) => {
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        db::delete_terminal_template(&pool, &name)
+                            .await
+                            .map_err(|e| format!("{:#}", e))?;
+                        db::get_terminal_templates(&pool)
+                            .await
+                            .map(|templates| (templates, format!("Deleted \"{}\"", name)))
+                            .map_err(|e| format!("{:#}", e))
+                    },
+                    Message::TerminalTemplatesUpdated,
+                )
+            }
+
+            Message::TerminalTemplatesUpdated(result) => {
+                match result {
+                    Ok((templates, status)) => {
+                        // Keep the preferred terminal in step with its saved template
+                        if let TerminalApp::Custom(current) = &self.state.terminal_preference {
+                            let was_saved = self.state.terminal_templates.iter().any(|t| t.name == current.name);
+                            match templates.iter().find(|t| t.name == current.name) {
+                                Some(template) if template != current => {
+                                    let terminal = TerminalApp::Custom(template.clone());
+                                    self.save_terminal_setting(Some(&terminal));
+                                    self.state.terminal_preference = terminal;
+                                }
+                                // The preferred template was deleted
+                                None if was_saved => {
+                                    self.save_terminal_setting(None);
+                                    self.state.terminal_preference = TerminalApp::default();
+                                }
+                                _ => {}
+                            }
+                        }
+                        self.state.terminal_templates = templates;
+                        self.state.terminal_template_form.status = Some(status);
+                    }
+                    Err(error) => self.state.terminal_template_form.status = Some(error),
+                }
+                Task::none()
+            }
+
+            Message::TestTerminalTemplate => {
+                let form = &mut self.state.terminal_template_form;
+                form.status = Some(
+                    match TerminalTemplate::parse(&form.name, &form.command)
+                        .and_then(|template| terminal_launcher::launch_test(&TerminalApp::Custom(template)))
+                    {
+                        Ok(()) => "Test window launched".to_string(),
+                        Err(e) => format!("Test failed: {:#}", e),
+                    },
+                );
+                Task::none()
+            }
+
+            Message::CurrentPasswordChanged(password) => {
+                self.state.password_change_form.current = password;
+                self.state.password_change_form.error = None;
+                Task::none()
+            }
+
+            Message::NewPasswordChanged(password) => {
+                self.state.password_change_form.new = password;
+                self.state.password_change_form.error = None;
+                Task::none()
+            }
+
+            Message::ConfirmPasswordChanged(password) => {
+                self.state.password_change_form.confirm = password;
+                self.state.password_change_form.error = None;
+                Task::none()
+            }
+
+            Message::ChangeMasterPassword => {
+                let form = &mut self.state.password_change_form;
+                form.changed = false;
+
+                if form.current.is_empty() || form.new.is_empty() {
+                    form.error = Some("Password cannot be empty".to_string());
+                    return Task::none();
+                }
+                if form.new != form.confirm {
+                    form.error = Some("New passwords do not match".to_string());
+                    return Task::none();
+                }
+
+                let old_password = form.current.clone();
+                let new_password = form.new.clone();
+                self.state.state = AppState::Loading;
+
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                let daemon = self.state.daemon.clone();
+                Task::perform(
+                    async move {
+                        let Some(daemon) = daemon else {
+                            return Vault::change_password(&pool, &old_password, &new_password)
+                                .await
+                                .map(Arc::new)
+                                .map_err(|e| format!("Failed to change master password: {}", e));
+                        };
+                        // The daemon re-keys the vault and starts its session over
+                        // with the new key, then this window unlocks with it too
+                        daemon
+                            .lock()
+                            .await
+                            .call(
+                                "change_password",
+                                json!({ "old_password": old_password, "new_password": new_password }),
+                            )
+                            .await
+                            .map_err(|e| format!("Failed to change master password: {}", e))?;
+                        Vault::unlock(&pool, &new_password)
+                            .await
+                            .map(Arc::new)
+                            .map_err(|e| format!("Failed to unlock with the new master password: {}", e))
+                    },
+                    Message::MasterPasswordChanged,
+                )
+            }
+
+            Message::KdfPresetSelected(preset) => {
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        db::set_kdf_preset(&pool, preset.name())
+                            .await
+                            .map(|_| preset)
+                            .map_err(|e| format!("Failed to save KDF preset: {}", e))
+                    },
+                    Message::KdfPresetSaved,
+                )
+            }
+
+            Message::KdfPresetSaved(result) => {
+                match result {
+                    Ok(preset) => self.state.kdf_preset = Some(preset),
+                    Err(error) => self.state.error_message = Some(error),
+                }
+                Task::none()
+            }
+
+            Message::CalibrateKdf => {
+                self.state.kdf_calibrating = true;
+                Task::perform(
+                    async move {
+                        tokio::task::spawn_blocking(KdfPreset::calibrate)
+                            .await
+                            .map_err(|e| e.to_string())?
+                            .map_err(|e| format!("Calibration failed: {}", e))
+                    },
+                    Message::KdfCalibrated,
+                )
+            }
+
+            Message::KdfCalibrated(result) => {
+                self.state.kdf_calibrating = false;
+                match result {
+                    Ok(timings) => self.state.kdf_timings = timings,
+                    Err(error) => self.state.error_message = Some(error),
+                }
+                Task::none()
+            }
+
+            Message::MasterPasswordChanged(result) => {
+                self.state.state = AppState::Settings;
+                match result {
+                    Ok(vault) => {
+                        self.state.vault = Some(vault);
+                        self.state.password_change_form.clear();
+                        self.state.password_change_form.changed = true;
+                    }
+                    Err(error) => {
+                        self.state.password_change_form.error = Some(error);
+                    }
+                }
+                Task::none()
+            }
+
+            Message::KnownHostsImportPathChanged(path) => {
+                self.state.known_hosts_form.import_path = path;
+                Task::none()
+            }
+
+            Message::KnownHostsExportPathChanged(path) => {
+                self.state.known_hosts_form.export_path = path;
+                Task::none()
+            }
+
+            Message::ImportKnownHosts => {
+                let path = expand_home(&self.state.known_hosts_form.import_path);
+                self.state.known_hosts_form.status = Some("Importing...".to_string());
+
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        known_hosts::import_file(&pool, &path)
+                            .await
+                            .map_err(|e| format!("Import failed: {:#}", e))
+                    },
+                    Message::KnownHostsImported,
+                )
+            }
+
+            Message::KnownHostsImported(result) => {
+                self.state.known_hosts_form.status = Some(match result {
+                    Ok(summary) => summary.to_string(),
+                    Err(error) => error,
+                });
+                Task::none()
+            }
+
+            Message::ExportKnownHosts => {
+                let path = expand_home(&self.state.known_hosts_form.export_path);
+                self.state.known_hosts_form.status = Some("Exporting...".to_string());
+
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        known_hosts::export_file(&pool, &path)
+                            .await
+                            .map(|_| path.display().to_string())
+                            .map_err(|e| format!("Export failed: {:#}", e))
+                    },
+                    Message::KnownHostsExported,
+                )
+            }
+
+            Message::KnownHostsExported(result) => {
+                self.state.known_hosts_form.status = Some(match result {
+                    Ok(path) => format!("Exported to {}", path),
+                    Err(error) => error,
+                });
+                Task::none()
+            }
+
+            Message::SshConfigPathChanged(path) => {
+                self.state.ssh_config_form.path = path;
+                Task::none()
+            }
+
+            Message::PreviewSshConfig => {
+                let path = expand_home(&self.state.ssh_config_form.path);
+                self.state.ssh_config_form.status = Some("Reading...".to_string());
+
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        let config = SshConfig::parse_file(&path).map_err(|e| format!("{:#}", e))?;
+                        ssh_config::plan(&pool, &config)
+                            .await
+                            .map_err(|e| format!("Failed to prepare import: {:#}", e))
+                    },
+                    Message::SshConfigPreviewReady,
+                )
+            }
+
+            Message::SshConfigPreviewReady(result) => {
+                match result {
+                    Ok(plan) if plan.hosts.is_empty() => {
+                        self.state.ssh_config_form.status = Some("No hosts found".to_string());
+                    }
+                    Ok(plan) => {
+                        self.state.ssh_config_form.status = None;
+                        self.state.ssh_config_form.plan = Some(plan);
+                        self.state.state = AppState::SshConfigPreview;
+                    }
+                    Err(error) => {
+                        self.state.ssh_config_form.status = Some(error);
+                    }
+                }
+                Task::none()
+            }
+
+            Message::ImportSshConfig => {
+                let Some(plan) = self.state.ssh_config_form.plan.take() else {
+                    return Task::none();
+                };
+                let Some(vault) = self.state.vault.clone() else {
+                    return Task::none();
+                };
+                self.state.state = AppState::Loading;
+
+                let Some(pool) = self.state.db_pool.clone() else {
+                    return Task::none();
+                };
+                Task::perform(
+                    async move {
+                        ssh_config::apply(&pool, &vault, &plan)
+                            .await
+                            .map_err(|e| format!("Import failed: {:#}", e))
+                    },
+                    Message::SshConfigImported,
+                )
+            }
+
+            Message::SshConfigImported(result) => {
+                self.state.state = AppState::Settings;
+                match result {
+                    Ok(summary) => {
+                        self.state.ssh_config_form.status = Some(summary.to_string());
+                        load_hosts(&self.state)
+                    }
+                    Err(error) => {
+                        self.state.ssh_config_form.status = Some(error);
+                        Task::none()
+                    }
+                }
+            }
+
+            Message::AgentConfirmRequested(request) => {
+                self.state.agent_confirmations.push(request);
+                Task::none()
+            }
+
+            Message::ResolveAgentConfirm(request_id, approved) => {
+                if let Some(agent) = &self.state.agent {
+                    agent.resolve_confirmation(request_id, approved);
+                }
+                self.state.agent_confirmations.retain(|request| request.id != request_id);
+                let Some(daemon) = self.state.daemon.clone() else {
+                    return Task::none();
+                };
+
+                Task::perform(
+                    async move {
+                        daemon
+                            .lock()
+                            .await
+                            .call("agent.resolve", json!({ "id": request_id, "approved": approved }))
+                            .await
+                            .map(|_| ())
+                            .map_err(|e| format!("Failed to answer the daemon: {}", e))
+                    },
+                    Message::DaemonCalled,
+                )
+            }
+
+            Message::PruneAgentConfirmations => {
+                // Drop prompts the agent already gave up on; the daemon's are
+                // replaced as a whole when polled
+                match (&self.state.agent, &self.state.daemon) {
+                    (Some(agent), _) => self
+                        .state
+                        .agent_confirmations
+                        .retain(|request| agent.is_confirmation_pending(request.id)),
+                    (None, Some(_)) => {}
+                    (None, None) => self.state.agent_confirmations.clear(),
+                }
+                Task::none()
+            }
+
+            Message::PollDaemonConfirmations => {
+                let Some(daemon) = self.state.daemon.clone() else {
+                    return Task::none();
+                };
+
+                Task::perform(
+                    async move {
+                        let requests = daemon
+                            .lock()
+                            .await
+                            .call("agent.confirmations", Value::Null)
+                            .await
+                            .map_err(|e| format!("Failed to ask the daemon for signing requests: {}", e))?;
+                        serde_json::from_value(requests).map_err(|e| format!("Invalid signing requests from the daemon: {}", e))
+                    },
+                    Message::DaemonConfirmationsLoaded,
+                )
+            }
+
+            Message::DaemonConfirmationsLoaded(result) => {
+                // Results of requests still in flight when the vault was locked
+                if self.state.daemon.is_none() {
+                    return Task::none();
+                }
+                match result {
+                    Ok(requests) => self.state.agent_confirmations = requests,
+                    Err(error) => self.state.error_message = Some(error),
+                }
+                Task::none()
+            }
+
+            Message::DaemonCalled(result) => {
+                if let Err(error) = result {
+                    self.state.error_message = Some(error);
+                }
+                Task::none()
+            }
+
+            Message::AgentSignLogLoaded(records) => {
+                self.state.agent_sign_log = records;
+                Task::none()
+            }
+        }
+    }
+
+    pub fn view(&self, window: window::Id) -> Element<'_, Message> {
+        match &self.state.launcher {
+            Some(launcher) if launcher.window == window => launcher::view_launcher(&self.state, launcher),
+            _ => super::views::render(&self.state),
+        }
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        let mut subscriptions = Vec::new();
+
+        // Idle tracking only matters while the vault is unlocked
+        if self.state.vault.is_some() && self.state.auto_lock.duration().is_some() {
+            subscriptions.push(iced::event::listen_with(|event, _status, _window| match event {
+                Event::Mouse(_) | Event::Keyboard(_) => Some(Message::UserActivity),
+                _ => None,
+            }));
+            subscriptions.push(iced::time::every(AUTO_LOCK_CHECK_INTERVAL).map(|_| Message::AutoLockTick));
+        }
+
+        // Keyboard navigation of the host lists; keys typed into an input
+        // never get here, except Escape, which an input only uses to unfocus
+        if (self.state.vault.is_some() && matches!(self.state.state, AppState::Ready)) || self.state.launcher.is_some() {
+            subscriptions.push(iced::event::listen_with(|event, status, window| match event {
+                Event::Keyboard(keyboard::Event::KeyPressed { key, modifiers, .. })
+                    if status == event::Status::Ignored || key == Key::Named(key::Named::Escape) =>
+                {
+                    Some(Message::KeyPressed(window, key, modifiers))
+                }
+                _ => None,
+            }));
+        }
+
+        subscriptions.push(iced::event::listen_with(|event, _status, window| match event {
+            Event::Window(window::Event::Closed) => Some(Message::WindowClosed(window)),
+            Event::Window(window::Event::Unfocused) => Some(Message::WindowUnfocused(window)),
+            _ => None,
+        }));
+        if self.state.hotkey.is_some() {
+            subscriptions.push(Subscription::run(hotkey::presses).map(Message::HotkeyPressed));
+        }
+
+        if let Some(agent) = &self.state.agent {
+            subscriptions.push(
+                Subscription::run_with_id(agent.instance(), agent.confirmation_requests())
+                    .map(Message::AgentConfirmRequested),
+            );
+        }
+        if self.state.daemon.is_some() {
+            subscriptions
+                .push(iced::time::every(DAEMON_CONFIRM_POLL_INTERVAL).map(|_| Message::PollDaemonConfirmations));
+        }
+        if !self.state.agent_confirmations.is_empty() {
+            subscriptions
+                .push(iced::time::every(AGENT_CONFIRM_PRUNE_INTERVAL).map(|_| Message::PruneAgentConfirmations));
+        }
+
+        Subscription::batch(subscriptions)
+    }
+}
+
+/// Expand a leading `~/` to the home directory
+fn expand_home(path: &str) -> std::path::PathBuf {
+    match (path.trim().strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => std::path::PathBuf::from(home).join(rest),
+        _ => std::path::PathBuf::from(path.trim()),
+    }
+}
+
+/// Shortcuts of the main window's host list
+fn host_list_key(key: Key<&str>, modifiers: Modifiers) -> Option<Message> {
+    match key {
+        Key::Named(key::Named::ArrowDown) => Some(Message::MoveHostSelection(1)),
+        Key::Named(key::Named::ArrowUp) => Some(Message::MoveHostSelection(-1)),
+        Key::Named(key::Named::PageDown) => Some(Message::MoveHostSelection(10)),
+        Key::Named(key::Named::PageUp) => Some(Message::MoveHostSelection(-10)),
+        Key::Named(key::Named::Enter) => Some(Message::ConnectSelectedHost),
+        Key::Named(key::Named::Escape) => Some(Message::ClearSearch),
+        Key::Character("/") => Some(Message::FocusSearch),
+        Key::Character("f") if modifiers.command() => Some(Message::FocusSearch),
+        _ => None,
+    }
+}
+
+/// Shortcuts of the launcher window
+fn launcher_key(key: Key<&str>) -> Option<Message> {
+    match key {
+        Key::Named(key::Named::ArrowDown) => Some(Message::MoveLauncherSelection(1)),
+        Key::Named(key::Named::ArrowUp) => Some(Message::MoveLauncherSelection(-1)),
+        Key::Named(key::Named::PageDown) => Some(Message::MoveLauncherSelection(10)),
+        Key::Named(key::Named::PageUp) => Some(Message::MoveLauncherSelection(-10)),
+        Key::Named(key::Named::Escape) => Some(Message::CloseLauncher),
+        _ => None,
+    }
+}
+
+/// Index of the host `delta` places from the selected one, clamped to the
+/// list; without a selection down starts at the top and up at the bottom
+fn step_selection(hosts: &[&models::Host], selected: Option<&String>, delta: isize) -> Option<usize> {
+    let last = hosts.len().checked_sub(1)?;
+    let current = selected.and_then(|id| hosts.iter().position(|host| host.id == *id));
+    Some(match current {
+        Some(index) => index.saturating_add_signed(delta).min(last),
+        None if delta < 0 => last,
+        None => 0,
+    })
+}
+
+/// Scroll position that keeps entry `index` of `len` roughly in view; rows
+/// differ in height, so it is only approximate
+fn list_offset(index: usize, len: usize) -> scrollable::RelativeOffset {
+    let offset = if len <= 1 { 0.0 } else { index as f32 / (len - 1) as f32 };
+    scrollable::RelativeOffset { x: 0.0, y: offset }
+}
+
+/// How ssh authenticates to a prepared host
+enum HostAuth {
+    /// The key is served on this agent socket
+    Agent(PathBuf),
+    /// ssh fetches the password through `nebulavault askpass`
+    Askpass {
+        program: PathBuf,
+        socket: PathBuf,
+        token: String,
+    },
+    /// ssh asks for the password on the terminal
+    Prompt,
+}
+
+/// A host whose identity has been handed to the agent or the askpass helper
+struct PreparedHost {
+    host: models::Host,
+    local_forwards: Vec<String>,
+    auth: HostAuth,
+}
+
+impl PreparedHost {
+    fn connection<'a>(&'a self, known_hosts_file: &'a Path) -> Connection<'a> {
+        let mut options = SshOptions {
+            agent_socket: None,
+            known_hosts_file: Some(known_hosts_file),
+            proxy_jump: self.host.proxy_jump.as_deref(),
+            local_forwards: &self.local_forwards,
+            askpass: None,
+            title: Some(&self.host.name),
+        };
+        match &self.auth {
+            HostAuth::Agent(socket) => options.agent_socket = Some(socket),
+            HostAuth::Askpass { program, socket, token } => {
+                options.askpass = Some(AskpassOptions { program, socket, token })
+            }
+            HostAuth::Prompt => {}
+        }
+
+        Connection {
+            hostname: &self.host.hostname,
+            port: self.host.port as u16,
+            username: &self.host.username,
+            options,
+        }
+    }
+}
+
+/// Decrypt `identity` and make its secret available to the ssh launched for `host`
+fn prepare_host(state: &NebulaVaultState, host: models::Host, identity: &models::Identity) -> Result<PreparedHost, String> {
+    let vault = state.vault.as_ref().ok_or("Vault not available")?;
+    let identity_data = vault
+        .decrypt_identity(&identity.encrypted_data)
+        .map_err(|e| format!("Failed to decrypt identity: {}", e))?;
+
+    let auth = match identity_data {
+        // The daemon's agent serves the key of every host on its host socket
+        models::IdentityData::SshKey { .. } if state.daemon.is_some() => {
+            HostAuth::Agent(SshAgent::host_socket_path(&daemon::agent_socket_path(), &host.id))
+        }
+        models::IdentityData::SshKey { private_key, passphrase } => {
+            // Hand the key to the in-memory agent and let ssh use it from
+            // a socket bound to this host
+            let policy = KeyPolicy {
+                confirm: identity.agent_confirm,
+                restrict_to_linked_hosts: identity.agent_restrict_hosts,
+            };
+            let agent = state.agent.as_ref().ok_or("SSH agent is not running")?;
+            agent
+                .add_identity(&identity.id, &identity.name, &private_key, passphrase.as_deref(), policy)
+                .and_then(|_| agent.host_socket(&host.id))
+                .map(HostAuth::Agent)
+                .map_err(|e| format!("Failed to add key to the SSH agent: {}", e))?
+        }
+        models::IdentityData::Password { password } => {
+            // ssh asks `nebulavault askpass` for the password, which
+            // fetches it once with a token only this ssh process knows
+            match (&state.askpass, std::env::current_exe()) {
+                (Some(askpass), Ok(program)) => HostAuth::Askpass {
+                    program,
+                    socket: askpass.socket_path().to_path_buf(),
+                    token: askpass.issue(&password),
+                },
+                // Without the helper the user types the password
+                _ => HostAuth::Prompt,
+            }
+        }
+    };
+
+    Ok(PreparedHost {
+        local_forwards: host.get_local_forwards(),
+        host,
+        auth,
+    })
+}
+
+/// Move the tags typed in the host dialog (comma or space separated) to its tag list
+fn add_typed_tags(form: &mut HostForm) -> Result<(), String> {
+    let typed: Vec<&str> = form
+        .tag_input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    let typed = tags::normalize_tags(&typed).map_err(|e| e.to_string())?;
+    for tag in typed {
+        if !form.tags.contains(&tag) {
+            form.tags.push(tag);
+        }
+    }
+    form.tags.sort();
+    form.tag_input.clear();
+    form.tag_error = None;
+    Ok(())
+}
+
+/// Reload the hosts, which reloads the groups and identities after them
+fn load_hosts(state: &NebulaVaultState) -> Task<Message> {
+    let Some(store) = state.store() else {
+        return Task::none();
+    };
+    Task::perform(
+        async move { store.hosts().await.unwrap_or_default() },
+        Message::HostsLoaded,
+    )
+}
+
+fn load_identities(state: &NebulaVaultState) -> Task<Message> {
+    let Some(store) = state.store() else {
+        return Task::none();
+    };
+    Task::perform(
+        async move { store.identities().await.unwrap_or_default() },
+        Message::IdentitiesLoaded,
+    )
+}
+
+fn load_groups(state: &NebulaVaultState) -> Task<Message> {
+    let Some(pool) = state.db_pool.clone() else {
+        return Task::none();
+    };
+    Task::perform(
+        async move { db::get_all_groups(&pool).await.unwrap_or_default() },
+        Message::GroupsLoaded,
+    )
+}
+
+/// Recent and frequent hosts, after purging the history past its retention period
+async fn host_usage(pool: &SqlitePool, retention_days: u64) -> Result<(Vec<String>, Vec<models::HostUsage>), String> {
+    db::purge_connection_history(pool, retention_days)
+        .await
+        .map_err(|e| format!("{:#}", e))?;
+    let recent = db::get_recent_hosts(pool, HOST_USAGE_LIMIT)
+        .await
+        .map_err(|e| format!("{:#}", e))?;
+    let frequent = db::get_frequent_hosts(pool, HOST_USAGE_LIMIT)
+        .await
+        .map_err(|e| format!("{:#}", e))?;
+    Ok((recent, frequent))
+}
+
+/// Reload the recent and frequent hosts
+fn load_host_usage(state: &NebulaVaultState) -> Task<Message> {
+    log_connections(state, Vec::new())
+}
+
+/// A connection attempt for the connection history
+struct ConnectionAttempt {
+    host_id: String,
+    identity_id: Option<String>,
+    target: String,
+    error: Option<String>,
+}
+
+impl ConnectionAttempt {
+    fn new(host: &models::Host, terminal: &TerminalApp, error: Option<String>) -> Self {
+        Self {
+            host_id: host.id.clone(),
+            identity_id: host.identity_id.clone(),
+            target: terminal.config_name().unwrap_or(terminal.display_name()).to_string(),
+            error,
+        }
+    }
+}
+
+/// Add connection attempts to the history, then reload the recent and
+/// frequent hosts
+fn log_connections(state: &NebulaVaultState, attempts: Vec<ConnectionAttempt>) -> Task<Message> {
+    let Some(pool) = state.db_pool.clone() else {
+        return Task::none();
+    };
+    let retention_days = state.history_retention.days();
+    Task::perform(
+        async move {
+            for attempt in &attempts {
+                db::log_connection(
+                    &pool,
+                    &attempt.host_id,
+                    attempt.identity_id.as_deref(),
+                    &attempt.target,
+                    attempt.error.as_deref(),
+                )
+                .await
+                .map_err(|e| format!("{:#}", e))?;
+            }
+            host_usage(&pool, retention_days).await
+        },
+        Message::HostUsageLoaded,
+    )
+}
+
+/// Connect to the daemon when it serves the vault at `vault_path` and unlock it
+/// with `password`; None when no daemon is running or it serves another vault
+async fn connect_daemon(vault_path: &Path, password: &str) -> Result<Option<DaemonConnection>, String> {
+    let Ok(mut client) = DaemonClient::connect(&daemon::socket_path()).await else {
+        return Ok(None);
+    };
+    let status = client
+        .call("status", Value::Null)
+        .await
+        .map_err(|e| format!("Failed to query the daemon: {}", e))?;
+    if status["vault"].as_str().map(Path::new) != Some(vault_path) {
+        return Ok(None);
+    }
+
+    client
+        .call("unlock", json!({ "password": password }))
+        .await
+        .map_err(|e| format!("Failed to unlock the daemon: {}", e))?;
+    Ok(Some(Arc::new(Mutex::new(client))))
+}
+
+/// Write the loaded hosts to `~/.ssh/nebulavault.conf`, when the include is
+/// turned on and the default vault is unlocked
+fn write_include(state: &NebulaVaultState) -> Task<Message> {
+    if state.vault.is_none() || !ssh_config::include_enabled(&state.settings, &state.vault_path) {
+        return Task::none();
+    }
+    let hosts = state.hosts.clone();
+    let agent_socket = match &state.daemon {
+        Some(_) => daemon::agent_socket_path(),
+        None => SshAgent::default_socket_path(),
+    };
+
+    Task::perform(
+        async move {
+            ssh_config::write_include(&ssh_config::include_file_path(), &hosts, &agent_socket)
+                .map_err(|e| format!("Failed to update ssh_config include: {:#}", e))
+        },
+        Message::SshIncludeWritten,
+    )
+}
+
+/// Serve the keys of the loaded hosts for `~/.ssh/nebulavault.conf` in the background
+fn serve_host_keys(state: &NebulaVaultState) -> Task<Message> {
+    if let Some(daemon) = state.daemon.clone() {
+        return Task::perform(
+            async move {
+                let served = daemon
+                    .lock()
+                    .await
+                    .call("agent.serve", Value::Null)
+                    .await
+                    .map_err(|e| format!("Failed to serve host keys: {}", e))?;
+                serde_json::from_value(served).map_err(|e| format!("Invalid response from the daemon: {}", e))
+            },
+            Message::HostKeysServed,
+        );
     }
+    let (Some(agent), Some(vault)) = (state.agent.clone(), state.vault.clone()) else {
+        return Task::none();
+    };
+    let hosts = state.hosts.clone();
+    let identities = state.identities.clone();
+
+    Task::perform(
+        async move {
+            // Decrypting is deliberately slow; keep it off the UI thread
+            tokio::task::spawn_blocking(move || ssh_config::serve_host_keys(&agent, &vault, &hosts, &identities))
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| format!("Failed to serve host keys: {:#}", e))
+        },
+        Message::HostKeysServed,
+    )
 }
@@ -0,0 +1,85 @@
+use futures::Stream;
+use global_hotkey::{hotkey::HotKey, GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+
+/// Parse a shortcut such as `ctrl+alt+space`. A modifier is required, so a
+/// plain key is not taken away from every other application.
+pub fn parse_hotkey(shortcut: &str) -> Result<HotKey, String> {
+    let hotkey: HotKey = shortcut
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid shortcut {:?}: {}", shortcut.trim(), e))?;
+    if hotkey.mods.is_empty() {
+        return Err(format!(
+            "Shortcut {:?} needs a modifier such as ctrl, alt, shift or super",
+            shortcut.trim()
+        ));
+    }
+    Ok(hotkey)
+}
+
+/// The system-wide shortcut opening the launcher, grabbed from the desktop
+pub struct GlobalHotkey {
+    manager: GlobalHotKeyManager,
+    registered: Option<HotKey>,
+}
+
+impl GlobalHotkey {
+    /// Connect to the desktop's shortcut handling. On Linux this needs an X11
+    /// display (XWayland counts); native Wayland has no global shortcuts.
+    pub fn new() -> Result<Self, String> {
+        // The X11 backend does not cope with a missing display
+        #[cfg(target_os = "linux")]
+        if std::env::var_os("DISPLAY").is_none() {
+            return Err("Global shortcuts need an X11 display".to_string());
+        }
+
+        let manager = GlobalHotKeyManager::new().map_err(|e| format!("Global shortcuts unavailable: {}", e))?;
+        Ok(Self {
+            manager,
+            registered: None,
+        })
+    }
+
+    /// Replace the registered shortcut; an empty one turns it off
+    pub fn register(&mut self, shortcut: &str) -> Result<(), String> {
+        let hotkey = match shortcut.trim() {
+            "" => None,
+            shortcut => Some(parse_hotkey(shortcut)?),
+        };
+        if hotkey == self.registered {
+            return Ok(());
+        }
+
+        if let Some(previous) = self.registered.take() {
+            let _ = self.manager.unregister(previous);
+        }
+        if let Some(hotkey) = hotkey {
+            self.manager
+                .register(hotkey)
+                .map_err(|e| format!("Failed to register {}: {}", shortcut.trim(), e))?;
+            self.registered = Some(hotkey);
+        }
+        Ok(())
+    }
+
+    /// Whether a press event belongs to the registered shortcut
+    pub fn is_pressed(&self, id: u32) -> bool {
+        self.registered.is_some_and(|hotkey| hotkey.id() == id)
+    }
+}
+
+/// Ids of the shortcuts pressed, from the global event channel
+pub fn presses() -> impl Stream<Item = u32> + Send + 'static {
+    futures::stream::unfold((), |()| async {
+        loop {
+            // The channel only blocks, so wait for it off the async workers
+            let event = tokio::task::spawn_blocking(|| GlobalHotKeyEvent::receiver().recv())
+                .await
+                .ok()?
+                .ok()?;
+            if event.state() == HotKeyState::Pressed {
+                return Some((event.id(), ()));
+            }
+        }
+    })
+}
@@ -1,4 +1,17 @@
-use crate::models::{Host, Identity};
+use crate::agent::{ConfirmRequest, SshAgent};
+use crate::askpass::AskpassServer;
+use crate::config::VaultEntry;
+use crate::known_hosts::{HostKeyStatus, ImportSummary, ServerKey};
+use crate::models::{AgentSignRecord, ConnectionRecord, Group, Host, HostUsage, Identity, KnownHost};
+use crate::ssh_config::{self, ImportPlan, ServedKeys};
+use crate::terminal_launcher::TerminalTemplate;
+use crate::vault::{KdfPreset, Vault};
+use super::state::{DaemonConnection, FolderChoice};
+use iced::keyboard::{Key, Modifiers};
+use iced::window;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Messages for the application
 #[derive(Debug, Clone)]
@@ -6,22 +19,130 @@ pub enum Message {
     // Authentication
     PasswordChanged(String),
     UnlockVault,
-    VaultUnlockResult(bool, Option<String>),
+    VaultSelected(VaultEntry),
+    ToggleVaultForm,
+    VaultNameChanged(String),
+    VaultPathChanged(String),
+    AddVault,
+    /// Take the selected vault off the list, keeping its file
+    RemoveVault,
+    /// The unlocked vault with its database, and the daemon serving it when
+    /// one is running
+    VaultUnlockResult(Result<(Arc<Vault>, SqlitePool, Option<DaemonConnection>), String>),
+    AgentStarted(Result<Arc<SshAgent>, String>),
+    HostKeysServed(Result<ServedKeys, String>),
+    AskpassStarted(Result<Arc<AskpassServer>, String>),
+    LockVault,
+    UserActivity,
+    AutoLockTick,
+    AutoLockChanged(super::state::AutoLockTimeout),
+    HistoryRetentionChanged(super::state::HistoryRetention),
+    SshIncludeToggled(bool),
     
     // Data loading
     HostsLoaded(Vec<Host>),
-    HostsLoadResult(bool, Option<String>),
+    GroupsLoaded(Vec<Group>),
+    SshIncludeWritten(Result<bool, String>),
     IdentitiesLoaded(Vec<Identity>),
+    /// Recent hosts and frequent hosts from the connection history
+    HostUsageLoaded(Result<(Vec<String>, Vec<HostUsage>), String>),
+    HostHistoryLoaded(Vec<ConnectionRecord>),
     
     // Navigation and Connection
     Connect(String),
-    DecryptAndConnect(crate::models::Host, Vec<u8>),
-    ConnectionResult(bool, Option<String>),
+    HostKeyChecked(String, Result<(ServerKey, HostKeyStatus), String>),
+    TrustHostKey,
+    HostKeyTrusted(String, Result<(), String>),
+    ConnectVerified(String),
+    DecryptAndConnect(crate::models::Host, Box<Identity>),
+    /// Outcome of connecting to a host, by id
+    ConnectionResult(String, bool, Option<String>),
+    HostSelectionToggled(String, bool),
+    ClearHostSelection,
+    TagFilterChanged(String),
+    SearchQueryChanged(String),
+    FocusSearch,
+    ClearSearch,
+    /// Move the keyboard selection by this many hosts
+    MoveHostSelection(isize),
+    ConnectSelectedHost,
+    /// Open every selected host at once in one tiled layout
+    BroadcastConnect,
+    BroadcastReady(Result<Vec<(Host, Identity)>, String>),
+    MoveSelectedHosts(FolderChoice),
+    HostsMoved(Result<(), String>),
     CancelDialog,
     Disconnect,
     ShowSettings,
     CloseSettings,
     TerminalPreferenceChanged(crate::terminal_launcher::TerminalApp),
+    MultiplexerSessionChanged(String),
+
+    // Quick-connect launcher
+    HotkeyInputChanged(String),
+    SaveHotkey,
+    /// A global shortcut was pressed, by id
+    HotkeyPressed(u32),
+    /// A key no widget handled, in the window it was pressed in
+    KeyPressed(window::Id, Key, Modifiers),
+    WindowUnfocused(window::Id),
+    WindowClosed(window::Id),
+    CloseLauncher,
+    LauncherQueryChanged(String),
+    LauncherPasswordChanged(String),
+    LauncherUnlock,
+    MoveLauncherSelection(isize),
+    /// Connect to the host and close the launcher
+    LauncherConnect(String),
+    LauncherConnectSelected,
+
+    // Custom terminal templates
+    TerminalTemplatesLoaded(Result<Vec<TerminalTemplate>, String>),
+    TerminalTemplateNameChanged(String),
+    TerminalTemplateCommandChanged(String),
+    EditTerminalTemplate(TerminalTemplate),
+    SaveTerminalTemplate,
+    DeleteTerminalTemplate(String),
+    /// Templates after a save or delete, with the status to show
+    TerminalTemplatesUpdated(Result<(Vec<TerminalTemplate>, String), String>),
+    TestTerminalTemplate,
+
+    // Master password change
+    CurrentPasswordChanged(String),
+    NewPasswordChanged(String),
+    ConfirmPasswordChanged(String),
+    ChangeMasterPassword,
+    MasterPasswordChanged(Result<Arc<Vault>, String>),
+
+    // Key derivation
+    KdfPresetSelected(KdfPreset),
+    KdfPresetSaved(Result<KdfPreset, String>),
+    CalibrateKdf,
+    KdfCalibrated(Result<Vec<(KdfPreset, Duration)>, String>),
+
+    // Known hosts files
+    KnownHostsImportPathChanged(String),
+    KnownHostsExportPathChanged(String),
+    ImportKnownHosts,
+    KnownHostsImported(Result<ImportSummary, String>),
+    ExportKnownHosts,
+    KnownHostsExported(Result<String, String>),
+
+    // ssh_config import
+    SshConfigPathChanged(String),
+    PreviewSshConfig,
+    SshConfigPreviewReady(Result<ImportPlan, String>),
+    ImportSshConfig,
+    SshConfigImported(Result<ssh_config::ImportSummary, String>),
+
+    // SSH agent key usage
+    AgentConfirmRequested(ConfirmRequest),
+    ResolveAgentConfirm(u64, bool),
+    PruneAgentConfirmations,
+    PollDaemonConfirmations,
+    DaemonConfirmationsLoaded(Result<Vec<ConfirmRequest>, String>),
+    DaemonCalled(Result<(), String>),
+    AgentSignLogLoaded(Vec<AgentSignRecord>),
     
     // Window controls
     CloseWindow,
@@ -39,12 +160,36 @@ pub enum Message {
     HostPortChanged(String),
     HostUsernameChanged(String),
     HostIdentityChanged(Option<String>),
+    HostGroupChanged(FolderChoice),
+    HostTagInputChanged(String),
+    /// Add the typed tags (comma separated) to the host
+    AddHostTags,
+    AddHostTag(String),
+    RemoveHostTag(String),
+    HostProxyJumpChanged(String),
+    HostLocalForwardsChanged(String),
+    KnownHostKeysLoaded(Vec<KnownHost>),
+    ForgetHostKey,
+    HostKeyForgotten(Result<u64, String>),
     
     // Host actions
     SaveHost,
     HostSaved(bool, Option<String>),
     DeleteHost(String),
     HostDeleted(bool, Option<String>),
+
+    // Folder tree
+    GroupToggled(String),
+    /// New folder, inside the given one or at the top level
+    ShowAddGroupDialog(Option<String>),
+    ShowEditGroupDialog(String),
+    ShowGroupDeleteConfirm(String),
+    GroupNameChanged(String),
+    GroupParentChanged(FolderChoice),
+    SaveGroup,
+    GroupSaved(Result<(), String>),
+    DeleteGroup(String),
+    GroupDeleted(Result<(), String>),
     
     // Identity management
     ShowIdentityList,
@@ -58,6 +203,8 @@ pub enum Message {
     IdentityPasswordChanged(String),
     IdentityKeyChanged(String),
     IdentityPassphraseChanged(String),
+    IdentityAgentConfirmToggled(bool),
+    IdentityAgentRestrictHostsToggled(bool),
     
     // Identity actions
     SaveIdentity,
@@ -3,11 +3,14 @@ pub mod state;
 pub mod app;
 pub mod views;
 pub mod widgets;
+pub mod hotkey;
+pub mod store;
 
 pub use app::NebulaVault;
 pub use messages::Message;
 
-pub fn run() -> iced::Result {
+/// Start the GUI on `vault_path`, or on the vault last used in it
+pub fn run(vault_path: Option<std::path::PathBuf>) -> iced::Result {
     use iced::{window, Theme, Size};
     
     let window_settings = window::Settings {
@@ -20,16 +23,19 @@ pub fn run() -> iced::Result {
         ..Default::default()
     };
     
-    iced::application(
+    // A daemon rather than an application, so the launcher can open as a
+    // second window with a view of its own
+    iced::daemon(
         "Nebula Vault",
         NebulaVault::update,
         NebulaVault::view,
     )
-    .theme(|_| Theme::Dark)
+    .theme(|_, _| Theme::Dark)
     .subscription(NebulaVault::subscription)
-    .window(window_settings)
-    .run_with(|| {
-        let app = NebulaVault::new();
+    .run_with(move || {
+        let (main_window, open) = window::open(window_settings);
+        let (app, task) = NebulaVault::new(vault_path, main_window);
+        let app = (app, open.discard().chain(task));
         
         // Apply vibrancy effect on macOS
         #[cfg(target_os = "macos")]
@@ -1,7 +1,23 @@
-use crate::models::{Host, Identity};
-use crate::vault::Vault;
+use crate::agent::{ConfirmRequest, SshAgent};
+use crate::askpass::AskpassServer;
+use crate::config::{Settings, VaultEntry};
+use crate::daemon::DaemonClient;
+use crate::db::VaultLock;
+use crate::gui::hotkey::GlobalHotkey;
+use crate::gui::store::Store;
+use crate::known_hosts::ServerKey;
+use crate::models::{AgentSignRecord, ConnectionRecord, Group, Host, HostUsage, Identity, KnownHost};
+use crate::search::{self, HostFields};
+use crate::ssh_config::ImportPlan;
+use crate::tags::TagQuery;
+use crate::terminal_launcher::TerminalTemplate;
+use crate::vault::{KdfPreset, Vault};
+use iced::window;
 use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
 /// Application state
@@ -12,16 +28,30 @@ pub enum AppState {
     Ready,
     HostDialog,
     DeleteConfirm(String),
+    GroupDialog,
+    GroupDeleteConfirm(String),
     IdentityList,
     IdentityDialog,
     IdentityDeleteConfirm(String),
+    HostKeyPrompt(PendingHostKey),
+    SshConfigPreview,
     Settings,
     Error(String),
 }
 
+/// A server key seen for the first time, waiting for the user to trust it
+#[derive(Debug, Clone)]
+pub struct PendingHostKey {
+    pub host_id: String,
+    pub hostname: String,
+    pub port: u16,
+    pub key: ServerKey,
+}
+
 /// Identity type selector
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum IdentityType {
+    #[default]
     Password,
     SshKey,
 }
@@ -35,6 +65,18 @@ pub struct HostForm {
     pub port: String,
     pub username: String,
     pub identity_id: Option<String>,
+    pub group_id: Option<String>,
+    pub proxy_jump: String,
+    /// LocalForward specs separated by commas, e.g. `8080 localhost:80`
+    pub local_forwards: String,
+    /// Host keys trusted for the host being edited
+    pub known_keys: Vec<KnownHost>,
+    /// Latest connection attempts to the host being edited, newest first
+    pub history: Vec<ConnectionRecord>,
+    pub tags: Vec<String>,
+    /// Tag being typed in the tag editor
+    pub tag_input: String,
+    pub tag_error: Option<String>,
 }
 
 impl HostForm {
@@ -52,6 +94,56 @@ impl HostForm {
         self.port = "22".to_string();
         self.username.clear();
         self.identity_id = None;
+        self.group_id = None;
+        self.proxy_jump.clear();
+        self.local_forwards.clear();
+        self.known_keys.clear();
+        self.history.clear();
+        self.tags.clear();
+        self.tag_input.clear();
+        self.tag_error = None;
+    }
+
+    /// Existing tags starting with the typed text, for autocomplete
+    pub fn tag_suggestions(&self, all_tags: &[String]) -> Vec<String> {
+        let typed = self.tag_input.trim().to_lowercase();
+        all_tags
+            .iter()
+            .filter(|tag| tag.starts_with(&typed) && !self.tags.contains(tag))
+            .take(8)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Group form data, for both new and renamed or moved folders
+#[derive(Debug, Clone, Default)]
+pub struct GroupForm {
+    pub editing_id: Option<String>,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub error: Option<String>,
+}
+
+impl GroupForm {
+    pub fn clear(&mut self) {
+        self.editing_id = None;
+        self.name.clear();
+        self.parent_id = None;
+        self.error = None;
+    }
+}
+
+/// Entry of a folder picker: the top level or a group shown by its path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderChoice {
+    pub id: Option<String>,
+    pub label: String,
+}
+
+impl std::fmt::Display for FolderChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.label)
     }
 }
 
@@ -64,12 +156,8 @@ pub struct IdentityForm {
     pub password: String,
     pub key: String,
     pub passphrase: String,
-}
-
-impl Default for IdentityType {
-    fn default() -> Self {
-        IdentityType::Password
-    }
+    pub agent_confirm: bool,
+    pub agent_restrict_hosts: bool,
 }
 
 impl IdentityForm {
@@ -84,44 +172,599 @@ impl IdentityForm {
         self.password.clear();
         self.key.clear();
         self.passphrase.clear();
+        self.agent_confirm = false;
+        self.agent_restrict_hosts = false;
+    }
+}
+
+/// Master password change form data
+#[derive(Debug, Clone, Default)]
+pub struct PasswordChangeForm {
+    pub current: String,
+    pub new: String,
+    pub confirm: String,
+    pub error: Option<String>,
+    pub changed: bool,
+}
+
+impl PasswordChangeForm {
+    pub fn clear(&mut self) {
+        self.current.clear();
+        self.new.clear();
+        self.confirm.clear();
+        self.error = None;
+        self.changed = false;
+    }
+}
+
+/// Known hosts import/export form data
+#[derive(Debug, Clone)]
+pub struct KnownHostsForm {
+    pub import_path: String,
+    pub export_path: String,
+    pub status: Option<String>,
+}
+
+impl Default for KnownHostsForm {
+    fn default() -> Self {
+        Self {
+            import_path: "~/.ssh/known_hosts".to_string(),
+            export_path: "~/.ssh/nebulavault_known_hosts".to_string(),
+            status: None,
+        }
+    }
+}
+
+/// Global shortcut form data
+#[derive(Debug, Clone, Default)]
+pub struct HotkeyForm {
+    pub input: String,
+    pub error: Option<String>,
+    /// Why global shortcuts cannot be registered on this desktop, if so
+    pub unavailable: Option<String>,
+}
+
+/// The small quick-connect window opened with the global shortcut
+#[derive(Debug, Clone)]
+pub struct Launcher {
+    pub window: window::Id,
+    pub query: String,
+    /// Host Enter connects to
+    pub selected_host_id: Option<String>,
+    /// Master password typed while the vault is locked
+    pub password: String,
+}
+
+impl Launcher {
+    pub fn new(window: window::Id) -> Self {
+        Self {
+            window,
+            query: String::new(),
+            selected_host_id: None,
+            password: String::new(),
+        }
+    }
+
+    /// The selected host among `results`, else the best match
+    pub fn selected<'a>(&self, results: &[&'a Host]) -> Option<&'a Host> {
+        self.selected_host_id
+            .as_ref()
+            .and_then(|id| results.iter().find(|host| host.id == *id))
+            .or(results.first())
+            .copied()
+    }
+}
+
+/// ssh_config import form data
+#[derive(Debug, Clone)]
+pub struct SshConfigImportForm {
+    pub path: String,
+    /// Preview shown before anything is written
+    pub plan: Option<ImportPlan>,
+    pub status: Option<String>,
+}
+
+impl Default for SshConfigImportForm {
+    fn default() -> Self {
+        Self {
+            path: "~/.ssh/config".to_string(),
+            plan: None,
+            status: None,
+        }
+    }
+}
+
+/// Custom terminal template form data
+#[derive(Debug, Clone, Default)]
+pub struct TerminalTemplateForm {
+    pub name: String,
+    /// Command line with placeholders, e.g. `wezterm start -- {ssh_argv}`
+    pub command: String,
+    pub status: Option<String>,
+}
+
+/// Form on the password screen for adding a vault to the list
+#[derive(Debug, Clone, Default)]
+pub struct VaultForm {
+    pub open: bool,
+    pub name: String,
+    /// Database file; empty for a file named after the vault in the data directory
+    pub path: String,
+    pub error: Option<String>,
+}
+
+/// Idle time after which the vault locks itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoLockTimeout {
+    Never,
+    Minutes(u64),
+}
+
+impl AutoLockTimeout {
+    pub const ALL: [AutoLockTimeout; 6] = [
+        AutoLockTimeout::Minutes(1),
+        AutoLockTimeout::Minutes(5),
+        AutoLockTimeout::Minutes(15),
+        AutoLockTimeout::Minutes(30),
+        AutoLockTimeout::Minutes(60),
+        AutoLockTimeout::Never,
+    ];
+
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            AutoLockTimeout::Never => None,
+            AutoLockTimeout::Minutes(minutes) => Some(Duration::from_secs(minutes * 60)),
+        }
+    }
+}
+
+impl Default for AutoLockTimeout {
+    fn default() -> Self {
+        AutoLockTimeout::Minutes(15)
+    }
+}
+
+impl std::fmt::Display for AutoLockTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AutoLockTimeout::Never => write!(f, "Never"),
+            AutoLockTimeout::Minutes(1) => write!(f, "After 1 minute"),
+            AutoLockTimeout::Minutes(minutes) => write!(f, "After {} minutes", minutes),
+        }
+    }
+}
+
+/// How long connection history is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryRetention {
+    Forever,
+    Days(u64),
+}
+
+impl HistoryRetention {
+    pub const ALL: [HistoryRetention; 5] = [
+        HistoryRetention::Days(7),
+        HistoryRetention::Days(30),
+        HistoryRetention::Days(90),
+        HistoryRetention::Days(365),
+        HistoryRetention::Forever,
+    ];
+
+    /// From the settings file, where 0 keeps history forever
+    pub fn from_days(days: u64) -> Self {
+        match days {
+            0 => HistoryRetention::Forever,
+            days => HistoryRetention::Days(days),
+        }
+    }
+
+    pub fn days(&self) -> u64 {
+        match self {
+            HistoryRetention::Forever => 0,
+            HistoryRetention::Days(days) => *days,
+        }
+    }
+}
+
+impl std::fmt::Display for HistoryRetention {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryRetention::Forever => write!(f, "Forever"),
+            HistoryRetention::Days(1) => write!(f, "1 day"),
+            HistoryRetention::Days(days) => write!(f, "{} days", days),
+        }
     }
 }
 
+/// Hosts listed under "Recent" and under "Frequent" in the sidebar
+const SIDEBAR_USAGE_HOSTS: usize = 5;
+
+/// Hosts connected to most recently that get a ranking bonus in search
+const RECENT_HOSTS_RANKED: usize = 8;
+/// Bonus of the most recently used host, shrinking for each older one
+const RECENT_HOST_BONUS: u32 = 32;
+
+/// Connection to the daemon, shared by the tasks that call it
+pub type DaemonConnection = Arc<Mutex<DaemonClient>>;
+
 /// Main application state
 pub struct NebulaVaultState {
     pub state: AppState,
+    pub main_window: Option<window::Id>,
+    pub launcher: Option<Launcher>,
+    /// Registration of the launcher shortcut; None where the desktop has no
+    /// global shortcuts
+    pub hotkey: Option<GlobalHotkey>,
+    pub hotkey_form: HotkeyForm,
     pub password_input: String,
-    pub vault: Option<Vault>,
+    pub vault: Option<Arc<Vault>>,
+    pub agent: Option<Arc<SshAgent>>,
+    /// Daemon serving this vault; while connected its agent serves the keys
+    /// and no agent of our own is started
+    pub daemon: Option<DaemonConnection>,
+    /// Identities whose key the agent could not serve, reported once per unlock
+    pub unservable_keys: HashSet<String>,
+    pub askpass: Option<Arc<AskpassServer>>,
+    /// Database of the vault, opened once per unlock
     pub db_pool: Option<SqlitePool>,
+    /// Database of the vault
+    pub vault_path: PathBuf,
+    /// Held while unlocked, so the CLI cannot import a backup over the vault
+    pub vault_lock: Option<VaultLock>,
+    pub vault_form: VaultForm,
+    /// Preferences as last written to the settings file
+    pub settings: Settings,
     pub hosts: Vec<Host>,
+    /// Folders of the sidebar tree
+    pub groups: Vec<Group>,
+    /// Folders folded away in the sidebar
+    pub collapsed_groups: HashSet<String>,
+    /// Tag filter of the sidebar as typed, e.g. `prod AND db NOT legacy`
+    pub tag_filter: String,
+    /// Parsed [`NebulaVaultState::tag_filter`]; None shows every host
+    pub tag_query: Option<TagQuery>,
+    pub tag_filter_error: Option<String>,
+    /// Fuzzy search over the hosts; while not empty the sidebar shows ranked results
+    pub search_query: String,
+    /// Host highlighted for keyboard navigation, connected to with Enter
+    pub selected_host_id: Option<String>,
+    /// Hosts by most recent connection, most recent first
+    pub recent_hosts: Vec<String>,
+    /// Hosts by number of successful connections, most used first
+    pub frequent_hosts: Vec<HostUsage>,
+    /// Hosts ticked in the sidebar for a broadcast launch
+    pub selected_hosts: Vec<String>,
     pub identities: Vec<Identity>,
     pub error_message: Option<String>,
     
     // Forms
     pub host_form: HostForm,
+    pub group_form: GroupForm,
     pub identity_form: IdentityForm,
+    pub password_change_form: PasswordChangeForm,
+    pub known_hosts_form: KnownHostsForm,
+    pub ssh_config_form: SshConfigImportForm,
     
     // Terminal preference
     pub terminal_preference: crate::terminal_launcher::TerminalApp,
+    pub terminal_templates: Vec<TerminalTemplate>,
+    pub terminal_template_form: TerminalTemplateForm,
+    /// Session that tmux, zellij and screen targets open in
+    pub multiplexer_session: String,
+
+    // Key derivation preset (applied at the next unlock)
+    pub kdf_preset: Option<KdfPreset>,
+    pub kdf_timings: Vec<(KdfPreset, Duration)>,
+    pub kdf_calibrating: bool,
     
+    // Auto-lock
+    pub auto_lock: AutoLockTimeout,
+    pub last_activity: Instant,
+    pub history_retention: HistoryRetention,
+
+    // SSH agent signing requests awaiting approval, oldest first
+    pub agent_confirmations: Vec<ConfirmRequest>,
+    pub agent_sign_log: Vec<AgentSignRecord>,
+
     // SSH Session (for cleanup)
     pub ssh_session: Option<Arc<Mutex<crate::ssh::SshSession>>>,
 }
 
 impl NebulaVaultState {
-    pub fn new() -> Self {
+    pub fn new(vault_path: PathBuf) -> Self {
         Self {
             state: AppState::PasswordEntry,
+            main_window: None,
+            launcher: None,
+            hotkey: None,
+            hotkey_form: HotkeyForm::default(),
             password_input: String::new(),
             vault: None,
+            agent: None,
+            daemon: None,
+            unservable_keys: HashSet::new(),
+            askpass: None,
             db_pool: None,
+            vault_path,
+            vault_lock: None,
+            vault_form: VaultForm::default(),
+            settings: Settings::default(),
             hosts: Vec::new(),
+            groups: Vec::new(),
+            collapsed_groups: HashSet::new(),
+            tag_filter: String::new(),
+            search_query: String::new(),
+            selected_host_id: None,
+            recent_hosts: Vec::new(),
+            frequent_hosts: Vec::new(),
+            tag_query: None,
+            tag_filter_error: None,
+            selected_hosts: Vec::new(),
             identities: Vec::new(),
             error_message: None,
             host_form: HostForm::new(),
+            group_form: GroupForm::default(),
             identity_form: IdentityForm::new(),
+            password_change_form: PasswordChangeForm::default(),
+            known_hosts_form: KnownHostsForm::default(),
+            ssh_config_form: SshConfigImportForm::default(),
             terminal_preference: crate::terminal_launcher::TerminalApp::default(),
+            terminal_templates: Vec::new(),
+            terminal_template_form: TerminalTemplateForm::default(),
+            multiplexer_session: crate::terminal_launcher::DEFAULT_MULTIPLEXER_SESSION.to_string(),
+            kdf_preset: None,
+            kdf_timings: Vec::new(),
+            kdf_calibrating: false,
+            auto_lock: AutoLockTimeout::default(),
+            last_activity: Instant::now(),
+            history_retention: HistoryRetention::Days(90),
+            agent_confirmations: Vec::new(),
+            agent_sign_log: Vec::new(),
             ssh_session: None,
         }
     }
+
+    /// Take the preferences from the settings file
+    pub fn apply_settings(&mut self, settings: Settings) {
+        self.auto_lock = match settings.auto_lock_minutes {
+            0 => AutoLockTimeout::Never,
+            minutes => AutoLockTimeout::Minutes(minutes),
+        };
+        self.history_retention = HistoryRetention::from_days(settings.history_retention_days);
+        self.multiplexer_session = settings.terminal.session.clone();
+        self.hotkey_form.input = settings.hotkey.clone();
+        if let Some(terminal) = settings.terminal.terminal() {
+            self.terminal_preference = terminal;
+        }
+        self.settings = settings;
+    }
+
+    /// Vaults to pick from, with the open one among them
+    pub fn vault_list(&self) -> Vec<VaultEntry> {
+        self.settings.vault_list(&self.vault_path)
+    }
+
+    /// The open vault's entry in [`NebulaVaultState::vault_list`]
+    pub fn current_vault(&self) -> Option<VaultEntry> {
+        self.vault_list().into_iter().find(|vault| vault.path == self.vault_path)
+    }
+
+    /// Groups directly inside `parent_id`, or at the top level for None
+    pub fn child_groups<'a>(&'a self, parent_id: Option<&'a str>) -> impl DoubleEndedIterator<Item = &'a Group> + 'a {
+        self.groups.iter().filter(move |group| group.parent_id.as_deref() == parent_id)
+    }
+
+    /// Folder a host is shown in; hosts of an unknown group sit at the top level
+    pub fn host_folder<'a>(&'a self, host: &'a Host) -> Option<&'a Group> {
+        let group_id = host.group_id.as_deref()?;
+        self.groups.iter().find(|group| group.id == group_id)
+    }
+
+    /// Every tag in use, sorted
+    pub fn all_tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self.hosts.iter().flat_map(|host| host.tags.iter().cloned()).collect();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    /// Whether a host passes the sidebar's tag filter
+    pub fn host_visible(&self, host: &Host) -> bool {
+        self.tag_query.as_ref().is_none_or(|query| query.matches(&host.tags))
+    }
+
+    /// Hosts matching the search query and the tag filter, best match first
+    pub fn search_results(&self) -> Vec<&Host> {
+        self.rank_hosts(&self.search_query, self.hosts.iter().filter(|host| self.host_visible(host)))
+    }
+
+    /// Every host ranked against the launcher's query; with no query the
+    /// recent hosts come first
+    pub fn launcher_results(&self) -> Vec<&Host> {
+        let query = self.launcher.as_ref().map_or("", |launcher| launcher.query.as_str());
+        self.rank_hosts(query, self.hosts.iter())
+    }
+
+    /// Fuzzy-match `hosts` against `query`, best first, with a bonus for the
+    /// hosts connected to last
+    fn rank_hosts<'a>(&'a self, query: &str, hosts: impl Iterator<Item = &'a Host>) -> Vec<&'a Host> {
+        let paths: HashMap<String, String> = self
+            .folder_choices(None)
+            .into_iter()
+            .filter_map(|choice| Some((choice.id?, choice.label)))
+            .collect();
+
+        let mut results: Vec<(u32, &Host)> = hosts
+            .filter_map(|host| {
+                let fields = HostFields {
+                    name: &host.name,
+                    hostname: &host.hostname,
+                    username: &host.username,
+                    tags: &host.tags,
+                    group_path: host.group_id.as_ref().and_then(|id| paths.get(id)).map(String::as_str),
+                };
+                let score = search::score_host(query, &fields)?;
+                let recent_bonus = self
+                    .recent_hosts
+                    .iter()
+                    .take(RECENT_HOSTS_RANKED)
+                    .position(|id| *id == host.id)
+                    .map_or(0, |rank| RECENT_HOST_BONUS >> rank);
+                Some((score + recent_bonus, host))
+            })
+            .collect();
+        results.sort_by(|(a_score, a), (b_score, b)| {
+            b_score.cmp(a_score).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        });
+        results.into_iter().map(|(_, host)| host).collect()
+    }
+
+    /// Hosts for the sidebar's "Recent" section
+    pub fn recent_sidebar_hosts(&self) -> Vec<&Host> {
+        self.recent_hosts
+            .iter()
+            .filter_map(|id| self.hosts.iter().find(|host| host.id == *id))
+            .filter(|host| self.host_visible(host))
+            .take(SIDEBAR_USAGE_HOSTS)
+            .collect()
+    }
+
+    /// Hosts for the sidebar's "Frequent" section, with their connection counts
+    pub fn frequent_sidebar_hosts(&self) -> Vec<(&Host, i64)> {
+        self.frequent_hosts
+            .iter()
+            .filter_map(|usage| {
+                let host = self.hosts.iter().find(|host| host.id == usage.host_id)?;
+                Some((host, usage.connections))
+            })
+            .filter(|(host, _)| self.host_visible(host))
+            .take(SIDEBAR_USAGE_HOSTS)
+            .collect()
+    }
+
+    /// Hosts in the order the sidebar shows them: search results while
+    /// searching, otherwise the hosts of expanded folders in tree order
+    pub fn listed_hosts(&self) -> Vec<&Host> {
+        if !self.search_query.trim().is_empty() {
+            return self.search_results();
+        }
+        let mut hosts = Vec::new();
+        self.collect_tree_hosts(None, &mut hosts);
+        hosts
+    }
+
+    fn collect_tree_hosts<'a>(&'a self, parent_id: Option<&str>, hosts: &mut Vec<&'a Host>) {
+        for group in self.child_groups(parent_id) {
+            if !self.collapsed_groups.contains(&group.id) {
+                self.collect_tree_hosts(Some(&group.id), hosts);
+            }
+        }
+        hosts.extend(self.hosts.iter().filter(|host| {
+            self.host_folder(host).map(|group| group.id.as_str()) == parent_id && self.host_visible(host)
+        }));
+    }
+
+    /// Number of visible hosts in a group and all of its subgroups
+    pub fn group_host_count(&self, group_id: &str) -> usize {
+        self.hosts
+            .iter()
+            .filter(|host| self.host_visible(host))
+            .filter(|host| {
+                self.host_folder(host)
+                    .is_some_and(|group| group.is_within(group_id, &self.groups))
+            })
+            .count()
+    }
+
+    /// Every folder in tree order, labelled with its path. `exclude` leaves
+    /// out a group and its subgroups, which a group cannot be moved into.
+    pub fn folder_choices(&self, exclude: Option<&str>) -> Vec<FolderChoice> {
+        let mut choices = vec![FolderChoice {
+            id: None,
+            label: "Top level".to_string(),
+        }];
+        let mut stack: Vec<(&Group, String)> = self
+            .child_groups(None)
+            .rev()
+            .map(|group| (group, group.name.clone()))
+            .collect();
+        while let Some((group, label)) = stack.pop() {
+            if exclude == Some(group.id.as_str()) {
+                continue;
+            }
+            stack.extend(
+                self.child_groups(Some(&group.id))
+                    .rev()
+                    .map(|child| (child, format!("{} / {}", label, child.name))),
+            );
+            choices.push(FolderChoice {
+                id: Some(group.id.clone()),
+                label,
+            });
+        }
+        choices
+    }
+
+    /// The entry of [`NebulaVaultState::folder_choices`] for a folder
+    pub fn folder_choice(&self, group_id: Option<&str>) -> Option<FolderChoice> {
+        self.folder_choices(None)
+            .into_iter()
+            .find(|choice| choice.id.as_deref() == group_id)
+    }
+
+    /// Where hosts and identities are read and written; None while locked
+    pub fn store(&self) -> Option<Store> {
+        match (&self.daemon, &self.db_pool) {
+            (Some(daemon), _) => Some(Store::Daemon(daemon.clone())),
+            (None, Some(pool)) => Some(Store::Database(pool.clone())),
+            (None, None) => None,
+        }
+    }
+
+    /// Drop the unlocked vault and every piece of decrypted or vault-derived
+    /// data, returning to the password prompt
+    pub fn lock(&mut self) {
+        self.vault = None;
+        self.vault_lock = None;
+        // Dropping the agent forgets its keys and removes the socket
+        self.agent = None;
+        self.daemon = None;
+        self.db_pool = None;
+        self.unservable_keys.clear();
+        // Likewise for passwords not yet fetched by ssh
+        self.askpass = None;
+        self.agent_confirmations.clear();
+        self.agent_sign_log.clear();
+        self.hosts.clear();
+        self.groups.clear();
+        self.collapsed_groups.clear();
+        self.tag_filter.clear();
+        self.search_query.clear();
+        self.selected_host_id = None;
+        self.recent_hosts.clear();
+        self.frequent_hosts.clear();
+        if let Some(launcher) = &mut self.launcher {
+            launcher.query.clear();
+            launcher.selected_host_id = None;
+        }
+        self.tag_query = None;
+        self.tag_filter_error = None;
+        self.selected_hosts.clear();
+        self.identities.clear();
+        self.host_form.clear();
+        self.group_form.clear();
+        self.identity_form.clear();
+        self.password_change_form.clear();
+        self.ssh_config_form.plan = None;
+        self.password_input.clear();
+        self.error_message = None;
+        self.ssh_session = None;
+        self.state = AppState::PasswordEntry;
+    }
+}
+
+impl Default for NebulaVaultState {
+    fn default() -> Self {
+        Self::new(crate::config::default_vault_path())
+    }
 }
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+
+use crate::db;
+use crate::models::{Host, Identity};
+
+use super::state::DaemonConnection;
+
+/// Where the GUI reads and writes hosts and identities: through the daemon
+/// while one serves the vault, so its session sees every edit, otherwise
+/// straight in the vault's database
+#[derive(Debug, Clone)]
+pub enum Store {
+    Database(SqlitePool),
+    Daemon(DaemonConnection),
+}
+
+impl Store {
+    pub async fn hosts(&self) -> Result<Vec<Host>> {
+        match self {
+            Store::Database(pool) => db::get_all_hosts(pool).await,
+            Store::Daemon(daemon) => call(daemon, "hosts.records", json!({})).await,
+        }
+    }
+
+    /// Create the host, or update the one with `id`, returning its id
+    pub async fn save_host(&self, id: Option<&str>, host: &db::HostInput) -> Result<String> {
+        match self {
+            Store::Database(pool) => db::save_host(pool, id, host).await,
+            Store::Daemon(daemon) => {
+                let saved: Value = call(daemon, "hosts.save", json!({ "id": id, "host": host })).await?;
+                returned_id(&saved)
+            }
+        }
+    }
+
+    pub async fn move_hosts(&self, ids: &[String], group_id: Option<String>) -> Result<()> {
+        match self {
+            Store::Database(pool) => db::set_hosts_group(pool, ids, group_id).await,
+            Store::Daemon(daemon) => {
+                call::<Value>(daemon, "hosts.move", json!({ "ids": ids, "group_id": group_id }))
+                    .await
+                    .map(|_| ())
+            }
+        }
+    }
+
+    pub async fn delete_host(&self, id: &str) -> Result<()> {
+        match self {
+            Store::Database(pool) => db::delete_host(pool, id).await,
+            Store::Daemon(daemon) => call::<Value>(daemon, "hosts.delete", json!({ "id": id })).await.map(|_| ()),
+        }
+    }
+
+    pub async fn identities(&self) -> Result<Vec<Identity>> {
+        match self {
+            Store::Database(pool) => db::get_all_identities(pool).await,
+            Store::Daemon(daemon) => call(daemon, "identities.records", json!({})).await,
+        }
+    }
+
+    /// Create the identity, or update the one with `id`, returning its id
+    pub async fn save_identity(&self, id: Option<&str>, identity: &db::IdentityInput) -> Result<String> {
+        match self {
+            Store::Database(pool) => db::save_identity(pool, id, identity).await,
+            Store::Daemon(daemon) => {
+                let saved: Value =
+                    call(daemon, "identities.save", json!({ "id": id, "identity": identity })).await?;
+                returned_id(&saved)
+            }
+        }
+    }
+
+    pub async fn delete_identity(&self, id: &str) -> Result<()> {
+        match self {
+            Store::Database(pool) => db::delete_identity(pool, id).await,
+            Store::Daemon(daemon) => {
+                call::<Value>(daemon, "identities.delete", json!({ "id": id }))
+                    .await
+                    .map(|_| ())
+            }
+        }
+    }
+}
+
+async fn call<T: DeserializeOwned>(daemon: &DaemonConnection, method: &str, params: Value) -> Result<T> {
+    let result = daemon.lock().await.call(method, params).await?;
+    serde_json::from_value(result).with_context(|| format!("Unexpected reply to {} from the daemon", method))
+}
+
+fn returned_id(reply: &Value) -> Result<String> {
+    reply["id"]
+        .as_str()
+        .map(str::to_string)
+        .context("The daemon did not return the saved id")
+}
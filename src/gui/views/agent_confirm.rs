@@ -0,0 +1,89 @@
+use iced::{widget::{button, column, container, row, text}, Element, Length};
+use crate::agent::ConfirmRequest;
+use crate::gui::messages::Message;
+use crate::gui::state::NebulaVaultState;
+
+/// Prompt for an agent signing request on a key that requires confirmation
+pub fn view_agent_confirm<'a>(state: &'a NebulaVaultState, request: &'a ConfirmRequest) -> Element<'a, Message> {
+    let title = text("Allow SSH key use?")
+        .size(24)
+        .style(|_theme| text::Style {
+            color: Some(iced::Color::from_rgb(0.95, 0.95, 0.95)),
+        });
+
+    let destination = match &request.host_id {
+        Some(host_id) => state
+            .hosts
+            .iter()
+            .find(|h| &h.id == host_id)
+            .map(|h| format!("{} ({}@{})", h.name, h.username, h.hostname))
+            .unwrap_or_else(|| "a deleted host".to_string()),
+        None => "a client of the shared agent socket".to_string(),
+    };
+
+    let details = column![
+        text(format!("Key: {}", request.comment)).size(14),
+        text(request.key_fingerprint.clone())
+            .size(12)
+            .style(|_theme| text::Style {
+                color: Some(iced::Color::from_rgb(0.7, 0.7, 0.75)),
+            }),
+        text(format!("Requested by: {}", destination)).size(14),
+    ]
+    .spacing(8);
+
+    let mut content = column![title, details].spacing(20).padding(30).max_width(500);
+
+    if request.forwarded {
+        content = content.push(
+            text("This request arrived through agent forwarding from a remote host.")
+                .size(14)
+                .style(|_theme| text::Style {
+                    color: Some(iced::Color::from_rgb(1.0, 0.6, 0.3)),
+                }),
+        );
+    }
+
+    if state.agent_confirmations.len() > 1 {
+        content = content.push(
+            text(format!("{} more requests waiting", state.agent_confirmations.len() - 1))
+                .size(12)
+                .style(|_theme| text::Style {
+                    color: Some(iced::Color::from_rgb(0.7, 0.7, 0.75)),
+                }),
+        );
+    }
+
+    let buttons = row![
+        button(text("Deny").size(14))
+            .on_press(Message::ResolveAgentConfirm(request.id, false))
+            .padding([10, 20]),
+        button(text("Allow").size(14))
+            .on_press(Message::ResolveAgentConfirm(request.id, true))
+            .padding([10, 20])
+            .style(|_theme, status| button::Style {
+                background: Some(iced::Background::Color(match status {
+                    button::Status::Hovered => iced::Color::from_rgb(0.3, 0.6, 0.9),
+                    _ => iced::Color::from_rgb(0.2, 0.5, 0.8),
+                })),
+                border: iced::Border {
+                    radius: 4.0.into(),
+                    ..Default::default()
+                },
+                text_color: iced::Color::WHITE,
+                ..Default::default()
+            }),
+    ]
+    .spacing(12);
+
+    container(content.push(buttons))
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .center_x(Length::Fill)
+        .center_y(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(iced::Background::Color(iced::Color::from_rgba(0.0, 0.0, 0.0, 0.8))),
+            ..Default::default()
+        })
+        .into()
+}
@@ -1,4 +1,5 @@
-use iced::{widget::{button, column, container, text, text_input}, Element, Length};
+use iced::{widget::{button, column, container, pick_list, row, text, text_input}, Element, Length};
+use crate::config::default_vault_path;
 use crate::gui::messages::Message;
 use crate::gui::state::NebulaVaultState;
 
@@ -9,11 +10,18 @@ pub fn view_password_entry(state: &NebulaVaultState) -> Element<'_, Message> {
             color: Some(iced::Color::from_rgb(0.95, 0.95, 0.95)),
         });
 
-    let subtitle = text("Enter your master password to unlock the vault")
-        .size(14)
-        .style(|_theme| text::Style {
-            color: Some(iced::Color::from_rgb(0.7, 0.7, 0.75)),
-        });
+    // Unlocking a vault that does not exist yet creates it
+    let subtitle = text(if state.vault_path.exists() {
+        "Enter your master password to unlock the vault"
+    } else {
+        "New vault: the password you enter becomes its master password"
+    })
+    .size(14)
+    .style(|_theme| text::Style {
+        color: Some(iced::Color::from_rgb(0.7, 0.7, 0.75)),
+    });
+
+    let vault_picker = render_vault_picker(state);
 
     let password_input = text_input("Master password", &state.password_input)
         .on_input(Message::PasswordChanged)
@@ -32,7 +40,7 @@ pub fn view_password_entry(state: &NebulaVaultState) -> Element<'_, Message> {
     .on_press(Message::UnlockVault)
     .padding([12, 24]);
 
-    let mut content = column![title, subtitle, password_input, unlock_button]
+    let mut content = column![title, subtitle, vault_picker, password_input, unlock_button]
         .spacing(20)
         .padding(40)
         .max_width(400);
@@ -58,6 +66,74 @@ pub fn view_password_entry(state: &NebulaVaultState) -> Element<'_, Message> {
         .into()
 }
 
+/// Vault list with add and remove actions
+fn render_vault_picker(state: &NebulaVaultState) -> Element<'_, Message> {
+    let form = &state.vault_form;
+
+    let mut picker_row = row![
+        pick_list(state.vault_list(), state.current_vault(), Message::VaultSelected)
+            .padding(10)
+            .width(Length::Fill),
+        button(text(if form.open { "Cancel" } else { "New" }).size(14))
+            .on_press(Message::ToggleVaultForm)
+            .padding([10, 14]),
+    ]
+    .spacing(8);
+    if state.vault_path != default_vault_path() {
+        picker_row = picker_row.push(
+            button(text("Remove").size(14))
+                .on_press(Message::RemoveVault)
+                .padding([10, 14]),
+        );
+    }
+
+    let path_text = text(state.vault_path.display().to_string())
+        .size(12)
+        .style(|_theme| text::Style {
+            color: Some(iced::Color::from_rgb(0.55, 0.55, 0.6)),
+        });
+
+    let mut picker = column![picker_row, path_text].spacing(6);
+
+    if form.open {
+        picker = picker.push(
+            text_input("Vault name", &form.name)
+                .on_input(Message::VaultNameChanged)
+                .on_submit(Message::AddVault)
+                .padding(10),
+        );
+        picker = picker.push(
+            text_input("File (optional, e.g. ~/vaults/client.db)", &form.path)
+                .on_input(Message::VaultPathChanged)
+                .on_submit(Message::AddVault)
+                .padding(10),
+        );
+        picker = picker.push(
+            text("An existing file is opened as it is; a new one becomes a vault with its own master password.")
+                .size(12)
+                .style(|_theme| text::Style {
+                    color: Some(iced::Color::from_rgb(0.55, 0.55, 0.6)),
+                }),
+        );
+        picker = picker.push(
+            button(text("Add Vault").size(14))
+                .on_press(Message::AddVault)
+                .padding([10, 14]),
+        );
+        if let Some(error) = &form.error {
+            picker = picker.push(
+                text(error)
+                    .size(13)
+                    .style(|_theme| text::Style {
+                        color: Some(iced::Color::from_rgb(1.0, 0.3, 0.3)),
+                    }),
+            );
+        }
+    }
+
+    picker.into()
+}
+
 pub fn view_loading() -> Element<'static, Message> {
     let loading_text = text("Loading...")
         .size(24)
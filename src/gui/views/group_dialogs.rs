@@ -0,0 +1,130 @@
+use iced::{widget::{button, column, container, pick_list, row, text, text_input}, Element, Length};
+use crate::gui::messages::Message;
+use crate::gui::state::NebulaVaultState;
+
+pub fn view_group_dialog(state: &NebulaVaultState) -> Element<'_, Message> {
+    let form = &state.group_form;
+
+    let title = text(if form.editing_id.is_some() {
+        "Edit Folder"
+    } else {
+        "New Folder"
+    })
+    .size(24)
+    .style(|_theme| text::Style {
+        color: Some(iced::Color::from_rgb(0.95, 0.95, 0.95)),
+    });
+
+    let name_input = column![
+        text("Name").size(14),
+        text_input("Production", &form.name)
+            .on_input(Message::GroupNameChanged)
+            .on_submit(Message::SaveGroup)
+            .padding(10)
+    ]
+    .spacing(8);
+
+    // A folder cannot be moved into itself or one of its subfolders
+    let parent_picker = column![
+        text("Parent folder").size(14),
+        pick_list(
+            state.folder_choices(form.editing_id.as_deref()),
+            state.folder_choice(form.parent_id.as_deref()),
+            Message::GroupParentChanged,
+        )
+        .padding(10)
+        .width(Length::Fill)
+    ]
+    .spacing(8);
+
+    let buttons = row![
+        button(text("Cancel").size(14))
+            .on_press(Message::CancelDialog)
+            .padding([10, 20]),
+        button(text("Save").size(14))
+            .on_press(Message::SaveGroup)
+            .padding([10, 20]),
+    ]
+    .spacing(12);
+
+    let mut dialog_content = column![title, name_input, parent_picker].spacing(20);
+
+    if let Some(error) = &form.error {
+        dialog_content = dialog_content.push(
+            text(error)
+                .size(14)
+                .style(|_theme| text::Style {
+                    color: Some(iced::Color::from_rgb(1.0, 0.3, 0.3)),
+                }),
+        );
+    }
+
+    let dialog_content = dialog_content
+        .push(buttons)
+        .padding(30)
+        .max_width(500);
+
+    container(dialog_content)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .center_x(Length::Fill)
+        .center_y(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(iced::Background::Color(iced::Color::from_rgba(0.0, 0.0, 0.0, 0.8))),
+            ..Default::default()
+        })
+        .into()
+}
+
+pub fn view_group_delete_confirm<'a>(state: &'a NebulaVaultState, group_id: &'a str) -> Element<'a, Message> {
+    let group = state.groups.iter().find(|g| g.id == group_id);
+    let group_name = group.map(|g| g.name.as_str()).unwrap_or("this folder");
+
+    // Nothing inside the folder is deleted with it
+    let destination = group
+        .and_then(|g| state.folder_choice(g.parent_id.as_deref()))
+        .map(|choice| choice.label)
+        .unwrap_or_else(|| "Top level".to_string());
+
+    let title = text("Delete Folder?")
+        .size(24)
+        .style(|_theme| text::Style {
+            color: Some(iced::Color::from_rgb(1.0, 0.3, 0.3)),
+        });
+
+    let message = text(format!(
+        "Delete \"{}\"? Its hosts and subfolders are kept and move to \"{}\".",
+        group_name, destination
+    ))
+    .size(16)
+    .style(|_theme| text::Style {
+        color: Some(iced::Color::from_rgb(0.9, 0.9, 0.9)),
+    });
+
+    let group_id_owned = group_id.to_string();
+    let buttons = row![
+        button(text("Cancel").size(14))
+            .on_press(Message::CancelDialog)
+            .padding([10, 20]),
+        button(text("Delete").size(14))
+            .on_press(Message::DeleteGroup(group_id_owned))
+            .padding([10, 20]),
+    ]
+    .spacing(12);
+
+    let dialog_content = column![title, message, buttons]
+        .spacing(20)
+        .padding(30)
+        .max_width(400);
+
+    container(dialog_content)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .center_x(Length::Fill)
+        .center_y(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(iced::Background::Color(iced::Color::from_rgba(0.0, 0.0, 0.0, 0.8))),
+            ..Default::default()
+        })
+        .into()
+}
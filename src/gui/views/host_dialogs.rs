@@ -1,6 +1,8 @@
-use iced::{widget::{button, column, container, row, scrollable, text, text_input}, Element, Length};
+use iced::{widget::{button, column, container, pick_list, row, scrollable, text, text_input}, Element, Length};
 use crate::gui::messages::Message;
-use crate::gui::state::NebulaVaultState;
+use crate::gui::state::{NebulaVaultState, PendingHostKey};
+use crate::gui::widgets::glass::tag_chip;
+use crate::models::ConnectionRecord;
 
 pub fn view_host_dialog(state: &NebulaVaultState) -> Element<'_, Message> {
     let title = text(if state.host_form.editing_id.is_some() {
@@ -45,6 +47,69 @@ pub fn view_host_dialog(state: &NebulaVaultState) -> Element<'_, Message> {
     ]
     .spacing(8);
 
+    let folder_picker = column![
+        text("Folder").size(14),
+        pick_list(
+            state.folder_choices(None),
+            state.folder_choice(state.host_form.group_id.as_deref()),
+            Message::HostGroupChanged,
+        )
+        .padding(10)
+        .width(Length::Fill)
+    ]
+    .spacing(8);
+
+    // Tags as removable chips, with existing tags offered while typing
+    let form = &state.host_form;
+    let mut tag_editor = column![text("Tags (optional)").size(14)].spacing(8);
+    if !form.tags.is_empty() {
+        let chips = form
+            .tags
+            .iter()
+            .map(|tag| tag_chip(format!("{} ×", tag), 12, Message::RemoveHostTag(tag.clone())));
+        tag_editor = tag_editor.push(row(chips).spacing(6).wrap());
+    }
+    tag_editor = tag_editor.push(
+        text_input("prod, db", &form.tag_input)
+            .on_input(Message::HostTagInputChanged)
+            .on_submit(Message::AddHostTags)
+            .padding(10),
+    );
+    if !form.tag_input.trim().is_empty() {
+        let suggestions = form.tag_suggestions(&state.all_tags());
+        if !suggestions.is_empty() {
+            let chips = suggestions
+                .into_iter()
+                .map(|tag| tag_chip(tag.clone(), 12, Message::AddHostTag(tag)));
+            tag_editor = tag_editor.push(row(chips).spacing(6).wrap());
+        }
+    }
+    if let Some(error) = &form.tag_error {
+        tag_editor = tag_editor.push(
+            text(error)
+                .size(12)
+                .style(|_theme| text::Style {
+                    color: Some(iced::Color::from_rgb(1.0, 0.3, 0.3)),
+                }),
+        );
+    }
+
+    let proxy_jump_input = column![
+        text("ProxyJump (optional)").size(14),
+        text_input("bastion.example.com", &state.host_form.proxy_jump)
+            .on_input(Message::HostProxyJumpChanged)
+            .padding(10)
+    ]
+    .spacing(8);
+
+    let local_forwards_input = column![
+        text("LocalForward (optional, comma separated)").size(14),
+        text_input("8080 localhost:80", &state.host_form.local_forwards)
+            .on_input(Message::HostLocalForwardsChanged)
+            .padding(10)
+    ]
+    .spacing(8);
+
     // Identity selector
     let identity_selector = if state.identities.is_empty() {
         column![
@@ -127,20 +192,58 @@ pub fn view_host_dialog(state: &NebulaVaultState) -> Element<'_, Message> {
     ]
     .spacing(12);
 
-    let dialog_content = column![
+    let mut dialog_content = column![
         title,
         name_input,
         hostname_input,
         port_input,
         username_input,
+        folder_picker,
+        tag_editor,
+        proxy_jump_input,
+        local_forwards_input,
         identity_selector,
-        buttons
     ]
-    .spacing(20)
-    .padding(30)
-    .max_width(500);
+    .spacing(20);
 
-    container(dialog_content)
+    // Trusted host keys, editable hosts only
+    if state.host_form.editing_id.is_some() {
+        let mut host_keys = column![text("Host Keys").size(14)].spacing(8);
+        if state.host_form.known_keys.is_empty() {
+            host_keys = host_keys.push(
+                text("No trusted host key yet. It will be shown on the next connection.")
+                    .size(12)
+                    .style(|_theme| text::Style {
+                        color: Some(iced::Color::from_rgb(0.6, 0.6, 0.65)),
+                    }),
+            );
+        } else {
+            for known in &state.host_form.known_keys {
+                host_keys = host_keys.push(
+                    text(format!("{}  {}", known.key_type, known.fingerprint))
+                        .size(12)
+                        .style(|_theme| text::Style {
+                            color: Some(iced::Color::from_rgb(0.7, 0.7, 0.75)),
+                        }),
+                );
+            }
+            host_keys = host_keys.push(
+                button(text("Forget Host Key").size(12))
+                    .on_press(Message::ForgetHostKey)
+                    .padding([6, 12]),
+            );
+        }
+        dialog_content = dialog_content.push(host_keys);
+        dialog_content = dialog_content.push(render_history(&state.host_form.history));
+    }
+
+    let dialog_content = dialog_content
+        .push(buttons)
+        .padding(30)
+        .max_width(500);
+
+    // Taller than small windows once tags and host keys are shown
+    container(scrollable(dialog_content))
         .width(Length::Fill)
         .height(Length::Fill)
         .center_x(Length::Fill)
@@ -152,6 +255,43 @@ pub fn view_host_dialog(state: &NebulaVaultState) -> Element<'_, Message> {
         .into()
 }
 
+/// Latest connection attempts of the host being edited
+fn render_history(history: &[ConnectionRecord]) -> Element<'_, Message> {
+    let mut section = column![text("Connection History").size(14)].spacing(8);
+    if history.is_empty() {
+        return section
+            .push(
+                text("Never connected")
+                    .size(12)
+                    .style(|_theme| text::Style {
+                        color: Some(iced::Color::from_rgb(0.6, 0.6, 0.65)),
+                    }),
+            )
+            .into();
+    }
+
+    for record in history {
+        let outcome = match &record.error {
+            _ if record.success => "Connected".to_string(),
+            Some(error) => format!("Failed: {}", error),
+            None => "Failed".to_string(),
+        };
+        let success = record.success;
+        section = section.push(
+            text(format!("{}  {}  {}", record.local_time(), record.target, outcome))
+                .size(12)
+                .style(move |_theme| text::Style {
+                    color: Some(if success {
+                        iced::Color::from_rgb(0.7, 0.7, 0.75)
+                    } else {
+                        iced::Color::from_rgb(1.0, 0.4, 0.4)
+                    }),
+                }),
+        );
+    }
+    section.into()
+}
+
 pub fn view_delete_confirm<'a>(state: &'a NebulaVaultState, host_id: &'a str) -> Element<'a, Message> {
     let host_name = state
         .hosts
@@ -199,3 +339,64 @@ pub fn view_delete_confirm<'a>(state: &'a NebulaVaultState, host_id: &'a str) ->
         })
         .into()
 }
+
+/// Trust-on-first-use prompt for a server key that is not in the known hosts store
+pub fn view_host_key_prompt<'a>(state: &'a NebulaVaultState, pending: &'a PendingHostKey) -> Element<'a, Message> {
+    let host_name = state
+        .hosts
+        .iter()
+        .find(|h| h.id == pending.host_id)
+        .map(|h| h.name.as_str())
+        .unwrap_or(pending.hostname.as_str());
+
+    let title = text("Unknown Host Key")
+        .size(24)
+        .style(|_theme| text::Style {
+            color: Some(iced::Color::from_rgb(0.95, 0.95, 0.95)),
+        });
+
+    let message = text(format!(
+        "The authenticity of \"{}\" ({}:{}) can't be established. Compare the fingerprint with the one published by the server's administrator before trusting it.",
+        host_name, pending.hostname, pending.port
+    ))
+    .size(14)
+    .style(|_theme| text::Style {
+        color: Some(iced::Color::from_rgb(0.9, 0.9, 0.9)),
+    });
+
+    let fingerprint = column![
+        text(format!("{} key fingerprint:", pending.key.key_type)).size(14),
+        text(pending.key.fingerprint.clone())
+            .size(16)
+            .style(|_theme| text::Style {
+                color: Some(iced::Color::from_rgb(0.486, 0.227, 0.929)),
+            }),
+    ]
+    .spacing(8);
+
+    let buttons = row![
+        button(text("Cancel").size(14))
+            .on_press(Message::CancelDialog)
+            .padding([10, 20]),
+        button(text("Trust and Connect").size(14))
+            .on_press(Message::TrustHostKey)
+            .padding([10, 20]),
+    ]
+    .spacing(12);
+
+    let dialog_content = column![title, message, fingerprint, buttons]
+        .spacing(20)
+        .padding(30)
+        .max_width(500);
+
+    container(dialog_content)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .center_x(Length::Fill)
+        .center_y(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(iced::Background::Color(iced::Color::from_rgba(0.0, 0.0, 0.0, 0.8))),
+            ..Default::default()
+        })
+        .into()
+}
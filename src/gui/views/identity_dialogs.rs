@@ -1,4 +1,4 @@
-use iced::{widget::{button, column, container, row, scrollable, text, text_input, Column}, Element, Length};
+use iced::{widget::{button, checkbox, column, container, row, scrollable, text, text_input, Column}, Element, Length};
 use crate::gui::messages::Message;
 use crate::gui::state::{IdentityType, NebulaVaultState};
 
@@ -52,7 +52,20 @@ pub fn view_identity_list(state: &NebulaVaultState) -> Element<'_, Message> {
             let type_icon = text("🔑")
                 .size(16);
 
-            let info_row = row![type_icon, name_text]
+            let mut policy = Vec::new();
+            if identity.agent_confirm {
+                policy.push("confirm each use");
+            }
+            if identity.agent_restrict_hosts {
+                policy.push("linked hosts only");
+            }
+            let policy_text = text(policy.join(" · "))
+                .size(12)
+                .style(|_theme| text::Style {
+                    color: Some(iced::Color::from_rgb(0.6, 0.6, 0.65)),
+                });
+
+            let info_row = row![type_icon, name_text, policy_text]
                 .spacing(12)
                 .align_y(iced::Alignment::Center)
                 .width(Length::Fill);
@@ -121,6 +134,7 @@ pub fn view_identity_list(state: &NebulaVaultState) -> Element<'_, Message> {
     let content = column![
         title_row,
         scrollable(identity_list).height(Length::Fill),
+        render_agent_activity(state),
         back_button
     ]
     .spacing(20)
@@ -137,6 +151,61 @@ pub fn view_identity_list(state: &NebulaVaultState) -> Element<'_, Message> {
         .into()
 }
 
+/// Recent agent signing requests and their outcome
+fn render_agent_activity(state: &NebulaVaultState) -> Element<'_, Message> {
+    let mut activity = Column::new().spacing(6).push(
+        text("Recent SSH agent activity")
+            .size(16)
+            .style(|_theme| text::Style {
+                color: Some(iced::Color::from_rgb(0.95, 0.95, 0.95)),
+            }),
+    );
+
+    if state.agent_sign_log.is_empty() {
+        activity = activity.push(
+            text("No signing requests yet.")
+                .size(12)
+                .style(|_theme| text::Style {
+                    color: Some(iced::Color::from_rgb(0.6, 0.6, 0.65)),
+                }),
+        );
+    }
+
+    for record in &state.agent_sign_log {
+        let key = record
+            .identity_id
+            .as_ref()
+            .and_then(|id| state.identities.iter().find(|i| &i.id == id))
+            .map(|i| i.name.as_str())
+            .unwrap_or(record.key_fingerprint.as_str());
+        let host = record
+            .host_id
+            .as_ref()
+            .and_then(|id| state.hosts.iter().find(|h| &h.id == id))
+            .map(|h| h.name.as_str())
+            .unwrap_or("shared socket");
+        let forwarded = if record.forwarded { " (forwarded)" } else { "" };
+        let denied = !matches!(record.decision.as_str(), "allowed" | "confirmed");
+
+        activity = activity.push(
+            text(format!(
+                "{}  {} → {}{}: {}",
+                record.created_at, key, host, forwarded, record.decision
+            ))
+            .size(12)
+            .style(move |_theme| text::Style {
+                color: Some(if denied {
+                    iced::Color::from_rgb(1.0, 0.5, 0.4)
+                } else {
+                    iced::Color::from_rgb(0.7, 0.7, 0.75)
+                }),
+            }),
+        );
+    }
+
+    activity.into()
+}
+
 pub fn view_identity_dialog(state: &NebulaVaultState) -> Element<'_, Message> {
     let title = text(if state.identity_form.editing_id.is_some() {
         "Edit Identity"
@@ -244,7 +313,24 @@ pub fn view_identity_dialog(state: &NebulaVaultState) -> Element<'_, Message> {
             ]
             .spacing(8);
 
-            form_fields = form_fields.push(key_input).push(passphrase_input);
+            let agent_policy = column![
+                text("SSH Agent").size(14),
+                checkbox("Confirm each use of this key", state.identity_form.agent_confirm)
+                    .on_toggle(Message::IdentityAgentConfirmToggled),
+                checkbox(
+                    "Only sign for hosts that use this identity",
+                    state.identity_form.agent_restrict_hosts,
+                )
+                .on_toggle(Message::IdentityAgentRestrictHostsToggled),
+                text("Needs OpenSSH 8.9 or newer, which tells the agent which server it signs for")
+                    .size(12)
+                    .style(|_theme| text::Style {
+                        color: Some(iced::Color::from_rgb(0.6, 0.6, 0.65)),
+                    }),
+            ]
+            .spacing(8);
+
+            form_fields = form_fields.push(key_input).push(passphrase_input).push(agent_policy);
         }
     }
 
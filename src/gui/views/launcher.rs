@@ -0,0 +1,174 @@
+use iced::{widget::{button, column, container, row, scrollable, text, text_input, Column}, window, Background, Border, Color, Element, Length, Size};
+use crate::gui::messages::Message;
+use crate::gui::state::{AppState, Launcher, NebulaVaultState};
+use crate::models::Host;
+
+/// Id of the launcher's input, the search or the master password
+pub const LAUNCHER_INPUT: &str = "launcher-input";
+/// Id of the launcher's result list, scrolled along with the selection
+pub const LAUNCHER_LIST: &str = "launcher-list";
+
+/// A small borderless window above everything else, in the middle of the screen
+pub fn window_settings() -> window::Settings {
+    window::Settings {
+        size: Size::new(560.0, 380.0),
+        position: window::Position::Centered,
+        resizable: false,
+        decorations: false,
+        level: window::Level::AlwaysOnTop,
+        exit_on_close_request: true,
+        ..Default::default()
+    }
+}
+
+pub fn view_launcher<'a>(state: &'a NebulaVaultState, launcher: &'a Launcher) -> Element<'a, Message> {
+    let content = if state.vault.is_some() {
+        view_search(state, launcher)
+    } else {
+        view_unlock(state, launcher)
+    };
+
+    container(content)
+        .padding(16)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(Background::Color(Color::from_rgb(0.059, 0.090, 0.165))),
+            border: Border {
+                color: Color::from_rgba(0.486, 0.227, 0.929, 0.6),
+                width: 1.0,
+                radius: 12.0.into(),
+            },
+            ..Default::default()
+        })
+        .into()
+}
+
+/// Master password first, when the shortcut is pressed with the vault locked
+fn view_unlock<'a>(state: &'a NebulaVaultState, launcher: &'a Launcher) -> Element<'a, Message> {
+    let vault_name = state
+        .current_vault()
+        .map(|vault| vault.name)
+        .unwrap_or_else(|| state.vault_path.display().to_string());
+
+    let title = text(format!("Unlock {}", vault_name))
+        .size(20)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.95, 0.95, 0.95)),
+        });
+
+    let unlocking = matches!(state.state, AppState::Loading);
+    let mut password_input = text_input("Master password", &launcher.password)
+        .id(text_input::Id::new(LAUNCHER_INPUT))
+        .secure(true)
+        .size(16)
+        .padding([10, 14]);
+    if !unlocking {
+        password_input = password_input
+            .on_input(Message::LauncherPasswordChanged)
+            .on_submit(Message::LauncherUnlock);
+    }
+
+    let mut content = column![title, password_input].spacing(16);
+
+    if unlocking {
+        content = content.push(hint("Unlocking..."));
+    } else if let Some(error) = &state.error_message {
+        content = content.push(
+            text(error)
+                .size(13)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgb(1.0, 0.4, 0.4)),
+                }),
+        );
+    }
+
+    content.push(hint("Enter unlock  ·  Esc close")).into()
+}
+
+fn view_search<'a>(state: &'a NebulaVaultState, launcher: &'a Launcher) -> Element<'a, Message> {
+    let search_input = text_input("Connect to...", &launcher.query)
+        .id(text_input::Id::new(LAUNCHER_INPUT))
+        .on_input(Message::LauncherQueryChanged)
+        .on_submit(Message::LauncherConnectSelected)
+        .size(16)
+        .padding([10, 14]);
+
+    let results = state.launcher_results();
+    let selected_id = launcher.selected(&results).map(|host| host.id.clone());
+
+    let list: Element<'_, Message> = if results.is_empty() {
+        let message = if state.hosts.is_empty() { "No hosts in this vault" } else { "No hosts match" };
+        container(hint(message)).padding([8, 4]).height(Length::Fill).into()
+    } else {
+        let folders = state.folder_choices(None);
+        let items = results.iter().map(|host| {
+            let folder = host
+                .group_id
+                .as_ref()
+                .and_then(|id| folders.iter().find(|choice| choice.id.as_ref() == Some(id)))
+                .map(|choice| choice.label.clone());
+            render_result(host, folder, selected_id.as_deref() == Some(host.id.as_str()))
+        });
+        scrollable(Column::with_children(items).spacing(4))
+            .id(scrollable::Id::new(LAUNCHER_LIST))
+            .height(Length::Fill)
+            .into()
+    };
+
+    column![search_input, list, hint("↑↓ select  ·  Enter connect  ·  Esc close")]
+        .spacing(12)
+        .into()
+}
+
+fn render_result(host: &Host, folder: Option<String>, highlighted: bool) -> Element<'static, Message> {
+    let name = text(host.name.clone())
+        .size(14)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.95, 0.95, 0.95)),
+        });
+
+    let mut address = format!("{}@{}", host.username, host.hostname);
+    if host.port != 22 {
+        address.push_str(&format!(":{}", host.port));
+    }
+    let address = text(address)
+        .size(12)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.6, 0.6, 0.65)),
+        });
+
+    let mut item = row![column![name, address].spacing(2).width(Length::Fill)]
+        .spacing(8)
+        .align_y(iced::Alignment::Center);
+    if let Some(folder) = folder {
+        item = item.push(hint(folder));
+    }
+
+    button(item)
+        .on_press(Message::LauncherConnect(host.id.clone()))
+        .padding([8, 12])
+        .width(Length::Fill)
+        .style(move |_theme, status| button::Style {
+            background: Some(Background::Color(match status {
+                _ if highlighted => Color::from_rgba(0.486, 0.227, 0.929, 0.3),
+                button::Status::Hovered => Color::from_rgba(1.0, 1.0, 1.0, 0.08),
+                _ => Color::TRANSPARENT,
+            })),
+            border: Border {
+                radius: 8.0.into(),
+                ..Default::default()
+            },
+            text_color: Color::from_rgb(0.95, 0.95, 0.95),
+            ..Default::default()
+        })
+        .into()
+}
+
+fn hint<'a>(label: impl text::IntoFragment<'a>) -> iced::widget::Text<'a> {
+    text(label)
+        .size(12)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgba(0.7, 0.7, 0.7, 0.9)),
+        })
+}
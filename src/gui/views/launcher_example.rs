@@ -5,7 +5,7 @@ use iced::{
     Element, Length,
 };
 use crate::gui::{messages::Message, state::NebulaVaultState};
-use super::widgets::glass::{self, GlassContainer};
+use crate::gui::widgets::glass::{self, GlassContainer};
 
 pub fn view_launcher(state: &NebulaVaultState) -> Element<'_, Message> {
     // Search input with neon glow
@@ -23,9 +23,10 @@ pub fn view_launcher(state: &NebulaVaultState) -> Element<'_, Message> {
     // Server list
     let mut server_list = Column::new().spacing(12).padding([0, 40]);
 
-    for host in &state.hosts {
+    // Ranked search results, or every host in sidebar order
+    for host in state.listed_hosts() {
         let is_selected = state.selected_host_id.as_ref() == Some(&host.id);
-        let is_hovered = state.hovered_host_id.as_ref() == Some(&host.id);
+        let is_hovered = false;
 
         let card = glass::server_card(
             &host.name,
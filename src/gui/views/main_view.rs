@@ -1,6 +1,13 @@
-use iced::{widget::{button, column, container, row, scrollable, text, Column, Stack, Image, Space}, Element, Length, Color, Background, Border, Gradient};
+use iced::{widget::{button, checkbox, column, container, pick_list, row, scrollable, text, text_input, Column, Space, Stack, Image}, Element, Length, Color, Background, Border, Gradient};
 use crate::gui::messages::Message;
 use crate::gui::state::NebulaVaultState;
+use crate::models::{Group, Host};
+
+/// Id of the host search input, focused with `/` or Ctrl+F
+pub const SEARCH_INPUT: &str = "host-search";
+/// Id of the scrollable host list, scrolled along with the keyboard selection
+pub const HOST_LIST: &str = "host-list";
+use crate::gui::widgets::glass::tag_chip;
 use crate::gui::widgets::GradientBackground;
 
 pub fn view_main(state: &NebulaVaultState) -> Element<'_, Message> {
@@ -54,28 +61,93 @@ fn render_sidebar(state: &NebulaVaultState) -> Element<'_, Message> {
                 text_color: Color::WHITE,
                 ..Default::default()
             }),
+        iced::widget::horizontal_space(),
+        button(text("Lock").size(12))
+            .on_press(Message::LockVault)
+            .padding([6, 10])
+            .style(|_theme, status| button::Style {
+                background: Some(Background::Color(match status {
+                    button::Status::Hovered => Color::from_rgba(1.0, 1.0, 1.0, 0.15),
+                    _ => Color::from_rgba(1.0, 1.0, 1.0, 0.06),
+                })),
+                border: Border {
+                    color: Color::from_rgba(1.0, 1.0, 1.0, 0.2),
+                    width: 1.0,
+                    radius: 6.0.into(),
+                },
+                text_color: Color::WHITE,
+                ..Default::default()
+            }),
     ]
     .spacing(10)
     .align_y(iced::Alignment::Center);
 
     let mut host_list = Column::new().spacing(8).padding(16);
 
-    if state.hosts.is_empty() {
+    if state.hosts.is_empty() && state.groups.is_empty() {
         let empty_text = text("No connections yet")
             .size(14)
             .style(|_theme| text::Style {
                 color: Some(iced::Color::from_rgb(0.6, 0.6, 0.65)),
             });
         host_list = host_list.push(empty_text);
+    } else if !state.search_query.trim().is_empty() {
+        host_list = render_search_results(state, host_list);
+    } else if state.tag_query.is_some() && !state.hosts.iter().any(|host| state.host_visible(host)) {
+        let empty_text = text("No hosts match the tag filter")
+            .size(14)
+            .style(|_theme| text::Style {
+                color: Some(iced::Color::from_rgb(0.6, 0.6, 0.65)),
+            });
+        host_list = host_list.push(empty_text);
     } else {
-        for host in &state.hosts {
-            host_list = host_list.push(render_host_item(&host.id, &host.name, &host.hostname));
-        }
+        host_list = render_usage_sections(state, host_list);
+        host_list = render_folder(state, None, 0, host_list);
     }
 
-    let sidebar_content = column![title_row, scrollable(host_list)]
-        .spacing(16)
-        .padding(16);
+    // Locking returns to the password screen, where another vault can be picked
+    let vault_name = text(state.current_vault().map(|vault| format!("Vault: {}", vault.name)).unwrap_or_default())
+    .size(12)
+    .style(|_theme| text::Style {
+        color: Some(Color::from_rgb(0.6, 0.6, 0.65)),
+    });
+
+    let new_folder_button = button(text("New Folder").size(12))
+        .on_press(Message::ShowAddGroupDialog(None))
+        .padding([4, 8])
+        .style(|_theme, status| button::Style {
+            background: Some(Background::Color(match status {
+                button::Status::Hovered => Color::from_rgba(1.0, 1.0, 1.0, 0.15),
+                _ => Color::from_rgba(1.0, 1.0, 1.0, 0.06),
+            })),
+            border: Border {
+                color: Color::from_rgba(1.0, 1.0, 1.0, 0.2),
+                width: 1.0,
+                radius: 6.0.into(),
+            },
+            text_color: Color::WHITE,
+            ..Default::default()
+        });
+
+    let vault_row = row![vault_name, iced::widget::horizontal_space(), new_folder_button]
+        .align_y(iced::Alignment::Center);
+
+    // Enter connects to the highlighted host, arrow keys move the highlight
+    let search_input = text_input("Search hosts  ( / )", &state.search_query)
+        .id(text_input::Id::new(SEARCH_INPUT))
+        .on_input(Message::SearchQueryChanged)
+        .on_submit(Message::ConnectSelectedHost)
+        .size(14)
+        .padding([8, 12]);
+
+    let mut sidebar_content = column![column![title_row, vault_row].spacing(4), search_input].spacing(16).padding(16);
+    if !state.tag_filter.is_empty() || state.hosts.iter().any(|host| !host.tags.is_empty()) {
+        sidebar_content = sidebar_content.push(render_tag_filter(state));
+    }
+    if !state.selected_hosts.is_empty() {
+        sidebar_content = sidebar_content.push(render_selection_bar(state));
+    }
+    let sidebar_content = sidebar_content.push(scrollable(host_list).id(scrollable::Id::new(HOST_LIST)));
 
     container(sidebar_content)
         .width(Length::Fixed(280.0))
@@ -97,8 +169,323 @@ fn render_sidebar(state: &NebulaVaultState) -> Element<'_, Message> {
         .into()
 }
 
-fn render_host_item(id: &str, name: &str, hostname: &str) -> Element<'static, Message> {
+/// Subgroups and hosts of a folder, followed by the contents of expanded subgroups
+fn render_folder<'a>(
+    state: &'a NebulaVaultState,
+    parent_id: Option<&'a str>,
+    depth: u16,
+    mut list: Column<'a, Message>,
+) -> Column<'a, Message> {
+    let indent = f32::from(depth) * 14.0;
+
+    for group in state.child_groups(parent_id) {
+        let host_count = state.group_host_count(&group.id);
+        // Folders without a matching host are left out while filtering
+        if state.tag_query.is_some() && host_count == 0 {
+            continue;
+        }
+        let collapsed = state.collapsed_groups.contains(&group.id);
+        list = list.push(row![
+            Space::with_width(indent),
+            render_group_item(group, host_count, collapsed),
+        ]);
+        if !collapsed {
+            list = render_folder(state, Some(&group.id), depth + 1, list);
+        }
+    }
+
+    for host in &state.hosts {
+        if state.host_folder(host).map(|group| group.id.as_str()) != parent_id || !state.host_visible(host) {
+            continue;
+        }
+        list = list.push(row![Space::with_width(indent), render_listed_host(state, host)]);
+    }
+
+    list
+}
+
+fn render_listed_host<'a>(state: &'a NebulaVaultState, host: &'a Host) -> Element<'static, Message> {
+    render_host_item(
+        &host.id,
+        &host.name,
+        &host.hostname,
+        &host.tags,
+        state.selected_hosts.contains(&host.id),
+        state.selected_host_id.as_deref() == Some(host.id.as_str()),
+    )
+}
+
+/// "Recent" and "Frequent" hosts from the connection history, above the folder tree
+fn render_usage_sections<'a>(state: &'a NebulaVaultState, mut list: Column<'a, Message>) -> Column<'a, Message> {
+    let recent = state.recent_sidebar_hosts();
+    let frequent = state.frequent_sidebar_hosts();
+    if recent.is_empty() && frequent.is_empty() {
+        return list;
+    }
+
+    if !recent.is_empty() {
+        list = list.push(render_section_label("Recent"));
+        for host in recent {
+            list = list.push(render_usage_host(state, host, host.hostname.clone()));
+        }
+    }
+    if !frequent.is_empty() {
+        list = list.push(render_section_label("Frequent"));
+        for (host, connections) in frequent {
+            let detail = match connections {
+                1 => "1 connection".to_string(),
+                connections => format!("{} connections", connections),
+            };
+            list = list.push(render_usage_host(state, host, detail));
+        }
+    }
+    list.push(render_section_label("All Hosts"))
+}
+
+fn render_section_label(label: &str) -> Element<'_, Message> {
+    text(label.to_uppercase())
+        .size(11)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.6, 0.6, 0.65)),
+        })
+        .into()
+}
+
+/// One-line host entry of the usage sections; a click connects
+fn render_usage_host(state: &NebulaVaultState, host: &Host, detail: String) -> Element<'static, Message> {
+    let highlighted = state.selected_host_id.as_deref() == Some(host.id.as_str());
+
+    let item_row = row![
+        text(host.name.clone())
+            .size(13)
+            .width(Length::Fill)
+            .style(|_theme| text::Style {
+                color: Some(Color::from_rgb(0.95, 0.95, 0.95)),
+            }),
+        text(detail)
+            .size(11)
+            .style(|_theme| text::Style {
+                color: Some(Color::from_rgb(0.6, 0.6, 0.65)),
+            }),
+    ]
+    .spacing(8)
+    .align_y(iced::Alignment::Center);
+
+    button(item_row)
+        .on_press(Message::Connect(host.id.clone()))
+        .padding([6, 12])
+        .width(Length::Fill)
+        .style(move |_theme, status| button::Style {
+            background: Some(Background::Color(match status {
+                button::Status::Hovered => Color::from_rgba(0.4, 0.3, 0.8, 0.25),
+                _ => Color::from_rgba(1.0, 1.0, 1.0, 0.04),
+            })),
+            border: Border {
+                color: if highlighted {
+                    Color::from_rgba(0.486, 0.227, 0.929, 0.9)
+                } else {
+                    Color::from_rgba(1.0, 1.0, 1.0, 0.08)
+                },
+                width: if highlighted { 2.0 } else { 1.0 },
+                radius: 8.0.into(),
+            },
+            text_color: Color::WHITE,
+            ..Default::default()
+        })
+        .into()
+}
+
+/// Search results, best match first
+fn render_search_results<'a>(state: &'a NebulaVaultState, mut list: Column<'a, Message>) -> Column<'a, Message> {
+    let results = state.search_results();
+    if results.is_empty() {
+        return list.push(
+            text("No hosts match the search")
+                .size(14)
+                .style(|_theme| text::Style {
+                    color: Some(iced::Color::from_rgb(0.6, 0.6, 0.65)),
+                }),
+        );
+    }
+    for host in results {
+        list = list.push(render_listed_host(state, host));
+    }
+    list
+}
+
+/// Tag filter input, e.g. `prod AND db NOT legacy`
+fn render_tag_filter(state: &NebulaVaultState) -> Element<'_, Message> {
+    let input = text_input("Filter tags: prod AND db NOT legacy", &state.tag_filter)
+        .on_input(Message::TagFilterChanged)
+        .size(12)
+        .padding([6, 10]);
+
+    let mut filter_row = row![input].spacing(8).align_y(iced::Alignment::Center);
+    if !state.tag_filter.is_empty() {
+        filter_row = filter_row.push(
+            button(text("×").size(12))
+                .on_press(Message::TagFilterChanged(String::new()))
+                .padding([4, 8]),
+        );
+    }
+
+    let mut filter = column![filter_row].spacing(4);
+    if let Some(error) = &state.tag_filter_error {
+        filter = filter.push(
+            text(error)
+                .size(12)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgb(1.0, 0.4, 0.4)),
+                }),
+        );
+    }
+    filter.into()
+}
+
+fn render_group_item(group: &Group, host_count: usize, collapsed: bool) -> Element<'static, Message> {
+    let name_text = text(format!("{} {}", if collapsed { "▸" } else { "▾" }, group.name))
+        .size(14)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.9, 0.9, 0.9)),
+        });
+
+    let count_text = text(host_count.to_string())
+        .size(12)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.6, 0.6, 0.65)),
+        });
+
+    let action_style = |_theme: &iced::Theme, status: button::Status| button::Style {
+        background: Some(Background::Color(match status {
+            button::Status::Hovered => Color::from_rgb(0.3, 0.5, 0.7),
+            _ => Color::from_rgba(1.0, 1.0, 1.0, 0.06),
+        })),
+        border: Border {
+            radius: 4.0.into(),
+            ..Default::default()
+        },
+        text_color: Color::WHITE,
+        ..Default::default()
+    };
+
+    let actions = row![
+        button(text("+").size(12))
+            .on_press(Message::ShowAddGroupDialog(Some(group.id.clone())))
+            .padding([2, 8])
+            .style(action_style),
+        button(text("Edit").size(12))
+            .on_press(Message::ShowEditGroupDialog(group.id.clone()))
+            .padding([2, 8])
+            .style(action_style),
+        button(text("Del").size(12))
+            .on_press(Message::ShowGroupDeleteConfirm(group.id.clone()))
+            .padding([2, 8])
+            .style(move |theme, status| button::Style {
+                background: Some(Background::Color(match status {
+                    button::Status::Hovered => Color::from_rgb(0.8, 0.3, 0.3),
+                    _ => Color::from_rgba(1.0, 1.0, 1.0, 0.06),
+                })),
+                ..action_style(theme, status)
+            }),
+    ]
+    .spacing(4);
+
+    let item_row = row![name_text, count_text, iced::widget::horizontal_space(), actions]
+        .spacing(8)
+        .align_y(iced::Alignment::Center)
+        .padding([6, 8]);
+
+    // Clicking the folder row folds it
+    button(item_row)
+        .on_press(Message::GroupToggled(group.id.clone()))
+        .width(Length::Fill)
+        .padding(0)
+        .style(|_theme, status| button::Style {
+            background: Some(Background::Color(match status {
+                button::Status::Hovered => Color::from_rgba(1.0, 1.0, 1.0, 0.08),
+                _ => Color::TRANSPARENT,
+            })),
+            border: Border {
+                radius: 8.0.into(),
+                ..Default::default()
+            },
+            text_color: Color::WHITE,
+            ..Default::default()
+        })
+        .into()
+}
+
+/// Actions for the hosts ticked in the sidebar
+fn render_selection_bar(state: &NebulaVaultState) -> Element<'_, Message> {
+    let count = state.selected_hosts.len();
+    let count_text = text(format!("{} selected", count))
+        .size(12)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+        });
+
+    // Opening a single host in panes is just a connection
+    let open_button = button(text("Open in panes").size(12))
+        .on_press_maybe((count > 1).then_some(Message::BroadcastConnect))
+        .padding([6, 10])
+        .style(|_theme, status| button::Style {
+            background: Some(Background::Color(match status {
+                button::Status::Hovered => Color::from_rgba(0.486, 0.227, 0.929, 0.5),
+                button::Status::Disabled => Color::from_rgba(0.486, 0.227, 0.929, 0.1),
+                _ => Color::from_rgba(0.486, 0.227, 0.929, 0.3),
+            })),
+            border: Border {
+                color: Color::from_rgba(0.486, 0.227, 0.929, 0.6),
+                width: 1.0,
+                radius: 6.0.into(),
+            },
+            text_color: match status {
+                button::Status::Disabled => Color::from_rgba(1.0, 1.0, 1.0, 0.4),
+                _ => Color::WHITE,
+            },
+            ..Default::default()
+        });
+
+    let clear_button = button(text("Clear").size(12))
+        .on_press(Message::ClearHostSelection)
+        .padding([6, 10])
+        .style(|_theme, status| button::Style {
+            background: Some(Background::Color(match status {
+                button::Status::Hovered => Color::from_rgba(1.0, 1.0, 1.0, 0.15),
+                _ => Color::from_rgba(1.0, 1.0, 1.0, 0.06),
+            })),
+            border: Border {
+                color: Color::from_rgba(1.0, 1.0, 1.0, 0.2),
+                width: 1.0,
+                radius: 6.0.into(),
+            },
+            text_color: Color::WHITE,
+            ..Default::default()
+        });
+
+    let actions = row![count_text, iced::widget::horizontal_space(), open_button, clear_button]
+        .spacing(8)
+        .align_y(iced::Alignment::Center);
+
+    let move_picker = pick_list(state.folder_choices(None), None::<crate::gui::state::FolderChoice>, Message::MoveSelectedHosts)
+        .placeholder("Move to folder…")
+        .text_size(12)
+        .padding([6, 10])
+        .width(Length::Fill);
+
+    column![actions, move_picker].spacing(8).into()
+}
+
+fn render_host_item(
+    id: &str,
+    name: &str,
+    hostname: &str,
+    tags: &[String],
+    selected: bool,
+    highlighted: bool,
+) -> Element<'static, Message> {
     let id_owned = id.to_string();
+    let id_for_select = id.to_string();
     let id_for_edit = id.to_string();
     let id_for_delete = id.to_string();
     let name_owned = name.to_string();
@@ -116,9 +503,16 @@ fn render_host_item(id: &str, name: &str, hostname: &str) -> Element<'static, Me
             color: Some(iced::Color::from_rgb(0.6, 0.6, 0.65)),
         });
 
-    let info_column = column![name_text, hostname_text]
+    let mut info_column = column![name_text, hostname_text]
         .spacing(4)
         .width(Length::Fill);
+    // Clicking a tag filters the sidebar by it
+    if !tags.is_empty() {
+        let chips = tags
+            .iter()
+            .map(|tag| tag_chip(tag.clone(), 11, Message::TagFilterChanged(tag.clone())));
+        info_column = info_column.push(row(chips).spacing(4).wrap());
+    }
 
     // Action buttons with text labels instead of icons
     let edit_button = button(text("Edit").size(12))
@@ -155,8 +549,14 @@ fn render_host_item(id: &str, name: &str, hostname: &str) -> Element<'static, Me
 
     let actions = row![edit_button, delete_button].spacing(4);
 
+    // Tick hosts to open several at once
+    let select_box = checkbox("", selected)
+        .on_toggle(move |selected| Message::HostSelectionToggled(id_for_select.clone(), selected))
+        .size(16)
+        .spacing(0);
+
     // Main clickable area
-    let item_row = row![info_column, actions]
+    let item_row = row![select_box, info_column, actions]
         .spacing(8)
         .align_y(iced::Alignment::Center)
         .padding(12)
@@ -165,7 +565,7 @@ fn render_host_item(id: &str, name: &str, hostname: &str) -> Element<'static, Me
     button(item_row)
         .on_press(Message::Connect(id_owned))
         .width(Length::Fill)
-        .style(|_theme, status| button::Style {
+        .style(move |_theme, status| button::Style {
             background: Some(Background::Gradient(Gradient::Linear(
                 iced::gradient::Linear::new(135.0) // Diagonal glassy gradient
                     .add_stop(0.0, match status {
@@ -177,12 +577,14 @@ fn render_host_item(id: &str, name: &str, hostname: &str) -> Element<'static, Me
                         _ => Color::from_rgba(0.2, 0.4, 0.7, 0.1),
                     })
             ))),
+            // The keyboard selection gets the electric purple focus border
             border: Border {
                 color: match status {
+                    _ if highlighted => Color::from_rgba(0.486, 0.227, 0.929, 0.9),
                     button::Status::Hovered => Color::from_rgba(1.0, 1.0, 1.0, 0.3),
                     _ => Color::from_rgba(1.0, 1.0, 1.0, 0.12),
                 },
-                width: 1.0,
+                width: if highlighted { 2.0 } else { 1.0 },
                 radius: 12.0.into(),
             },
             shadow: iced::Shadow {
@@ -202,8 +604,6 @@ fn render_host_item(id: &str, name: &str, hostname: &str) -> Element<'static, Me
 }
 
 fn render_main_content(state: &NebulaVaultState) -> Element<'_, Message> {
-    use iced::widget::Space;
-    
     // Welcome header
     let welcome = text("Nebula Vault")
         .size(48)
@@ -1,8 +1,11 @@
 // Main view modules
+pub mod agent_confirm;
 pub mod auth;
 pub mod main_view;
 pub mod host_dialogs;
+pub mod group_dialogs;
 pub mod identity_dialogs;
+pub mod launcher;
 pub mod settings;
 
 use iced::Element;
@@ -10,15 +13,24 @@ use crate::gui::messages::Message;
 use crate::gui::state::{AppState, NebulaVaultState};
 
 pub fn render(state: &NebulaVaultState) -> Element<'_, Message> {
+    // Pending agent confirmations take over the window until answered
+    if let Some(request) = state.agent_confirmations.first() {
+        return agent_confirm::view_agent_confirm(state, request);
+    }
+
     match &state.state {
         AppState::PasswordEntry => auth::view_password_entry(state),
         AppState::Loading => auth::view_loading(),
         AppState::Ready => main_view::view_main(state),
         AppState::HostDialog => host_dialogs::view_host_dialog(state),
         AppState::DeleteConfirm(host_id) => host_dialogs::view_delete_confirm(state, host_id),
+        AppState::GroupDialog => group_dialogs::view_group_dialog(state),
+        AppState::GroupDeleteConfirm(group_id) => group_dialogs::view_group_delete_confirm(state, group_id),
         AppState::IdentityList => identity_dialogs::view_identity_list(state),
         AppState::IdentityDialog => identity_dialogs::view_identity_dialog(state),
         AppState::IdentityDeleteConfirm(identity_id) => identity_dialogs::view_identity_delete_confirm(state, identity_id),
+        AppState::HostKeyPrompt(pending) => host_dialogs::view_host_key_prompt(state, pending),
+        AppState::SshConfigPreview => settings::view_ssh_config_preview(state),
         AppState::Settings => settings::view_settings(state),
         AppState::Error(e) => auth::view_error(e),
     }
@@ -1,7 +1,15 @@
-use iced::{widget::{button, column, container, pick_list, row, text, Space}, Element, Length, Color, Background, Border};
+use iced::{widget::{button, checkbox, column, container, pick_list, row, scrollable, text, text_input, Space}, Element, Length, Color, Background, Border};
+use crate::config;
+use crate::daemon;
 use crate::gui::messages::Message;
-use crate::gui::state::NebulaVaultState;
-use crate::terminal_launcher::TerminalApp;
+use crate::gui::state::{AutoLockTimeout, HistoryRetention, NebulaVaultState};
+use crate::ssh_config::{self, ImportAction, ImportPlan};
+use crate::terminal_launcher::{MultiplexerTarget, TerminalApp, TEMPLATE_PLACEHOLDERS};
+use crate::vault::KdfPreset;
+use std::time::Duration;
+
+/// Derivation time above which a preset is no longer recommended
+const KDF_TIME_BUDGET: Duration = Duration::from_secs(1);
 
 pub fn view_settings(state: &NebulaVaultState) -> Element<'_, Message> {
     let title = text("Settings")
@@ -29,8 +37,18 @@ pub fn view_settings(state: &NebulaVaultState) -> Element<'_, Message> {
             color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
         });
 
-    // Get available terminals
-    let available_terminals = TerminalApp::detect_available();
+    // Get available terminals, followed by saved custom terminals
+    let mut available_terminals: Vec<TerminalApp> = TerminalApp::detect_available()
+        .into_iter()
+        .map(|terminal| match terminal {
+            TerminalApp::Multiplexer(target) => TerminalApp::Multiplexer(MultiplexerTarget {
+                session: state.multiplexer_session.clone(),
+                ..target
+            }),
+            terminal => terminal,
+        })
+        .collect();
+    available_terminals.extend(state.terminal_templates.iter().cloned().map(TerminalApp::Custom));
     
     let terminal_picker = pick_list(
         available_terminals,
@@ -39,7 +57,7 @@ pub fn view_settings(state: &NebulaVaultState) -> Element<'_, Message> {
     )
     .placeholder("Select terminal...")
     .width(Length::Fixed(300.0))
-    .style(|theme, status| {
+    .style(|_theme, status| {
         let base = pick_list::Style {
             background: Background::Color(Color::from_rgba(0.1, 0.1, 0.15, 0.6)),
             border: Border {
@@ -74,6 +92,50 @@ pub fn view_settings(state: &NebulaVaultState) -> Element<'_, Message> {
         color: Some(Color::from_rgba(0.486, 0.227, 0.929, 0.9)),
     });
 
+    let multiplexer_session: Element<'_, Message> = match &state.terminal_preference {
+        TerminalApp::Multiplexer(target) => {
+            let hint = match MultiplexerTarget::validate_session(&target.session) {
+                Ok(()) => format!(
+                    "Connections open in session '{}', which is started detached if it is not running",
+                    target.session
+                ),
+                Err(e) => e.to_string(),
+            };
+            column![
+                text("Session:")
+                    .size(14)
+                    .style(|_theme| text::Style {
+                        color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+                    }),
+                text_input("nebulavault", &state.multiplexer_session)
+                    .on_input(Message::MultiplexerSessionChanged)
+                    .padding(10)
+                    .width(Length::Fixed(300.0)),
+                text(hint)
+                    .size(13)
+                    .style(|_theme| text::Style {
+                        color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+                    }),
+            ]
+            .spacing(8)
+            .into()
+        }
+        _ => Space::with_height(0).into(),
+    };
+
+    let agent_socket = text(match &state.agent {
+        Some(agent) => format!("SSH agent: SSH_AUTH_SOCK={}", agent.socket_path().display()),
+        None if state.daemon.is_some() => format!(
+            "SSH agent: served by the daemon, SSH_AUTH_SOCK={}",
+            daemon::agent_socket_path().display()
+        ),
+        None => "SSH agent: not running".to_string(),
+    })
+    .size(13)
+    .style(|_theme| text::Style {
+        color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+    });
+
     // Glass container for terminal settings
     let terminal_section = container(
         column![
@@ -81,8 +143,10 @@ pub fn view_settings(state: &NebulaVaultState) -> Element<'_, Message> {
             Space::with_height(12),
             terminal_label,
             terminal_picker,
+            multiplexer_session,
             Space::with_height(8),
             current_terminal,
+            agent_socket,
         ]
         .spacing(8)
     )
@@ -98,6 +162,15 @@ pub fn view_settings(state: &NebulaVaultState) -> Element<'_, Message> {
         ..Default::default()
     });
 
+    let terminal_templates_section = render_terminal_templates_section(state);
+    let auto_lock_section = render_auto_lock_section(state);
+    let hotkey_section = render_hotkey_section(state);
+    let history_section = render_history_section(state);
+    let password_section = render_password_section(state);
+    let kdf_section = render_kdf_section(state);
+    let known_hosts_section = render_known_hosts_section(state);
+    let ssh_config_section = render_ssh_config_section(state);
+
     // Back button with glass styling
     let back_button = button(
         text("← Back")
@@ -119,12 +192,38 @@ pub fn view_settings(state: &NebulaVaultState) -> Element<'_, Message> {
         ..Default::default()
     });
 
+    let mut header = column![title, subtitle].spacing(8);
+    if let Some(error) = &state.error_message {
+        header = header.push(
+            text(error)
+                .size(14)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgb(1.0, 0.4, 0.4)),
+                }),
+        );
+    }
+
     let content = column![
-        title,
-        subtitle,
+        header,
         Space::with_height(32),
         terminal_section,
         Space::with_height(24),
+        terminal_templates_section,
+        Space::with_height(24),
+        auto_lock_section,
+        Space::with_height(24),
+        hotkey_section,
+        Space::with_height(24),
+        history_section,
+        Space::with_height(24),
+        password_section,
+        Space::with_height(24),
+        kdf_section,
+        Space::with_height(24),
+        known_hosts_section,
+        Space::with_height(24),
+        ssh_config_section,
+        Space::with_height(24),
         back_button,
     ]
     .spacing(8)
@@ -133,7 +232,7 @@ pub fn view_settings(state: &NebulaVaultState) -> Element<'_, Message> {
     .max_width(700);
 
     // Center the content
-    let centered = container(content)
+    let centered = container(scrollable(content))
         .width(Length::Fill)
         .height(Length::Fill)
         .center_x(Length::Fill)
@@ -149,3 +248,715 @@ pub fn view_settings(state: &NebulaVaultState) -> Element<'_, Message> {
         })
         .into()
 }
+
+fn render_terminal_templates_section(state: &NebulaVaultState) -> Element<'_, Message> {
+    let form = &state.terminal_template_form;
+
+    let section_title = text("Custom Terminals")
+        .size(18)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.9, 0.9, 0.9)),
+        });
+
+    let placeholders: Vec<String> = TEMPLATE_PLACEHOLDERS
+        .iter()
+        .map(|(placeholder, meaning)| format!("{} {}", placeholder, meaning))
+        .collect();
+    let description = text(format!(
+        "Launch any terminal from a command template. Arguments are passed to the program directly, never through a shell. Placeholders: {}.",
+        placeholders.join(", ")
+    ))
+    .size(13)
+    .style(|_theme| text::Style {
+        color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+    });
+
+    let mut saved = column![].spacing(6);
+    for template in &state.terminal_templates {
+        saved = saved.push(
+            row![
+                column![
+                    text(&template.name).size(14),
+                    text(template.command_line())
+                        .size(12)
+                        .style(|_theme| text::Style {
+                            color: Some(Color::from_rgba(0.7, 0.7, 0.7, 0.9)),
+                        }),
+                ]
+                .spacing(2)
+                .width(Length::Fill),
+                button(text("Use").size(13))
+                    .on_press(Message::TerminalPreferenceChanged(TerminalApp::Custom(template.clone())))
+                    .padding([6, 12]),
+                button(text("Edit").size(13))
+                    .on_press(Message::EditTerminalTemplate(template.clone()))
+                    .padding([6, 12]),
+                button(text("Delete").size(13))
+                    .on_press(Message::DeleteTerminalTemplate(template.name.clone()))
+                    .padding([6, 12]),
+            ]
+            .spacing(8)
+            .align_y(iced::Alignment::Center),
+        );
+    }
+
+    let form_row = row![
+        text_input("Name", &form.name)
+            .on_input(Message::TerminalTemplateNameChanged)
+            .padding(10)
+            .width(Length::FillPortion(1)),
+        text_input("wezterm start --class {title} -- {ssh_argv}", &form.command)
+            .on_input(Message::TerminalTemplateCommandChanged)
+            .padding(10)
+            .width(Length::FillPortion(3)),
+    ]
+    .spacing(8);
+
+    let buttons = row![
+        button(text("Test Launch").size(14))
+            .on_press(Message::TestTerminalTemplate)
+            .padding([10, 20]),
+        button(text("Save").size(14))
+            .on_press(Message::SaveTerminalTemplate)
+            .padding([10, 20]),
+    ]
+    .spacing(8);
+
+    let mut section = column![
+        section_title,
+        Space::with_height(12),
+        description,
+        saved,
+        form_row,
+        buttons,
+    ]
+    .spacing(8);
+
+    if let Some(status) = &form.status {
+        section = section.push(
+            text(status)
+                .size(13)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgba(0.486, 0.227, 0.929, 0.9)),
+                }),
+        );
+    }
+
+    container(section)
+        .padding(24)
+        .width(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(Background::Color(Color::from_rgba(1.0, 1.0, 1.0, 0.05))),
+            border: Border {
+                color: Color::from_rgba(1.0, 1.0, 1.0, 0.1),
+                width: 1.0,
+                radius: 12.0.into(),
+            },
+            ..Default::default()
+        })
+        .into()
+}
+
+fn render_auto_lock_section(state: &NebulaVaultState) -> Element<'_, Message> {
+    let section_title = text("Auto-Lock")
+        .size(18)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.9, 0.9, 0.9)),
+        });
+
+    let timeout_label = text("Lock the vault when idle:")
+        .size(14)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+        });
+
+    let timeout_picker = pick_list(
+        AutoLockTimeout::ALL,
+        Some(state.auto_lock),
+        Message::AutoLockChanged,
+    )
+    .width(Length::Fixed(300.0));
+
+    let lock_button = button(text("Lock Now").size(14))
+        .on_press(Message::LockVault)
+        .padding([10, 20]);
+
+    container(
+        column![
+            section_title,
+            Space::with_height(12),
+            timeout_label,
+            timeout_picker,
+            Space::with_height(8),
+            lock_button,
+        ]
+        .spacing(8)
+    )
+    .padding(24)
+    .width(Length::Fill)
+    .style(|_theme| container::Style {
+        background: Some(Background::Color(Color::from_rgba(1.0, 1.0, 1.0, 0.05))),
+        border: Border {
+            color: Color::from_rgba(1.0, 1.0, 1.0, 0.1),
+            width: 1.0,
+            radius: 12.0.into(),
+        },
+        ..Default::default()
+    })
+    .into()
+}
+
+fn render_hotkey_section(state: &NebulaVaultState) -> Element<'_, Message> {
+    let form = &state.hotkey_form;
+
+    let section_title = text("Quick Connect")
+        .size(18)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.9, 0.9, 0.9)),
+        });
+
+    let description = text("A shortcut that opens the launcher from any application, e.g. ctrl+alt+space. Leave it empty to turn it off.")
+        .size(14)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+        });
+
+    let shortcut_row = row![
+        text_input(crate::config::DEFAULT_HOTKEY, &form.input)
+            .on_input(Message::HotkeyInputChanged)
+            .on_submit(Message::SaveHotkey)
+            .padding(10)
+            .width(Length::Fixed(300.0)),
+        button(text("Apply").size(14))
+            .on_press(Message::SaveHotkey)
+            .padding([10, 20]),
+    ]
+    .spacing(8)
+    .align_y(iced::Alignment::Center);
+
+    let mut section = column![section_title, Space::with_height(12), description, shortcut_row].spacing(8);
+
+    if let Some(error) = &form.error {
+        section = section.push(
+            text(error)
+                .size(13)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgb(1.0, 0.4, 0.4)),
+                }),
+        );
+    }
+    if let Some(reason) = &form.unavailable {
+        section = section.push(
+            text(format!("{}; the shortcut is saved but not active", reason))
+                .size(13)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+                }),
+        );
+    }
+
+    container(section)
+        .padding(24)
+        .width(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(Background::Color(Color::from_rgba(1.0, 1.0, 1.0, 0.05))),
+            border: Border {
+                color: Color::from_rgba(1.0, 1.0, 1.0, 0.1),
+                width: 1.0,
+                radius: 12.0.into(),
+            },
+            ..Default::default()
+        })
+        .into()
+}
+
+fn render_history_section(state: &NebulaVaultState) -> Element<'_, Message> {
+    let section_title = text("Connection History")
+        .size(18)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.9, 0.9, 0.9)),
+        });
+
+    let retention_label = text("Keep connection history for:")
+        .size(14)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+        });
+
+    let retention_picker = pick_list(
+        HistoryRetention::ALL,
+        Some(state.history_retention),
+        Message::HistoryRetentionChanged,
+    )
+    .width(Length::Fixed(300.0));
+
+    container(
+        column![
+            section_title,
+            Space::with_height(12),
+            retention_label,
+            retention_picker,
+        ]
+        .spacing(8)
+    )
+    .padding(24)
+    .width(Length::Fill)
+    .style(|_theme| container::Style {
+        background: Some(Background::Color(Color::from_rgba(1.0, 1.0, 1.0, 0.05))),
+        border: Border {
+            color: Color::from_rgba(1.0, 1.0, 1.0, 0.1),
+            width: 1.0,
+            radius: 12.0.into(),
+        },
+        ..Default::default()
+    })
+    .into()
+}
+
+fn render_password_section(state: &NebulaVaultState) -> Element<'_, Message> {
+    let form = &state.password_change_form;
+
+    let section_title = text("Master Password")
+        .size(18)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.9, 0.9, 0.9)),
+        });
+
+    let description = text("Re-encrypts every stored identity with the new password")
+        .size(14)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+        });
+
+    let current_input = text_input("Current password", &form.current)
+        .on_input(Message::CurrentPasswordChanged)
+        .secure(true)
+        .padding(10)
+        .width(Length::Fixed(300.0));
+
+    let new_input = text_input("New password", &form.new)
+        .on_input(Message::NewPasswordChanged)
+        .secure(true)
+        .padding(10)
+        .width(Length::Fixed(300.0));
+
+    let confirm_input = text_input("Confirm new password", &form.confirm)
+        .on_input(Message::ConfirmPasswordChanged)
+        .on_submit(Message::ChangeMasterPassword)
+        .secure(true)
+        .padding(10)
+        .width(Length::Fixed(300.0));
+
+    let change_button = button(text("Change Password").size(14))
+        .on_press(Message::ChangeMasterPassword)
+        .padding([10, 20])
+        .style(|_theme, status| button::Style {
+            background: Some(Background::Color(match status {
+                button::Status::Hovered => Color::from_rgba(0.486, 0.227, 0.929, 0.3),
+                _ => Color::from_rgba(0.486, 0.227, 0.929, 0.2),
+            })),
+            border: Border {
+                color: Color::from_rgba(0.486, 0.227, 0.929, 0.5),
+                width: 1.0,
+                radius: 8.0.into(),
+            },
+            text_color: Color::from_rgb(0.95, 0.95, 0.95),
+            ..Default::default()
+        });
+
+    let mut section = column![
+        section_title,
+        Space::with_height(12),
+        description,
+        current_input,
+        new_input,
+        confirm_input,
+        Space::with_height(8),
+        change_button,
+    ]
+    .spacing(8);
+
+    if let Some(error) = &form.error {
+        section = section.push(
+            text(error)
+                .size(13)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgb(1.0, 0.4, 0.4)),
+                }),
+        );
+    } else if form.changed {
+        section = section.push(
+            text("Master password changed")
+                .size(13)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgba(0.486, 0.227, 0.929, 0.9)),
+                }),
+        );
+    }
+
+    container(section)
+        .padding(24)
+        .width(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(Background::Color(Color::from_rgba(1.0, 1.0, 1.0, 0.05))),
+            border: Border {
+                color: Color::from_rgba(1.0, 1.0, 1.0, 0.1),
+                width: 1.0,
+                radius: 12.0.into(),
+            },
+            ..Default::default()
+        })
+        .into()
+}
+
+fn render_kdf_section(state: &NebulaVaultState) -> Element<'_, Message> {
+    let section_title = text("Key Derivation")
+        .size(18)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.9, 0.9, 0.9)),
+        });
+
+    let preset_label = text("Argon2id cost preset:")
+        .size(14)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+        });
+
+    let preset_picker = pick_list(
+        KdfPreset::ALL,
+        state.kdf_preset,
+        Message::KdfPresetSelected,
+    )
+    .placeholder("Custom parameters")
+    .width(Length::Fixed(300.0));
+
+    let current_params = state.vault.as_ref().map(|vault| vault.kdf_params());
+    let current = text(match current_params {
+        Some(params) => format!(
+            "Currently using: {} MB, t={}, p={}",
+            params.m_cost / 1024,
+            params.t_cost,
+            params.p_cost
+        ),
+        None => "Vault is locked".to_string(),
+    })
+    .size(13)
+    .style(|_theme| text::Style {
+        color: Some(Color::from_rgba(0.486, 0.227, 0.929, 0.9)),
+    });
+
+    let mut section = column![
+        section_title,
+        Space::with_height(12),
+        preset_label,
+        preset_picker,
+        Space::with_height(8),
+        current,
+    ]
+    .spacing(8);
+
+    let pending = match (state.kdf_preset, current_params) {
+        (Some(preset), Some(params)) => preset.params() != params,
+        _ => false,
+    };
+    if pending {
+        section = section.push(
+            text("The new preset is applied the next time the vault is unlocked")
+                .size(13)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+                }),
+        );
+    }
+
+    let calibrate_button = button(
+        text(if state.kdf_calibrating { "Calibrating..." } else { "Calibrate" }).size(14),
+    )
+    .on_press_maybe((!state.kdf_calibrating).then_some(Message::CalibrateKdf))
+    .padding([10, 20]);
+
+    section = section.push(Space::with_height(8)).push(calibrate_button);
+
+    if !state.kdf_timings.is_empty() {
+        let recommended = KdfPreset::recommended(&state.kdf_timings, KDF_TIME_BUDGET);
+        for (preset, elapsed) in &state.kdf_timings {
+            let marker = if *preset == recommended { "  (recommended)" } else { "" };
+            section = section.push(
+                text(format!("{}: {} ms{}", preset, elapsed.as_millis(), marker))
+                    .size(13)
+                    .style(|_theme| text::Style {
+                        color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+                    }),
+            );
+        }
+    }
+
+    container(section)
+        .padding(24)
+        .width(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(Background::Color(Color::from_rgba(1.0, 1.0, 1.0, 0.05))),
+            border: Border {
+                color: Color::from_rgba(1.0, 1.0, 1.0, 0.1),
+                width: 1.0,
+                radius: 12.0.into(),
+            },
+            ..Default::default()
+        })
+        .into()
+}
+
+fn render_known_hosts_section(state: &NebulaVaultState) -> Element<'_, Message> {
+    let form = &state.known_hosts_form;
+
+    let section_title = text("Known Hosts")
+        .size(18)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.9, 0.9, 0.9)),
+        });
+
+    let description = text("Share trusted host keys with OpenSSH. Hashed entries, @cert-authority and @revoked lines are kept.")
+        .size(13)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+        });
+
+    let import_row = row![
+        text_input("~/.ssh/known_hosts", &form.import_path)
+            .on_input(Message::KnownHostsImportPathChanged)
+            .padding(10),
+        button(text("Import").size(14))
+            .on_press(Message::ImportKnownHosts)
+            .padding([10, 20]),
+    ]
+    .spacing(8);
+
+    let export_row = row![
+        text_input("~/.ssh/nebulavault_known_hosts", &form.export_path)
+            .on_input(Message::KnownHostsExportPathChanged)
+            .padding(10),
+        button(text("Export").size(14))
+            .on_press(Message::ExportKnownHosts)
+            .padding([10, 20]),
+    ]
+    .spacing(8);
+
+    let mut section = column![
+        section_title,
+        Space::with_height(12),
+        description,
+        import_row,
+        export_row,
+    ]
+    .spacing(8);
+
+    if let Some(status) = &form.status {
+        section = section.push(
+            text(status)
+                .size(13)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgba(0.486, 0.227, 0.929, 0.9)),
+                }),
+        );
+    }
+
+    container(section)
+        .padding(24)
+        .width(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(Background::Color(Color::from_rgba(1.0, 1.0, 1.0, 0.05))),
+            border: Border {
+                color: Color::from_rgba(1.0, 1.0, 1.0, 0.1),
+                width: 1.0,
+                radius: 12.0.into(),
+            },
+            ..Default::default()
+        })
+        .into()
+}
+
+fn render_ssh_config_section(state: &NebulaVaultState) -> Element<'_, Message> {
+    let form = &state.ssh_config_form;
+
+    let section_title = text("OpenSSH Config")
+        .size(18)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.9, 0.9, 0.9)),
+        });
+
+    let description = text("Add the hosts of an OpenSSH config file. Referenced IdentityFiles are stored as encrypted identities. Nothing is written before you confirm the preview.")
+        .size(13)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+        });
+
+    let include_toggle = checkbox(
+        format!("Write the default vault's hosts to {}", ssh_config::include_file_path().display()),
+        state.settings.ssh_include,
+    )
+    .on_toggle(Message::SshIncludeToggled);
+
+    let mut include_hint = format!(
+        "Add \"Include {}\" at the top of ~/.ssh/config to use them with ssh, scp, rsync and git. Their keys come from Nebula Vault's agent, so ssh can only use them while Nebula Vault runs with the vault unlocked.",
+        ssh_config::INCLUDE_FILE_NAME
+    );
+    if state.settings.ssh_include && state.vault_path != config::default_vault_path() {
+        include_hint.push_str(" The open vault is not the default vault, so its hosts are not written.");
+    }
+    let include_hint = text(include_hint)
+        .size(13)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+        });
+
+    let path_row = row![
+        text_input("~/.ssh/config", &form.path)
+            .on_input(Message::SshConfigPathChanged)
+            .padding(10),
+        button(text("Preview").size(14))
+            .on_press(Message::PreviewSshConfig)
+            .padding([10, 20]),
+    ]
+    .spacing(8);
+
+    let mut section = column![
+        section_title,
+        Space::with_height(12),
+        include_toggle,
+        include_hint,
+        Space::with_height(8),
+        description,
+        path_row,
+    ]
+    .spacing(8);
+
+    if let Some(status) = &form.status {
+        section = section.push(
+            text(status)
+                .size(13)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgba(0.486, 0.227, 0.929, 0.9)),
+                }),
+        );
+    }
+
+    container(section)
+        .padding(24)
+        .width(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(Background::Color(Color::from_rgba(1.0, 1.0, 1.0, 0.05))),
+            border: Border {
+                color: Color::from_rgba(1.0, 1.0, 1.0, 0.1),
+                width: 1.0,
+                radius: 12.0.into(),
+            },
+            ..Default::default()
+        })
+        .into()
+}
+
+/// Preview of an ssh_config import, confirmed before anything is written
+pub fn view_ssh_config_preview(state: &NebulaVaultState) -> Element<'_, Message> {
+    let empty = ImportPlan::default();
+    let plan = state.ssh_config_form.plan.as_ref().unwrap_or(&empty);
+
+    let title = text("Import Hosts")
+        .size(24)
+        .style(|_theme| text::Style {
+            color: Some(Color::from_rgb(0.95, 0.95, 0.95)),
+        });
+
+    let summary = text(format!(
+        "{} to create, {} to merge, {} to skip",
+        plan.count(|a| *a == ImportAction::Create),
+        plan.count(|a| matches!(a, ImportAction::Merge { .. })),
+        plan.count(|a| matches!(a, ImportAction::Skip { .. })),
+    ))
+    .size(14)
+    .style(|_theme| text::Style {
+        color: Some(Color::from_rgb(0.9, 0.9, 0.9)),
+    });
+
+    let mut entries = column![].spacing(12);
+    for planned in &plan.hosts {
+        let host = &planned.host;
+        let (action, color) = match &planned.action {
+            ImportAction::Create => ("Create".to_string(), Color::from_rgb(0.4, 0.9, 0.5)),
+            ImportAction::Merge { .. } => ("Merge".to_string(), Color::from_rgb(0.486, 0.227, 0.929)),
+            ImportAction::Skip { reason } => (format!("Skip: {}", reason), Color::from_rgb(0.6, 0.6, 0.65)),
+        };
+
+        let mut entry = column![
+            row![
+                text(host.alias.clone()).size(15),
+                text(action)
+                    .size(13)
+                    .style(move |_theme| text::Style { color: Some(color) }),
+            ]
+            .spacing(12),
+            text(format!("{}@{}:{}", host.user, host.hostname, host.port))
+                .size(13)
+                .style(|_theme| text::Style {
+                    color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.9)),
+                }),
+        ]
+        .spacing(4);
+
+        let mut details = Vec::new();
+        if let Some(path) = &planned.identity_file {
+            details.push(format!("Key: {}", path.display()));
+        }
+        if let Some(proxy_jump) = &host.proxy_jump {
+            details.push(format!("ProxyJump: {}", proxy_jump));
+        }
+        for forward in &host.local_forwards {
+            details.push(format!("LocalForward: {}", forward));
+        }
+        for detail in details {
+            entry = entry.push(text(detail).size(12).style(|_theme| text::Style {
+                color: Some(Color::from_rgba(0.8, 0.8, 0.8, 0.7)),
+            }));
+        }
+        for note in &planned.notes {
+            entry = entry.push(text(note.clone()).size(12).style(|_theme| text::Style {
+                color: Some(Color::from_rgb(1.0, 0.6, 0.3)),
+            }));
+        }
+
+        entries = entries.push(entry);
+    }
+
+    let mut dialog_content = column![title, summary].spacing(20);
+    for note in &plan.notes {
+        dialog_content = dialog_content.push(text(note.clone()).size(13).style(|_theme| text::Style {
+            color: Some(Color::from_rgb(1.0, 0.6, 0.3)),
+        }));
+    }
+
+    let buttons = row![
+        button(text("Cancel").size(14))
+            .on_press(Message::ShowSettings)
+            .padding([10, 20]),
+        button(text("Import").size(14))
+            .on_press(Message::ImportSshConfig)
+            .padding([10, 20]),
+    ]
+    .spacing(12);
+
+    let dialog_content = dialog_content
+        .push(scrollable(entries).height(Length::Fixed(320.0)))
+        .push(buttons)
+        .padding(30)
+        .max_width(500);
+
+    container(dialog_content)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .center_x(Length::Fill)
+        .center_y(Length::Fill)
+        .style(|_theme| container::Style {
+            background: Some(Background::Color(Color::from_rgba(0.0, 0.0, 0.0, 0.8))),
+            ..Default::default()
+        })
+        .into()
+}
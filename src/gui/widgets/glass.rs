@@ -1,6 +1,6 @@
 use iced::{
     widget::{container, Container},
-    Background, Border, Color, Element, Length, Shadow, Theme,
+    Background, Border, Color, Element, Shadow, Theme,
 };
 
 /// Glass container styling for glassmorphism effect
@@ -8,6 +8,7 @@ pub struct GlassContainer;
 
 impl GlassContainer {
     /// Create a glass container with the given child
+    #[allow(clippy::new_ret_no_self)]
     pub fn new<'a, Message: 'a>(
         content: impl Into<Element<'a, Message>>,
     ) -> Container<'a, Message> {
@@ -148,6 +149,29 @@ pub fn server_card<'a>(
     }
 }
 
+/// Tag pill, in the style of the [`server_card`] tags, that can be clicked
+pub fn tag_chip<'a, Message: Clone + 'a>(label: String, size: u16, on_press: Message) -> Element<'a, Message> {
+    use iced::widget::{button, text};
+
+    button(text(label).size(size))
+        .on_press(on_press)
+        .padding([2, 8])
+        .style(|_theme: &Theme, status| button::Style {
+            background: Some(Background::Color(match status {
+                button::Status::Hovered => Color::from_rgba(0.486, 0.227, 0.929, 0.5),
+                _ => Color::from_rgba(0.486, 0.227, 0.929, 0.3),
+            })),
+            border: Border {
+                color: Color::from_rgba(0.486, 0.227, 0.929, 0.5),
+                width: 1.0,
+                radius: 12.0.into(),
+            },
+            text_color: Color::from_rgb(0.9, 0.9, 0.9),
+            ..Default::default()
+        })
+        .into()
+}
+
 /// Neon glow input styling
 pub mod neon_input {
     use iced::{
@@ -1,4 +1,4 @@
-use iced::{widget::container, Color, Element, Length, Background, Border, Gradient};
+use iced::{widget::container, Color, Element, Length, Background, Gradient};
 
 pub struct GradientBackground;
 
@@ -1,4 +1,4 @@
-use iced::{widget::container, Color, Element, Length, Background, Border};
+use iced::{widget::container, Color, Element, Length, Background};
 use std::time::Instant;
 
 pub struct NebulaShader {
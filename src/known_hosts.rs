@@ -0,0 +1,539 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use russh_keys::encoding::Reader;
+use russh_keys::key::PublicKey;
+use russh_keys::PublicKeyBase64;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+
+use crate::agent::SshAgent;
+use crate::db;
+use crate::fs_util::write_private_file;
+
+/// Prefix of hostnames hashed with `HashKnownHosts`
+const HASHED_HOST_PREFIX: &str = "|1|";
+
+/// Line markers understood in known_hosts files
+const MARKER_CERT_AUTHORITY: &str = "cert-authority";
+const MARKER_REVOKED: &str = "revoked";
+
+/// A server host key as presented during key exchange
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerKey {
+    /// Key type from the key blob, e.g. `ssh-ed25519` or `ssh-rsa`
+    pub key_type: String,
+    /// `SHA256:<base64>`, as printed by `ssh-keygen -l`
+    pub fingerprint: String,
+    /// Base64 key blob, as in OpenSSH known_hosts files
+    pub public_key: String,
+}
+
+impl ServerKey {
+    pub fn from_public_key(key: &PublicKey) -> Self {
+        Self::from_blob(&key.public_key_bytes()).unwrap_or_else(|_| Self {
+            key_type: key.name().to_string(),
+            fingerprint: format!("SHA256:{}", key.fingerprint()),
+            public_key: key.public_key_base64(),
+        })
+    }
+
+    /// Describe a key blob; the key type is read from the blob itself, since
+    /// RSA keys report their signature algorithm as name
+    pub fn from_blob(blob: &[u8]) -> Result<Self> {
+        let key_type = blob
+            .reader(0)
+            .read_string()
+            .ok()
+            .and_then(|t| std::str::from_utf8(t).ok())
+            .filter(|t| !t.is_empty())
+            .context("Malformed public key")?
+            .to_string();
+
+        Ok(Self {
+            key_type,
+            fingerprint: format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(blob))),
+            public_key: STANDARD.encode(blob),
+        })
+    }
+}
+
+/// How a presented host key compares with the known hosts store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    /// The key is trusted for this host
+    Trusted,
+    /// Nothing is stored for the host
+    Unknown,
+    /// A different key of the same type is stored: possible man-in-the-middle
+    Mismatch { stored_fingerprint: String },
+    /// Only keys of other types are stored; accepting the new type on first use
+    /// would let an attacker downgrade to a key type they hold
+    KeyTypeChanged { trusted_types: Vec<String> },
+    /// The key is marked `@revoked` for this host
+    Revoked,
+}
+
+/// A server key that was not accepted while connecting
+#[derive(Debug, Clone)]
+pub struct HostKeyError {
+    pub hostname: String,
+    pub port: u16,
+    pub key: ServerKey,
+    pub status: HostKeyStatus,
+}
+
+impl std::fmt::Display for HostKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.status {
+            HostKeyStatus::Mismatch { stored_fingerprint } => write!(
+                f,
+                "Host key for {}:{} has changed! Expected {}, server offered {} ({})",
+                self.hostname, self.port, stored_fingerprint, self.key.fingerprint, self.key.key_type
+            ),
+            HostKeyStatus::Revoked => write!(
+                f,
+                "Host key for {}:{} has been revoked: {} ({})",
+                self.hostname, self.port, self.key.fingerprint, self.key.key_type
+            ),
+            HostKeyStatus::KeyTypeChanged { trusted_types } => write!(
+                f,
+                "Host key type for {}:{} has changed! Trusted {}, server offered {} ({})",
+                self.hostname,
+                self.port,
+                trusted_types.join(", "),
+                self.key.fingerprint,
+                self.key.key_type
+            ),
+            _ => write!(
+                f,
+                "Host key for {}:{} is not trusted: {} ({})",
+                self.hostname, self.port, self.key.fingerprint, self.key.key_type
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HostKeyError {}
+
+/// Hostnames are compared case-insensitively, as ssh does
+fn normalize_hostname(hostname: &str) -> String {
+    hostname.trim().to_ascii_lowercase()
+}
+
+/// Name a host is looked up by in known_hosts files: `host`, or `[host]:port`
+/// for other ports than 22
+fn lookup_name(hostname: &str, port: u16) -> String {
+    if port == 22 {
+        hostname.to_string()
+    } else {
+        format!("[{}]:{}", hostname, port)
+    }
+}
+
+/// Whether a known_hosts host field (hashed name or pattern list) matches the host
+fn hosts_field_matches(hosts: &str, hostname: &str, port: u16) -> bool {
+    let name = lookup_name(&normalize_hostname(hostname), port);
+
+    if let Some(hashed) = hosts.strip_prefix(HASHED_HOST_PREFIX) {
+        return hashed_name_matches(hashed, &name);
+    }
+
+    pattern_list_matches(hosts.split(','), &name)
+}
+
+/// Match a name against ssh host patterns (`*`, `?`, `!negation`), case-insensitively
+pub(crate) fn pattern_list_matches<'a>(patterns: impl IntoIterator<Item = &'a str>, name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let mut matched = false;
+    for pattern in patterns {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        if wildcard_match(pattern.to_ascii_lowercase().as_bytes(), name.as_bytes()) {
+            // A negated match rules the line out whatever else matches
+            if negated {
+                return false;
+            }
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Check `salt|hash` from a `|1|salt|hash` entry: HMAC-SHA1 of the name keyed with the salt
+fn hashed_name_matches(hashed: &str, name: &str) -> bool {
+    let Some((salt, hash)) = hashed.split_once('|') else {
+        return false;
+    };
+    let (Ok(salt), Ok(hash)) = (STANDARD.decode(salt), STANDARD.decode(hash)) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(&salt) else {
+        return false;
+    };
+    mac.update(name.as_bytes());
+    mac.verify_slice(&hash).is_ok()
+}
+
+/// `*` and `?` glob matching, as used in ssh host patterns
+pub(crate) fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], name) || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Compare a presented key with the keys trusted for the host, both those accepted
+/// in NebulaVault and those imported from known_hosts files
+pub async fn check(pool: &SqlitePool, hostname: &str, port: u16, key: &ServerKey) -> Result<HostKeyStatus> {
+    let entries: Vec<_> = db::get_known_host_entries(pool)
+        .await?
+        .into_iter()
+        .filter(|entry| hosts_field_matches(&entry.hosts, hostname, port))
+        .collect();
+
+    if entries
+        .iter()
+        .any(|entry| entry.marker == MARKER_REVOKED && entry.public_key == key.public_key)
+    {
+        return Ok(HostKeyStatus::Revoked);
+    }
+
+    // (key type, key, fingerprint) of every key trusted for the host
+    let mut trusted: Vec<(String, String, String)> = db::get_known_hosts(pool, &normalize_hostname(hostname), port as i64)
+        .await?
+        .into_iter()
+        .map(|k| (k.key_type, k.public_key, k.fingerprint))
+        .collect();
+    // CA keys sign host certificates, which the built-in client does not use;
+    // they only matter to ssh through the exported file
+    trusted.extend(
+        entries
+            .into_iter()
+            .filter(|entry| entry.marker.is_empty())
+            .map(|entry| (entry.key_type, entry.public_key, entry.fingerprint)),
+    );
+
+    let same_type: Vec<_> = trusted.iter().filter(|(key_type, _, _)| *key_type == key.key_type).collect();
+    if same_type.iter().any(|(_, public_key, _)| *public_key == key.public_key) {
+        return Ok(HostKeyStatus::Trusted);
+    }
+    if let Some((_, _, fingerprint)) = same_type.first() {
+        return Ok(HostKeyStatus::Mismatch {
+            stored_fingerprint: fingerprint.clone(),
+        });
+    }
+
+    if trusted.is_empty() {
+        return Ok(HostKeyStatus::Unknown);
+    }
+    let mut trusted_types: Vec<String> = trusted.into_iter().map(|(key_type, _, _)| key_type).collect();
+    trusted_types.sort();
+    trusted_types.dedup();
+    Ok(HostKeyStatus::KeyTypeChanged { trusted_types })
+}
+
+/// Trust a key for the host (trust on first use)
+pub async fn trust(pool: &SqlitePool, hostname: &str, port: u16, key: &ServerKey) -> Result<()> {
+    db::add_known_host(
+        pool,
+        &normalize_hostname(hostname),
+        port as i64,
+        &key.key_type,
+        &key.fingerprint,
+        &key.public_key,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Forget every key stored for the host, e.g. after a legitimate key rotation
+pub async fn forget(pool: &SqlitePool, hostname: &str, port: u16) -> Result<u64> {
+    db::delete_known_hosts(pool, &normalize_hostname(hostname), port as i64).await
+}
+
+/// One line of an OpenSSH known_hosts file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KnownHostsLine {
+    /// Empty, `cert-authority` or `revoked`
+    marker: String,
+    hosts: String,
+    key: ServerKey,
+    comment: Option<String>,
+}
+
+/// Parse one known_hosts line; `None` for blank lines and comments
+fn parse_line(line: &str) -> Option<Result<KnownHostsLine>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    Some((|| {
+        let mut fields = line.split_whitespace();
+        let mut first = fields.next().context("Missing host field")?;
+
+        let marker = match first.strip_prefix('@') {
+            Some(marker @ (MARKER_CERT_AUTHORITY | MARKER_REVOKED)) => {
+                first = fields.next().context("Missing host field")?;
+                marker.to_string()
+            }
+            Some(marker) => anyhow::bail!("Unknown marker @{}", marker),
+            None => String::new(),
+        };
+
+        let key_type = fields.next().context("Missing key type")?;
+        let blob = STANDARD
+            .decode(fields.next().context("Missing key")?)
+            .context("Key is not valid base64")?;
+        let key = ServerKey::from_blob(&blob)?;
+        if key.key_type != key_type {
+            anyhow::bail!("Key type {} does not match key blob ({})", key_type, key.key_type);
+        }
+
+        let comment = fields.collect::<Vec<_>>().join(" ");
+
+        Ok(KnownHostsLine {
+            marker,
+            hosts: first.to_string(),
+            key,
+            comment: (!comment.is_empty()).then_some(comment),
+        })
+    })())
+}
+
+/// Outcome of a known_hosts import
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Lines that were already stored
+    pub duplicates: usize,
+    /// Lines that could not be parsed
+    pub skipped: usize,
+}
+
+impl std::fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Imported {} host keys ({} already known, {} unreadable lines skipped)",
+            self.imported, self.duplicates, self.skipped
+        )
+    }
+}
+
+/// Import the contents of an OpenSSH known_hosts file
+pub async fn import(pool: &SqlitePool, contents: &str) -> Result<ImportSummary> {
+    let mut summary = ImportSummary::default();
+
+    for line in contents.lines().filter_map(parse_line) {
+        let Ok(line) = line else {
+            summary.skipped += 1;
+            continue;
+        };
+
+        let added = db::add_known_host_entry(
+            pool,
+            &line.marker,
+            &line.hosts,
+            &line.key.key_type,
+            &line.key.fingerprint,
+            &line.key.public_key,
+            line.comment.as_deref(),
+        )
+        .await?;
+        if added {
+            summary.imported += 1;
+        } else {
+            summary.duplicates += 1;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Import an OpenSSH known_hosts file
+pub async fn import_file(pool: &SqlitePool, path: &Path) -> Result<ImportSummary> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    import(pool, &contents).await
+}
+
+/// Render every trusted key as an OpenSSH known_hosts file
+pub async fn export(pool: &SqlitePool) -> Result<String> {
+    let mut contents = String::from("# Generated by NebulaVault. Changes are overwritten.\n");
+
+    for known in db::get_all_known_hosts(pool).await? {
+        contents.push_str(&format!(
+            "{} {} {}\n",
+            lookup_name(&known.hostname, known.port as u16),
+            known.key_type,
+            known.public_key
+        ));
+    }
+
+    for entry in db::get_known_host_entries(pool).await? {
+        if !entry.marker.is_empty() {
+            contents.push_str(&format!("@{} ", entry.marker));
+        }
+        contents.push_str(&format!("{} {} {}", entry.hosts, entry.key_type, entry.public_key));
+        if let Some(comment) = &entry.comment {
+            contents.push_str(&format!(" {}", comment));
+        }
+        contents.push('\n');
+    }
+
+    Ok(contents)
+}
+
+/// Write the exported known_hosts file, replacing it atomically
+pub async fn export_file(pool: &SqlitePool, path: &Path) -> Result<()> {
+    let contents = export(pool).await?;
+    write_private_file(path, contents.as_bytes())
+}
+
+/// Exported file handed to ssh when launching connections to hosts of the vault
+/// at `vault_path`, next to the agent socket. Each vault has its own, named after
+/// a hash of its absolute path, so ssh is never handed another vault's keys.
+pub fn launch_file_path(vault_path: &Path) -> PathBuf {
+    let vault_path = std::path::absolute(vault_path).unwrap_or_else(|_| vault_path.to_path_buf());
+    let digest = Sha256::digest(vault_path.as_os_str().as_encoded_bytes());
+    let id: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    SshAgent::default_socket_path().with_file_name(format!("known_hosts-{}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh_keys::key::KeyPair;
+
+    #[tokio::test]
+    async fn test_trust_on_first_use_then_mismatch() {
+        let test_db = db::TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let key = ServerKey::from_public_key(&KeyPair::generate_ed25519().unwrap().clone_public_key().unwrap());
+        let rotated = ServerKey::from_public_key(&KeyPair::generate_ed25519().unwrap().clone_public_key().unwrap());
+        assert_eq!(key.key_type, "ssh-ed25519");
+        assert!(key.fingerprint.starts_with("SHA256:"));
+
+        assert_eq!(
+            check(&pool, "Example.com", 22, &key).await.unwrap(),
+            HostKeyStatus::Unknown
+        );
+
+        trust(&pool, "Example.com", 22, &key).await.unwrap();
+        assert_eq!(check(&pool, "example.com", 22, &key).await.unwrap(), HostKeyStatus::Trusted);
+        // Same host on another port is a different entry
+        assert!(matches!(
+            check(&pool, "example.com", 2222, &key).await.unwrap(),
+            HostKeyStatus::Unknown
+        ));
+        assert_eq!(
+            check(&pool, "example.com", 22, &rotated).await.unwrap(),
+            HostKeyStatus::Mismatch {
+                stored_fingerprint: key.fingerprint.clone()
+            }
+        );
+
+        // A key of another type must not be trusted on first use
+        let other_type = ServerKey {
+            key_type: "ecdsa-sha2-nistp256".to_string(),
+            ..rotated.clone()
+        };
+        assert_eq!(
+            check(&pool, "example.com", 22, &other_type).await.unwrap(),
+            HostKeyStatus::KeyTypeChanged {
+                trusted_types: vec!["ssh-ed25519".to_string()]
+            }
+        );
+
+        assert_eq!(forget(&pool, "example.com", 22).await.unwrap(), 1);
+        assert!(matches!(
+            check(&pool, "example.com", 22, &rotated).await.unwrap(),
+            HostKeyStatus::Unknown
+        ));
+    }
+
+    #[test]
+    fn test_each_vault_has_its_own_launch_file() {
+        let cwd = std::env::current_dir().unwrap();
+        let default = launch_file_path(Path::new("vault.db"));
+        assert_eq!(default, launch_file_path(&cwd.join("vault.db")));
+        assert_ne!(default, launch_file_path(&cwd.join("work.db")));
+        assert_eq!(default.parent(), SshAgent::default_socket_path().parent());
+    }
+
+    #[tokio::test]
+    async fn test_import_openssh_known_hosts() {
+        // Hashed with `ssh-keygen -H` from hashed.example.com and [alt.example.com]:2222
+        const K1: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIK4qpQWm3W3/w6iZHDGn3MCptWR1oVXwZgaFQGMOWIF6";
+        const K2: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIOlkIl60EBRHmXNU1Ffq2xQWbCO7zuLkB5mgi/25tTR5";
+        const K3: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIJxuk8s/KmRE8fnLxJTVzc1EIxlXLIq6nL4qmBgJegZx";
+        let contents = format!(
+            "# comment\n\
+             |1|+s3Ee6iNCoQFZclObLBGnqBdlMI=|Vs/XaKKl1iIcs0s/XYnoyOuS4oI= ssh-ed25519 {K1}\n\
+             |1|BCg2KPrbKG2fxutwx4au1XmkTtY=|kFHIg5wKuc+HwV2EbaUjrKUgSqE= ssh-ed25519 {K2}\n\
+             *.corp.example.com,!bastion.corp.example.com ssh-ed25519 {K3}\n\
+             @cert-authority *.example.com ssh-ed25519 {K3} example CA\n\
+             @revoked * ssh-ed25519 {K2}\n\
+             not a valid line\n"
+        );
+
+        let test_db = db::TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let summary = import(&pool, &contents).await.unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                imported: 5,
+                duplicates: 0,
+                skipped: 1
+            }
+        );
+        assert_eq!(import(&pool, &contents).await.unwrap().duplicates, 5);
+
+        let key = |b64: &str| ServerKey::from_blob(&STANDARD.decode(b64).unwrap()).unwrap();
+        assert_eq!(key(K1).fingerprint, "SHA256:DL3UnKpjG2KnCDFf2Xqhox7+QeCvlxatfkXZohOmblM");
+
+        assert_eq!(check(&pool, "hashed.example.com", 22, &key(K1)).await.unwrap(), HostKeyStatus::Trusted);
+        assert_eq!(
+            check(&pool, "hashed.example.com", 22, &key(K3)).await.unwrap(),
+            HostKeyStatus::Mismatch {
+                stored_fingerprint: key(K1).fingerprint
+            }
+        );
+        assert_eq!(check(&pool, "alt.example.com", 2222, &key(K2)).await.unwrap(), HostKeyStatus::Revoked);
+        assert_eq!(check(&pool, "web.corp.example.com", 22, &key(K3)).await.unwrap(), HostKeyStatus::Trusted);
+        // Negated pattern, and CA keys do not trust hosts directly
+        assert!(matches!(
+            check(&pool, "bastion.corp.example.com", 22, &key(K3)).await.unwrap(),
+            HostKeyStatus::Unknown
+        ));
+        assert!(matches!(
+            check(&pool, "www.example.com", 22, &key(K3)).await.unwrap(),
+            HostKeyStatus::Unknown
+        ));
+
+        trust(&pool, "new.example.com", 2200, &key(K1)).await.unwrap();
+        let exported = export(&pool).await.unwrap();
+        assert!(exported.contains(&format!("[new.example.com]:2200 ssh-ed25519 {K1}\n")));
+        assert!(exported.contains(&format!("@cert-authority *.example.com ssh-ed25519 {K3} example CA\n")));
+        assert!(exported.contains(&format!("@revoked * ssh-ed25519 {K2}\n")));
+        assert!(exported.contains(&format!("|1|+s3Ee6iNCoQFZclObLBGnqBdlMI=|Vs/XaKKl1iIcs0s/XYnoyOuS4oI= ssh-ed25519 {K1}\n")));
+    }
+}
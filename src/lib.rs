@@ -1,4 +1,14 @@
+pub mod agent;
+pub mod askpass;
+pub mod cli;
+pub mod config;
+pub mod daemon;
 pub mod db;
+pub mod fs_util;
+pub mod known_hosts;
+pub mod search;
+pub mod ssh_config;
+pub mod tags;
 pub mod models;
 pub mod vault;
 pub mod ssh;
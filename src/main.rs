@@ -1,3 +1,55 @@
-fn main() -> iced::Result {
-    nebulavault::gui::run()
+use clap::Parser;
+use nebulavault::cli::Cli;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        // ssh runs $SSH_ASKPASS with the prompt as its only argument
+        Err(_) if nebulavault::askpass::is_requested() => {
+            return match nebulavault::askpass::answer(std::env::args().nth(1).as_deref()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Err(e) => e.exit(),
+    };
+    if cli.vault.is_none() {
+        match nebulavault::config::migrate_legacy_vault() {
+            Ok(Some(legacy)) => eprintln!(
+                "Copied the vault at {} to {}; the old file can be deleted",
+                legacy.display(),
+                nebulavault::config::default_vault_path().display()
+            ),
+            Ok(None) => {}
+            Err(e) => eprintln!("Warning: the vault at the old location was not copied: {:#}", e),
+        }
+    }
+    if cli.command.is_none() {
+        return match nebulavault::gui::run(cli.vault.is_some().then(|| cli.vault_path())) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(nebulavault::cli::run(cli)) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -11,12 +11,53 @@ pub struct Group {
     pub updated_at: String,
 }
 
+impl Group {
+    /// Whether this group is `ancestor_id` itself or nested somewhere below it
+    pub fn is_within(&self, ancestor_id: &str, groups: &[Group]) -> bool {
+        let mut current = Some(self);
+        // Bounded walk, so a corrupted parent chain cannot loop forever
+        for _ in 0..=groups.len() {
+            let Some(group) = current else {
+                return false;
+            };
+            if group.id == ancestor_id {
+                return true;
+            }
+            current = group
+                .parent_id
+                .as_deref()
+                .and_then(|parent_id| groups.iter().find(|g| g.id == parent_id));
+        }
+        false
+    }
+}
+
 /// Identity represents an encrypted SSH key or password
-#[derive(Debug, Clone, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Identity {
     pub id: String,
     pub name: String,
+    #[serde(with = "base64_bytes")]
     pub encrypted_data: Vec<u8>,
+    /// Ask for confirmation in the GUI before the agent signs with this key
+    pub agent_confirm: bool,
+    /// Only let the agent sign for hosts linked to this identity
+    pub agent_restrict_hosts: bool,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// VaultHeader stores the per-vault KDF salt, parameters and password verifier
+#[derive(Debug, Clone, FromRow)]
+pub struct VaultHeader {
+    pub salt: Vec<u8>,
+    pub kdf_algorithm: String,
+    pub kdf_m_cost: i64,
+    pub kdf_t_cost: i64,
+    pub kdf_p_cost: i64,
+    pub kdf_version: i64,
+    pub kdf_preset: Option<String>,
+    pub verifier: Vec<u8>,
     pub created_at: String,
     pub updated_at: String,
 }
@@ -44,22 +85,170 @@ pub struct Host {
     pub hostname: String,
     pub port: i64,
     pub username: String,
-    pub tags: Option<String>, // JSON array
+    /// Normalized tags from `host_tags`, sorted
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub proxy_jump: Option<String>,
+    pub local_forwards: Option<String>, // JSON array of LocalForward specs
     pub created_at: String,
     pub updated_at: String,
 }
 
 impl Host {
-    /// Parse tags from JSON string
-    pub fn get_tags(&self) -> Vec<String> {
-        self.tags
+    /// Parse LocalForward specs from JSON string
+    pub fn get_local_forwards(&self) -> Vec<String> {
+        self.local_forwards
             .as_ref()
-            .and_then(|t| serde_json::from_str(t).ok())
+            .and_then(|f| serde_json::from_str(f).ok())
             .unwrap_or_default()
     }
+}
+
+/// AgentSignRecord is one entry of the agent sign log
+#[derive(Debug, Clone, FromRow)]
+pub struct AgentSignRecord {
+    pub id: String,
+    pub identity_id: Option<String>,
+    pub host_id: Option<String>,
+    pub key_fingerprint: String,
+    pub forwarded: bool,
+    pub decision: String,
+    pub created_at: String,
+}
+
+/// ConnectionRecord is one connection attempt in the connection history
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ConnectionRecord {
+    pub id: String,
+    pub host_id: String,
+    pub identity_id: Option<String>,
+    /// Terminal or multiplexer the session was launched in; `ssh` for `nebulavault connect`
+    pub target: String,
+    pub success: bool,
+    /// Why the attempt failed
+    pub error: Option<String>,
+    pub connected_at: String,
+}
+
+impl ConnectionRecord {
+    /// When the attempt was made, in local time
+    pub fn local_time(&self) -> String {
+        chrono::DateTime::parse_from_rfc3339(&self.connected_at)
+            .map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|_| self.connected_at.clone())
+    }
+}
+
+/// Host as printed by the CLI and returned by the daemon
+#[derive(Debug, Serialize)]
+pub struct HostView {
+    pub id: String,
+    pub name: String,
+    /// Alias in the generated ssh_config include
+    pub alias: String,
+    pub hostname: String,
+    pub port: i64,
+    pub username: String,
+    pub identity: Option<String>,
+    pub proxy_jump: Option<String>,
+    pub local_forwards: Vec<String>,
+    pub tags: Vec<String>,
+    /// Latest connection attempts, printed by `show`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<ConnectionRecord>>,
+}
+
+impl HostView {
+    pub fn new(host: &Host, identities: &[Identity]) -> Self {
+        Self {
+            id: host.id.clone(),
+            name: host.name.clone(),
+            alias: crate::ssh_config::host_alias(&host.name),
+            hostname: host.hostname.clone(),
+            port: host.port,
+            username: host.username.clone(),
+            identity: host
+                .identity_id
+                .as_ref()
+                .map(|id| identities.iter().find(|i| &i.id == id).map_or(id.clone(), |i| i.name.clone())),
+            proxy_jump: host.proxy_jump.clone(),
+            local_forwards: host.get_local_forwards(),
+            tags: host.tags.clone(),
+            history: None,
+        }
+    }
+}
+
+/// Identity as printed by the CLI and returned by the daemon; never includes secrets
+#[derive(Debug, Serialize)]
+pub struct IdentityView {
+    pub id: String,
+    pub name: String,
+    pub agent_confirm: bool,
+    pub agent_restrict_hosts: bool,
+    /// Number of hosts using the identity
+    pub hosts: usize,
+}
+
+impl IdentityView {
+    pub fn new(identity: &Identity, hosts: &[Host]) -> Self {
+        Self {
+            id: identity.id.clone(),
+            name: identity.name.clone(),
+            agent_confirm: identity.agent_confirm,
+            agent_restrict_hosts: identity.agent_restrict_hosts,
+            hosts: hosts.iter().filter(|h| h.identity_id.as_ref() == Some(&identity.id)).count(),
+        }
+    }
+}
+
+/// HostUsage counts the successful connections to a host
+#[derive(Debug, Clone, FromRow)]
+pub struct HostUsage {
+    pub host_id: String,
+    pub connections: i64,
+    pub last_connected_at: String,
+}
+
+/// KnownHost is a server host key trusted for a hostname and port
+#[derive(Debug, Clone, FromRow)]
+pub struct KnownHost {
+    pub id: String,
+    pub hostname: String,
+    pub port: i64,
+    pub key_type: String,
+    pub fingerprint: String,
+    pub public_key: String,
+    pub created_at: String,
+}
+
+/// KnownHostEntry is a line imported from an OpenSSH known_hosts file
+#[derive(Debug, Clone, FromRow)]
+pub struct KnownHostEntry {
+    pub id: String,
+    /// Empty, `cert-authority` or `revoked`
+    pub marker: String,
+    pub hosts: String,
+    pub key_type: String,
+    pub fingerprint: String,
+    pub public_key: String,
+    pub comment: Option<String>,
+    pub created_at: String,
+}
+
+/// Serialize binary fields, such as encrypted identity data, as base64 strings
+pub(crate) mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
 
-    /// Set tags as JSON string
-    pub fn set_tags(&mut self, tags: Vec<String>) {
-        self.tags = serde_json::to_string(&tags).ok();
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
     }
 }
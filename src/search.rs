@@ -0,0 +1,166 @@
+/// Points for each matched character
+const SCORE_MATCH: u32 = 16;
+/// Bonus for a character right after the previous match
+const BONUS_CONSECUTIVE: u32 = 8;
+/// Bonus for a match at the start of a word (`db` in `prod-db`, `Db` in `prodDb`)
+const BONUS_WORD_START: u32 = 10;
+/// Bonus when the whole field starts with the pattern
+const BONUS_PREFIX: u32 = 24;
+/// Bonus when the whole field is the pattern
+const BONUS_EXACT: u32 = 48;
+/// Cost of each skipped character between two matches, up to [`MAX_GAP_PENALTY`]
+const PENALTY_GAP: u32 = 1;
+const MAX_GAP_PENALTY: u32 = 12;
+
+/// Score how well `pattern` matches `candidate` as a case-insensitive
+/// subsequence, or None when some character of the pattern is missing.
+/// Contiguous matches, word starts and prefixes score higher.
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<u32> {
+    let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    let original: Vec<char> = candidate.chars().collect();
+    let lower: Vec<char> = original.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+
+    // A contiguous occurrence beats the scattered one found left to right
+    let contiguous = (0..lower.len())
+        .filter(|&start| lower[start..].starts_with(&pattern))
+        .map(|start| score_positions(&original, start..start + pattern.len()))
+        .max();
+
+    let scattered = {
+        let mut positions = Vec::with_capacity(pattern.len());
+        let mut next = 0;
+        for c in &pattern {
+            let found = lower[next..].iter().position(|l| l == c)? + next;
+            positions.push(found);
+            next = found + 1;
+        }
+        score_positions(&original, positions)
+    };
+
+    let mut score = contiguous.unwrap_or(0).max(scattered);
+    if lower.starts_with(&pattern) {
+        score += BONUS_PREFIX;
+    }
+    if lower == pattern {
+        score += BONUS_EXACT;
+    }
+    Some(score)
+}
+
+fn score_positions(candidate: &[char], positions: impl IntoIterator<Item = usize>) -> u32 {
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+    for position in positions {
+        score += SCORE_MATCH;
+        if is_word_start(candidate, position) {
+            score += BONUS_WORD_START;
+        }
+        match previous {
+            Some(previous) if position == previous + 1 => score += BONUS_CONSECUTIVE,
+            Some(previous) => {
+                let gap = (position - previous - 1) as u32;
+                score = score.saturating_sub((gap * PENALTY_GAP).min(MAX_GAP_PENALTY));
+            }
+            None => {}
+        }
+        previous = Some(position);
+    }
+    score
+}
+
+fn is_word_start(candidate: &[char], position: usize) -> bool {
+    let Some(before) = position.checked_sub(1).map(|i| candidate[i]) else {
+        return true;
+    };
+    let current = candidate[position];
+    !before.is_alphanumeric() || (before.is_lowercase() && current.is_uppercase())
+}
+
+/// The searchable fields of a host
+#[derive(Debug, Clone, Copy)]
+pub struct HostFields<'a> {
+    pub name: &'a str,
+    pub hostname: &'a str,
+    pub username: &'a str,
+    pub tags: &'a [String],
+    /// Folder path, e.g. `Production / Databases`
+    pub group_path: Option<&'a str>,
+}
+
+/// Score a host against a query. Every whitespace-separated term has to match
+/// one of the fields; the name counts most.
+pub fn score_host(query: &str, host: &HostFields) -> Option<u32> {
+    let mut total = 0;
+    for term in query.split_whitespace() {
+        let best = [
+            fuzzy_score(term, host.name).map(|score| score * 2),
+            fuzzy_score(term, host.hostname),
+            fuzzy_score(term, host.username),
+            host.tags.iter().filter_map(|tag| fuzzy_score(term, tag)).max(),
+            host.group_path.and_then(|path| fuzzy_score(term, path)),
+        ]
+        .into_iter()
+        .flatten()
+        .max()?;
+        total += best;
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields<'a>(name: &'a str, hostname: &'a str, tags: &'a [String]) -> HostFields<'a> {
+        HostFields {
+            name,
+            hostname,
+            username: "deploy",
+            tags,
+            group_path: None,
+        }
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("pdb", "prod-db").is_some());
+        assert!(fuzzy_score("PDB", "prod-db").is_some());
+        assert!(fuzzy_score("dbp", "prod-db").is_none());
+        assert!(fuzzy_score("x", "").is_none());
+
+        // Exact beats prefix beats contiguous beats scattered
+        let exact = fuzzy_score("web", "web").unwrap();
+        let prefix = fuzzy_score("web", "web-01").unwrap();
+        let contiguous = fuzzy_score("web", "old-web-01").unwrap();
+        let scattered = fuzzy_score("web", "west-bastion").unwrap();
+        assert!(exact > prefix && prefix > contiguous && contiguous > scattered);
+
+        // Word starts count: "db" in "prodDb" beats "db" buried in "xdbx"
+        assert!(fuzzy_score("db", "prodDb").unwrap() > fuzzy_score("db", "xdbx").unwrap());
+    }
+
+    #[test]
+    fn test_score_host() {
+        let tags = vec!["prod".to_string(), "eu".to_string()];
+        let host = fields("Billing DB", "10.0.0.5", &tags);
+
+        assert!(score_host("bill", &host).is_some());
+        assert!(score_host("bill prod", &host).is_some());
+        assert!(score_host("bill staging", &host).is_none());
+        assert!(score_host("10.0", &host).is_some());
+        assert!(score_host("deploy", &host).is_some());
+
+        let path_host = HostFields {
+            group_path: Some("Production / Databases"),
+            ..fields("pg", "pg.internal", &[])
+        };
+        assert!(score_host("databases", &path_host).is_some());
+
+        // A name match outranks the same match in another field
+        let other = fields("api", "billing.example.com", &[]);
+        assert!(score_host("billing", &host).unwrap() > score_host("billing", &other).unwrap());
+    }
+}
@@ -2,10 +2,28 @@ use anyhow::{anyhow, Result};
 use russh::client::{self, Handle};
 use russh::*;
 use russh_keys::key::PublicKey;
-use std::sync::Arc;
+use sqlx::SqlitePool;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-/// SSH client handler
-struct Client;
+use crate::known_hosts::{self, HostKeyError, HostKeyStatus, ServerKey};
+use crate::models::Host;
+
+/// How long a host key probe may take before the server counts as unreachable
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Server key seen during the handshake and how it compared with the store
+type PresentedKey = Arc<Mutex<Option<(ServerKey, HostKeyStatus)>>>;
+
+/// SSH client handler that only accepts server keys trusted in the known hosts store
+struct Client {
+    pool: SqlitePool,
+    hostname: String,
+    port: u16,
+    /// Record the server key and abort the handshake
+    probe: bool,
+    presented: PresentedKey,
+}
 
 #[async_trait::async_trait]
 impl client::Handler for Client {
@@ -13,14 +31,94 @@ impl client::Handler for Client {
 
     async fn check_server_key(
         &mut self,
-        _server_public_key: &PublicKey,
+        server_public_key: &PublicKey,
     ) -> Result<bool, Self::Error> {
-        // For now, accept all server keys
-        // TODO: Implement proper host key verification
-        Ok(true)
+        let key = ServerKey::from_public_key(server_public_key);
+        let status = match known_hosts::check(&self.pool, &self.hostname, self.port, &key).await {
+            Ok(status) => status,
+            // A key we cannot check is not trusted
+            Err(_) => return Ok(false),
+        };
+
+        let accept = !self.probe && status == HostKeyStatus::Trusted;
+        if let Ok(mut presented) = self.presented.lock() {
+            *presented = Some((key, status));
+        }
+        Ok(accept)
+    }
+}
+
+fn client_config() -> Arc<client::Config> {
+    Arc::new(client::Config {
+        inactivity_timeout: Some(std::time::Duration::from_secs(300)),
+        ..<_>::default()
+    })
+}
+
+/// Connect and verify the server key, failing with [`HostKeyError`] when it is not trusted
+async fn connect(pool: &SqlitePool, hostname: &str, port: u16) -> Result<Handle<Client>> {
+    let presented = PresentedKey::default();
+    let sh = Client {
+        pool: pool.clone(),
+        hostname: hostname.to_string(),
+        port,
+        probe: false,
+        presented: presented.clone(),
+    };
+
+    match client::connect(client_config(), (hostname, port), sh).await {
+        Ok(handle) => Ok(handle),
+        Err(e) => {
+            let rejected = presented.lock().ok().and_then(|mut p| p.take());
+            match rejected {
+                Some((key, status)) if status != HostKeyStatus::Trusted => Err(HostKeyError {
+                    hostname: hostname.to_string(),
+                    port,
+                    key,
+                    status,
+                }
+                .into()),
+                _ => Err(anyhow!("Failed to connect: {}", e)),
+            }
+        }
     }
 }
 
+/// Fetch the server's host key and compare it with the known hosts store,
+/// without authenticating. Gives up after [`PROBE_TIMEOUT`].
+///
+/// The probe connects directly, so it cannot check hosts only reachable through
+/// a `ProxyJump`; see [`can_probe`].
+pub async fn probe_host_key(pool: &SqlitePool, hostname: &str, port: u16) -> Result<(ServerKey, HostKeyStatus)> {
+    let presented = PresentedKey::default();
+    let sh = Client {
+        pool: pool.clone(),
+        hostname: hostname.to_string(),
+        port,
+        probe: true,
+        presented: presented.clone(),
+    };
+
+    // The handler rejects every key, so the handshake always ends in an error
+    let result = tokio::time::timeout(PROBE_TIMEOUT, client::connect(client_config(), (hostname, port), sh))
+        .await
+        .map_err(|_| anyhow!("Timed out after {}s connecting to {}:{}", PROBE_TIMEOUT.as_secs(), hostname, port))?;
+
+    let seen = presented.lock().ok().and_then(|mut p| p.take());
+    match (seen, result) {
+        (Some(seen), _) => Ok(seen),
+        (None, Err(e)) => Err(anyhow!("Failed to connect: {}", e)),
+        (None, Ok(_)) => Err(anyhow!("Server did not present a host key")),
+    }
+}
+
+/// Whether the vault can check `host`'s key itself. Hosts behind a `ProxyJump`
+/// are left to ssh, which checks their key through the jump against the exported
+/// known hosts with `StrictHostKeyChecking`.
+pub fn can_probe(host: &Host) -> bool {
+    host.proxy_jump.is_none()
+}
+
 /// SSH session wrapper
 pub struct SshSession {
     pub host_id: String,
@@ -33,23 +131,13 @@ pub struct SshSession {
 impl SshSession {
     /// Connect to SSH server with password authentication
     pub async fn connect_password(
+        pool: &SqlitePool,
         hostname: &str,
         port: u16,
         username: &str,
         password: &str,
     ) -> Result<Self> {
-        let config = client::Config {
-            inactivity_timeout: Some(std::time::Duration::from_secs(300)),
-            ..<_>::default()
-        };
-
-        let config = Arc::new(config);
-        let sh = Client;
-
-        // Connect to the server
-        let mut session = client::connect(config, (hostname, port), sh)
-            .await
-            .map_err(|e| anyhow!("Failed to connect: {}", e))?;
+        let mut session = connect(pool, hostname, port).await?;
 
         // Authenticate with password
         let auth_res = session
@@ -72,24 +160,14 @@ impl SshSession {
 
     /// Connect to SSH server with public key authentication
     pub async fn connect_key(
+        pool: &SqlitePool,
         hostname: &str,
         port: u16,
         username: &str,
         private_key: &str,
         passphrase: Option<&str>,
     ) -> Result<Self> {
-        let config = client::Config {
-            inactivity_timeout: Some(std::time::Duration::from_secs(300)),
-            ..<_>::default()
-        };
-
-        let config = Arc::new(config);
-        let sh = Client;
-
-        // Connect to the server
-        let mut session = client::connect(config, (hostname, port), sh)
-            .await
-            .map_err(|e| anyhow!("Failed to connect: {}", e))?;
+        let mut session = connect(pool, hostname, port).await?;
 
         // Parse the private key (with optional passphrase)
         let key_pair = russh_keys::decode_secret_key(private_key, passphrase)
@@ -236,3 +314,112 @@ impl std::fmt::Debug for SshSession {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use russh::server;
+    use russh_keys::key::KeyPair;
+
+    /// Server that completes key exchange and rejects every authentication
+    struct TestServer;
+
+    #[async_trait::async_trait]
+    impl server::Handler for TestServer {
+        type Error = russh::Error;
+    }
+
+    /// Start a server on a free local port; returns the port and its host key
+    async fn start_server() -> (u16, ServerKey) {
+        let key_pair = KeyPair::generate_ed25519().unwrap();
+        let key = ServerKey::from_public_key(&key_pair.clone_public_key().unwrap());
+        let config = Arc::new(server::Config {
+            keys: vec![key_pair],
+            ..Default::default()
+        });
+
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let config = config.clone();
+                tokio::spawn(async move {
+                    if let Ok(session) = server::run_stream(config, stream, TestServer).await {
+                        let _ = session.await;
+                    }
+                });
+            }
+        });
+
+        (port, key)
+    }
+
+    /// Status of the host key `connect` refused
+    fn refused_status(result: Result<Handle<Client>>) -> HostKeyStatus {
+        match result {
+            Ok(_) => panic!("connection was accepted"),
+            Err(e) => e.downcast::<HostKeyError>().expect("not a host key error").status,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_key_is_reported_then_trusted() {
+        let test_db = db::TestDb::new().await;
+        let pool = test_db.pool.clone();
+        let (port, key) = start_server().await;
+
+        let (presented, status) = probe_host_key(&pool, "127.0.0.1", port).await.unwrap();
+        assert_eq!(presented, key);
+        assert_eq!(status, HostKeyStatus::Unknown);
+        assert_eq!(refused_status(connect(&pool, "127.0.0.1", port).await), HostKeyStatus::Unknown);
+
+        known_hosts::trust(&pool, "127.0.0.1", port, &key).await.unwrap();
+        assert_eq!(probe_host_key(&pool, "127.0.0.1", port).await.unwrap().1, HostKeyStatus::Trusted);
+        assert!(connect(&pool, "127.0.0.1", port).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_changed_key_is_refused() {
+        let test_db = db::TestDb::new().await;
+        let pool = test_db.pool.clone();
+        let (port, _) = start_server().await;
+
+        let stored = ServerKey::from_public_key(&KeyPair::generate_ed25519().unwrap().clone_public_key().unwrap());
+        known_hosts::trust(&pool, "127.0.0.1", port, &stored).await.unwrap();
+
+        let mismatch = HostKeyStatus::Mismatch {
+            stored_fingerprint: stored.fingerprint.clone(),
+        };
+        assert_eq!(probe_host_key(&pool, "127.0.0.1", port).await.unwrap().1, mismatch);
+        assert_eq!(refused_status(connect(&pool, "127.0.0.1", port).await), mismatch);
+
+        // A key of another type trusted for the host does not let a new type in
+        known_hosts::forget(&pool, "127.0.0.1", port).await.unwrap();
+        let other_type = ServerKey {
+            key_type: "ecdsa-sha2-nistp256".to_string(),
+            ..stored
+        };
+        known_hosts::trust(&pool, "127.0.0.1", port, &other_type).await.unwrap();
+        let type_changed = HostKeyStatus::KeyTypeChanged {
+            trusted_types: vec!["ecdsa-sha2-nistp256".to_string()],
+        };
+        assert_eq!(probe_host_key(&pool, "127.0.0.1", port).await.unwrap().1, type_changed);
+        assert_eq!(refused_status(connect(&pool, "127.0.0.1", port).await), type_changed);
+    }
+
+    #[tokio::test]
+    async fn test_revoked_key_is_refused() {
+        let test_db = db::TestDb::new().await;
+        let pool = test_db.pool.clone();
+        let (port, key) = start_server().await;
+
+        known_hosts::trust(&pool, "127.0.0.1", port, &key).await.unwrap();
+        known_hosts::import(&pool, &format!("@revoked * {} {}\n", key.key_type, key.public_key))
+            .await
+            .unwrap();
+
+        assert_eq!(probe_host_key(&pool, "127.0.0.1", port).await.unwrap().1, HostKeyStatus::Revoked);
+        assert_eq!(refused_status(connect(&pool, "127.0.0.1", port).await), HostKeyStatus::Revoked);
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::Result;
+
+/// Longest accepted tag
+const MAX_TAG_LEN: usize = 64;
+
+/// Words with a meaning in tag queries, which cannot be tags themselves
+const OPERATORS: [&str; 3] = ["and", "or", "not"];
+
+/// Check a tag and return it in its stored form: trimmed and lowercase
+pub fn normalize_tag(tag: &str) -> Result<String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() {
+        anyhow::bail!("Tag cannot be empty");
+    }
+    if tag.chars().count() > MAX_TAG_LEN {
+        anyhow::bail!("Tag {:?} is longer than {} characters", tag, MAX_TAG_LEN);
+    }
+    if !tag.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '/')) {
+        anyhow::bail!("Tag {:?} may only contain letters, digits and - _ . : /", tag);
+    }
+    if OPERATORS.contains(&tag.as_str()) {
+        anyhow::bail!("{:?} is a filter keyword and cannot be a tag", tag);
+    }
+    Ok(tag)
+}
+
+/// Rewrite a tag stored before tags were checked into one [`normalize_tag`]
+/// accepts: other characters, such as whitespace and commas, become `-`.
+/// None when nothing usable is left, e.g. for a filter keyword.
+pub fn repair_tag(tag: &str) -> Option<String> {
+    let mut repaired = String::new();
+    for c in tag.trim().to_lowercase().chars() {
+        if c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '/') {
+            repaired.push(c);
+        } else if !repaired.ends_with('-') {
+            repaired.push('-');
+        }
+    }
+    let repaired: String = repaired.trim_matches('-').chars().take(MAX_TAG_LEN).collect();
+    normalize_tag(&repaired).ok()
+}
+
+/// Normalize a list of tags, sorted and without duplicates
+pub fn normalize_tags<S: AsRef<str>>(tags: &[S]) -> Result<Vec<String>> {
+    let mut tags = tags
+        .iter()
+        .map(|tag| normalize_tag(tag.as_ref()))
+        .collect::<Result<Vec<_>>>()?;
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+/// Boolean filter over host tags, e.g. `prod AND db NOT legacy`
+///
+/// Terms next to each other are combined with AND, `NOT` binds tighter than
+/// `AND`, which binds tighter than `OR`, and parentheses group. Keywords are
+/// case-insensitive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagQuery {
+    Tag(String),
+    Not(Box<TagQuery>),
+    And(Vec<TagQuery>),
+    Or(Vec<TagQuery>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Open,
+    Close,
+}
+
+impl TagQuery {
+    pub fn parse(query: &str) -> Result<TagQuery> {
+        let tokens = tokenize(query);
+        if tokens.is_empty() {
+            anyhow::bail!("Empty tag filter");
+        }
+        let mut parser = Parser { tokens, position: 0 };
+        let query = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(query),
+            Some(Token::Close) => anyhow::bail!("Unmatched \")\" in tag filter"),
+            Some(token) => anyhow::bail!("Unexpected {:?} in tag filter", token),
+        }
+    }
+
+    /// Whether a host with these (normalized) tags passes the filter
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            TagQuery::Tag(tag) => tags.iter().any(|t| t == tag),
+            TagQuery::Not(query) => !query.matches(tags),
+            TagQuery::And(queries) => queries.iter().all(|q| q.matches(tags)),
+            TagQuery::Or(queries) => queries.iter().any(|q| q.matches(tags)),
+        }
+    }
+}
+
+fn tokenize(query: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in query.chars() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            if !word.is_empty() {
+                tokens.push(Token::Word(std::mem::take(&mut word)));
+            }
+            match c {
+                '(' => tokens.push(Token::Open),
+                ')' => tokens.push(Token::Close),
+                _ => {}
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+    tokens
+}
+
+/// Recursive descent over the tokens of a query
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn or(&mut self) -> Result<TagQuery> {
+        let mut terms = vec![self.and()?];
+        while self.peek_keyword("or") {
+            self.position += 1;
+            terms.push(self.and()?);
+        }
+        Ok(if terms.len() == 1 { terms.remove(0) } else { TagQuery::Or(terms) })
+    }
+
+    fn and(&mut self) -> Result<TagQuery> {
+        let mut terms = vec![self.not()?];
+        loop {
+            if self.peek_keyword("and") {
+                self.position += 1;
+            } else if self.peek_keyword("or") || matches!(self.tokens.get(self.position), None | Some(Token::Close)) {
+                break;
+            }
+            terms.push(self.not()?);
+        }
+        Ok(if terms.len() == 1 { terms.remove(0) } else { TagQuery::And(terms) })
+    }
+
+    fn not(&mut self) -> Result<TagQuery> {
+        if self.peek_keyword("not") {
+            self.position += 1;
+            return Ok(TagQuery::Not(Box::new(self.not()?)));
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<TagQuery> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Open) => {
+                let query = self.or()?;
+                if self.tokens.get(self.position) != Some(&Token::Close) {
+                    anyhow::bail!("Missing \")\" in tag filter");
+                }
+                self.position += 1;
+                Ok(query)
+            }
+            Some(Token::Word(word)) => {
+                if OPERATORS.iter().any(|op| word.eq_ignore_ascii_case(op)) {
+                    anyhow::bail!("Expected a tag before \"{}\"", word);
+                }
+                Ok(TagQuery::Tag(normalize_tag(&word)?))
+            }
+            Some(Token::Close) => anyhow::bail!("Unexpected \")\" in tag filter"),
+            None => anyhow::bail!("Tag filter ends too early"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_normalize_tags() {
+        assert_eq!(normalize_tags(&[" Prod", "db", "prod"]).unwrap(), tags(&["db", "prod"]));
+        assert_eq!(normalize_tag("eu-west/1").unwrap(), "eu-west/1");
+        for tag in ["", "  ", "two words", "db,prod", "NOT", "x\ty", &"a".repeat(65)] {
+            assert!(normalize_tag(tag).is_err(), "{:?}", tag);
+        }
+    }
+
+    #[test]
+    fn test_repair_tag() {
+        assert_eq!(repair_tag("Two  Words").as_deref(), Some("two-words"));
+        assert_eq!(repair_tag("db, prod").as_deref(), Some("db-prod"));
+        assert_eq!(repair_tag("(eu-west)").as_deref(), Some("eu-west"));
+        assert_eq!(repair_tag(&"a".repeat(70)), Some("a".repeat(64)));
+        for tag in ["", " , ", "AND", "(not)"] {
+            assert_eq!(repair_tag(tag), None, "{:?}", tag);
+        }
+    }
+
+    #[test]
+    fn test_tag_query() {
+        let query = TagQuery::parse("prod AND db NOT legacy").unwrap();
+        assert!(query.matches(&tags(&["db", "prod"])));
+        assert!(!query.matches(&tags(&["db", "legacy", "prod"])));
+        assert!(!query.matches(&tags(&["prod"])));
+
+        // Juxtaposition is AND, which binds tighter than OR
+        let query = TagQuery::parse("prod db or Staging").unwrap();
+        assert!(query.matches(&tags(&["staging"])));
+        assert!(query.matches(&tags(&["db", "prod"])));
+        assert!(!query.matches(&tags(&["prod"])));
+
+        let query = TagQuery::parse("not (web or db)").unwrap();
+        assert!(query.matches(&tags(&["cache"])));
+        assert!(!query.matches(&tags(&["web"])));
+
+        for query in ["", "prod AND", "or prod", "(prod", "prod)", "not", "a,b"] {
+            assert!(TagQuery::parse(query).is_err(), "{:?}", query);
+        }
+    }
+}
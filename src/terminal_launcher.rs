@@ -1,6 +1,8 @@
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
 use std::process::Command;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Supported terminal applications
 #[derive(Debug, Clone, PartialEq)]
@@ -15,10 +17,309 @@ pub enum TerminalApp {
     Kitty,
     /// Warp (macOS)
     Warp,
-    /// Custom command
-    Custom(String),
+    /// WezTerm (cross-platform)
+    WezTerm,
+    /// GNOME Terminal (Linux)
+    GnomeTerminal,
+    /// Konsole (Linux)
+    Konsole,
+    /// foot (Linux, Wayland)
+    Foot,
+    /// xterm (Linux)
+    XTerm,
+    /// Tilix (Linux)
+    Tilix,
+    /// Terminator (Linux)
+    Terminator,
+    /// A window or pane in a tmux, zellij or screen session instead of a new terminal
+    Multiplexer(MultiplexerTarget),
+    /// Custom terminal from an argument-vector template
+    Custom(TerminalTemplate),
 }
 
+/// Terminal multiplexers that can host connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplexer {
+    Tmux,
+    Zellij,
+    Screen,
+}
+
+impl Multiplexer {
+    pub const ALL: [Multiplexer; 3] = [Multiplexer::Tmux, Multiplexer::Zellij, Multiplexer::Screen];
+
+    /// Executable name
+    pub fn command(&self) -> &'static str {
+        match self {
+            Multiplexer::Tmux => "tmux",
+            Multiplexer::Zellij => "zellij",
+            Multiplexer::Screen => "screen",
+        }
+    }
+}
+
+/// Session used when none is configured
+pub const DEFAULT_MULTIPLEXER_SESSION: &str = "nebulavault";
+
+/// Where in a multiplexer a connection opens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiplexerTarget {
+    pub multiplexer: Multiplexer,
+    /// Session to open in; it is created detached when it is not running
+    pub session: String,
+    /// Split the session's current window (tmux) or tab (zellij) instead of
+    /// opening a new one; screen always opens a window
+    pub pane: bool,
+}
+
+impl MultiplexerTarget {
+    /// Every target for `multiplexer`, in session `session`
+    pub fn targets(multiplexer: Multiplexer, session: &str) -> Vec<MultiplexerTarget> {
+        let panes: &[bool] = match multiplexer {
+            Multiplexer::Screen => &[false],
+            _ => &[false, true],
+        };
+        panes
+            .iter()
+            .map(|&pane| MultiplexerTarget {
+                multiplexer,
+                session: session.to_string(),
+                pane,
+            })
+            .collect()
+    }
+
+    fn display_name(&self) -> &'static str {
+        match (self.multiplexer, self.pane) {
+            (Multiplexer::Tmux, false) => "tmux (new window)",
+            (Multiplexer::Tmux, true) => "tmux (new pane)",
+            (Multiplexer::Zellij, false) => "zellij (new tab)",
+            (Multiplexer::Zellij, true) => "zellij (new pane)",
+            (Multiplexer::Screen, _) => "screen (new window)",
+        }
+    }
+
+    /// Session names are restricted to what all three multiplexers accept in a target
+    pub fn validate_session(session: &str) -> Result<()> {
+        if session.is_empty() || !session.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')) {
+            anyhow::bail!("Session names may only contain letters, digits, '-' and '_'");
+        }
+        if session.starts_with('-') {
+            anyhow::bail!("Session names cannot start with '-'");
+        }
+        Ok(())
+    }
+
+    /// Whether the session is running
+    fn session_exists(&self) -> bool {
+        let output = match self.multiplexer {
+            Multiplexer::Tmux => Command::new("tmux").args(["has-session", "-t", &format!("={}", self.session)]).output(),
+            Multiplexer::Zellij => Command::new("zellij").args(["list-sessions", "--short", "--no-formatting"]).output(),
+            Multiplexer::Screen => Command::new("screen").args(["-ls", &self.session]).output(),
+        };
+        let Ok(output) = output else {
+            return false;
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        match self.multiplexer {
+            Multiplexer::Tmux => output.status.success(),
+            Multiplexer::Zellij => stdout.lines().any(|line| line.trim() == self.session),
+            // "\t12345.name\t(Detached)"; screen exits non-zero even when sessions exist
+            Multiplexer::Screen => stdout.lines().any(|line| {
+                line.split_whitespace()
+                    .next()
+                    .and_then(|id| id.split_once('.'))
+                    .is_some_and(|(_, name)| name == self.session)
+            }),
+        }
+    }
+
+    /// Commands that open `values.ssh_argv` in the session, run in order.
+    ///
+    /// The multiplexer server does not inherit our environment, so tmux gets it
+    /// with `-e` and the others through `env`.
+    fn commands(&self, values: &TemplateValues, session_exists: bool) -> Vec<Command> {
+        let session = self.session.as_str();
+        let target = format!("={}:", session);
+
+        let mut commands = Vec::new();
+        match self.multiplexer {
+            Multiplexer::Tmux => {
+                commands.push(match (session_exists, self.pane) {
+                    (false, _) => self.tmux_command(&["new-session", "-d", "-s", session, "-n", values.title], values),
+                    (true, false) => self.tmux_command(&["new-window", "-t", &target, "-n", values.title], values),
+                    (true, true) => self.tmux_command(&["split-window", "-t", &target], values),
+                });
+
+                if session_exists && self.pane {
+                    commands.push(self.command(&["select-layout", "-t", &target, "tiled"]));
+                }
+            }
+
+            Multiplexer::Zellij => {
+                // A new session or tab starts with a shell; ssh replaces it until it exits
+                let in_place = if !session_exists {
+                    commands.push(self.command(&["attach", "--create-background", session]));
+                    true
+                } else if !self.pane {
+                    commands.push(self.command(&["--session", session, "action", "new-tab", "--name", values.title]));
+                    true
+                } else {
+                    false
+                };
+                commands.push(self.zellij_run(values, in_place));
+            }
+
+            Multiplexer::Screen => {
+                let mut cmd = if session_exists {
+                    self.command(&["-S", session, "-X", "screen", "-t", values.title])
+                } else {
+                    self.command(&["-dmS", session, "-t", values.title])
+                };
+                cmd.args(command_argv(values));
+                commands.push(cmd);
+            }
+        }
+        commands
+    }
+
+    /// Commands that open one tab of tiled panes in zellij, one pane per host,
+    /// with input synchronized across them
+    fn zellij_broadcast_commands(&self, values: &[TemplateValues], session_exists: bool) -> Vec<Command> {
+        let session = self.session.as_str();
+        let tab_name = broadcast_title(values);
+
+        let mut commands = vec![if session_exists {
+            self.command(&["--session", session, "action", "new-tab", "--name", &tab_name])
+        } else {
+            self.command(&["attach", "--create-background", session])
+        }];
+        for (i, values) in values.iter().enumerate() {
+            commands.push(self.zellij_run(values, i == 0));
+        }
+        commands.push(self.command(&["--session", session, "action", "toggle-active-sync-tab"]));
+        commands
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(self.multiplexer.command());
+        cmd.args(args);
+        cmd
+    }
+
+    /// A tmux command that runs `values.ssh_argv` in a new pane or window
+    fn tmux_command(&self, args: &[&str], values: &TemplateValues) -> Command {
+        let mut cmd = self.command(args);
+        for (name, value) in values.env {
+            cmd.arg("-e").arg(format!("{}={}", name, value));
+        }
+        cmd.arg("--").args(values.ssh_argv);
+        cmd
+    }
+
+    /// `zellij run` in the session, replacing the focused pane's shell or in a new pane
+    fn zellij_run(&self, values: &TemplateValues, in_place: bool) -> Command {
+        let mut cmd = self.command(&["--session", &self.session, "run", "--name", values.title]);
+        cmd.arg(if in_place { "--in-place" } else { "--close-on-exit" });
+        cmd.arg("--").args(command_argv(values));
+        cmd
+    }
+
+    /// Run one multiplexer command, returning its output
+    fn run(&self, mut cmd: Command) -> Result<String> {
+        let output = cmd
+            .output()
+            .with_context(|| format!("Failed to run {}", self.multiplexer.command()))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} failed: {}",
+                self.multiplexer.command(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Open `values.ssh_argv` in the session, creating the session if needed
+    fn launch(&self, values: &TemplateValues) -> Result<()> {
+        Self::validate_session(&self.session)?;
+
+        for cmd in self.commands(values, self.session_exists()) {
+            self.run(cmd)?;
+        }
+        Ok(())
+    }
+
+    /// Open every connection in one new window (tmux) or tab (zellij) of the
+    /// session, tiled, with keystrokes sent to all panes
+    fn launch_broadcast(&self, values: &[TemplateValues]) -> Result<()> {
+        Self::validate_session(&self.session)?;
+        let session_exists = self.session_exists();
+
+        match self.multiplexer {
+            Multiplexer::Tmux => {
+                let session = self.session.as_str();
+                let target = format!("={}:", session);
+                let window_name = broadcast_title(values);
+                let (first, rest) = values.split_first().context("No hosts to connect to")?;
+
+                // Later commands address the window and panes by id, so they
+                // still work if the user switches windows meanwhile
+                let format = "#{window_id} #{pane_id}";
+                let created = self.run(if session_exists {
+                    self.tmux_command(&["new-window", "-P", "-F", format, "-t", &target, "-n", &window_name], first)
+                } else {
+                    self.tmux_command(&["new-session", "-d", "-P", "-F", format, "-s", session, "-n", &window_name], first)
+                })?;
+                let (window, first_pane) = created
+                    .split_once(' ')
+                    .context("Unexpected output from tmux")?;
+
+                let mut panes = vec![(first_pane.to_string(), first.title)];
+                for values in rest {
+                    let pane = self.run(self.tmux_command(&["split-window", "-P", "-F", "#{pane_id}", "-t", window], values))?;
+                    panes.push((pane, values.title));
+                    // Re-tile after every split so the window never runs out of room
+                    self.run(self.command(&["select-layout", "-t", window, "tiled"]))?;
+                }
+                for (pane, title) in &panes {
+                    self.run(self.command(&["select-pane", "-t", pane, "-T", title]))?;
+                }
+                self.run(self.command(&["set-window-option", "-t", window, "synchronize-panes", "on"]))?;
+            }
+
+            Multiplexer::Zellij => {
+                for cmd in self.zellij_broadcast_commands(values, session_exists) {
+                    self.run(cmd)?;
+                }
+            }
+
+            Multiplexer::Screen => anyhow::bail!("screen cannot split a window into panes"),
+        }
+        Ok(())
+    }
+}
+
+/// Name of the window or tab a broadcast opens
+fn broadcast_title(values: &[TemplateValues]) -> String {
+    format!("{} hosts", values.len())
+}
+
+/// Terminals found by command name, in the order they are preferred when the
+/// user has not configured one
+const COMMAND_TERMINALS: &[(&str, TerminalApp)] = &[
+    ("alacritty", TerminalApp::Alacritty),
+    ("kitty", TerminalApp::Kitty),
+    ("wezterm", TerminalApp::WezTerm),
+    ("foot", TerminalApp::Foot),
+    ("gnome-terminal", TerminalApp::GnomeTerminal),
+    ("konsole", TerminalApp::Konsole),
+    ("tilix", TerminalApp::Tilix),
+    ("terminator", TerminalApp::Terminator),
+    ("xterm", TerminalApp::XTerm),
+];
+
 impl TerminalApp {
     /// Detect available terminals on the system
     pub fn detect_available() -> Vec<TerminalApp> {
@@ -37,45 +338,37 @@ impl TerminalApp {
             }
         }
 
-        // Cross-platform terminals
-        if Self::is_command_available("alacritty") {
-            available.push(TerminalApp::Alacritty);
-        }
-        if Self::is_command_available("kitty") {
-            available.push(TerminalApp::Kitty);
+        // Cross-platform and Linux terminals
+        for (command, terminal) in COMMAND_TERMINALS {
+            if Self::is_command_available(command) {
+                available.push(terminal.clone());
+            }
         }
 
-        available
-    }
-
-    /// Get default terminal for the platform
-    pub fn default() -> TerminalApp {
-        #[cfg(target_os = "macos")]
-        {
-            if Self::is_app_installed("iTerm") {
-                return TerminalApp::ITerm2;
+        for multiplexer in Multiplexer::ALL {
+            if Self::is_command_available(multiplexer.command()) {
+                available.extend(
+                    MultiplexerTarget::targets(multiplexer, DEFAULT_MULTIPLEXER_SESSION)
+                        .into_iter()
+                        .map(TerminalApp::Multiplexer),
+                );
             }
-            return TerminalApp::Terminal;
         }
 
+        // A $TERMINAL we have no variant for
         #[cfg(not(target_os = "macos"))]
-        {
-            if Self::is_command_available("alacritty") {
-                return TerminalApp::Alacritty;
-            }
-            if Self::is_command_available("kitty") {
-                return TerminalApp::Kitty;
-            }
-            // Fallback to custom with default terminal emulator
-            TerminalApp::Custom("x-terminal-emulator".to_string())
+        if let Some(terminal @ TerminalApp::Custom(_)) = Self::configured() {
+            available.push(terminal);
         }
+
+        available
     }
 
     /// Check if a macOS app is installed
     #[cfg(target_os = "macos")]
     fn is_app_installed(app_name: &str) -> bool {
         Command::new("mdfind")
-            .args(&[
+            .args([
                 "kMDItemKind == 'Application'",
                 "&&",
                 &format!("kMDItemFSName == '{}.app'", app_name),
@@ -94,6 +387,105 @@ impl TerminalApp {
             .unwrap_or(false)
     }
 
+    /// Name of the terminal in the settings file: its command, or the
+    /// multiplexer's; custom terminals are stored with their template instead
+    pub fn config_name(&self) -> Option<&'static str> {
+        let name = match self {
+            TerminalApp::ITerm2 => "iterm2",
+            TerminalApp::Terminal => "terminal",
+            TerminalApp::Warp => "warp",
+            TerminalApp::Multiplexer(target) => target.multiplexer.command(),
+            TerminalApp::Custom(_) => return None,
+            terminal => COMMAND_TERMINALS
+                .iter()
+                .find(|(_, known)| known == terminal)
+                .map(|(command, _)| *command)?,
+        };
+        Some(name)
+    }
+
+    /// Terminal for a name from [`TerminalApp::config_name`]; multiplexers open
+    /// in `target`'s session
+    pub fn from_config_name(name: &str, target: impl FnOnce(Multiplexer) -> MultiplexerTarget) -> Option<TerminalApp> {
+        let terminal = match name {
+            "iterm2" => TerminalApp::ITerm2,
+            "terminal" => TerminalApp::Terminal,
+            "warp" => TerminalApp::Warp,
+            name => match Multiplexer::ALL.into_iter().find(|multiplexer| multiplexer.command() == name) {
+                Some(multiplexer) => TerminalApp::Multiplexer(target(multiplexer)),
+                None => TerminalApp::from_command(name)?,
+            },
+        };
+        Some(terminal)
+    }
+
+    /// Terminal for a command such as `gnome-terminal` or `/usr/bin/foot`
+    pub fn from_command(command: &str) -> Option<TerminalApp> {
+        let name = Path::new(command).file_name()?.to_str()?;
+        // Debian's alternatives point at e.g. gnome-terminal.wrapper
+        let name = name.strip_suffix(".wrapper").unwrap_or(name);
+        let name = match name {
+            "wezterm-gui" => "wezterm",
+            "footclient" => "foot",
+            "uxterm" | "koi8rxterm" | "lxterm" => "xterm",
+            name => name,
+        };
+        COMMAND_TERMINALS
+            .iter()
+            .find(|(command, _)| *command == name)
+            .map(|(_, terminal)| terminal.clone())
+    }
+
+    /// Terminal for an XDG desktop entry id such as `org.gnome.Terminal.desktop`
+    pub fn from_desktop_id(id: &str) -> Option<TerminalApp> {
+        let terminal = match id.strip_suffix(".desktop").unwrap_or(id) {
+            "Alacritty" | "alacritty" => TerminalApp::Alacritty,
+            "kitty" => TerminalApp::Kitty,
+            "org.wezfurlong.wezterm" => TerminalApp::WezTerm,
+            "foot" | "footclient" | "org.codeberg.dnkl.foot" | "org.codeberg.dnkl.footclient" => TerminalApp::Foot,
+            "org.gnome.Terminal" => TerminalApp::GnomeTerminal,
+            "org.kde.konsole" => TerminalApp::Konsole,
+            "com.gexperts.Tilix" => TerminalApp::Tilix,
+            "terminator" => TerminalApp::Terminator,
+            "xterm" | "debian-xterm" | "debian-uxterm" => TerminalApp::XTerm,
+            _ => return None,
+        };
+        Some(terminal)
+    }
+
+    /// The terminal the user has chosen outside Nebula Vault: `$TERMINAL`, then
+    /// the XDG default terminal (`xdg-terminals.list`), then Debian's
+    /// `x-terminal-emulator` alternative
+    pub fn configured() -> Option<TerminalApp> {
+        if let Some(command) = std::env::var("TERMINAL").ok().filter(|c| !c.trim().is_empty()) {
+            if let Some(terminal) = Self::from_command(&command) {
+                return Some(terminal);
+            }
+            if Self::is_command_available(&command) {
+                let name = Path::new(&command).file_name().and_then(|n| n.to_str()).unwrap_or(&command);
+                return Some(TerminalApp::Custom(TerminalTemplate {
+                    name: format!("{} ($TERMINAL)", name),
+                    argv: vec![command.clone(), "-e".to_string(), "{ssh_argv}".to_string()],
+                }));
+            }
+        }
+
+        let desktops = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+        for file in xdg_terminals_list_paths(&desktops) {
+            let Ok(contents) = std::fs::read_to_string(&file) else {
+                continue;
+            };
+            let terminal = xdg_terminal_ids(&contents).find_map(Self::from_desktop_id);
+            if terminal.is_some() {
+                return terminal;
+            }
+        }
+
+        std::fs::canonicalize("/etc/alternatives/x-terminal-emulator")
+            .ok()
+            .and_then(|path| Self::from_command(path.to_str()?))
+    }
+
     /// Get display name for the terminal
     pub fn display_name(&self) -> &str {
         match self {
@@ -102,7 +494,80 @@ impl TerminalApp {
             TerminalApp::Alacritty => "Alacritty",
             TerminalApp::Kitty => "Kitty",
             TerminalApp::Warp => "Warp",
-            TerminalApp::Custom(_) => "Custom",
+            TerminalApp::WezTerm => "WezTerm",
+            TerminalApp::GnomeTerminal => "GNOME Terminal",
+            TerminalApp::Konsole => "Konsole",
+            TerminalApp::Foot => "foot",
+            TerminalApp::XTerm => "xterm",
+            TerminalApp::Tilix => "Tilix",
+            TerminalApp::Terminator => "Terminator",
+            TerminalApp::Multiplexer(target) => target.display_name(),
+            TerminalApp::Custom(template) => &template.name,
+        }
+    }
+}
+
+/// `xdg-terminals.list` files in lookup order: for each config directory, the
+/// lists for the current desktops (`$XDG_CURRENT_DESKTOP`, lowercased) before
+/// the generic one
+fn xdg_terminals_list_paths(current_desktops: &str) -> Vec<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    let config_dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string());
+
+    let dirs = config_home
+        .into_iter()
+        .chain(config_dirs.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from));
+
+    let mut paths = Vec::new();
+    for dir in dirs {
+        for desktop in current_desktops.split(':').filter(|d| !d.is_empty()) {
+            paths.push(dir.join(format!("{}-xdg-terminals.list", desktop.to_lowercase())));
+        }
+        paths.push(dir.join("xdg-terminals.list"));
+    }
+    paths
+}
+
+/// Terminals listed in an `xdg-terminals.list`, most preferred first: one desktop
+/// entry id per line, optionally prefixed with `+` and followed by `:action`;
+/// `-` lines exclude a terminal and are skipped
+fn xdg_terminal_ids(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('-'))
+        .map(|line| line.trim_start_matches('+'))
+        .map(|line| line.split_once(':').map_or(line, |(id, _)| id))
+}
+
+impl Default for TerminalApp {
+    /// Get default terminal for the platform
+    fn default() -> TerminalApp {
+        #[cfg(target_os = "macos")]
+        {
+            if TerminalApp::is_app_installed("iTerm") {
+                return TerminalApp::ITerm2;
+            }
+            return TerminalApp::Terminal;
+        }
+
+        #[cfg(not(target_os = "macos"))]
+        {
+            if let Some(terminal) = TerminalApp::configured() {
+                return terminal;
+            }
+            for (command, terminal) in COMMAND_TERMINALS {
+                if TerminalApp::is_command_available(command) {
+                    return terminal.clone();
+                }
+            }
+            // Fallback to custom with default terminal emulator
+            TerminalApp::Custom(TerminalTemplate::x_terminal_emulator())
         }
     }
 }
@@ -113,144 +578,905 @@ impl std::fmt::Display for TerminalApp {
     }
 }
 
+/// Placeholders a [`TerminalTemplate`] may use
+pub const TEMPLATE_PLACEHOLDERS: &[(&str, &str)] = &[
+    ("{ssh_argv}", "ssh and its arguments, as separate arguments"),
+    ("{ssh_command}", "the ssh command line as one shell-quoted argument"),
+    ("{host}", "hostname"),
+    ("{user}", "username"),
+    ("{port}", "port"),
+    ("{title}", "window title"),
+];
+
+/// A custom terminal described by the argument vector that launches it, e.g.
+/// `["wezterm", "start", "--", "{ssh_argv}"]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TerminalTemplate {
+    pub name: String,
+    pub argv: Vec<String>,
+}
+
+/// Values substituted into a [`TerminalTemplate`]
+#[derive(Debug, Clone, Copy)]
+pub struct TemplateValues<'a> {
+    /// Environment ssh needs, added with `env` in front of the ssh command
+    pub env: &'a [(&'static str, String)],
+    /// The command to run, program first
+    pub ssh_argv: &'a [String],
+    pub host: &'a str,
+    pub user: &'a str,
+    pub port: u16,
+    pub title: &'a str,
+}
+
+impl TerminalTemplate {
+    /// Debian's `x-terminal-emulator`, used when no known terminal is installed
+    pub fn x_terminal_emulator() -> Self {
+        Self {
+            name: "x-terminal-emulator".to_string(),
+            argv: vec!["x-terminal-emulator".to_string(), "-e".to_string(), "{ssh_argv}".to_string()],
+        }
+    }
+
+    /// Parse a template from a command line written with shell quoting, e.g.
+    /// `wezterm start -- {ssh_argv}`.
+    ///
+    /// The line is only split into words, never run by a shell. It must start
+    /// with a program, use `{ssh_argv}` or `{ssh_command}`, and use no other
+    /// `{...}` placeholders than [`TEMPLATE_PLACEHOLDERS`].
+    pub fn parse(name: &str, command_line: &str) -> Result<Self> {
+        let name = name.trim();
+        if name.is_empty() {
+            anyhow::bail!("Template name is required");
+        }
+        let argv = shlex::split(command_line).context("Unbalanced quotes in the command")?;
+        let Some(program) = argv.first() else {
+            anyhow::bail!("Command is required");
+        };
+        if program.contains('{') {
+            anyhow::bail!("The command must start with a program, not a placeholder");
+        }
+
+        for arg in &argv {
+            let mut rest = arg.as_str();
+            while let Some(start) = rest.find('{') {
+                let end = rest[start..].find('}').map(|end| start + end + 1).unwrap_or(rest.len());
+                let placeholder = &rest[start..end];
+                if !TEMPLATE_PLACEHOLDERS.iter().any(|(known, _)| *known == placeholder) {
+                    anyhow::bail!("Unknown placeholder {}", placeholder);
+                }
+                if placeholder == "{ssh_argv}" && arg != "{ssh_argv}" {
+                    anyhow::bail!("{{ssh_argv}} must be an argument of its own");
+                }
+                rest = &rest[end..];
+            }
+        }
+        if !argv.iter().any(|arg| arg == "{ssh_argv}" || arg.contains("{ssh_command}")) {
+            anyhow::bail!("The command must contain {{ssh_argv}} or {{ssh_command}}");
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            argv,
+        })
+    }
+
+    /// The template as a command line that [`TerminalTemplate::parse`] reads back
+    pub fn command_line(&self) -> String {
+        self.argv.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ")
+    }
+
+    /// The argument vector to run, program first.
+    ///
+    /// `{ssh_argv}` becomes one argument per word of the ssh command, so the
+    /// terminal receives it exactly as given; the other placeholders are replaced
+    /// inside arguments and are never interpreted by a shell.
+    pub fn expand(&self, values: &TemplateValues) -> Vec<String> {
+        let mut argv = Vec::new();
+        for arg in &self.argv {
+            if arg == "{ssh_argv}" {
+                argv.extend(command_argv(values));
+                continue;
+            }
+            let (program, args) = values.ssh_argv.split_first().map_or(("", &[][..]), |(p, a)| (p.as_str(), a));
+            argv.push(
+                arg.replace("{ssh_command}", &shell_command(values.env, program, args))
+                    .replace("{host}", values.host)
+                    .replace("{user}", values.user)
+                    .replace("{port}", &values.port.to_string())
+                    .replace("{title}", values.title),
+            );
+        }
+        argv
+    }
+}
+
+/// Options for the ssh command line besides the destination
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SshOptions<'a> {
+    /// Authenticate through the NebulaVault agent instead of a key file
+    pub agent_socket: Option<&'a Path>,
+    /// Host keys exported from the vault
+    pub known_hosts_file: Option<&'a Path>,
+    /// `ProxyJump` destination(s)
+    pub proxy_jump: Option<&'a str>,
+    /// `LocalForward` specs in ssh_config form, e.g. `8080 localhost:80`
+    pub local_forwards: &'a [String],
+    /// Answer ssh's password prompt through `nebulavault askpass`
+    pub askpass: Option<AskpassOptions<'a>>,
+    /// Window title for terminals that take one; `user@hostname` if unset
+    pub title: Option<&'a str>,
+}
+
+/// Where `nebulavault askpass` fetches the password for one connection
+#[derive(Debug, Clone, Copy)]
+pub struct AskpassOptions<'a> {
+    /// The nebulavault executable, run by ssh as `SSH_ASKPASS`
+    pub program: &'a Path,
+    /// Socket of the process holding the password
+    pub socket: &'a Path,
+    /// Single-use token for the password
+    pub token: &'a str,
+}
+
+/// Environment ssh needs besides its arguments: the agent socket and the
+/// askpass helper, which `SSH_ASKPASS_REQUIRE=force` makes ssh use even when
+/// it has a terminal
+pub fn ssh_env(options: &SshOptions) -> Vec<(&'static str, String)> {
+    let mut env = vec![];
+    if let Some(socket) = options.agent_socket {
+        env.push(("SSH_AUTH_SOCK", socket.display().to_string()));
+    }
+    if let Some(askpass) = options.askpass {
+        env.push(("SSH_ASKPASS", askpass.program.display().to_string()));
+        env.push(("SSH_ASKPASS_REQUIRE", "force".to_string()));
+        env.push((crate::askpass::SOCKET_ENV, askpass.socket.display().to_string()));
+        env.push((crate::askpass::TOKEN_ENV, askpass.token.to_string()));
+    }
+    env
+}
+
+/// Arguments for `ssh` to connect to `username@hostname:port` with the given options.
+///
+/// The known hosts file is passed as `GlobalKnownHostsFile`, which ssh reads but
+/// never writes, so ssh trusts the same host keys as the vault. It comes with
+/// `StrictHostKeyChecking=ask`, so a changed key is refused and an unknown one
+/// (a host behind a jump, which the vault cannot probe) is asked about in the
+/// terminal, whatever the user's ssh_config says.
+pub fn ssh_args(hostname: &str, port: u16, username: &str, options: &SshOptions) -> Vec<String> {
+    let mut ssh_args = vec![];
+
+    // Trust the same host keys as the vault
+    if let Some(file) = options.known_hosts_file {
+        ssh_args.push("-o".to_string());
+        ssh_args.push(format!("GlobalKnownHostsFile={}", file.display()));
+        ssh_args.push("-o".to_string());
+        ssh_args.push("StrictHostKeyChecking=ask".to_string());
+    }
+
+    // Use the vault agent if provided
+    if let Some(socket) = options.agent_socket {
+        ssh_args.push("-o".to_string());
+        ssh_args.push(format!("IdentityAgent={}", socket.display()));
+    }
+
+    if let Some(proxy_jump) = options.proxy_jump {
+        ssh_args.push("-J".to_string());
+        ssh_args.push(proxy_jump.to_string());
+    }
+
+    // "8080 localhost:80" in ssh_config is "-L 8080:localhost:80" on the command line
+    for forward in options.local_forwards {
+        ssh_args.push("-L".to_string());
+        ssh_args.push(forward.split_whitespace().collect::<Vec<_>>().join(":"));
+    }
+
+    // Add port if not default
+    if port != 22 {
+        ssh_args.push("-p".to_string());
+        ssh_args.push(port.to_string());
+    }
+
+    // Add connection details; "--" keeps a hostile destination from being read as an option
+    ssh_args.push("--".to_string());
+    ssh_args.push(format!("{}@{}", username, hostname));
+
+    ssh_args
+}
 
 /// Launch SSH connection in external terminal
+///
+/// With an agent socket, `IdentityAgent` is passed on the command line (terminals
+/// started through AppleScript do not inherit our environment) and `SSH_AUTH_SOCK`
+/// is set for terminals spawned directly. The askpass variables are set the same
+/// way, or prefixed to the command with `env` for AppleScript terminals.
 pub fn launch_ssh_connection(
     terminal: &TerminalApp,
     hostname: &str,
     port: u16,
     username: &str,
-    identity_path: Option<&PathBuf>,
+    options: &SshOptions,
 ) -> Result<()> {
-    let mut ssh_args = vec![];
+    let connection = Connection {
+        hostname,
+        port,
+        username,
+        options: *options,
+    };
+    let args = ConnectionArgs::new(&connection);
+    launch(terminal, &args.values(&connection))
+}
 
-    // Add identity file if provided
-    if let Some(key_path) = identity_path {
-        ssh_args.push("-i".to_string());
-        ssh_args.push(key_path.to_string_lossy().to_string());
+/// One host of a broadcast launch
+#[derive(Debug, Clone, Copy)]
+pub struct Connection<'a> {
+    pub hostname: &'a str,
+    pub port: u16,
+    pub username: &'a str,
+    pub options: SshOptions<'a>,
+}
+
+/// The owned parts of a connection's `TemplateValues`
+struct ConnectionArgs {
+    env: Vec<(&'static str, String)>,
+    ssh_argv: Vec<String>,
+    title: String,
+}
+
+impl ConnectionArgs {
+    fn new(connection: &Connection) -> Self {
+        let mut ssh_argv = vec!["ssh".to_string()];
+        ssh_argv.extend(ssh_args(connection.hostname, connection.port, connection.username, &connection.options));
+        Self {
+            env: ssh_env(&connection.options),
+            ssh_argv,
+            title: match connection.options.title {
+                Some(title) => title.to_string(),
+                None => format!("{}@{}", connection.username, connection.hostname),
+            },
+        }
     }
 
-    // Add connection details
-    ssh_args.push(format!("{}@{}", username, hostname));
+    fn values<'a>(&'a self, connection: &'a Connection) -> TemplateValues<'a> {
+        TemplateValues {
+            env: &self.env,
+            ssh_argv: &self.ssh_argv,
+            host: connection.hostname,
+            user: connection.username,
+            port: connection.port,
+            title: &self.title,
+        }
+    }
+}
 
-    // Add port if not default
-    if port != 22 {
-        ssh_args.push("-p".to_string());
-        ssh_args.push(port.to_string());
+/// Open several hosts at once in one tiled layout.
+///
+/// tmux and zellij open a new window or tab in the configured session with
+/// one pane per host and input synchronized across the panes; Kitty opens a
+/// window split into a grid. Other terminals get one window per host.
+pub fn launch_ssh_broadcast(terminal: &TerminalApp, connections: &[Connection]) -> Result<()> {
+    if connections.is_empty() {
+        anyhow::bail!("No hosts to connect to");
     }
+    let args: Vec<ConnectionArgs> = connections.iter().map(ConnectionArgs::new).collect();
+    let values: Vec<TemplateValues> = connections
+        .iter()
+        .zip(&args)
+        .map(|(connection, args)| args.values(connection))
+        .collect();
 
     match terminal {
+        TerminalApp::Multiplexer(target) if target.multiplexer != Multiplexer::Screen => {
+            target.launch_broadcast(&values)
+        }
+        TerminalApp::Kitty => {
+            let session = kitty_session(&values)?;
+            let mut child = Command::new("kitty")
+                .arg("--session=-")
+                .stdin(std::process::Stdio::piped())
+                .spawn()
+                .context("Failed to launch Kitty")?;
+            // Dropping stdin after writing tells Kitty the session is complete
+            child
+                .stdin
+                .take()
+                .context("Failed to open Kitty's stdin")?
+                .write_all(session.as_bytes())
+                .context("Failed to send the session to Kitty")?;
+            Ok(())
+        }
+        // No layout to split: one window per host
+        _ => values.iter().try_for_each(|values| launch(terminal, values)),
+    }
+}
+
+/// A Kitty session with one window per host in a grid, read by `kitty --session=-`.
+///
+/// Kitty splits each line like a shell, so the arguments are quoted; a line
+/// break would end the line early, so arguments containing one are refused
+/// and titles have them replaced.
+fn kitty_session(values: &[TemplateValues]) -> Result<String> {
+    let mut session = String::from("layout grid\n");
+    for values in values {
+        let argv = command_argv(values);
+        if argv.iter().any(|arg| arg.contains(['\n', '\r'])) {
+            anyhow::bail!("Cannot pass an argument containing a line break to Kitty");
+        }
+        let title = values.title.replace(|c: char| c.is_control(), " ");
+
+        session.push_str("launch --title ");
+        session.push_str(&shell_quote(&title));
+        for arg in &argv {
+            session.push(' ');
+            session.push_str(&shell_quote(arg));
+        }
+        session.push('\n');
+    }
+    Ok(session)
+}
+
+/// Open `terminal` with a harmless command that prints a message and waits
+/// for Enter, to check a custom terminal's template
+pub fn launch_test(terminal: &TerminalApp) -> Result<()> {
+    let ssh_argv = [
+        "sh".to_string(),
+        "-c".to_string(),
+        "echo 'Nebula Vault terminal test: this window would run ssh.'; echo 'Press Enter to close.'; read -r _".to_string(),
+    ];
+    let values = TemplateValues {
+        env: &[],
+        ssh_argv: &ssh_argv,
+        host: "example.com",
+        user: "user",
+        port: 22,
+        title: "Nebula Vault test",
+    };
+    launch(terminal, &values)
+}
+
+/// Open `terminal` running `values.ssh_argv`
+fn launch(terminal: &TerminalApp, values: &TemplateValues) -> Result<()> {
+    if let TerminalApp::Multiplexer(target) = terminal {
+        return target.launch(values);
+    }
+
+    launch_command(terminal, values)?
+        .spawn()
+        .with_context(|| format!("Failed to launch {}", terminal))?;
+
+    Ok(())
+}
+
+/// The process that opens `terminal` running `values.ssh_argv`.
+///
+/// Arguments are passed as an argument vector wherever the terminal allows it.
+/// Terminals that only take a command line get one with every word quoted by
+/// [`shell_quote`], and AppleScript receives it as a script argument rather
+/// than inside the script source.
+fn launch_command(terminal: &TerminalApp, values: &TemplateValues) -> Result<Command> {
+    let (program, args) = values
+        .ssh_argv
+        .split_first()
+        .context("Nothing to run in the terminal")?;
+    #[cfg(target_os = "macos")]
+    let command_line = shell_command(values.env, program, args);
+
+    let cmd = match terminal {
         #[cfg(target_os = "macos")]
         TerminalApp::ITerm2 => {
-            let ssh_command = format!("ssh {}", ssh_args.join(" "));
-            Command::new("osascript")
-                .args(&[
-                    "-e",
-                    &format!(
-                        "tell application \"iTerm\"\n\
-                         activate\n\
-                         create window with default profile command \"{}\"\n\
-                         end tell",
-                        ssh_command
-                    ),
-                ])
-                .spawn()
-                .context("Failed to launch iTerm2")?;
+            let mut cmd = Command::new("osascript");
+            cmd.args([
+                "-e",
+                "on run argv\n\
+                 tell application \"iTerm\"\n\
+                 activate\n\
+                 create window with default profile command (item 1 of argv)\n\
+                 end tell\n\
+                 end run",
+                &command_line,
+            ]);
+            cmd
         }
 
         #[cfg(target_os = "macos")]
         TerminalApp::Terminal => {
-            let ssh_command = format!("ssh {}", ssh_args.join(" "));
-            Command::new("osascript")
-                .args(&[
-                    "-e",
-                    &format!(
-                        "tell application \"Terminal\"\n\
-                         activate\n\
-                         do script \"{}\"\n\
-                         end tell",
-                        ssh_command
-                    ),
-                ])
-                .spawn()
-                .context("Failed to launch Terminal.app")?;
+            let mut cmd = Command::new("osascript");
+            cmd.args([
+                "-e",
+                "on run argv\n\
+                 tell application \"Terminal\"\n\
+                 activate\n\
+                 do script (item 1 of argv)\n\
+                 end tell\n\
+                 end run",
+                &command_line,
+            ]);
+            cmd
         }
 
         #[cfg(target_os = "macos")]
         TerminalApp::Warp => {
-            let ssh_command = format!("ssh {}", ssh_args.join(" "));
-            Command::new("open")
-                .args(&["-a", "Warp", "--args", &ssh_command])
-                .spawn()
-                .context("Failed to launch Warp")?;
+            let mut cmd = Command::new("open");
+            cmd.args(["-a", "Warp", "--args", &command_line]);
+            cmd
         }
 
         TerminalApp::Alacritty => {
             let mut cmd = Command::new("alacritty");
-            cmd.arg("-e").arg("ssh");
-            for arg in &ssh_args {
-                cmd.arg(arg);
-            }
-            cmd.spawn().context("Failed to launch Alacritty")?;
+            cmd.envs(values.env.iter().cloned());
+            cmd.args(["--title", values.title, "-e"]).arg(program).args(args);
+            cmd
         }
 
         TerminalApp::Kitty => {
             let mut cmd = Command::new("kitty");
-            cmd.arg("ssh");
-            for arg in &ssh_args {
-                cmd.arg(arg);
-            }
-            cmd.spawn().context("Failed to launch Kitty")?;
+            cmd.envs(values.env.iter().cloned());
+            cmd.args(["--title", values.title]).arg(program).args(args);
+            cmd
         }
 
-        TerminalApp::Custom(command) => {
-            let ssh_command = format!("ssh {}", ssh_args.join(" "));
-            Command::new("sh")
-                .args(&["-c", &format!("{} -e '{}'", command, ssh_command)])
-                .spawn()
-                .context("Failed to launch custom terminal")?;
+        TerminalApp::WezTerm => {
+            let mut cmd = Command::new("wezterm");
+            cmd.envs(values.env.iter().cloned());
+            cmd.args(["start", "--"]).args(command_argv(values));
+            cmd
+        }
+
+        // GNOME Terminal, Tilix and Terminator hand the command to an already
+        // running instance, which does not see our environment; `command_argv`
+        // passes it with `env`
+        TerminalApp::GnomeTerminal => {
+            let mut cmd = Command::new("gnome-terminal");
+            cmd.arg(format!("--title={}", values.title)).arg("--").args(command_argv(values));
+            cmd
+        }
+
+        TerminalApp::Konsole => {
+            let mut cmd = Command::new("konsole");
+            cmd.envs(values.env.iter().cloned());
+            cmd.args(["-p", &format!("tabtitle={}", values.title), "-e"]).args(command_argv(values));
+            cmd
+        }
+
+        TerminalApp::Foot => {
+            let mut cmd = Command::new("foot");
+            cmd.envs(values.env.iter().cloned());
+            cmd.arg(format!("--title={}", values.title)).arg("--").args(command_argv(values));
+            cmd
+        }
+
+        TerminalApp::XTerm => {
+            // -e must come last and takes the rest of the arguments
+            let mut cmd = Command::new("xterm");
+            cmd.envs(values.env.iter().cloned());
+            cmd.args(["-T", values.title, "-e"]).args(command_argv(values));
+            cmd
+        }
+
+        TerminalApp::Tilix => {
+            // Tilix only takes the command as one string, which it splits like a shell
+            let mut cmd = Command::new("tilix");
+            cmd.arg(format!("--title={}", values.title))
+                .arg("-e")
+                .arg(shell_command(values.env, program, args));
+            cmd
+        }
+
+        TerminalApp::Terminator => {
+            // -x takes the rest of the arguments as the command
+            let mut cmd = Command::new("terminator");
+            cmd.arg(format!("--title={}", values.title)).arg("-x").args(command_argv(values));
+            cmd
+        }
+
+        TerminalApp::Multiplexer(_) => {
+            anyhow::bail!("{} runs several commands, not one process", terminal);
+        }
+
+        TerminalApp::Custom(template) => {
+            let argv = template.expand(values);
+            let (program, args) = argv.split_first().context("Terminal template is empty")?;
+            let mut cmd = Command::new(program);
+            cmd.envs(values.env.iter().cloned());
+            cmd.args(args);
+            cmd
         }
 
         #[cfg(not(target_os = "macos"))]
         _ => {
             anyhow::bail!("Terminal not supported on this platform");
         }
+    };
+
+    Ok(cmd)
+}
+
+/// `values.ssh_argv`, preceded by `env NAME=value ...` when ssh needs
+/// environment variables, for terminals that may not pass ours on
+fn command_argv(values: &TemplateValues) -> Vec<String> {
+    let mut argv = Vec::new();
+    if !values.env.is_empty() {
+        argv.push("env".to_string());
+        argv.extend(values.env.iter().map(|(name, value)| format!("{}={}", name, value)));
     }
+    argv.extend(values.ssh_argv.iter().cloned());
+    argv
+}
 
-    Ok(())
+/// Quote `arg` as one word for a POSIX shell.
+///
+/// Words made only of characters the shell never interprets are left as they
+/// are; anything else is wrapped in single quotes, with embedded single quotes
+/// written as `'\''`.
+pub fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || matches!(c, '@' | '%' | '+' | '=' | ':' | ',' | '.' | '/' | '-' | '_');
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
 }
 
-/// Write SSH key to temporary file with secure permissions
-pub fn write_temp_key(key_data: &str) -> Result<PathBuf> {
-    use std::fs;
-    use std::io::Write;
+/// A shell command line running `program` with `args` and the `env` variables,
+/// for terminals that do not inherit our environment
+pub fn shell_command(env: &[(&str, String)], program: &str, args: &[String]) -> String {
+    let mut words = Vec::new();
+    if !env.is_empty() {
+        words.push("env".to_string());
+        words.extend(env.iter().map(|(name, value)| shell_quote(&format!("{}={}", name, value))));
+    }
+    words.push(shell_quote(program));
+    words.extend(args.iter().map(|arg| shell_quote(arg)));
+    words.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let temp_dir = std::env::temp_dir();
-    let key_path = temp_dir.join(format!("nebulavault_key_{}", uuid::Uuid::new_v4()));
+    const HOSTILE: &[&str] = &[
+        "",
+        "plain",
+        "with space",
+        "it's",
+        "'; touch /tmp/pwned; '",
+        "$(touch /tmp/pwned)",
+        "`touch /tmp/pwned`",
+        "\"double\" \\ back",
+        "line\nbreak",
+        "-oProxyCommand=sh",
+        "*?[glob]~",
+    ];
 
-    let mut file = fs::File::create(&key_path)
-        .context("Failed to create temporary key file")?;
+    #[test]
+    fn test_shell_quote_survives_the_shell() {
+        let args: Vec<String> = HOSTILE.iter().map(|s| s.to_string()).collect();
+        let script = shell_command(&[], "printf", &["%s\\0".to_string()])
+            + " "
+            + &args.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ");
 
-    // Set secure permissions (owner read/write only)
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = file.metadata()?.permissions();
-        perms.set_mode(0o600);
-        fs::set_permissions(&key_path, perms)?;
+        let output = Command::new("sh").args(["-c", &script]).output().unwrap();
+        assert!(output.status.success());
+        let printed: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .split_terminator('\0')
+            .map(String::from)
+            .collect();
+        assert_eq!(printed, args);
     }
 
-    file.write_all(key_data.as_bytes())
-        .context("Failed to write key data")?;
+    #[test]
+    fn test_shell_command_quotes_env_and_arguments() {
+        let env = [("SSH_ASKPASS", "/Applications/Nebula Vault/nebulavault".to_string())];
+        assert_eq!(
+            shell_command(&env, "ssh", &["--".to_string(), "o'neil@db".to_string()]),
+            "env 'SSH_ASKPASS=/Applications/Nebula Vault/nebulavault' ssh -- 'o'\\''neil@db'"
+        );
+    }
 
-    Ok(key_path)
-}
+    #[test]
+    fn test_destination_follows_end_of_options() {
+        let args = ssh_args("-oProxyCommand=sh", 2222, "deploy", &SshOptions::default());
+        assert_eq!(args, ["-p", "2222", "--", "deploy@-oProxyCommand=sh"]);
+    }
 
-/// Clean up temporary key file
-pub fn cleanup_temp_key(key_path: &PathBuf) -> Result<()> {
-    if key_path.exists() {
-        std::fs::remove_file(key_path)
-            .context("Failed to remove temporary key file")?;
+    #[test]
+    fn test_vault_host_keys_are_checked_strictly() {
+        let options = SshOptions {
+            known_hosts_file: Some(Path::new("/run/nv/known_hosts")),
+            proxy_jump: Some("bastion"),
+            ..SshOptions::default()
+        };
+        let args = ssh_args("db.internal", 22, "deploy", &options);
+        assert_eq!(
+            args,
+            [
+                "-o",
+                "GlobalKnownHostsFile=/run/nv/known_hosts",
+                "-o",
+                "StrictHostKeyChecking=ask",
+                "-J",
+                "bastion",
+                "--",
+                "deploy@db.internal"
+            ]
+        );
+    }
+
+    fn values<'a>(env: &'a [(&'static str, String)], ssh_argv: &'a [String], host: &'a str, user: &'a str) -> TemplateValues<'a> {
+        TemplateValues {
+            env,
+            ssh_argv,
+            host,
+            user,
+            port: 2222,
+            title: "Prod DB",
+        }
+    }
+
+    #[test]
+    fn test_custom_terminal_gets_arguments_as_a_vector() {
+        let template = TerminalTemplate::parse("WezTerm", "wezterm start --class {title} -- {ssh_argv}").unwrap();
+        let terminal = TerminalApp::Custom(template);
+        for hostile in HOSTILE {
+            let mut ssh_argv = vec!["ssh".to_string()];
+            ssh_argv.extend(ssh_args(hostile, 22, hostile, &SshOptions::default()));
+            let cmd = launch_command(&terminal, &values(&[], &ssh_argv, hostile, hostile)).unwrap();
+
+            assert_eq!(cmd.get_program(), "wezterm");
+            let argv: Vec<&str> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+            assert_eq!(argv[..4], ["start", "--class", "Prod DB", "--"]);
+            assert_eq!(argv[4..], ssh_argv.iter().map(String::as_str).collect::<Vec<_>>()[..]);
+        }
+    }
+
+    #[test]
+    fn test_template_placeholders() {
+        let ssh_argv = ["ssh".to_string(), "--".to_string(), "o'neil@db".to_string()];
+        let env = [("SSH_ASKPASS_REQUIRE", "force".to_string())];
+
+        let template = TerminalTemplate::parse("t", "term -T '{user}@{host}:{port}' -x {ssh_argv}").unwrap();
+        assert_eq!(
+            template.expand(&values(&env, &ssh_argv, "db", "o'neil")),
+            ["term", "-T", "o'neil@db:2222", "-x", "env", "SSH_ASKPASS_REQUIRE=force", "ssh", "--", "o'neil@db"]
+        );
+
+        let template = TerminalTemplate::parse("t", "cmd.exe /c start -- \"{ssh_command}\"").unwrap();
+        assert_eq!(
+            template.expand(&values(&[], &ssh_argv, "db", "o'neil")),
+            ["cmd.exe", "/c", "start", "--", "ssh -- 'o'\\''neil@db'"]
+        );
+    }
+
+    #[test]
+    fn test_template_parse_rejects_bad_templates() {
+        for (name, command) in [
+            ("", "xterm -e {ssh_argv}"),
+            ("t", ""),
+            ("t", "xterm -e"),
+            ("t", "{ssh_argv}"),
+            ("t", "xterm -e '{ssh_argv'"),
+            ("t", "xterm -e {ssh_args}"),
+            ("t", "xterm -e ssh:{ssh_argv}"),
+            ("t", "xterm -e 'unbalanced"),
+        ] {
+            assert!(TerminalTemplate::parse(name, command).is_err(), "{:?}", command);
+        }
+
+        let template = TerminalTemplate::parse(" Foot ", "foot --title 'Nebula {title}' {ssh_argv}").unwrap();
+        assert_eq!(template.name, "Foot");
+        assert_eq!(TerminalTemplate::parse("Foot", &template.command_line()).unwrap(), template);
+    }
+
+    #[test]
+    fn test_terminal_from_command_and_desktop_id() {
+        assert_eq!(TerminalApp::from_command("/usr/bin/gnome-terminal.wrapper"), Some(TerminalApp::GnomeTerminal));
+        assert_eq!(TerminalApp::from_command("footclient"), Some(TerminalApp::Foot));
+        assert_eq!(TerminalApp::from_command("wezterm-gui"), Some(TerminalApp::WezTerm));
+        assert_eq!(TerminalApp::from_command("st"), None);
+
+        assert_eq!(TerminalApp::from_desktop_id("org.kde.konsole.desktop"), Some(TerminalApp::Konsole));
+        assert_eq!(TerminalApp::from_desktop_id("com.gexperts.Tilix.desktop"), Some(TerminalApp::Tilix));
+        assert_eq!(TerminalApp::from_desktop_id("org.example.Unknown.desktop"), None);
+
+        let list = "# preferred terminals\n-org.gnome.Terminal.desktop\n\n+foot.desktop:new-window\nxterm.desktop\n";
+        assert_eq!(xdg_terminal_ids(list).collect::<Vec<_>>(), ["foot.desktop", "xterm.desktop"]);
+        assert_eq!(xdg_terminal_ids(list).find_map(TerminalApp::from_desktop_id), Some(TerminalApp::Foot));
+    }
+
+    #[test]
+    fn test_linux_terminal_argument_conventions() {
+        let env = [("SSH_AUTH_SOCK", "/run/user/1000/nebulavault/agent.sock".to_string())];
+        for hostile in HOSTILE {
+            let mut ssh_argv = vec!["ssh".to_string()];
+            ssh_argv.extend(ssh_args(hostile, 22, hostile, &SshOptions::default()));
+            let values = TemplateValues {
+                title: hostile,
+                ..values(&env, &ssh_argv, hostile, hostile)
+            };
+            let mut command = vec!["env", "SSH_AUTH_SOCK=/run/user/1000/nebulavault/agent.sock"];
+            command.extend(ssh_argv.iter().map(String::as_str));
+
+            let title_option = format!("--title={}", hostile);
+            let tabtitle = format!("tabtitle={}", hostile);
+            for (terminal, program, options) in [
+                (TerminalApp::WezTerm, "wezterm", vec!["start", "--"]),
+                (TerminalApp::GnomeTerminal, "gnome-terminal", vec![title_option.as_str(), "--"]),
+                (TerminalApp::Konsole, "konsole", vec!["-p", tabtitle.as_str(), "-e"]),
+                (TerminalApp::Foot, "foot", vec![title_option.as_str(), "--"]),
+                (TerminalApp::XTerm, "xterm", vec!["-T", hostile, "-e"]),
+                (TerminalApp::Terminator, "terminator", vec![title_option.as_str(), "-x"]),
+            ] {
+                let cmd = launch_command(&terminal, &values).unwrap();
+                assert_eq!(cmd.get_program(), program);
+                let argv: Vec<&str> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+                assert_eq!(argv[..options.len()], options[..], "{}", terminal);
+                assert_eq!(argv[options.len()..], command[..], "{}", terminal);
+            }
+
+            // Tilix takes one string and splits it like a shell
+            let cmd = launch_command(&TerminalApp::Tilix, &values).unwrap();
+            let argv: Vec<&str> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+            assert_eq!(argv[..2], [title_option.as_str(), "-e"]);
+            assert_eq!(shlex::split(argv[2]).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn test_multiplexer_commands() {
+        let env = [("SSH_AUTH_SOCK", "/run/user/1000/nebulavault/agent.sock".to_string())];
+        let mut ssh_argv = vec!["ssh".to_string()];
+        ssh_argv.extend(ssh_args("db; rm -rf ~", 22, "$(id)", &SshOptions::default()));
+        let values = values(&env, &ssh_argv, "db", "deploy");
+        let mut ssh: Vec<&str> = vec!["--"];
+        ssh.extend(ssh_argv.iter().map(String::as_str));
+        let mut env_ssh = vec!["--", "env", "SSH_AUTH_SOCK=/run/user/1000/nebulavault/agent.sock"];
+        env_ssh.extend(ssh_argv.iter().map(String::as_str));
+
+        let argv_of = |multiplexer, pane, exists| -> Vec<Vec<String>> {
+            let target = MultiplexerTarget {
+                multiplexer,
+                session: "ops".to_string(),
+                pane,
+            };
+            target
+                .commands(&values, exists)
+                .iter()
+                .map(|cmd| {
+                    assert_eq!(cmd.get_program(), multiplexer.command());
+                    cmd.get_args().map(|arg| arg.to_str().unwrap().to_string()).collect()
+                })
+                .collect()
+        };
+        let joined = |head: &[&str], tail: &[&str]| -> Vec<String> {
+            head.iter().chain(tail).map(|arg| arg.to_string()).collect()
+        };
+        let tmux_env = ["-e", "SSH_AUTH_SOCK=/run/user/1000/nebulavault/agent.sock"];
+
+        assert_eq!(
+            argv_of(Multiplexer::Tmux, true, false),
+            [joined(&["new-session", "-d", "-s", "ops", "-n", "Prod DB", tmux_env[0], tmux_env[1]], &ssh)]
+        );
+        assert_eq!(
+            argv_of(Multiplexer::Tmux, false, true),
+            [joined(&["new-window", "-t", "=ops:", "-n", "Prod DB", tmux_env[0], tmux_env[1]], &ssh)]
+        );
+        assert_eq!(
+            argv_of(Multiplexer::Tmux, true, true),
+            [
+                joined(&["split-window", "-t", "=ops:", tmux_env[0], tmux_env[1]], &ssh),
+                joined(&["select-layout", "-t", "=ops:", "tiled"], &[]),
+            ]
+        );
+
+        assert_eq!(
+            argv_of(Multiplexer::Zellij, false, false),
+            [
+                joined(&["attach", "--create-background", "ops"], &[]),
+                joined(&["--session", "ops", "run", "--name", "Prod DB", "--in-place"], &env_ssh),
+            ]
+        );
+        assert_eq!(
+            argv_of(Multiplexer::Zellij, false, true),
+            [
+                joined(&["--session", "ops", "action", "new-tab", "--name", "Prod DB"], &[]),
+                joined(&["--session", "ops", "run", "--name", "Prod DB", "--in-place"], &env_ssh),
+            ]
+        );
+        assert_eq!(
+            argv_of(Multiplexer::Zellij, true, true),
+            [joined(&["--session", "ops", "run", "--name", "Prod DB", "--close-on-exit"], &env_ssh)]
+        );
+
+        assert_eq!(
+            argv_of(Multiplexer::Screen, false, false),
+            [joined(&["-dmS", "ops", "-t", "Prod DB"], &env_ssh[1..])]
+        );
+        assert_eq!(
+            argv_of(Multiplexer::Screen, false, true),
+            [joined(&["-S", "ops", "-X", "screen", "-t", "Prod DB"], &env_ssh[1..])]
+        );
+    }
+
+    #[test]
+    fn test_multiplexer_session_names() {
+        assert!(MultiplexerTarget::validate_session("nebulavault").is_ok());
+        assert!(MultiplexerTarget::validate_session("ops_2-prod").is_ok());
+        for bad in ["", "ops:1", "ops.1", "=ops", "-t", "with space", "ops;id"] {
+            assert!(MultiplexerTarget::validate_session(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_zellij_broadcast_commands() {
+        let ssh_argv = ["ssh".to_string(), "--".to_string(), "db".to_string()];
+        let web = TemplateValues {
+            title: "web",
+            ..values(&[], &ssh_argv, "web", "deploy")
+        };
+        let db = values(&[], &ssh_argv, "db", "deploy");
+        let target = MultiplexerTarget {
+            multiplexer: Multiplexer::Zellij,
+            session: "ops".to_string(),
+            pane: false,
+        };
+
+        let argv_of = |exists| -> Vec<Vec<String>> {
+            target
+                .zellij_broadcast_commands(&[web, db], exists)
+                .iter()
+                .map(|cmd| cmd.get_args().map(|arg| arg.to_str().unwrap().to_string()).collect())
+                .collect()
+        };
+        let tail = [
+            vec!["--session", "ops", "run", "--name", "web", "--in-place", "--", "ssh", "--", "db"],
+            vec!["--session", "ops", "run", "--name", "Prod DB", "--close-on-exit", "--", "ssh", "--", "db"],
+            vec!["--session", "ops", "action", "toggle-active-sync-tab"],
+        ];
+        assert_eq!(argv_of(false)[0], ["attach", "--create-background", "ops"]);
+        assert_eq!(argv_of(true)[0], ["--session", "ops", "action", "new-tab", "--name", "2 hosts"]);
+        for exists in [false, true] {
+            assert_eq!(argv_of(exists)[1..], tail);
+        }
+    }
+
+    #[test]
+    fn test_kitty_session_quotes_each_window() {
+        let env = [("SSH_AUTH_SOCK", "/run/user/1000/nebula vault/agent.sock".to_string())];
+        let hostile: Vec<&str> = HOSTILE.iter().copied().filter(|s| !s.contains('\n')).collect();
+        let argvs: Vec<Vec<String>> = hostile
+            .iter()
+            .map(|hostile| {
+                let mut ssh_argv = vec!["ssh".to_string()];
+                ssh_argv.extend(ssh_args(hostile, 22, hostile, &SshOptions::default()));
+                ssh_argv
+            })
+            .collect();
+        let windows: Vec<TemplateValues> = hostile
+            .iter()
+            .zip(&argvs)
+            .map(|(hostile, ssh_argv)| TemplateValues {
+                title: hostile,
+                ..values(&env, ssh_argv, hostile, hostile)
+            })
+            .collect();
+
+        let session = kitty_session(&windows).unwrap();
+        let lines: Vec<&str> = session.lines().collect();
+        assert_eq!(lines[0], "layout grid");
+        assert_eq!(lines.len(), windows.len() + 1);
+        for (line, values) in lines[1..].iter().zip(&windows) {
+            let mut expected = vec!["launch".to_string(), "--title".to_string(), values.title.to_string()];
+            expected.extend(command_argv(values));
+            assert_eq!(shlex::split(line).unwrap(), expected);
+        }
+
+        // A line break would start a new session command
+        let titled = TemplateValues {
+            title: "db\nlaunch sh",
+            ..windows[0]
+        };
+        assert_eq!(kitty_session(&[titled]).unwrap().lines().count(), 2);
+        let ssh_argv = ["ssh".to_string(), "db\nlaunch sh".to_string()];
+        assert!(kitty_session(&[values(&[], &ssh_argv, "db", "deploy")]).is_err());
     }
-    Ok(())
 }
@@ -1,22 +1,178 @@
 use age::secrecy::{ExposeSecret, Secret};
-use anyhow::{Context, Result};
-use argon2::{
-    password_hash::{PasswordHasher, SaltString},
-    Argon2, ParamsBuilder,
-};
+use anyhow::{anyhow, Context, Result};
+use argon2::{Argon2, ParamsBuilder};
+use rand::RngCore;
+use sqlx::SqlitePool;
 use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::db;
+use crate::models::{IdentityData, VaultHeader};
+
+/// Length of the random per-vault salt in bytes
+pub const SALT_LEN: usize = 16;
+
+/// KDF algorithm identifier stored in the vault header
+const KDF_ALGORITHM: &str = "argon2id";
+
+/// Domain separator of the pre-header salt scheme (salt = SHA-256(domain || password))
+const LEGACY_SALT_DOMAIN: &[u8] = b"nebulavault-salt-v1";
+
+/// Known plaintext encrypted into the vault header to check the master password
+const VERIFIER_PLAINTEXT: &[u8] = b"nebulavault-verifier-v1";
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub m_cost: u32,
+    /// Number of iterations
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+    /// Argon2 version (0x13)
+    pub version: u32,
+}
+
+impl KdfParams {
+    /// Read the parameters recorded in a vault header
+    pub fn from_header(header: &VaultHeader) -> Result<Self> {
+        if header.kdf_algorithm != KDF_ALGORITHM {
+            anyhow::bail!("Unsupported KDF algorithm: {}", header.kdf_algorithm);
+        }
+
+        Ok(Self {
+            m_cost: u32::try_from(header.kdf_m_cost).context("Invalid KDF memory cost")?,
+            t_cost: u32::try_from(header.kdf_t_cost).context("Invalid KDF time cost")?,
+            p_cost: u32::try_from(header.kdf_p_cost).context("Invalid KDF parallelism")?,
+            version: u32::try_from(header.kdf_version).context("Invalid KDF version")?,
+        })
+    }
+
+    /// Parameters the vault should use: the preset selected in Settings if any,
+    /// otherwise the parameters it was last derived with
+    pub fn target_for_header(header: &VaultHeader) -> Result<Self> {
+        match header.kdf_preset.as_deref().and_then(KdfPreset::from_name) {
+            Some(preset) => Ok(preset.params()),
+            None => Self::from_header(header),
+        }
+    }
+
+    /// Time a single key derivation with these parameters on this machine
+    pub fn benchmark(&self) -> Result<Duration> {
+        let mut vault = Vault::new();
+        let start = Instant::now();
+        vault.derive_key("nebulavault-calibration", &[0u8; SALT_LEN], self)?;
+        Ok(start.elapsed())
+    }
+}
+
+impl Default for KdfParams {
+    /// OWASP recommendations: m=19MB, t=2, p=1
+    fn default() -> Self {
+        KdfPreset::Interactive.params()
+    }
+}
+
+/// KDF cost presets selectable in Settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdfPreset {
+    /// OWASP minimum: m=19MB, t=2, p=1
+    Interactive,
+    /// m=64MB, t=3, p=4
+    Moderate,
+    /// m=256MB, t=4, p=4
+    Paranoid,
+}
+
+impl KdfPreset {
+    pub const ALL: [KdfPreset; 3] = [KdfPreset::Interactive, KdfPreset::Moderate, KdfPreset::Paranoid];
+
+    /// Argon2id parameters of the preset
+    pub fn params(&self) -> KdfParams {
+        let (m_cost, t_cost, p_cost) = match self {
+            KdfPreset::Interactive => (19 * 1024, 2, 1),
+            KdfPreset::Moderate => (64 * 1024, 3, 4),
+            KdfPreset::Paranoid => (256 * 1024, 4, 4),
+        };
+
+        KdfParams {
+            m_cost,
+            t_cost,
+            p_cost,
+            version: argon2::Version::V0x13 as u32,
+        }
+    }
+
+    /// Find the preset matching the given parameters
+    pub fn from_params(params: &KdfParams) -> Option<KdfPreset> {
+        KdfPreset::ALL.into_iter().find(|p| p.params() == *params)
+    }
+
+    /// Name stored in the vault header
+    pub fn name(&self) -> &'static str {
+        match self {
+            KdfPreset::Interactive => "interactive",
+            KdfPreset::Moderate => "moderate",
+            KdfPreset::Paranoid => "paranoid",
+        }
+    }
+
+    /// Parse a name stored in the vault header
+    pub fn from_name(name: &str) -> Option<KdfPreset> {
+        KdfPreset::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    /// Time a key derivation for every preset on this machine
+    pub fn calibrate() -> Result<Vec<(KdfPreset, Duration)>> {
+        KdfPreset::ALL
+            .into_iter()
+            .map(|preset| Ok((preset, preset.params().benchmark()?)))
+            .collect()
+    }
+
+    /// Strongest preset whose measured derivation stays under `budget`
+    pub fn recommended(timings: &[(KdfPreset, Duration)], budget: Duration) -> KdfPreset {
+        timings
+            .iter()
+            .rev()
+            .find(|(_, elapsed)| *elapsed <= budget)
+            .map(|(preset, _)| *preset)
+            .unwrap_or(KdfPreset::Interactive)
+    }
+}
 
-use crate::models::IdentityData;
+impl std::fmt::Display for KdfPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let params = self.params();
+        let label = match self {
+            KdfPreset::Interactive => "Interactive",
+            KdfPreset::Moderate => "Moderate",
+            KdfPreset::Paranoid => "Paranoid",
+        };
+        write!(
+            f,
+            "{} ({} MB, t={}, p={})",
+            label,
+            params.m_cost / 1024,
+            params.t_cost,
+            params.p_cost
+        )
+    }
+}
 
 /// Vault handles encryption and decryption of sensitive data
 pub struct Vault {
     master_key: Option<Secret<Vec<u8>>>,
+    kdf_params: KdfParams,
 }
 
 impl std::fmt::Debug for Vault {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Vault")
             .field("master_key", &"<redacted>")
+            .field("kdf_params", &self.kdf_params)
             .finish()
     }
 }
@@ -24,115 +180,191 @@ impl std::fmt::Debug for Vault {
 impl Vault {
     /// Create a new Vault instance
     pub fn new() -> Self {
-        Self { master_key: None }
+        Self {
+            master_key: None,
+            kdf_params: KdfParams::default(),
+        }
+    }
+
+    /// KDF parameters the current key was derived with
+    pub fn kdf_params(&self) -> KdfParams {
+        self.kdf_params
+    }
+
+    /// Generate a random per-vault salt
+    pub fn generate_salt() -> Vec<u8> {
+        let mut salt = vec![0u8; SALT_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+        salt
     }
 
     /// Derive a key from the master password using Argon2id
-    /// Parameters follow OWASP recommendations: m=19MB, t=2, p=1
-    /// 
-    /// IMPORTANT: Uses a deterministic salt derived from the password itself
-    /// to ensure the same password always produces the same encryption key.
-    /// This is necessary for encrypting/decrypting data across sessions.
-    pub fn derive_key(&mut self, password: &str) -> Result<()> {
-        // Use a deterministic salt based on the password
-        // This ensures the same password always produces the same key
-        use sha2::{Sha256, Digest};
+    pub fn derive_key(&mut self, password: &str, salt: &[u8], params: &KdfParams) -> Result<()> {
+        let argon2_params = ParamsBuilder::new()
+            .m_cost(params.m_cost)
+            .t_cost(params.t_cost)
+            .p_cost(params.p_cost)
+            .build()
+            .map_err(|e| anyhow!("Failed to build Argon2 params: {:?}", e))?;
+
+        let version = argon2::Version::try_from(params.version)
+            .map_err(|e| anyhow!("Unsupported Argon2 version: {:?}", e))?;
+
+        let argon2 = Argon2::new(argon2::Algorithm::Argon2id, version, argon2_params);
+
+        let mut key = vec![0u8; 32];
+        argon2
+            .hash_password_into(password.as_bytes(), salt, &mut key)
+            .map_err(|e| anyhow!("Failed to hash password: {:?}", e))?;
+
+        self.master_key = Some(Secret::new(key));
+        self.kdf_params = *params;
+        Ok(())
+    }
+
+    /// Derive a key with the pre-header scheme, where the salt was computed
+    /// from the password itself. Only used to migrate old vaults.
+    fn derive_legacy_key(&mut self, password: &str) -> Result<()> {
+        use sha2::{Digest, Sha256};
         let mut hasher = Sha256::new();
-        hasher.update(b"nebulavault-salt-v1");
+        hasher.update(LEGACY_SALT_DOMAIN);
         hasher.update(password.as_bytes());
         let salt_bytes = hasher.finalize();
-        
-        // Convert to SaltString format (base64)
-        let salt = SaltString::encode_b64(&salt_bytes[..16])
-            .map_err(|e| anyhow::anyhow!("Failed to encode salt: {:?}", e))?;
-
-        // Configure Argon2 parameters (OWASP recommendations)
-        let params = ParamsBuilder::new()
-            .m_cost(19 * 1024) // 19 MB
-            .t_cost(2) // 2 iterations
-            .p_cost(1) // 1 parallelism
-            .build()
-            .map_err(|e| anyhow::anyhow!("Failed to build Argon2 params: {:?}", e))?;
-
-        let argon2 = Argon2::new(
-            argon2::Algorithm::Argon2id,
-            argon2::Version::V0x13,
-            params,
-        );
-
-        let password_hash = argon2
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|e| anyhow::anyhow!("Failed to hash password: {:?}", e))?;
-
-        // Extract the hash bytes as the encryption key
-        let hash_bytes = password_hash
-            .hash
-            .context("No hash in password hash")?
-            .as_bytes()
-            .to_vec();
-
-        self.master_key = Some(Secret::new(hash_bytes));
-        Ok(())
+
+        self.derive_key(password, &salt_bytes[..SALT_LEN], &KdfParams::default())
     }
 
-    /// Encrypt identity data using age
-    pub fn encrypt_identity(&self, data: &IdentityData) -> Result<Vec<u8>> {
-        let key = self
-            .master_key
-            .as_ref()
-            .context("Master key not derived")?;
+    /// Unlock the vault stored in `pool` with the master password.
+    ///
+    /// Vaults without a header are either new (no identities yet) or were created
+    /// with the legacy password-derived salt. In both cases a random salt and a
+    /// verifier are written, and legacy identities are re-encrypted with the new key.
+    ///
+    /// If a different KDF preset was selected since the last unlock, the vault is
+    /// re-keyed with the new parameters before it is returned.
+    pub async fn unlock(pool: &SqlitePool, password: &str) -> Result<Self> {
+        if let Some(header) = db::get_vault_header(pool).await? {
+            let params = KdfParams::from_header(&header)?;
+            let mut vault = Vault::new();
+            vault.derive_key(password, &header.salt, &params)?;
+            vault.check_verifier(&header.verifier)?;
+
+            let target = KdfParams::target_for_header(&header)?;
+            if target != params {
+                return vault.rekey(pool, &header.salt, password, target, header.kdf_preset).await;
+            }
+            return Ok(vault);
+        }
 
-        // Serialize the identity data
-        let json = serde_json::to_vec(data).context("Failed to serialize identity data")?;
+        // No header yet: the identities are decrypted with the legacy key inside
+        // the re-keying transaction, so a wrong password fails without side effects
+        let mut legacy = Vault::new();
+        legacy.derive_legacy_key(password)?;
+
+        let (vault, header) = Vault::create(password, KdfParams::default())?;
+        db::rekey_vault(pool, None, &header, |identity| {
+            let data = legacy
+                .decrypt_identity(&identity.encrypted_data)
+                .map_err(|_| anyhow!("Incorrect master password"))?;
+            vault.encrypt_identity(&data)
+        })
+        .await?;
+
+        Ok(vault)
+    }
 
-        // Create age encryptor with passphrase
-        let encryptor = age::Encryptor::with_user_passphrase(Secret::new(
-            String::from_utf8_lossy(key.expose_secret()).to_string(),
-        ));
+    /// Re-derive the key with new parameters and a fresh salt, re-encrypting
+    /// every identity in one transaction
+    async fn rekey(
+        &self,
+        pool: &SqlitePool,
+        previous_salt: &[u8],
+        password: &str,
+        params: KdfParams,
+        kdf_preset: Option<String>,
+    ) -> Result<Self> {
+        let (new_vault, mut header) = Vault::create(password, params)?;
+        header.kdf_preset = kdf_preset;
+
+        db::rekey_vault(pool, Some(previous_salt), &header, |identity| {
+            let data = self
+                .decrypt_identity(&identity.encrypted_data)
+                .with_context(|| format!("Failed to decrypt identity \"{}\"", identity.name))?;
+            new_vault.encrypt_identity(&data)
+        })
+        .await?;
+
+        Ok(new_vault)
+    }
 
-        let mut encrypted = Vec::new();
-        let mut writer = encryptor
-            .wrap_output(&mut encrypted)
-            .context("Failed to create age encryptor")?;
+    /// Change the master password of the vault stored in `pool`.
+    ///
+    /// The old password is checked against the verifier, every identity is
+    /// decrypted with the old key and re-encrypted with a key derived from the new
+    /// password and a fresh salt. All writes happen in one transaction, so any
+    /// failure leaves the vault untouched.
+    pub async fn change_password(
+        pool: &SqlitePool,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<Self> {
+        let old_vault = Vault::unlock(pool, old_password).await?;
+        let header = db::get_vault_header(pool)
+            .await?
+            .context("Vault has no header")?;
+
+        old_vault
+            .rekey(pool, &header.salt, new_password, old_vault.kdf_params(), header.kdf_preset)
+            .await
+    }
 
-        writer
-            .write_all(&json)
-            .context("Failed to write encrypted data")?;
-        writer
-            .finish()
-            .context("Failed to finalize encryption")?;
+    /// Derive a key from a fresh random salt and build the matching vault header
+    pub fn create(password: &str, params: KdfParams) -> Result<(Self, VaultHeader)> {
+        let salt = Vault::generate_salt();
+        let mut vault = Vault::new();
+        vault.derive_key(password, &salt, &params)?;
+
+        let now = chrono::Utc::now().to_rfc3339();
+        let header = VaultHeader {
+            salt,
+            kdf_algorithm: KDF_ALGORITHM.to_string(),
+            kdf_m_cost: params.m_cost.into(),
+            kdf_t_cost: params.t_cost.into(),
+            kdf_p_cost: params.p_cost.into(),
+            kdf_version: params.version.into(),
+            kdf_preset: None,
+            verifier: vault.create_verifier()?,
+            created_at: now.clone(),
+            updated_at: now,
+        };
 
-        Ok(encrypted)
+        Ok((vault, header))
     }
 
-    /// Decrypt identity data using age
-    pub fn decrypt_identity(&self, encrypted_data: &[u8]) -> Result<IdentityData> {
-        let key = self
-            .master_key
-            .as_ref()
-            .context("Master key not derived")?;
+    /// Encrypt the known verifier plaintext with the current key
+    pub fn create_verifier(&self) -> Result<Vec<u8>> {
+        self.encrypt_bytes(VERIFIER_PLAINTEXT)
+    }
 
-        // Create age decryptor with passphrase
-        let decryptor = match age::Decryptor::new(encrypted_data)
-            .context("Failed to create age decryptor")?
-        {
-            age::Decryptor::Passphrase(d) => d,
-            _ => anyhow::bail!("Unexpected decryptor type"),
-        };
+    /// Check that the current key decrypts the verifier stored in the vault header
+    pub fn check_verifier(&self, verifier: &[u8]) -> Result<()> {
+        match self.decrypt_bytes(verifier) {
+            Ok(plaintext) if plaintext == VERIFIER_PLAINTEXT => Ok(()),
+            _ => anyhow::bail!("Incorrect master password"),
+        }
+    }
 
-        let mut decrypted = Vec::new();
-        let mut reader = decryptor
-            .decrypt(
-                &age::secrecy::Secret::new(
-                    String::from_utf8_lossy(key.expose_secret()).to_string(),
-                ),
-                None,
-            )
-            .context("Failed to decrypt (wrong password?)")?;
+    /// Encrypt identity data using age
+    pub fn encrypt_identity(&self, data: &IdentityData) -> Result<Vec<u8>> {
+        // Serialize the identity data
+        let json = serde_json::to_vec(data).context("Failed to serialize identity data")?;
 
-        reader
-            .read_to_end(&mut decrypted)
-            .context("Failed to read decrypted data")?;
+        self.encrypt_bytes(&json)
+    }
+
+    /// Decrypt identity data using age
+    pub fn decrypt_identity(&self, encrypted_data: &[u8]) -> Result<IdentityData> {
+        let decrypted = self.decrypt_bytes(encrypted_data)?;
 
         // Deserialize the identity data
         let data: IdentityData =
@@ -141,77 +373,85 @@ impl Vault {
         Ok(data)
     }
 
-    /// Export the entire database to an encrypted blob
-    pub fn export_to_blob(&self, db_path: &str) -> Result<Vec<u8>> {
+    /// Encrypt arbitrary bytes with the master key as age passphrase
+    fn encrypt_bytes(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
         let key = self
             .master_key
             .as_ref()
             .context("Master key not derived")?;
 
-        // Read the database file
-        let db_data = std::fs::read(db_path).context("Failed to read database file")?;
-
-        // Compress the data
-        let compressed = compress_data(&db_data)?;
-
-        // Encrypt the compressed data
-        let encryptor = age::Encryptor::with_user_passphrase(Secret::new(
-            String::from_utf8_lossy(key.expose_secret()).to_string(),
-        ));
-
-        let mut encrypted = Vec::new();
-        let mut writer = encryptor
-            .wrap_output(&mut encrypted)
-            .context("Failed to create age encryptor")?;
-
-        writer
-            .write_all(&compressed)
-            .context("Failed to write encrypted data")?;
-        writer
-            .finish()
-            .context("Failed to finalize encryption")?;
-
-        Ok(encrypted)
+        encrypt_with_passphrase(plaintext, String::from_utf8_lossy(key.expose_secret()).to_string())
     }
 
-    /// Import database from an encrypted blob
-    pub fn import_from_blob(&self, blob: &[u8], db_path: &str) -> Result<()> {
+    /// Decrypt bytes produced by `encrypt_bytes`
+    fn decrypt_bytes(&self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
         let key = self
             .master_key
             .as_ref()
             .context("Master key not derived")?;
 
-        // Decrypt the blob
-        let decryptor =
-            match age::Decryptor::new(blob).context("Failed to create age decryptor")? {
-                age::Decryptor::Passphrase(d) => d,
-                _ => anyhow::bail!("Unexpected decryptor type"),
-            };
-
-        let mut decrypted = Vec::new();
-        let mut reader = decryptor
-            .decrypt(
-                &age::secrecy::Secret::new(
-                    String::from_utf8_lossy(key.expose_secret()).to_string(),
-                ),
-                None,
-            )
-            .context("Failed to decrypt (wrong password?)")?;
-
-        reader
-            .read_to_end(&mut decrypted)
-            .context("Failed to read decrypted data")?;
-
-        // Decompress the data
-        let db_data = decompress_data(&decrypted)?;
-
-        // Write to database file
-        std::fs::write(db_path, db_data).context("Failed to write database file")?;
-
-        Ok(())
+        decrypt_with_passphrase(encrypted_data, String::from_utf8_lossy(key.expose_secret()).to_string())
     }
 }
 
+/// Encrypt a backup of the database at `db_path`.
+///
+/// The backup is encrypted for the master password itself with age's scrypt
+/// recipient rather than with the vault key, whose salt is random per vault and
+/// changes with the password. It can therefore be restored on a fresh install
+/// or after the master password changed, with the password it was made with.
+pub fn export_backup(db_path: &Path, password: &str) -> Result<Vec<u8>> {
+    let db_data = std::fs::read(db_path).context("Failed to read database file")?;
+    let compressed = compress_data(&db_data)?;
+    encrypt_with_passphrase(&compressed, password.to_string())
+}
+
+/// Decrypt a backup made by [`export_backup`] and return the database it holds
+pub fn open_backup(blob: &[u8], password: &str) -> Result<Vec<u8>> {
+    let compressed = decrypt_with_passphrase(blob, password.to_string())?;
+    decompress_data(&compressed)
+}
+
+/// Encrypt with age's scrypt recipient for `passphrase`
+fn encrypt_with_passphrase(plaintext: &[u8], passphrase: String) -> Result<Vec<u8>> {
+    let encryptor = age::Encryptor::with_user_passphrase(Secret::new(passphrase));
+
+    let mut encrypted = Vec::new();
+    let mut writer = encryptor
+        .wrap_output(&mut encrypted)
+        .context("Failed to create age encryptor")?;
+
+    writer
+        .write_all(plaintext)
+        .context("Failed to write encrypted data")?;
+    writer
+        .finish()
+        .context("Failed to finalize encryption")?;
+
+    Ok(encrypted)
+}
+
+/// Decrypt data produced by `encrypt_with_passphrase`
+fn decrypt_with_passphrase(encrypted_data: &[u8], passphrase: String) -> Result<Vec<u8>> {
+    let decryptor = match age::Decryptor::new(encrypted_data)
+        .context("Failed to create age decryptor")?
+    {
+        age::Decryptor::Passphrase(d) => d,
+        _ => anyhow::bail!("Unexpected decryptor type"),
+    };
+
+    let mut decrypted = Vec::new();
+    let mut reader = decryptor
+        .decrypt(&age::secrecy::Secret::new(passphrase), None)
+        .context("Failed to decrypt (wrong password?)")?;
+
+    reader
+        .read_to_end(&mut decrypted)
+        .context("Failed to read decrypted data")?;
+
+    Ok(decrypted)
+}
+
 impl Default for Vault {
     fn default() -> Self {
         Self::new()
@@ -250,7 +490,9 @@ mod tests {
     #[test]
     fn test_encrypt_decrypt_identity() {
         let mut vault = Vault::new();
-        vault.derive_key("test_password").unwrap();
+        vault
+            .derive_key("test_password", &Vault::generate_salt(), &KdfParams::default())
+            .unwrap();
 
         let identity = IdentityData::Password {
             password: "secret123".to_string(),
@@ -266,4 +508,122 @@ mod tests {
             _ => panic!("Wrong identity type"),
         }
     }
+
+    #[test]
+    fn test_verifier_rejects_wrong_password() {
+        let (vault, header) = Vault::create("correct horse", KdfParams::default()).unwrap();
+        vault.check_verifier(&header.verifier).unwrap();
+
+        let mut wrong = Vault::new();
+        wrong
+            .derive_key("wrong horse", &header.salt, &KdfParams::default())
+            .unwrap();
+        assert!(wrong.check_verifier(&header.verifier).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unlock_migrates_legacy_vault() {
+        let test_db = db::TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let mut legacy = Vault::new();
+        legacy.derive_legacy_key("test_password").unwrap();
+        let identity = IdentityData::Password {
+            password: "secret123".to_string(),
+        };
+        let stored = db::create_identity(&pool, "legacy".to_string(), legacy.encrypt_identity(&identity).unwrap())
+            .await
+            .unwrap();
+
+        assert!(Vault::unlock(&pool, "wrong_password").await.is_err());
+        assert!(db::get_vault_header(&pool).await.unwrap().is_none());
+
+        let vault = Vault::unlock(&pool, "test_password").await.unwrap();
+        assert!(db::get_vault_header(&pool).await.unwrap().is_some());
+
+        let migrated = db::get_identity(&pool, &stored.id).await.unwrap().unwrap();
+        assert!(legacy.decrypt_identity(&migrated.encrypted_data).is_err());
+        match vault.decrypt_identity(&migrated.encrypted_data).unwrap() {
+            IdentityData::Password { password } => assert_eq!(password, "secret123"),
+            _ => panic!("Wrong identity type"),
+        }
+
+        assert!(Vault::unlock(&pool, "wrong_password").await.is_err());
+        Vault::unlock(&pool, "test_password").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_change_password_reencrypts_identities() {
+        let test_db = db::TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let vault = Vault::unlock(&pool, "old_password").await.unwrap();
+        let identity = IdentityData::Password {
+            password: "secret123".to_string(),
+        };
+        let stored = db::create_identity(&pool, "server".to_string(), vault.encrypt_identity(&identity).unwrap())
+            .await
+            .unwrap();
+
+        assert!(Vault::change_password(&pool, "wrong_password", "new_password").await.is_err());
+        let old_salt = db::get_vault_header(&pool).await.unwrap().unwrap().salt;
+
+        let new_vault = Vault::change_password(&pool, "old_password", "new_password").await.unwrap();
+        assert!(Vault::unlock(&pool, "old_password").await.is_err());
+        Vault::unlock(&pool, "new_password").await.unwrap();
+
+        let rekeyed = db::get_identity(&pool, &stored.id).await.unwrap().unwrap();
+        match new_vault.decrypt_identity(&rekeyed.encrypted_data).unwrap() {
+            IdentityData::Password { password } => assert_eq!(password, "secret123"),
+            _ => panic!("Wrong identity type"),
+        }
+
+        // A second re-key started from the old header, as another process that
+        // unlocked before the change would, writes nothing
+        let stale = vault
+            .rekey(&pool, &old_salt, "other_password", vault.kdf_params(), None)
+            .await;
+        assert!(stale.is_err());
+        Vault::unlock(&pool, "new_password").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backup_opens_with_the_password_it_was_made_with() {
+        let test_db = db::TestDb::new().await;
+        let pool = test_db.pool.clone();
+        let db_path = test_db.dir.join("vault.db");
+
+        Vault::unlock(&pool, "old_password").await.unwrap();
+        let backup = export_backup(&db_path, "old_password").unwrap();
+
+        // Changing the password afterwards does not lock the backup out
+        Vault::change_password(&pool, "old_password", "new_password").await.unwrap();
+        assert!(open_backup(&backup, "new_password").is_err());
+        let database = open_backup(&backup, "old_password").unwrap();
+        assert!(database.starts_with(b"SQLite format 3\0"));
+    }
+
+    #[tokio::test]
+    async fn test_unlock_applies_selected_preset() {
+        let test_db = db::TestDb::new().await;
+        let pool = test_db.pool.clone();
+
+        let weak = KdfParams {
+            m_cost: 8 * 1024,
+            t_cost: 1,
+            ..KdfParams::default()
+        };
+        let (vault, header) = Vault::create("test_password", weak).unwrap();
+        db::rekey_vault(&pool, None, &header, |_| unreachable!()).await.unwrap();
+        assert_eq!(vault.kdf_params(), weak);
+        let original_salt = header.salt;
+
+        db::set_kdf_preset(&pool, KdfPreset::Interactive.name()).await.unwrap();
+        let upgraded = Vault::unlock(&pool, "test_password").await.unwrap();
+        assert_eq!(upgraded.kdf_params(), KdfPreset::Interactive.params());
+
+        let header = db::get_vault_header(&pool).await.unwrap().unwrap();
+        assert_eq!(KdfParams::from_header(&header).unwrap(), KdfPreset::Interactive.params());
+        assert_ne!(header.salt, original_salt);
+    }
 }